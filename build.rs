@@ -0,0 +1,8 @@
+fn main() {
+    // the gRPC service is generated from its definition, which needs `protoc`:
+    #[cfg(feature = "grpc")]
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/mrc2tiff.proto"], &["proto"])
+        .expect("failed to compile proto/mrc2tiff.proto, is protoc installed?");
+}
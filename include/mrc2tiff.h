@@ -0,0 +1,71 @@
+/*
+ * C interface of the mrc_to_tiff library, built with `cargo build --release
+ * --features ffi` as libmrc_to_tiff.so, mrc_to_tiff.dll or
+ * libmrc_to_tiff.dylib.
+ */
+#ifndef MRC2TIFF_H
+#define MRC2TIFF_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* return codes of mrc2tiff_convert, the same as the command line exit codes */
+#define MRC2TIFF_OK 0
+#define MRC2TIFF_ERROR 1
+#define MRC2TIFF_BAD_INPUT 2
+#define MRC2TIFF_UNSUPPORTED 3
+#define MRC2TIFF_IO 4
+#define MRC2TIFF_CANCELLED 5
+
+/*
+ * Called after each written file with the number of finished and total
+ * steps, from the worker threads; returning non-zero cancels the conversion.
+ */
+typedef int (*mrc2tiff_progress)(size_t num_done, size_t total, void *user_data);
+
+typedef struct {
+    /* 1-indexed */
+    size_t start_at_frame;
+    /* 1-indexed and inclusive, 0 for the last frame of the stack */
+    size_t stop_at_frame;
+    /* bin factor N for NxN binning, 0 or 1 for none */
+    size_t bin;
+    /* like the command line option, e.g. "min-max"; NULL for none */
+    const char *normalize;
+    /* "int16", "int32", "uint8", "uint16" or "float32"; NULL for the input type */
+    const char *output_type;
+    /* write native instead of big endian files */
+    bool native_endian;
+} mrc2tiff_options;
+
+/* options that convert all frames without any processing */
+mrc2tiff_options mrc2tiff_default_options(void);
+
+/*
+ * Convert the stack at mrc_path into TIFF files in the existing directory
+ * dest_path. options may be NULL for the defaults, progress may be NULL.
+ * The files written so far are removed when the conversion is cancelled.
+ */
+int mrc2tiff_convert(
+    const char *mrc_path,
+    const char *dest_path,
+    const mrc2tiff_options *options,
+    mrc2tiff_progress progress,
+    void *user_data
+);
+
+/*
+ * The message of the last error on this thread, or NULL. It stays valid
+ * until the next call of mrc2tiff_convert on the same thread.
+ */
+const char *mrc2tiff_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
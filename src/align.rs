@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use log::debug;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Deserialize;
+
+use crate::{
+    Error,
+    process::{BinMode, Image, Processing, bin},
+    read::Volume3D,
+};
+
+/// Longest side of the images the shifts are refined on; larger slices are
+/// binned first, so their shifts are only accurate to the bin factor.
+const FINE_SIZE: usize = 1024;
+
+/// Longest side of the images on which the whole range of shifts is searched
+const COARSE_SIZE: usize = 256;
+
+/// Largest shift searched for, unless given otherwise
+pub const DEFAULT_MAX_SHIFT: usize = 32;
+
+/// What the slices are aligned to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlignReference {
+    /// The first exported slice
+    #[default]
+    First,
+    /// The average of the slices aligned so far, which follows slow changes
+    /// of the specimen better
+    Running,
+}
+
+/// Rigid drift correction, with the shifts found by cross-correlation.
+#[derive(Debug, Clone, Copy)]
+pub struct Alignment {
+    pub reference: AlignReference,
+    /// Largest shift that is searched for, in output pixels
+    pub max_shift: usize,
+}
+
+/// Offset (x, y) of a slice from the reference, in output pixels
+pub type Shift = (isize, isize);
+
+/// The shift of each of the slices `idxs` after the `processing` transform,
+/// relative to the reference.
+pub fn estimate_shifts(
+    volume: &Volume3D,
+    idxs: &[usize],
+    processing: &Processing,
+    alignment: &Alignment,
+) -> Result<HashMap<usize, Shift>, Error> {
+    let (nx, ny, _) = volume.dimensions();
+    let load = |z: usize| -> Result<Pyramid, Error> {
+        let image = processing.transform(&volume.get_slice(z)?, nx, ny);
+        Ok(Pyramid::new(&image))
+    };
+    let Some(&first) = idxs.first() else {
+        return Ok(HashMap::new());
+    };
+    let shifts = match alignment.reference {
+        AlignReference::First => {
+            let reference = load(first)?;
+            idxs.par_iter()
+                .map(|&z| Ok((z, reference.shift_of(&load(z)?, alignment.max_shift))))
+                .collect::<Result<_, Error>>()?
+        }
+        AlignReference::Running => {
+            let mut shifts = HashMap::new();
+            let mut sum: Option<Image> = None;
+            for (count, &z) in idxs.iter().enumerate() {
+                let moving = load(z)?;
+                let shift = match &sum {
+                    Some(sum) => {
+                        let mut average = sum.clone();
+                        average.data.iter_mut().for_each(|v| *v /= count as f32);
+                        let reference = Pyramid::from_fine(average, moving.factor);
+                        reference.shift_of(&moving, alignment.max_shift)
+                    }
+                    None => (0, 0),
+                };
+                let factor = moving.factor as isize;
+                let aligned = shift_image(&moving.levels[0], (shift.0 / factor, shift.1 / factor));
+                match &mut sum {
+                    Some(sum) => sum
+                        .data
+                        .iter_mut()
+                        .zip(aligned.data)
+                        .for_each(|(s, v)| *s += v),
+                    None => sum = Some(aligned),
+                }
+                shifts.insert(z, shift);
+            }
+            shifts
+        }
+    };
+    debug!("estimated shifts: {shifts:?}");
+    Ok(shifts)
+}
+
+/// Move the content of `image` by `-shift`, undoing the drift. The uncovered
+/// border is filled with the mean, so it doesn't affect the normalization much.
+pub fn shift_image(image: &Image, (sx, sy): Shift) -> Image {
+    let (w, h) = (image.width as isize, image.height as isize);
+    let mean = image.data.iter().sum::<f32>() / image.data.len().max(1) as f32;
+    let data = (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x + sx, y + sy)))
+        .map(|(x, y)| {
+            if (0..w).contains(&x) && (0..h).contains(&y) {
+                image.data[(y * w + x) as usize]
+            } else {
+                mean
+            }
+        })
+        .collect();
+    Image {
+        data,
+        width: image.width,
+        height: image.height,
+    }
+}
+
+/// Mean-free versions of a slice, from `FINE_SIZE` halving down to `COARSE_SIZE`.
+struct Pyramid {
+    levels: Vec<Image>,
+    /// Bin factor of the finest level, relative to the output pixels
+    factor: usize,
+}
+
+impl Pyramid {
+    fn new(image: &Image) -> Self {
+        let factor = image.width.max(image.height).div_ceil(FINE_SIZE).max(1);
+        let fine = if factor > 1 {
+            bin(image, factor, BinMode::Mean)
+        } else {
+            image.clone()
+        };
+        Self::from_fine(fine, factor)
+    }
+
+    fn from_fine(mut fine: Image, factor: usize) -> Self {
+        let mean = fine.data.iter().sum::<f32>() / fine.data.len().max(1) as f32;
+        fine.data.iter_mut().for_each(|v| *v -= mean);
+        let mut levels = vec![fine];
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width.max(last.height) <= COARSE_SIZE || last.width.min(last.height) < 4 {
+                break;
+            }
+            levels.push(bin(last, 2, BinMode::Mean));
+        }
+        Self { levels, factor }
+    }
+
+    /// Shift of `moving` relative to this reference: searched over the whole
+    /// range on the coarsest level, then refined on each finer one.
+    fn shift_of(&self, moving: &Pyramid, max_shift: usize) -> Shift {
+        let coarsest = self.levels.len() - 1;
+        let mut shift = (0, 0);
+        for level in (0..=coarsest).rev() {
+            let radius = if level == coarsest {
+                max_shift.div_ceil(self.factor << level) as isize
+            } else {
+                shift = (shift.0 * 2, shift.1 * 2);
+                1
+            };
+            shift = best_shift(&self.levels[level], &moving.levels[level], shift, radius);
+        }
+        let factor = self.factor as isize;
+        (shift.0 * factor, shift.1 * factor)
+    }
+}
+
+/// The shift within `radius` around `center` with the highest correlation.
+fn best_shift(reference: &Image, moving: &Image, center: Shift, radius: isize) -> Shift {
+    let shifts = (-radius..=radius)
+        .flat_map(|dy| (-radius..=radius).map(move |dx| (center.0 + dx, center.1 + dy)));
+    shifts
+        .map(|shift| (shift, correlation(reference, moving, shift)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(center, |(shift, _)| shift)
+}
+
+/// Mean product of the overlapping pixels, with `moving` shifted by `shift`.
+fn correlation(reference: &Image, moving: &Image, (sx, sy): Shift) -> f32 {
+    let (w, h) = (reference.width as isize, reference.height as isize);
+    let xs = 0.max(-sx)..w.min(w - sx);
+    let ys = 0.max(-sy)..h.min(h - sy);
+    if xs.is_empty() || ys.is_empty() {
+        return f32::MIN;
+    }
+    let mut sum = 0.0;
+    for y in ys.clone() {
+        let ref_row = &reference.data[(y * w) as usize..][xs.start as usize..xs.end as usize];
+        let start = ((y + sy) * w + xs.start + sx) as usize;
+        let moving_row = &moving.data[start..][..ref_row.len()];
+        sum += ref_row
+            .iter()
+            .zip(moving_row)
+            .map(|(a, b)| a * b)
+            .sum::<f32>();
+    }
+    sum / (xs.len() * ys.len()) as f32
+}
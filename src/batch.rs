@@ -0,0 +1,234 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use log::{error, info};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    Error,
+    convert::{self, ConvertOptions},
+    progress::ProgressSink,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    #[error("{num_failed} of {total} inputs failed to convert")]
+    Failed { num_failed: usize, total: usize },
+    #[error("with --files-from or --watch, only the destination directory can be given")]
+    UnexpectedInput,
+    #[error("invalid destination template {template:?}: {reason}")]
+    InvalidTemplate { template: String, reason: String },
+    #[error("inputs {first:?} and {second:?} would both be written to {dest:?}")]
+    DestinationCollision {
+        first: PathBuf,
+        second: PathBuf,
+        dest: PathBuf,
+    },
+}
+
+/// Template for the per-input destination used if none is given.
+pub const DEFAULT_DEST_TEMPLATE: &str = "{stem}";
+
+/// Expand the placeholders `{stem}`, `{name}` and `{parent}` of a destination
+/// template for the given input path.
+pub fn expand_template(template: &str, input: &Path) -> Result<PathBuf, BatchError> {
+    let invalid = |reason: &str| BatchError::InvalidTemplate {
+        template: template.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let stem = input
+        .file_stem()
+        .unwrap_or(input.as_os_str())
+        .to_string_lossy();
+    let name = input
+        .file_name()
+        .unwrap_or(input.as_os_str())
+        .to_string_lossy();
+    let parent = input
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .map(|parent| parent.to_string_lossy())
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| invalid("unclosed '{'"))?
+            + open;
+        out.push_str(match &rest[open + 1..close] {
+            "stem" => &stem,
+            "name" => &name,
+            "parent" => &parent,
+            other => return Err(invalid(&format!("unknown placeholder {{{other}}}"))),
+        });
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    Ok(PathBuf::from(out))
+}
+
+/// Destination directory of each input, checked for collisions up front so
+/// no input silently overwrites (or fails on) the output of another one.
+pub fn destinations(
+    inputs: &[PathBuf],
+    dest_path: &Path,
+    template: &str,
+) -> Result<Vec<PathBuf>, BatchError> {
+    let mut seen: HashMap<PathBuf, &PathBuf> = HashMap::new();
+    inputs
+        .iter()
+        .map(|input| {
+            let dest = dest_path.join(expand_template(template, input)?);
+            if let Some(first) = seen.insert(dest.clone(), input) {
+                return Err(BatchError::DestinationCollision {
+                    first: first.clone(),
+                    second: input.clone(),
+                    dest,
+                });
+            }
+            Ok(dest)
+        })
+        .collect()
+}
+
+/// Read newline-separated input paths from `source`, or from stdin if it is `-`.
+/// Empty lines are skipped.
+pub fn read_file_list(source: &Path) -> Result<Vec<PathBuf>, Error> {
+    let reader: Box<dyn BufRead> = if source == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(source)?))
+    };
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if !line.is_empty() {
+            paths.push(PathBuf::from(line));
+        }
+    }
+    Ok(paths)
+}
+
+/// Convert each input into its own directory, as returned by `destinations`.
+/// Failed inputs are logged and skipped, so one broken file doesn't abort a
+/// long batch job.
+///
+/// Unless `sequential` is set, multiple inputs are converted at the same time,
+/// sharing the worker threads with the slices of each input: a big input can
+/// use all of the threads, while small inputs that can't keep them busy on
+/// their own run side by side. With a memory limit, the inputs are always
+/// converted one after the other, as the limit applies to each conversion.
+pub fn convert_batch(
+    inputs: &[PathBuf],
+    dests: &[PathBuf],
+    sequential: bool,
+    options: &ConvertOptions,
+    progress: &dyn ProgressSink,
+) -> Result<(), Error> {
+    let num_failed = AtomicUsize::new(0);
+    let num_started = AtomicUsize::new(0);
+    let convert_idx = |idx: usize| {
+        let (input, dest) = (&inputs[idx], &dests[idx]);
+        let num = num_started.fetch_add(1, Ordering::SeqCst) + 1;
+        info!(
+            "converting {input:?} to {dest:?} ({num} of {})",
+            inputs.len()
+        );
+        if let Err(e) = convert_one(input, dest, options, progress) {
+            error!("failed to convert {input:?}: {e}");
+            progress.error(&format!("{input:?}: {e}"));
+            num_failed.fetch_add(1, Ordering::SeqCst);
+        }
+    };
+
+    if sequential || options.max_memory.is_some() {
+        (0..inputs.len()).for_each(convert_idx);
+    } else {
+        // start with the biggest inputs, so the small ones fill the gaps at the end:
+        let mut order: Vec<usize> = (0..inputs.len()).collect();
+        order.sort_by_cached_key(|&idx| {
+            Reverse(fs::metadata(&inputs[idx]).map_or(0, |meta| meta.len()))
+        });
+        order.into_par_iter().for_each(convert_idx);
+    }
+
+    let num_failed = num_failed.into_inner();
+    if num_failed > 0 {
+        return Err(BatchError::Failed {
+            num_failed,
+            total: inputs.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Convert `input` into `dest`, creating the directory if needed.
+pub fn convert_one(
+    input: &Path,
+    dest: &Path,
+    options: &ConvertOptions,
+    progress: &dyn ProgressSink,
+) -> Result<(), Error> {
+    fs::create_dir_all(dest)?;
+    convert::convert(input.to_owned(), dest.to_owned(), options, progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_placeholders() {
+        let input = Path::new("/data/grid1/ts_01.mrc");
+        assert_eq!(
+            expand_template("{stem}", input).unwrap(),
+            Path::new("ts_01")
+        );
+        assert_eq!(
+            expand_template("{name}", input).unwrap(),
+            Path::new("ts_01.mrc")
+        );
+        assert_eq!(
+            expand_template("{parent}/{stem}_tiff", input).unwrap(),
+            Path::new("grid1/ts_01_tiff")
+        );
+        assert_eq!(expand_template("fixed", input).unwrap(), Path::new("fixed"));
+    }
+
+    #[test]
+    fn invalid_templates() {
+        let input = Path::new("a.mrc");
+        assert!(matches!(
+            expand_template("{stem", input),
+            Err(BatchError::InvalidTemplate { .. })
+        ));
+        assert!(matches!(
+            expand_template("{date}", input),
+            Err(BatchError::InvalidTemplate { .. })
+        ));
+    }
+
+    #[test]
+    fn destinations_per_input() {
+        let inputs = [PathBuf::from("a/ts.mrc"), PathBuf::from("b/ts.mrc")];
+        assert_eq!(
+            destinations(&inputs, Path::new("out"), "{parent}_{stem}").unwrap(),
+            [Path::new("out/a_ts"), Path::new("out/b_ts")]
+        );
+        assert!(matches!(
+            destinations(&inputs, Path::new("out"), DEFAULT_DEST_TEMPLATE),
+            Err(BatchError::DestinationCollision { .. })
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use log::info;
+use mrc::MrcMmap;
+use rayon::{
+    ThreadPoolBuilder,
+    iter::{IntoParallelIterator, ParallelIterator},
+};
+
+use mrc_to_tiff::{
+    common::ArgEndianess,
+    convert::OutputNaming,
+    process::{Normalize, Processing},
+    read::{Slice, Volume3D},
+    write::{SampleType, TiffMetadata, WriteBackend, write_tiff},
+};
+
+/// Throughput of one benchmark stage.
+struct Measurement {
+    stage: &'static str,
+    threads: usize,
+    bytes: usize,
+    slices: usize,
+    elapsed: Duration,
+}
+
+impl Measurement {
+    fn print(&self) {
+        let secs = self.elapsed.as_secs_f64();
+        println!(
+            "{:<20} {:>7} {:>10.1} {:>10.1}",
+            self.stage,
+            self.threads,
+            self.bytes as f64 / secs / 1e6,
+            self.slices as f64 / secs,
+        );
+    }
+}
+
+/// Thread counts used if none are given: powers of two up to the number of CPUs.
+pub fn default_thread_counts() -> Vec<usize> {
+    let max_threads = rayon::current_num_threads();
+    let mut counts: Vec<usize> = (0..)
+        .map(|exp| 1 << exp)
+        .take_while(|&n| n < max_threads)
+        .collect();
+    counts.push(max_threads);
+    counts
+}
+
+/// Measure reading, processing and writing separately, for each of the thread
+/// counts and for both writer backends, and print the throughput to stdout.
+/// Files are written to a fresh sub-directory of `scratch_dir`, which is
+/// removed afterwards.
+pub fn bench(
+    mrc_path: &Path,
+    scratch_dir: &Path,
+    max_slices: usize,
+    thread_counts: &[usize],
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let data = MrcMmap::open(mrc_path)?;
+    let volume = Volume3D::new(data.read_view()?);
+    let (nx, ny, nz) = volume.dimensions();
+    let num_slices = nz.min(max_slices);
+    let slice_bytes = nx * ny * size_of::<i16>();
+    info!("benchmarking {num_slices} slices of {nx}x{ny}");
+
+    let dest = scratch_dir.join(format!("mrc-to-tiff-bench-{}", std::process::id()));
+    fs::create_dir(&dest)?;
+    let res = run_stages(&volume, &dest, num_slices, slice_bytes, thread_counts);
+    fs::remove_dir_all(&dest)?;
+    res
+}
+
+fn run_stages(
+    volume: &Volume3D,
+    dest: &Path,
+    num_slices: usize,
+    slice_bytes: usize,
+    thread_counts: &[usize],
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (nx, ny, _) = volume.dimensions();
+    let processing = Processing {
+        normalize: Normalize::MinMax,
+        output_type: Some(SampleType::U16),
+        ..Default::default()
+    };
+    let metadata = TiffMetadata::default();
+    let naming = OutputNaming::default();
+
+    println!(
+        "{:<20} {:>7} {:>10} {:>10}",
+        "stage", "threads", "MB/s", "slices/s"
+    );
+    for &threads in thread_counts {
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build()?;
+        let measure = |stage: &'static str,
+                       f: &(dyn Fn(usize) -> Result<(), Box<dyn Error + Sync + Send>> + Sync)|
+         -> Result<Measurement, Box<dyn Error + Sync + Send>> {
+            let t0 = Instant::now();
+            pool.install(|| (0..num_slices).into_par_iter().try_for_each(f))?;
+            Ok(Measurement {
+                stage,
+                threads,
+                bytes: num_slices * slice_bytes,
+                slices: num_slices,
+                elapsed: t0.elapsed(),
+            })
+        };
+
+        // copy the slices out of the mapping, so that every page is actually read:
+        measure("read", &|z| {
+            std::hint::black_box(volume.get_slice(z)?.into_owned());
+            Ok(())
+        })?
+        .print();
+        measure("process", &|z| {
+            let image = processing.apply(&volume.get_slice(z)?, nx, ny);
+            std::hint::black_box(image.to_samples::<u16>());
+            Ok(())
+        })?
+        .print();
+        for (stage, endianess, backend) in [
+            (
+                "write (big endian)",
+                ArgEndianess::Big,
+                WriteBackend::Buffered,
+            ),
+            (
+                "write (native)",
+                ArgEndianess::Native,
+                WriteBackend::Buffered,
+            ),
+            (
+                "write (direct I/O)",
+                ArgEndianess::Big,
+                WriteBackend::Direct,
+            ),
+        ] {
+            measure(stage, &|z| {
+                let out_path = dest.join(naming.slice_filename(z + 1));
+                match volume.get_slice(z)? {
+                    Slice::Signed(values) => {
+                        write_tiff(&out_path, &values, nx, ny, &endianess, backend, &metadata)?
+                    }
+                    Slice::Unsigned(values) => {
+                        write_tiff(&out_path, &values, nx, ny, &endianess, backend, &metadata)?
+                    }
+                }
+                Ok(())
+            })?
+            .print();
+            clear_dir(dest)?;
+        }
+    }
+    Ok(())
+}
+
+fn clear_dir(dir: &Path) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    for path in paths {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use std::sync::Mutex;
+
+/// Buffers for whole slices, which are reused by the workers of a conversion
+/// instead of allocating new ones for each slice. That keeps the allocator
+/// from fragmenting or holding on to memory when thousands of large slices
+/// pass through in parallel.
+#[derive(Debug)]
+pub struct BufferPool<T> {
+    buffers: Mutex<Vec<Vec<T>>>,
+    /// Of each buffer, in elements
+    capacity: usize,
+    /// Buffers that are kept; more are dropped when they are returned
+    max_buffers: usize,
+}
+
+impl<T> BufferPool<T> {
+    /// For buffers of `capacity` elements, like the number of pixels of a
+    /// slice, keeping up to `max_buffers`, like the number of slices in flight.
+    pub fn new(capacity: usize, max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            capacity,
+            max_buffers,
+        }
+    }
+
+    /// An empty buffer with room for at least `capacity` elements.
+    pub fn take(&self) -> Vec<T> {
+        let buffer = self
+            .buffers
+            .lock()
+            .expect("buffer pool lock poisoned")
+            .pop();
+        buffer.unwrap_or_else(|| Vec::with_capacity(self.capacity))
+    }
+
+    /// Return a buffer for reuse. Buffers that are too small for a slice,
+    /// like those of binned images, are dropped instead.
+    pub fn put(&self, mut buffer: Vec<T>) {
+        if buffer.capacity() < self.capacity {
+            return;
+        }
+        let mut buffers = self.buffers.lock().expect("buffer pool lock poisoned");
+        if buffers.len() < self.max_buffers {
+            buffer.clear();
+            buffers.push(buffer);
+        }
+    }
+}
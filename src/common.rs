@@ -1,6 +1,112 @@
+use std::{error::Error, path::Path, str::FromStr};
 
-#[derive(Debug, clap::ValueEnum, Clone)]
+#[derive(
+    Debug, Default, clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum ArgEndianess {
+    #[default]
     Big,
     Native,
 }
+
+/// The message of `err`, followed by one line for each of its causes.
+pub fn error_chain(err: &dyn Error) -> String {
+    let mut msg = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        msg += &format!("\ncaused by: {cause}");
+        source = cause.source();
+    }
+    msg
+}
+
+/// Free space in bytes on the file system containing `path`, as far as
+/// it can be determined on this platform.
+pub fn available_space(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: `c_path` is nul-terminated and `stat` is a valid out pointer
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid size {0:?}, expected a number of bytes with an optional K, M, G or T suffix")]
+pub struct ParseMemorySizeError(String);
+
+/// An amount of memory in bytes, parsed from strings like `8G` or `512M`.
+/// The suffixes are powers of 1024.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct MemorySize(pub u64);
+
+impl FromStr for MemorySize {
+    type Err = ParseMemorySizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseMemorySizeError(s.to_owned());
+        let trimmed = s
+            .trim()
+            .trim_end_matches(['B', 'b'])
+            .trim_end_matches(['i']);
+        let (number, shift) = match trimmed.char_indices().last() {
+            Some((idx, suffix)) if suffix.is_ascii_alphabetic() => {
+                let shift = match suffix.to_ascii_uppercase() {
+                    'K' => 10,
+                    'M' => 20,
+                    'G' => 30,
+                    'T' => 40,
+                    _ => return Err(err()),
+                };
+                (&trimmed[..idx], shift)
+            }
+            _ => (trimmed, 0),
+        };
+        let number: f64 = number.trim().parse().map_err(|_| err())?;
+        if !number.is_finite() || number < 0.0 {
+            return Err(err());
+        }
+        Ok(MemorySize((number * (1u64 << shift) as f64) as u64))
+    }
+}
+
+impl TryFrom<String> for MemorySize {
+    type Error = ParseMemorySizeError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_sizes() {
+        let size = |s: &str| s.parse::<MemorySize>().ok().map(|MemorySize(bytes)| bytes);
+        assert_eq!(size("4096"), Some(4096));
+        assert_eq!(size("512M"), Some(512 << 20));
+        assert_eq!(size("8G"), Some(8 << 30));
+        assert_eq!(size("8GiB"), Some(8 << 30));
+        assert_eq!(size("1.5k"), Some(1536));
+        assert_eq!(size(" 2 T "), Some(2 << 40));
+        assert_eq!(size("8X"), None);
+        assert_eq!(size("-1G"), None);
+        assert_eq!(size("G"), None);
+        assert_eq!(size(""), None);
+    }
+}
@@ -1,107 +1,1131 @@
 use std::{
-    error::Error,
-    path::PathBuf,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Display,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        mpsc::Sender,
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver},
     },
+    thread,
     time::Instant,
 };
 
-use indicatif::{MultiProgress, ParallelProgressIterator, ProgressBar};
-use log::{debug, info};
+use log::{debug, info, warn};
 use mrc::MrcMmap;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::{
+    ThreadPool, ThreadPoolBuilder,
+    iter::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelBridge,
+        ParallelIterator,
+    },
+    slice::ParallelSlice,
+};
+use serde::Deserialize;
 
 use crate::{
+    Error,
+    align::{self, Alignment, shift_image},
+    buffer_pool::BufferPool,
     common::ArgEndianess,
-    read::Volume3D,
-    write::{write_tiff_big_endian, write_tiff_native_endian},
+    dose::{DEFAULT_VOLTAGE, DoseFilter, DoseWeighting},
+    fft::{SPECTRUM_SIZE, log_power_spectrum},
+    index::{DatasetIndex, IndexEntry, SectionGeometry},
+    manifest::{Chunk, ChunkManifest},
+    montage::Montage,
+    process::{self, Crop, Image, Partial, Processing, Projection},
+    processor::{SliceInfo, SliceProcessors},
+    progress::ProgressSink,
+    read::{self, Axis, Slice, Volume3D},
+    stats::{self, SliceStats},
+    throttle::IoLimiter,
+    tilt,
+    timings::{Stage, StageTimings, time, time_write},
+    write::{
+        PreallocatedStack, SampleType, StackPage, StackWriter, TiffMetadata, WriteBackend,
+        write_image, write_tiff,
+    },
 };
 
-#[derive(Debug)]
-pub enum ProgressMessage {
-    InProgress { num_done: usize, total: usize },
-    Done { total: usize },
-    Error { msg: String },
+/// Number of slices on each side that a slice is compared to when fixing temporal outliers
+const TEMPORAL_NEIGHBORS: usize = 2;
+
+/// The transformed slices of a running average window and their indices, in order
+type Window = VecDeque<(usize, Image)>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConvertError {
+    #[error("input data can't be read as a 16bit integer stack")]
+    UnsupportedMode { source: mrc::Error },
+    #[error("invalid frame range {start_at_frame}..={stop_at_frame} for a stack with {nz} frames")]
+    InvalidFrameRange {
+        start_at_frame: usize,
+        stop_at_frame: usize,
+        nz: usize,
+    },
+    #[error("frame {frame} is not part of the stack with {nz} frames")]
+    InvalidFrame { frame: usize, nz: usize },
+    #[error("can't compute a projection over an empty range of frames")]
+    EmptyProjection,
+    #[error("crop region {crop} doesn't fit into the slice size {nx}x{ny}")]
+    InvalidCrop { crop: Crop, nx: usize, ny: usize },
+    #[error("the scale bar can only be drawn onto 8-bit exports, use --output-type uint8")]
+    ScalebarNeeds8Bit,
+    #[error("the input file doesn't specify a pixel size, which is needed for {needed_for}")]
+    UnknownPixelSize { needed_for: &'static str },
+    #[error("no exposure per frame in the .mdoc file or the extended header, use --dose-per-frame")]
+    UnknownDose,
+    #[error("the statistics file {path:?} already exists")]
+    StatsFileExists { path: PathBuf },
+    #[error("writing the stack has stopped")]
+    StackWriterStopped,
+    #[error("the conversion was cancelled")]
+    Cancelled,
+}
+
+/// Stops a running conversion from another thread, like a cancel button.
+/// Clones share the same state.
+#[derive(Debug, Default, Clone)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The conversions using this token stop before the next slice, and
+    /// remove the files they have written so far.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Convert the 1-indexed, inclusive frame numbers given by the user into a
+/// 0-indexed range of slices, validated against the stack depth `nz`.
+pub fn frame_range(
+    start_at_frame: usize,
+    stop_at_frame: Option<usize>,
+    nz: usize,
+) -> Result<Range<usize>, ConvertError> {
+    let stop_at_frame = stop_at_frame.unwrap_or(nz);
+    if start_at_frame == 0 || start_at_frame > stop_at_frame + 1 || stop_at_frame > nz {
+        return Err(ConvertError::InvalidFrameRange {
+            start_at_frame,
+            stop_at_frame,
+            nz,
+        });
+    }
+    Ok(start_at_frame - 1..stop_at_frame)
+}
+
+/// Convert a list of 1-indexed frame numbers into 0-indexed slices, in the
+/// given order.
+pub fn frame_list(frames: &[usize], nz: usize) -> Result<Vec<usize>, ConvertError> {
+    frames
+        .iter()
+        .map(|&frame| match frame {
+            1.. if frame <= nz => Ok(frame - 1),
+            _ => Err(ConvertError::InvalidFrame { frame, nz }),
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Extension {
+    #[default]
+    Tif,
+    Tiff,
+}
+
+impl Extension {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Extension::Tif => "tif",
+            Extension::Tiff => "tiff",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid name template {0:?}, it needs to contain {{index}} and no path separators")]
+pub struct ParseTemplateError(String);
+
+/// Name of the written slices, without the extension, with `{index}`
+/// standing for the zero-padded slice number.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    const PLACEHOLDER: &str = "{index}";
+
+    fn render(&self, idx: usize) -> String {
+        self.0.replace(Self::PLACEHOLDER, &format!("{idx:05}"))
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        NameTemplate("slice_{index}".to_owned())
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = ParseTemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // without the index, all slices would be written to the same file:
+        if !s.contains(Self::PLACEHOLDER) || s.contains(['/', '\\']) {
+            return Err(ParseTemplateError(s.to_owned()));
+        }
+        Ok(NameTemplate(s.to_owned()))
+    }
+}
+
+impl TryFrom<String> for NameTemplate {
+    type Error = ParseTemplateError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for NameTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// How the written files are named.
+#[derive(Debug, Clone, Default)]
+pub struct OutputNaming {
+    pub template: NameTemplate,
+    pub extension: Extension,
+    /// Appended to the name of each file, before the extension
+    pub suffix: String,
+    /// Tilt angle of each written slice in degrees, added to its name
+    pub angles: Option<Vec<f32>>,
+}
+
+impl OutputNaming {
+    /// File name of the `idx`-th written slice, 1-indexed relative to the first exported frame.
+    pub fn slice_filename(&self, idx: usize) -> String {
+        let angle = match self.angles.as_ref().and_then(|angles| angles.get(idx - 1)) {
+            Some(angle) => format!("_{angle:+.1}"),
+            None => String::new(),
+        };
+        format!(
+            "{}{angle}{}.{}",
+            self.template.render(idx),
+            self.suffix,
+            self.extension.as_str()
+        )
+    }
+
+    pub fn projection_filename(&self, projection: Projection) -> String {
+        format!(
+            "projection_{}{}.{}",
+            projection.name(),
+            self.suffix,
+            self.extension.as_str()
+        )
+    }
+
+    /// File name of the `idx`-th montage, 1-indexed.
+    pub fn montage_filename(&self, idx: usize) -> String {
+        format!(
+            "montage_{idx:03}{}.{}",
+            self.suffix,
+            self.extension.as_str()
+        )
+    }
+
+    /// File name of the single file with all slices as its pages.
+    pub fn stack_filename(&self) -> String {
+        format!("stack{}.{}", self.suffix, self.extension.as_str())
+    }
+
+    /// File name of the `DatasetIndex` of the written files.
+    pub fn index_filename(&self) -> String {
+        format!("index{}.json", self.suffix)
+    }
+
+    /// File name of the `ChunkManifest` of the written files.
+    pub fn manifest_filename(&self) -> String {
+        format!("manifest{}.json", self.suffix)
+    }
+}
+
+/// Options controlling which frames are converted and how they are written.
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    pub endianess: ArgEndianess,               // tif output endianess
+    pub write_backend: WriteBackend,           // how the files are written to disk
+    pub start_at_frame: usize,                 // 1-indexed
+    pub stop_at_frame: Option<usize>,          // 1-indexed, last frame if not given
+    pub frames: Option<Vec<usize>>,            // 1-indexed, converted instead of the range
+    pub axis: Axis,                            // axis perpendicular to the written slices
+    pub assume_unsigned: bool,                 // read the values of mode 1 as unsigned
+    pub processors: SliceProcessors,           // custom steps, before the processing
+    pub processing: Processing,                // applied to each slice before writing
+    pub fix_temporal_outliers: Option<f32>,    // sigma, before the processing
+    pub dose_weighting: Option<DoseWeighting>, // exposure filter, after the processing transform
+    pub align: Option<Alignment>,              // drift correction, after the dose weighting
+    pub projection: Option<Projection>,        // write a single projection instead of all slices
+    pub grouping: Option<Grouping>,            // combine groups of consecutive slices
+    pub running_average: Option<usize>,        // average a window of N slices around each one
+    pub montage: Option<Montage>,              // write tiled overviews instead of all slices
+    pub export_fft: bool,                      // write the log power spectra instead of the slices
+    pub single_file: bool,                     // write the slices as the pages of one file
+    pub max_memory: Option<u64>,               // bytes, bounds the number of slices in flight
+    pub io_limit: Option<Arc<IoLimiter>>,      // shared by all conversions using these options
+    pub timings: Option<Arc<StageTimings>>,    // records the stages of each slice, also shared
+    pub naming: OutputNaming,                  // extension and suffix of the written files
+    pub stats: Option<StatsExport>,            // CSV of the input statistics of each slice
+    pub write_index: bool,                     // JSON file relating the files to the input sections
+    pub write_manifest: bool,                  // JSON file with the data offset of each file
+    pub cancel: Option<CancelToken>,           // stops the conversion between slices once set
 }
 
+/// Where to write the statistics of each converted input slice, and which
+/// percentiles to include besides the min, max, mean and standard deviation.
+#[derive(Debug, Clone)]
+pub struct StatsExport {
+    /// Relative to the output directory
+    pub path: PathBuf,
+    pub percentiles: Vec<f32>,
+}
+
+/// Combine every `size` consecutive slices into one output frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Grouping {
+    pub size: usize,
+    pub mode: Projection, // how the slices of a group are combined
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            endianess: ArgEndianess::Big,
+            write_backend: WriteBackend::Buffered,
+            start_at_frame: 1,
+            stop_at_frame: None,
+            frames: None,
+            axis: Axis::Z,
+            assume_unsigned: false,
+            processors: SliceProcessors::default(),
+            processing: Processing::default(),
+            fix_temporal_outliers: None,
+            dose_weighting: None,
+            align: None,
+            projection: None,
+            grouping: None,
+            running_average: None,
+            montage: None,
+            export_fft: false,
+            single_file: false,
+            max_memory: None,
+            io_limit: None,
+            timings: None,
+            naming: OutputNaming::default(),
+            stats: None,
+            write_index: false,
+            write_manifest: false,
+            cancel: None,
+        }
+    }
+}
+
+impl ConvertOptions {
+    /// Rough total size of the files written for `num_frames` input frames of
+    /// `nx` x `ny`, not counting the tiff headers.
+    pub fn estimated_output_bytes(&self, nx: usize, ny: usize, num_frames: usize) -> u64 {
+        let processing = &self.processing;
+        let (width, height) = processing.output_dimensions(nx, ny);
+        let (num_files, (width, height), sample_type) =
+            match (self.projection, self.montage, self.grouping) {
+                (Some(projection), _, _) => (
+                    1,
+                    (width, height),
+                    projection.output_sample_type(processing),
+                ),
+                (None, Some(montage), _) => (
+                    num_frames.div_ceil(montage.tiles()),
+                    montage.dimensions(width, height),
+                    processing.output_sample_type(),
+                ),
+                (None, None, Some(grouping)) if grouping.size > 1 => (
+                    num_frames / grouping.size,
+                    (width, height),
+                    grouping.mode.output_sample_type(processing),
+                ),
+                _ if self.running_average.is_some_and(|n| n > 1) => (
+                    num_frames,
+                    (width, height),
+                    Projection::Mean.output_sample_type(processing),
+                ),
+                _ => (num_frames, (width, height), processing.output_sample_type()),
+            };
+        let ((width, height), sample_type) = if self.export_fft {
+            let size = (width.min(SPECTRUM_SIZE), height.min(SPECTRUM_SIZE));
+            (size, processing.for_spectra().output_sample_type())
+        } else {
+            ((width, height), sample_type)
+        };
+        (num_files * width * height * sample_type.bytes_per_sample()) as u64
+    }
+}
+
+/// Convert the frames of the stack at `mrc_path` selected by `options`,
+/// writing the files into the existing directory `dest_path`, and reporting
+/// the progress to `progress`.
 pub fn convert(
-    mrc_path: PathBuf,            // 3d, 16bit
-    dest_path: PathBuf,           // directory
-    endianess: ArgEndianess,      // tif output endianess
-    start_at_frame: usize,        // 1-indexed
-    stop_at_frame: Option<usize>, // 1-indexed, last frame if not given
-    multi_progress: &MultiProgress,
-    progress_q: Option<Sender<ProgressMessage>>,
-) -> Result<(), Box<dyn Error + Sync + Send>> {
+    mrc_path: PathBuf,  // 3d, 16bit
+    dest_path: PathBuf, // directory
+    options: &ConvertOptions,
+    progress: &dyn ProgressSink,
+) -> Result<(), Error> {
     let t0 = Instant::now();
+    info!("converting {mrc_path:?} to {dest_path:?}");
+    debug!("options: {options:?}");
+    let ConvertOptions {
+        endianess,
+        write_backend,
+        start_at_frame,
+        stop_at_frame,
+        frames,
+        axis,
+        assume_unsigned,
+        processors,
+        processing,
+        fix_temporal_outliers,
+        dose_weighting,
+        align,
+        projection,
+        grouping,
+        running_average,
+        montage,
+        export_fft,
+        single_file,
+        max_memory,
+        io_limit,
+        timings,
+        naming,
+        stats,
+        write_index,
+        write_manifest,
+        cancel,
+    } = options;
 
-    let data = MrcMmap::open(mrc_path)?;
+    // it is only written at the end, which shouldn't fail after the whole conversion:
+    if let Some(export) = stats {
+        let path = dest_path.join(&export.path);
+        if path.exists() {
+            return Err(ConvertError::StatsFileExists { path }.into());
+        }
+    }
 
-    let (nx, ny, nz) = data.read_view()?.dimensions();
-    info!("dimensions: {nz}x{ny}x{nx}");
+    let data = MrcMmap::open(&mrc_path)?;
+    read::check_file_size(&mrc_path, data.header())?;
 
     let view = data.read_view()?;
+    {
+        let (nx, ny, nz) = view.dimensions();
+        info!("dimensions: {nz}x{ny}x{nx}");
+    }
 
-    let ints = view.data.as_i16_slice()?;
-    debug!("len of slice: {}", ints.len());
+    read::check_mode(&view).map_err(|source| ConvertError::UnsupportedMode { source })?;
 
     info!("endianess: {:?}", endianess);
+    if *write_backend != WriteBackend::Buffered {
+        info!("write backend: {write_backend:?}");
+    }
+    if *axis != Axis::Z {
+        info!("slicing along axis {axis:?}");
+    }
+    if !processors.is_empty() {
+        info!("custom processors: {processors:?}");
+    }
+    if !processing.is_identity() {
+        info!("processing: {processing:?}");
+    }
 
-    let start = start_at_frame - 1;
-    let stop = stop_at_frame.unwrap_or(nz);
+    let header = view.header();
+    let pixel_size = (header.mx > 0 && header.xlen > 0.0).then(|| header.xlen / header.mx as f32);
+    if processing.scalebar.is_some() {
+        if processing.output_type != Some(SampleType::U8) {
+            return Err(ConvertError::ScalebarNeeds8Bit.into());
+        }
+        if pixel_size.is_none() {
+            return Err(ConvertError::UnknownPixelSize {
+                needed_for: "the scale bar",
+            }
+            .into());
+        }
+    }
+    if processing.target_pixel_size.is_some() && pixel_size.is_none() {
+        return Err(ConvertError::UnknownPixelSize {
+            needed_for: "resampling to the target pixel size",
+        }
+        .into());
+    }
+    let unsigned = *assume_unsigned || read::is_unsigned(header);
+    if unsigned {
+        info!("reading the values as unsigned");
+    }
+    let processing = Processing {
+        pixel_size,
+        unsigned_input: unsigned,
+        ..processing.clone()
+    };
+    let dose_filter = match dose_weighting {
+        Some(_) if *axis != Axis::Z => {
+            warn!("dose weighting only applies to slices along z, skipping it");
+            None
+        }
+        Some(weighting) => {
+            let nz = view.dimensions().2;
+            let doses = match weighting.dose_per_frame {
+                Some(dose) => vec![dose; nz],
+                None => {
+                    tilt::exposure_doses(&mrc_path, header, nz).ok_or(ConvertError::UnknownDose)?
+                }
+            };
+            let voltage = weighting
+                .voltage
+                .or_else(|| tilt::acceleration_voltage(&mrc_path, header))
+                .unwrap_or(DEFAULT_VOLTAGE);
+            let needed_for = "dose weighting";
+            let pixel_size = processing
+                .output_pixel_size()
+                .ok_or(ConvertError::UnknownPixelSize { needed_for })?;
+            info!("dose weighting with {voltage} kV and exposures {doses:?} e/Å²");
+            Some(DoseFilter::new(&doses, pixel_size, voltage))
+        }
+        None => None,
+    };
+    let processing = Processing {
+        dose_weighted: dose_filter.is_some(),
+        ..processing
+    };
+    let geometry = write_index.then(|| SectionGeometry::new(&mrc_path, header, *axis));
 
-    assert!(start <= stop);
+    let volume = Volume3D::new(view).along(*axis).assume_unsigned(unsigned);
+    let (nx, ny, nz) = volume.dimensions();
 
-    let volume = Volume3D::new(view);
-    let idxs: Vec<usize> = (start..stop).collect();
-    let len = idxs.len() as u64;
-    let progress = multi_progress.add(ProgressBar::new(len));
+    let idxs: Vec<usize> = match frames {
+        Some(frames) => frame_list(frames, nz)?,
+        None => frame_range(*start_at_frame, *stop_at_frame, nz)?.collect(),
+    };
+    if let Some(crop) = processing.crop
+        && !crop.fits_into(nx, ny)
+    {
+        return Err(ConvertError::InvalidCrop { crop, nx, ny }.into());
+    }
+    if let Some(dark) = &processing.dark {
+        dark.check_dimensions(nx, ny)?;
+    }
+    if let Some(gain) = &processing.gain {
+        gain.check_dimensions(nx, ny)?;
+    }
 
-    // alternative "progress bar" for GUI version
-    let done = AtomicUsize::new(0);
+    let pool = match max_memory {
+        Some(max_memory) => Some(bounded_pool(
+            *max_memory,
+            nx * ny,
+            processing.is_identity(),
+        )?),
+        None => None,
+    };
 
-    let res: Result<Vec<()>, _> = idxs
-        .into_par_iter()
-        .progress_with(progress.clone())
-        .map(|z| -> Result<(), Box<dyn Error + Sync + Send>> {
-            let slice = volume.get_slice(z)?;
-            let idx = z + 1 - start;
-            let out_path = dest_path.join(format!("slice_{idx:05}.tif"));
-            match endianess {
-                ArgEndianess::Big => {
-                    write_tiff_big_endian(&out_path, slice, nx, ny)?;
-                }
-                ArgEndianess::Native => {
-                    write_tiff_native_endian(&out_path, slice, nx, ny)?;
-                }
+    // a projection is normalized as a single image, so there are no stack-wide statistics to prepare:
+    let processing = if projection.is_none() && !export_fft {
+        match grouping.filter(|g| g.size > 1 && montage.is_none()) {
+            // the statistics are those of the combined frames:
+            Some(grouping) => {
+                let groups: Vec<&[usize]> = idxs.chunks_exact(grouping.size).collect();
+                install(pool.as_ref(), || {
+                    processing.prepare_groups(&volume, &groups, grouping.mode)
+                })?
             }
-            done.fetch_add(1, Ordering::SeqCst);
-            if let Some(prog_q) = &progress_q {
-                prog_q
-                    .send(ProgressMessage::InProgress {
-                        num_done: done.load(Ordering::SeqCst),
-                        total: len as usize,
-                    })?;
+            None => install(pool.as_ref(), || processing.prepare(&volume, &idxs))?,
+        }
+    } else {
+        processing.clone()
+    };
+    let shifts = match align {
+        Some(alignment) => {
+            info!(
+                "aligning the slices to the {:?} reference...",
+                alignment.reference
+            );
+            let shifts = install(pool.as_ref(), || {
+                align::estimate_shifts(&volume, &idxs, &processing, alignment)
+            })?;
+            Some(shifts)
+        }
+        None => None,
+    };
+    // buffers for the loaded slices, enough for two slices in flight on each thread:
+    let buffers = BufferPool::new(
+        nx * ny,
+        install(pool.as_ref(), rayon::current_num_threads) * 2,
+    );
+    let timings = timings.as_deref();
+    let load = |z: usize| -> Result<Image, Error> {
+        time(timings, Stage::Read, || {
+            let slice = volume
+                .get_slice(z)
+                .map_err(|e| Error::from(e).in_slice(z))?;
+            Ok(Image::from_slice_in(&slice, nx, ny, buffers.take()))
+        })
+    };
+    let recycle = |image: Image| buffers.put(image.data);
+    // statistics of each converted slice, before any processing:
+    let slice_stats = Mutex::new(HashMap::new());
+    let record_stats = |z: usize, data: &[f32]| {
+        if let Some(export) = stats {
+            let values = SliceStats::of(data, &export.percentiles);
+            slice_stats
+                .lock()
+                .expect("slice stats lock poisoned")
+                .insert(z, values);
+        }
+    };
+    // slice `z` after the processing transform, the dose weighting and the drift correction:
+    let transform = |z: usize| -> Result<Image, Error> {
+        let mut image = load(z)?;
+        record_stats(z, &image.data);
+        if let Some(sigma) = *fix_temporal_outliers {
+            let end = (z + TEMPORAL_NEIGHBORS + 1).min(nz);
+            let neighbors = (z.saturating_sub(TEMPORAL_NEIGHBORS)..end)
+                .filter(|&n| n != z)
+                .map(load)
+                .collect::<Result<Vec<_>, _>>()?;
+            let (fixed, num_fixed) = process::fix_temporal_outliers(&image, &neighbors, sigma);
+            recycle(std::mem::replace(&mut image, fixed));
+            neighbors.into_iter().for_each(recycle);
+            if num_fixed > 0 {
+                debug!("replaced {num_fixed} temporal outliers in slice {z}");
             }
-            debug!("created {out_path:?}");
-            Ok(())
+        }
+        if !processors.is_empty() {
+            let info = SliceInfo {
+                input: &mrc_path,
+                index: z,
+                num_slices: nz,
+                pixel_size,
+            };
+            image = processors.apply(image, &info).map_err(|e| e.in_slice(z))?;
+        }
+        let mut image = processing.transform_image(image);
+        if let Some(dose_filter) = &dose_filter {
+            image = dose_filter.apply(&image, z);
+        }
+        Ok(match shifts.as_ref().and_then(|shifts| shifts.get(&z)) {
+            Some(&shift) => shift_image(&image, shift),
+            None => image,
         })
-        .collect();
-    res?;
-
-    progress.finish();
-    if let Some(prog_q) = &progress_q {
-        prog_q
-            .send(ProgressMessage::Done {
-                total: len as usize,
+    };
+    let spectra = export_fft.then(|| processing.for_spectra());
+    // the adjusted output image and its sample type, or the power spectrum instead:
+    let output = |image: Image, sample_type: SampleType| match &spectra {
+        Some(spectra) => {
+            let spectrum = log_power_spectrum(&image, SPECTRUM_SIZE);
+            (spectra.adjust(spectrum), spectra.output_sample_type())
+        }
+        None => (processing.adjust(image), sample_type),
+    };
+    let montage = montage.filter(|_| projection.is_none() && !export_fft);
+    let mut metadata = processing.tiff_metadata();
+    // the pixel size doesn't apply to the downsampled tiles of montages, or to spectra:
+    if montage.is_some() || *export_fft {
+        metadata.pixel_size = None;
+    }
+    // the pages of a single file are described with the acquisition
+    // parameters of their sections, like the tilt angle:
+    let ungrouped = grouping.is_none_or(|g| g.size <= 1);
+    let stacked = *single_file && projection.is_none() && montage.is_none() && ungrouped;
+    if stacked && *axis == Axis::Z {
+        let acquisition = tilt::Acquisition::read(&mrc_path, volume.header(), nz);
+        if !acquisition.is_empty() {
+            metadata.page_descriptions = idxs.iter().map(|&z| acquisition.describe(z)).collect();
+        }
+    }
+    let write = |path: &Path, image: &Image, sample_type| {
+        time_write(timings, || {
+            write_image(
+                path,
+                image,
+                sample_type,
+                endianess,
+                *write_backend,
+                &metadata,
+            )
+        })
+    };
+    let grouping = grouping.filter(|g| g.size > 1 && projection.is_none() && montage.is_none());
+    // half of the running average window, which is rounded up to an odd size:
+    let half_window = running_average.map(|n| n / 2).filter(|&k| k > 0);
+    // slice `z`, or the average of the running window around it. The
+    // transformed slices of the window are kept in `window` while it slides
+    // along the stack, so each worker transforms every slice only once:
+    let frame = |window: &mut Window, z: usize| -> Result<Image, Error> {
+        let Some(k) = half_window else {
+            return transform(z);
+        };
+        let range = z.saturating_sub(k)..(z + k + 1).min(nz);
+        // after a jump back, none of the kept slices can be reused:
+        if window.back().is_some_and(|&(n, _)| n >= range.end) {
+            window.drain(..).for_each(|(_, image)| recycle(image));
+        }
+        while window.front().is_some_and(|&(n, _)| n < range.start) {
+            window
+                .pop_front()
+                .into_iter()
+                .for_each(|(_, image)| recycle(image));
+        }
+        let next = window
+            .back()
+            .map_or(range.start, |&(n, _)| range.start.max(n + 1));
+        for n in next..range.end {
+            window.push_back((n, transform(n)?));
+        }
+        let sum = window
+            .iter()
+            .map(|(_, image)| Partial::from(image))
+            .reduce(|a, b| Projection::Mean.combine(a, b))
+            .expect("the window contains at least slice `z`");
+        Ok(Projection::Mean.finish(sum, window.len()))
+    };
+    // whether the slices are written as they are, without any processing:
+    let identity = processing.is_identity()
+        && processors.is_empty()
+        && fix_temporal_outliers.is_none()
+        && half_window.is_none()
+        && dose_filter.is_none()
+        && shifts.is_none()
+        && spectra.is_none();
+    let frame_sample_type = match half_window {
+        Some(_) => Projection::Mean.output_sample_type(&processing),
+        None => processing.output_sample_type(),
+    };
+    // frame `z` as it is encoded, and its sample type:
+    let processed = |window: &mut Window, z: usize| -> Result<(Image, SampleType), Error> {
+        time(timings, Stage::Process, || {
+            Ok(output(frame(window, z)?, frame_sample_type))
+        })
+    };
+    let len = match (montage, grouping) {
+        (Some(montage), _) => idxs.len().div_ceil(montage.tiles()),
+        (None, Some(grouping)) => {
+            if !idxs.len().is_multiple_of(grouping.size) {
+                warn!(
+                    "skipping the last {} frames, which don't fill a whole group of {}",
+                    idxs.len() % grouping.size,
+                    grouping.size
+                );
+            }
+            idxs.len() / grouping.size
+        }
+        (None, None) => idxs.len(),
+    };
+
+    // each written file and the slices it is computed from; a running
+    // average is listed under the slice in its center:
+    let outputs: Vec<(String, Vec<usize>)> = if let Some(projection) = projection {
+        vec![(naming.projection_filename(*projection), idxs.clone())]
+    } else if let Some(montage) = montage {
+        idxs.chunks(montage.tiles())
+            .enumerate()
+            .map(|(montage_idx, page)| (naming.montage_filename(montage_idx + 1), page.to_vec()))
+            .collect()
+    } else if let Some(grouping) = grouping {
+        idxs.chunks_exact(grouping.size)
+            .enumerate()
+            .map(|(group_idx, group)| (naming.slice_filename(group_idx + 1), group.to_vec()))
+            .collect()
+    } else if *single_file {
+        vec![(naming.stack_filename(), idxs.clone())]
+    } else {
+        idxs.iter()
+            .enumerate()
+            .map(|(pos, &z)| (naming.slice_filename(pos + 1), vec![z]))
+            .collect()
+    };
+    let index = geometry.map(|geometry| {
+        let files = outputs
+            .iter()
+            .map(|(file, sections)| IndexEntry {
+                file: file.clone(),
+                sections: sections.iter().map(|&z| geometry.section(z)).collect(),
             })
-            .unwrap();
+            .collect();
+        DatasetIndex {
+            input: mrc_path.clone(),
+            axis: *axis,
+            pixel_size: if montage.is_some() || *export_fft {
+                None
+            } else {
+                processing.output_pixel_size()
+            },
+            files,
+        }
+    });
+
+    let throttle = |bytes: usize| {
+        if let Some(io_limit) = io_limit {
+            io_limit.acquire(bytes);
+        }
+    };
+    let slice_bytes = nx * ny * size_of::<i16>();
+    let image_bytes =
+        |image: &Image, sample_type: SampleType| image.data.len() * sample_type.bytes_per_sample();
+
+    let check_cancelled = || -> Result<(), ConvertError> {
+        match cancel {
+            Some(cancel) if cancel.is_cancelled() => Err(ConvertError::Cancelled),
+            _ => Ok(()),
+        }
+    };
+
+    // removed again if the conversion is cancelled:
+    let written = Mutex::new(Vec::new());
+    let created = |out_path: &Path| {
+        debug!("created {out_path:?}");
+        written
+            .lock()
+            .expect("written files lock poisoned")
+            .push(out_path.to_owned());
+    };
+
+    let done = AtomicUsize::new(0);
+    let report_done = |out_path: &Path| -> Result<(), Error> {
+        let num_done = done.fetch_add(1, Ordering::SeqCst) + 1;
+        progress.advance(&mrc_path, num_done, len, out_path)
+    };
+
+    // slice `z` as a page of the single file:
+    let stack_page = |window: &mut Window, z: usize| -> Result<StackPage, Error> {
+        check_cancelled()?;
+        throttle(slice_bytes);
+        if identity {
+            let slice = time(timings, Stage::Read, || volume.get_slice(z))
+                .map_err(|e| Error::from(e).in_slice(z))?;
+            if stats.is_some() {
+                record_stats(z, &Image::from_slice(&slice, nx, ny).data);
+            }
+            let encode = || match &slice {
+                Slice::Signed(values) => StackPage::encode(values, nx, ny, endianess),
+                Slice::Unsigned(values) => StackPage::encode(values, nx, ny, endianess),
+            };
+            return Ok(time(timings, Stage::Encode, encode));
+        }
+        let (image, sample_type) = processed(window, z)?;
+        let page = time(timings, Stage::Encode, || {
+            StackPage::from_image(&image, sample_type, endianess)
+        });
+        recycle(image);
+        Ok(page)
+    };
+
+    progress.start(&mrc_path, len);
+    install(pool.as_ref(), || -> Result<(), Error> {
+        if let Some(projection) = projection {
+            let out_path = dest_path.join(naming.projection_filename(*projection));
+            let image = idxs
+                .into_par_iter()
+                .map(|z| -> Result<Partial, Error> {
+                    check_cancelled()?;
+                    throttle(slice_bytes);
+                    let image = time(timings, Stage::Process, || transform(z))?;
+                    report_done(&out_path)?;
+                    Ok(Partial::from(image))
+                })
+                .try_reduce_with(|a, b| Ok(projection.combine(a, b)))
+                .ok_or(ConvertError::EmptyProjection)??;
+            let image = time(timings, Stage::Process, || {
+                processing.adjust(projection.finish(image, len))
+            });
+            let sample_type = projection.output_sample_type(&processing);
+            throttle(image_bytes(&image, sample_type));
+            write(&out_path, &image, sample_type)?;
+            created(&out_path);
+        } else if let Some(montage) = montage {
+            let res: Result<Vec<()>, _> = idxs
+                .par_chunks(montage.tiles())
+                .enumerate()
+                .map(|(montage_idx, page)| -> Result<(), Error> {
+                    let out_path = dest_path.join(naming.montage_filename(montage_idx + 1));
+                    let tiles = page
+                        .par_iter()
+                        .map(|&z| -> Result<Image, Error> {
+                            check_cancelled()?;
+                            throttle(slice_bytes);
+                            let image = time(timings, Stage::Process, || transform(z))?;
+                            let tile = montage.downsample(&image);
+                            recycle(image);
+                            Ok(tile)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let compose = || processing.adjust(montage.compose(&tiles));
+                    let image = time(timings, Stage::Process, compose);
+                    let sample_type = processing.output_sample_type();
+                    throttle(image_bytes(&image, sample_type));
+                    write(&out_path, &image, sample_type)?;
+                    created(&out_path);
+                    report_done(&out_path)
+                })
+                .collect();
+            res?;
+        } else if let Some(grouping) = grouping {
+            let res: Result<Vec<()>, _> = idxs
+                .par_chunks_exact(grouping.size)
+                .enumerate()
+                .map(|(group_idx, group)| -> Result<(), Error> {
+                    let out_path = dest_path.join(naming.slice_filename(group_idx + 1));
+                    let mut combined: Option<Partial> = None;
+                    for &z in group {
+                        check_cancelled()?;
+                        throttle(slice_bytes);
+                        let image = time(timings, Stage::Process, || transform(z))?.into();
+                        combined = Some(match combined {
+                            Some(combined) => grouping.mode.combine(combined, image),
+                            None => image,
+                        });
+                    }
+                    let combined = combined.expect("groups are never empty");
+                    let (image, sample_type) = time(timings, Stage::Process, || {
+                        output(
+                            grouping.mode.finish(combined, group.len()),
+                            grouping.mode.output_sample_type(&processing),
+                        )
+                    });
+                    throttle(image_bytes(&image, sample_type));
+                    write(&out_path, &image, sample_type)?;
+                    created(&out_path);
+                    report_done(&out_path)
+                })
+                .collect();
+            res?;
+        } else if *single_file && *write_backend == WriteBackend::Preallocated {
+            let out_path = dest_path.join(naming.stack_filename());
+            // the first page sets the size of the file:
+            let Some((&first, rest)) = idxs.split_first() else {
+                return Ok(());
+            };
+            let page = stack_page(&mut Window::new(), first)?;
+            let stack = time(timings, Stage::Write, || {
+                PreallocatedStack::create(&out_path, &page, len, endianess, &metadata)
+            })?;
+            created(&out_path);
+            report_done(&out_path)?;
+            let res: Result<Vec<()>, _> = rest
+                .par_iter()
+                .enumerate()
+                .map_init(Window::new, |window, (pos, &z)| -> Result<(), Error> {
+                    let page = stack_page(window, z)?;
+                    throttle(page.num_bytes());
+                    time(timings, Stage::Write, || stack.write_page(pos + 1, &page))?;
+                    report_done(&out_path)
+                })
+                .collect();
+            res?;
+            stack.finish()?;
+        } else if *single_file {
+            let out_path = dest_path.join(naming.stack_filename());
+            // encoded pages waiting to be written, about one for each thread:
+            let (pages, received) = mpsc::sync_channel(rayon::current_num_threads());
+            thread::scope(|scope| -> Result<(), Error> {
+                let assembler = scope.spawn(|| {
+                    assemble_stack(
+                        &out_path, received, len, endianess, &metadata, timings, &created,
+                    )
+                });
+                // the slices are taken in order, so the pages mostly arrive in order:
+                let res: Result<Vec<()>, _> = idxs
+                    .into_iter()
+                    .enumerate()
+                    .par_bridge()
+                    .map_init(Window::new, |window, (pos, z)| -> Result<(), Error> {
+                        let page = stack_page(window, z)?;
+                        throttle(page.num_bytes());
+                        pages
+                            .send((pos, page))
+                            .map_err(|_| ConvertError::StackWriterStopped)?;
+                        report_done(&out_path)
+                    })
+                    .collect();
+                drop(pages);
+                // the workers only fail to send once the assembler has failed:
+                assembler.join().expect("stack assembler panicked")?;
+                res?;
+                Ok(())
+            })?;
+        } else {
+            let res: Result<Vec<()>, _> = idxs
+                .into_par_iter()
+                .enumerate()
+                .map_init(Window::new, |window, (pos, z)| -> Result<(), Error> {
+                    check_cancelled()?;
+                    throttle(slice_bytes);
+                    let idx = pos + 1;
+                    let out_path = dest_path.join(naming.slice_filename(idx));
+                    if identity {
+                        let slice = time(timings, Stage::Read, || volume.get_slice(z))
+                            .map_err(|e| Error::from(e).in_slice(z))?;
+                        if stats.is_some() {
+                            record_stats(z, &Image::from_slice(&slice, nx, ny).data);
+                        }
+                        throttle(slice_bytes);
+                        let backend = *write_backend;
+                        time_write(timings, || match &slice {
+                            Slice::Signed(values) => {
+                                write_tiff(&out_path, values, nx, ny, endianess, backend, &metadata)
+                            }
+                            Slice::Unsigned(values) => {
+                                write_tiff(&out_path, values, nx, ny, endianess, backend, &metadata)
+                            }
+                        })
+                        .map_err(|e| e.in_slice(z))?;
+                    } else {
+                        let (image, sample_type) = processed(window, z)?;
+                        throttle(image_bytes(&image, sample_type));
+                        write(&out_path, &image, sample_type).map_err(|e| e.in_slice(z))?;
+                        recycle(image);
+                    }
+                    created(&out_path);
+                    report_done(&out_path)
+                })
+                .collect();
+            res?;
+        }
+        Ok(())
+    })
+    .inspect_err(|e| {
+        progress.abort(&mrc_path);
+        if e.is_cancelled() {
+            remove_partial_output(&written.into_inner().expect("written files lock poisoned"));
+        }
+    })?;
+    if let Some(index) = index {
+        let index_path = dest_path.join(naming.index_filename());
+        index
+            .write(&index_path)
+            .inspect_err(|_| progress.abort(&mrc_path))?;
+        debug!("created {index_path:?}");
     }
-    multi_progress.remove(&progress);
+    if *write_manifest {
+        let manifest_path = dest_path.join(naming.manifest_filename());
+        // the layout is read back from the headers, as the encoders decide where the data goes:
+        outputs
+            .iter()
+            .map(|(file, _)| Chunk::read(&dest_path, file))
+            .collect::<Result<_, _>>()
+            .and_then(|files| ChunkManifest { files }.write(&manifest_path))
+            .inspect_err(|_| progress.abort(&mrc_path))?;
+        debug!("created {manifest_path:?}");
+    }
+    if let Some(export) = stats {
+        let stats_path = dest_path.join(&export.path);
+        let slice_stats = slice_stats.into_inner().expect("slice stats lock poisoned");
+        let rows = outputs.iter().flat_map(|(file, sections)| {
+            sections
+                .iter()
+                .filter_map(|z| Some((file.as_str(), z + 1, slice_stats.get(z)?)))
+        });
+        stats::write_csv(&stats_path, &export.percentiles, rows)
+            .inspect_err(|_| progress.abort(&mrc_path))?;
+        debug!("created {stats_path:?}");
+    }
+    progress.finish(&mrc_path, len);
 
     info!("conversion done in {:?}", t0.elapsed());
 
     Ok(())
 }
+
+/// Write the `num_pages` pages of the stack at `path` in order, as they
+/// arrive from the workers in any order. Stops without writing the IFDs if
+/// the workers stop early, whose error is reported instead.
+fn assemble_stack(
+    path: &Path,
+    pages: Receiver<(usize, StackPage)>,
+    num_pages: usize,
+    endianess: &ArgEndianess,
+    metadata: &TiffMetadata,
+    timings: Option<&StageTimings>,
+    created: &(dyn Fn(&Path) + Sync),
+) -> Result<(), Error> {
+    let mut writer: Option<StackWriter> = None;
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    for (pos, page) in pages {
+        pending.insert(pos, page);
+        while let Some(page) = pending.remove(&next) {
+            match &mut writer {
+                Some(writer) => time(timings, Stage::Write, || writer.write_page(&page))?,
+                None => {
+                    let first = time(timings, Stage::Write, || {
+                        StackWriter::create(path, &page, num_pages, endianess, metadata)
+                    })?;
+                    writer = Some(first);
+                    created(path);
+                }
+            }
+            next += 1;
+        }
+    }
+    match writer {
+        Some(writer) if next == num_pages => writer.finish(),
+        _ => Ok(()),
+    }
+}
+
+/// Remove the files of a cancelled conversion, so no incomplete export is
+/// mistaken for a finished one.
+fn remove_partial_output(written: &[PathBuf]) {
+    info!(
+        "cancelled, removing the {} files written so far",
+        written.len()
+    );
+    for path in written {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!("failed to remove {path:?}: {e}");
+        }
+    }
+}
+
+/// Thread pool with few enough threads that the slices processed at the same
+/// time fit into `max_memory` bytes, each thread working on one slice of
+/// `slice_len` pixels at a time.
+fn bounded_pool(max_memory: u64, slice_len: usize, identity: bool) -> Result<ThreadPool, Error> {
+    // raw slices only need the big-endian copy, processed slices are held as
+    // floats, with up to two intermediate images and the encoded output:
+    let bytes_per_pixel = if identity { 4 } else { 16 };
+    let bytes_per_slice = (slice_len * bytes_per_pixel) as u64;
+    let max_threads = rayon::current_num_threads();
+    let num_threads = ((max_memory / bytes_per_slice.max(1)) as usize).clamp(1, max_threads);
+    if max_memory < bytes_per_slice {
+        warn!(
+            "a single slice needs about {bytes_per_slice} bytes, more than the memory limit of {max_memory} bytes"
+        );
+    }
+    info!("using {num_threads} of {max_threads} threads to stay below the memory limit");
+    Ok(ThreadPoolBuilder::new().num_threads(num_threads).build()?)
+}
+
+/// Run `f` in `pool` if given, or in the global thread pool otherwise.
+fn install<R: Send>(pool: Option<&ThreadPool>, f: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{fft, process::Image};
+
+/// Acceleration voltage assumed if it's neither given nor in the extended header
+pub const DEFAULT_VOLTAGE: f32 = 300.0;
+
+/// Exposure weighting of each frame of a movie, which damps the high
+/// frequencies of the later frames according to how much radiation damage
+/// they have accumulated.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DoseWeighting {
+    /// Exposure of each frame in e/Å², taken from the .mdoc file or the
+    /// extended header if not given
+    pub dose_per_frame: Option<f32>,
+    /// Acceleration voltage in kV, taken from the extended header if not given
+    pub voltage: Option<f32>,
+}
+
+/// The dose weighting, resolved for one stack.
+#[derive(Debug, Clone)]
+pub struct DoseFilter {
+    /// Accumulated exposure in the middle of each frame, in e/Å²
+    exposures: Vec<f32>,
+    /// Size of the filtered pixels in Å
+    pixel_size: f32,
+    /// Scale of the critical exposure, which is lower at lower voltages
+    voltage_factor: f32,
+}
+
+impl DoseFilter {
+    /// For frames with the exposures `doses`, in the order they were recorded.
+    pub fn new(doses: &[f32], pixel_size: f32, voltage: f32) -> Self {
+        let exposures = doses
+            .iter()
+            .scan(0.0, |total, &dose| {
+                let middle = *total + dose / 2.0;
+                *total += dose;
+                Some(middle)
+            })
+            .collect();
+        Self {
+            exposures,
+            pixel_size,
+            // as for 200 kV in Grant & Grigorieff (2015):
+            voltage_factor: if voltage < 250.0 { 0.8 } else { 1.0 },
+        }
+    }
+
+    /// Filter frame `z`, keeping the frequencies that survive its accumulated exposure.
+    pub fn apply(&self, image: &Image, z: usize) -> Image {
+        let Some(&exposure) = self.exposures.get(z).filter(|&&e| e > 0.0) else {
+            return image.clone();
+        };
+        fft::filter(image, |fx, fy| {
+            let k = fx.hypot(fy) / self.pixel_size;
+            if k == 0.0 {
+                return 1.0;
+            }
+            (-exposure / (2.0 * self.critical_exposure(k))).exp()
+        })
+    }
+
+    /// Exposure in e/Å² after which the frequency `k` (in 1/Å) has decayed to
+    /// 1/e, as fitted by Grant & Grigorieff (2015) for 300 kV
+    fn critical_exposure(&self, k: f32) -> f32 {
+        (0.245 * k.powf(-1.665) + 2.81) * self.voltage_factor
+    }
+}
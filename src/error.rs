@@ -0,0 +1,95 @@
+use std::io;
+#[cfg(feature = "native")]
+use std::sync::mpsc::SendError;
+
+#[cfg(feature = "native")]
+use crate::{batch::BatchError, convert::ConvertError, progress::ProgressMessage};
+use crate::{
+    manifest::ManifestError, read::ReadError, reference::ReferenceError, write::WriteError,
+};
+
+/// Everything that can go wrong in the library, with the errors of each
+/// module as their own variants.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The header or the data of an MRC file can't be read
+    #[error("can't read the MRC file")]
+    Header(#[from] mrc::Error),
+    /// The header of an MRC file doesn't match its size
+    #[error(transparent)]
+    Read(#[from] ReadError),
+    /// Invalid options for the input, or an unsupported data mode
+    #[cfg(feature = "native")]
+    #[error(transparent)]
+    Convert(#[from] ConvertError),
+    #[error(transparent)]
+    Reference(#[from] ReferenceError),
+    #[error(transparent)]
+    Write(#[from] WriteError),
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+    #[cfg(feature = "native")]
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+    #[error("I/O error")]
+    Io(#[from] io::Error),
+    #[error("failed to encode the tiff file")]
+    Tiff(#[from] tiff::TiffError),
+    #[cfg(feature = "native")]
+    #[error("failed to start the worker threads")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    /// A custom `SliceProcessor` failed
+    #[error("slice processor {name} failed")]
+    Processor {
+        name: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// Whoever followed the progress stopped listening
+    #[error("the receiver of the progress messages is gone")]
+    ProgressDisconnected,
+    /// Reading, processing or writing the slice `index` (0-indexed) failed
+    #[error("failed at frame {}", .index + 1)]
+    Slice {
+        index: usize,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Attribute the error to the slice `index`, unless it already is, or it
+    /// isn't specific to a slice, like a cancellation.
+    pub fn in_slice(self, index: usize) -> Self {
+        match self {
+            Error::Slice { .. } => self,
+            #[cfg(feature = "native")]
+            Error::Convert(ConvertError::Cancelled) => self,
+            source => Error::Slice {
+                index,
+                source: Box::new(source),
+            },
+        }
+    }
+
+    /// Whether the conversion was stopped by its `CancelToken`.
+    #[cfg(feature = "native")]
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.kind(), Error::Convert(ConvertError::Cancelled))
+    }
+
+    /// The error itself, or the one of the slice it is attributed to.
+    pub fn kind(&self) -> &Error {
+        match self {
+            Error::Slice { source, .. } => source.kind(),
+            _ => self,
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+impl From<SendError<ProgressMessage>> for Error {
+    fn from(_: SendError<ProgressMessage>) -> Self {
+        Error::ProgressDisconnected
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_int, c_void},
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    Error,
+    common::{ArgEndianess, error_chain},
+    convert::{self, CancelToken, ConvertError, ConvertOptions},
+    process::{Normalize, Processing},
+    progress::ProgressSink,
+    write::SampleType,
+};
+
+pub const MRC2TIFF_OK: c_int = 0;
+pub const MRC2TIFF_ERROR: c_int = 1;
+pub const MRC2TIFF_BAD_INPUT: c_int = 2;
+pub const MRC2TIFF_UNSUPPORTED: c_int = 3;
+pub const MRC2TIFF_IO: c_int = 4;
+pub const MRC2TIFF_CANCELLED: c_int = 5;
+
+/// Called after each written file with the number of finished and total
+/// steps; returning non-zero cancels the conversion.
+pub type Mrc2TiffProgress =
+    Option<unsafe extern "C" fn(num_done: usize, total: usize, user_data: *mut c_void) -> c_int>;
+
+/// The options of `mrc2tiff_convert`, initialized by `mrc2tiff_default_options`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Mrc2TiffOptions {
+    /// 1-indexed
+    pub start_at_frame: usize,
+    /// 1-indexed and inclusive, 0 for the last frame of the stack
+    pub stop_at_frame: usize,
+    /// Bin factor N for NxN binning, 0 or 1 for none
+    pub bin: usize,
+    /// Like the command line option, e.g. "min-max"; NULL for none
+    pub normalize: *const c_char,
+    /// "int16", "int32", "uint8", "uint16" or "float32"; NULL for the input type
+    pub output_type: *const c_char,
+    /// Write native instead of big endian files
+    pub native_endian: bool,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Options that convert all frames without any processing.
+#[unsafe(no_mangle)]
+pub extern "C" fn mrc2tiff_default_options() -> Mrc2TiffOptions {
+    Mrc2TiffOptions {
+        start_at_frame: 1,
+        stop_at_frame: 0,
+        bin: 0,
+        normalize: ptr::null(),
+        output_type: ptr::null(),
+        native_endian: false,
+    }
+}
+
+/// The message of the last error on this thread, or NULL. It stays valid
+/// until the next call of `mrc2tiff_convert` on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn mrc2tiff_last_error() -> *const c_char {
+    LAST_ERROR.with_borrow(|msg| msg.as_ref().map_or(ptr::null(), |msg| msg.as_ptr()))
+}
+
+/// Convert the stack at `mrc_path` into TIFF files in the existing directory
+/// `dest_path`, returning one of the `MRC2TIFF_*` codes. `options` may be
+/// NULL for the defaults. `progress` is called with `user_data` from the
+/// worker threads, so it needs to be thread safe.
+///
+/// # Safety
+///
+/// The paths and the strings of `options` need to be NUL-terminated, and
+/// `options` needs to be NULL or point to valid options.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mrc2tiff_convert(
+    mrc_path: *const c_char,
+    dest_path: *const c_char,
+    options: *const Mrc2TiffOptions,
+    progress: Mrc2TiffProgress,
+    user_data: *mut c_void,
+) -> c_int {
+    LAST_ERROR.set(None);
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        // SAFETY: as required from the caller
+        let (mrc_path, dest_path) = unsafe { (path_arg(mrc_path)?, path_arg(dest_path)?) };
+        let defaults = mrc2tiff_default_options();
+        // SAFETY: as required from the caller
+        let options = unsafe { convert_options(options.as_ref().unwrap_or(&defaults))? };
+        let cancel = CancelToken::new();
+        let options = ConvertOptions {
+            cancel: Some(cancel.clone()),
+            ..options
+        };
+        let sink = CallbackProgress {
+            callback: progress,
+            user_data,
+            cancel,
+        };
+        convert::convert(mrc_path, dest_path, &options, &sink).map_err(|e| {
+            let code = error_code(&e);
+            (code, error_chain(&e))
+        })
+    }));
+    let (code, msg) = match res {
+        Ok(Ok(())) => return MRC2TIFF_OK,
+        Ok(Err(err)) => err,
+        Err(_) => (MRC2TIFF_ERROR, "the conversion panicked".to_owned()),
+    };
+    let msg = CString::new(msg.replace('\0', "")).expect("NUL bytes were removed");
+    LAST_ERROR.set(Some(msg));
+    code
+}
+
+type FfiError = (c_int, String);
+
+fn error_code(err: &Error) -> c_int {
+    match err.kind() {
+        Error::Convert(ConvertError::Cancelled) => MRC2TIFF_CANCELLED,
+        Error::Convert(ConvertError::UnsupportedMode { .. }) => MRC2TIFF_UNSUPPORTED,
+        Error::Header(_) | Error::Read(_) | Error::Convert(_) | Error::Reference(_) => {
+            MRC2TIFF_BAD_INPUT
+        }
+        Error::Io(_) | Error::Write(_) => MRC2TIFF_IO,
+        _ => MRC2TIFF_ERROR,
+    }
+}
+
+/// # Safety
+///
+/// `s` needs to be NULL or NUL-terminated.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<Option<&'a str>, FfiError> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    // SAFETY: as required from the caller
+    let s = unsafe { CStr::from_ptr(s) };
+    s.to_str()
+        .map(Some)
+        .map_err(|_| (MRC2TIFF_BAD_INPUT, format!("{name} is not valid UTF-8")))
+}
+
+/// # Safety
+///
+/// `s` needs to be NULL or NUL-terminated.
+unsafe fn path_arg(s: *const c_char) -> Result<PathBuf, FfiError> {
+    // SAFETY: as required from the caller
+    match unsafe { str_arg(s, "path")? } {
+        Some(s) => Ok(PathBuf::from(s)),
+        None => Err((MRC2TIFF_BAD_INPUT, "path is NULL".to_owned())),
+    }
+}
+
+fn parse_choice<T: ValueEnum>(value: &str) -> Result<T, FfiError> {
+    T::from_str(value, true).map_err(|msg| (MRC2TIFF_BAD_INPUT, msg))
+}
+
+/// # Safety
+///
+/// The strings of `options` need to be NULL or NUL-terminated.
+unsafe fn convert_options(options: &Mrc2TiffOptions) -> Result<ConvertOptions, FfiError> {
+    // SAFETY: as required from the caller
+    let (normalize, output_type) = unsafe {
+        (
+            str_arg(options.normalize, "normalize")?,
+            str_arg(options.output_type, "output_type")?,
+        )
+    };
+    let normalize = normalize
+        .map(|s| {
+            s.parse::<Normalize>()
+                .map_err(|msg| (MRC2TIFF_BAD_INPUT, msg))
+        })
+        .transpose()?;
+    Ok(ConvertOptions {
+        endianess: if options.native_endian {
+            ArgEndianess::Native
+        } else {
+            ArgEndianess::Big
+        },
+        start_at_frame: options.start_at_frame,
+        stop_at_frame: (options.stop_at_frame > 0).then_some(options.stop_at_frame),
+        processing: Processing {
+            bin: (options.bin > 1).then_some(options.bin),
+            normalize: normalize.unwrap_or_default(),
+            output_type: output_type.map(parse_choice::<SampleType>).transpose()?,
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+}
+
+/// Forwards the progress to the C callback, which can cancel the conversion.
+struct CallbackProgress {
+    callback: Mrc2TiffProgress,
+    user_data: *mut c_void,
+    cancel: CancelToken,
+}
+
+// SAFETY: the callback is documented to be called from the worker threads
+unsafe impl Sync for CallbackProgress {}
+
+impl ProgressSink for CallbackProgress {
+    fn advance(
+        &self,
+        _input: &Path,
+        num_done: usize,
+        total: usize,
+        _output: &Path,
+    ) -> Result<(), Error> {
+        if let Some(callback) = self.callback {
+            // SAFETY: the callback and `user_data` are valid, as required
+            // from the caller of `mrc2tiff_convert`
+            if unsafe { callback(num_done, total, self.user_data) } != 0 {
+                self.cancel.cancel();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::PathBuf};
+
+    use super::*;
+    use crate::read::ReadError;
+
+    #[test]
+    fn error_codes() {
+        let unknown_mode = ReadError::UnknownMode {
+            path: PathBuf::from("a.mrc"),
+            mode: 12,
+        };
+        assert_eq!(error_code(&unknown_mode.into()), MRC2TIFF_BAD_INPUT);
+        assert_eq!(
+            error_code(&ConvertError::Cancelled.into()),
+            MRC2TIFF_CANCELLED
+        );
+        assert_eq!(error_code(&io::Error::other("full").into()), MRC2TIFF_IO);
+    }
+}
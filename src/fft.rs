@@ -0,0 +1,467 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, Mul, Sub},
+};
+
+use crate::process::Image;
+
+/// Size of the tiles the power spectra are averaged over
+pub const SPECTRUM_SIZE: usize = 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    /// `e^(i * angle)`, computed in double precision
+    fn from_angle(angle: f64) -> Self {
+        Self::new(angle.cos() as f32, angle.sin() as f32)
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn scale(self, factor: f32) -> Self {
+        Self::new(self.re * factor, self.im * factor)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+/// Iterative radix-2 FFT, for power of two lengths.
+struct Radix2 {
+    /// `e^(-2πik/n)` for `k < n/2`
+    twiddles: Vec<Complex>,
+}
+
+impl Radix2 {
+    fn new(len: usize) -> Self {
+        debug_assert!(len.is_power_of_two());
+        let twiddles = (0..len / 2)
+            .map(|k| Complex::from_angle(-2.0 * PI * k as f64 / len as f64))
+            .collect();
+        Self { twiddles }
+    }
+
+    fn forward(&self, data: &mut [Complex]) {
+        let n = data.len();
+        if n <= 1 {
+            return;
+        }
+        let bits = n.trailing_zeros();
+        for i in 0..n {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+        let mut size = 2;
+        while size <= n {
+            let half = size / 2;
+            let step = n / size;
+            for block in data.chunks_exact_mut(size) {
+                let (lower, upper) = block.split_at_mut(half);
+                for (k, (a, b)) in lower.iter_mut().zip(upper).enumerate() {
+                    let t = *b * self.twiddles[k * step];
+                    *b = *a - t;
+                    *a = *a + t;
+                }
+            }
+            size *= 2;
+        }
+    }
+}
+
+/// Bluestein's algorithm, expressing a DFT of any length as a convolution
+/// that is computed with a power of two FFT.
+struct Bluestein {
+    inner: Radix2,
+    /// `e^(-πik²/n)`
+    chirp: Vec<Complex>,
+    /// FFT of the conjugated chirp, wrapped around for the circular convolution
+    filter: Vec<Complex>,
+}
+
+impl Bluestein {
+    fn new(len: usize) -> Self {
+        let padded = (2 * len - 1).next_power_of_two();
+        let inner = Radix2::new(padded);
+        // k² mod 2n keeps the angle exact for large k:
+        let chirp: Vec<Complex> = (0..len)
+            .map(|k| {
+                let k2 = (k * k) % (2 * len);
+                Complex::from_angle(-PI * k2 as f64 / len as f64)
+            })
+            .collect();
+        let mut filter = vec![Complex::default(); padded];
+        for (k, c) in chirp.iter().enumerate() {
+            filter[k] = c.conj();
+            if k > 0 {
+                filter[padded - k] = c.conj();
+            }
+        }
+        inner.forward(&mut filter);
+        Self {
+            inner,
+            chirp,
+            filter,
+        }
+    }
+
+    fn forward(&self, data: &mut [Complex], scratch: &mut Vec<Complex>) {
+        let padded = self.filter.len();
+        scratch.clear();
+        scratch.extend(data.iter().zip(&self.chirp).map(|(&x, &c)| x * c));
+        scratch.resize(padded, Complex::default());
+        self.inner.forward(scratch);
+        scratch
+            .iter_mut()
+            .zip(&self.filter)
+            .for_each(|(x, &f)| *x = (*x * f).conj());
+        // the inverse FFT, as the conjugate of the forward FFT of the conjugate:
+        self.inner.forward(scratch);
+        let scale = 1.0 / padded as f32;
+        for ((x, &y), &c) in data.iter_mut().zip(scratch.iter()).zip(&self.chirp) {
+            *x = y.conj().scale(scale) * c;
+        }
+    }
+}
+
+/// A one-dimensional FFT of a fixed length.
+enum Plan {
+    Radix2(Radix2),
+    Bluestein(Bluestein),
+}
+
+impl Plan {
+    fn new(len: usize) -> Self {
+        if len.is_power_of_two() {
+            Plan::Radix2(Radix2::new(len))
+        } else {
+            Plan::Bluestein(Bluestein::new(len))
+        }
+    }
+
+    /// Unnormalized in both directions
+    fn process(&self, data: &mut [Complex], inverse: bool, scratch: &mut Vec<Complex>) {
+        if inverse {
+            data.iter_mut().for_each(|x| *x = x.conj());
+        }
+        match self {
+            Plan::Radix2(plan) => plan.forward(data),
+            Plan::Bluestein(plan) => plan.forward(data, scratch),
+        }
+        if inverse {
+            data.iter_mut().for_each(|x| *x = x.conj());
+        }
+    }
+}
+
+/// The two-dimensional discrete Fourier transform of an image, with the zero
+/// frequency at index 0.
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    pub data: Vec<Complex>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Spectrum {
+    pub fn of(image: &Image) -> Self {
+        let data = image.data.iter().map(|&v| Complex::new(v, 0.0)).collect();
+        let mut spectrum = Self {
+            data,
+            width: image.width,
+            height: image.height,
+        };
+        spectrum.transform(false);
+        spectrum
+    }
+
+    /// The real part of the inverse transform.
+    pub fn into_image(mut self) -> Image {
+        self.transform(true);
+        let scale = 1.0 / (self.width * self.height).max(1) as f32;
+        Image {
+            data: self.data.iter().map(|c| c.re * scale).collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Signed frequency of column `x` and row `y`, in cycles per pixel.
+    pub fn frequency(&self, x: usize, y: usize) -> (f32, f32) {
+        let signed = |i: usize, n: usize| {
+            let i = if i > n / 2 {
+                i as f32 - n as f32
+            } else {
+                i as f32
+            };
+            i / n as f32
+        };
+        (signed(x, self.width), signed(y, self.height))
+    }
+
+    /// Multiply each coefficient by `weight(fx, fy)`, with the frequencies in
+    /// cycles per pixel.
+    pub fn apply_filter(&mut self, weight: impl Fn(f32, f32) -> f32) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (fx, fy) = self.frequency(x, y);
+                let idx = y * self.width + x;
+                self.data[idx] = self.data[idx].scale(weight(fx, fy));
+            }
+        }
+    }
+
+    fn transform(&mut self, inverse: bool) {
+        let (w, h) = (self.width, self.height);
+        if w == 0 || h == 0 {
+            return;
+        }
+        let mut scratch = Vec::new();
+        let rows = Plan::new(w);
+        for row in self.data.chunks_exact_mut(w) {
+            rows.process(row, inverse, &mut scratch);
+        }
+        let columns = Plan::new(h);
+        let mut column = vec![Complex::default(); h];
+        for x in 0..w {
+            column
+                .iter_mut()
+                .enumerate()
+                .for_each(|(y, c)| *c = self.data[y * w + x]);
+            columns.process(&mut column, inverse, &mut scratch);
+            column
+                .iter()
+                .enumerate()
+                .for_each(|(y, &c)| self.data[y * w + x] = c);
+        }
+    }
+}
+
+/// Filter `image` in Fourier space with `weight(fx, fy)`, see `Spectrum::apply_filter`.
+pub fn filter(image: &Image, weight: impl Fn(f32, f32) -> f32) -> Image {
+    let mut spectrum = Spectrum::of(image);
+    spectrum.apply_filter(weight);
+    spectrum.into_image()
+}
+
+/// Downsample `image` to `width` x `height` by only keeping the frequencies
+/// that fit into the smaller size, which avoids aliasing. The mean is kept.
+pub fn fourier_crop(image: &Image, width: usize, height: usize) -> Image {
+    let spectrum = Spectrum::of(image);
+    // the input index with the same signed frequency as the output index `i`:
+    let source = |i: usize, m: usize, n: usize| {
+        if i < m.div_ceil(2) { i } else { n + i - m }
+    };
+    let scale = (width * height) as f32 / (image.width * image.height).max(1) as f32;
+    let data = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let sx = source(x, width, image.width);
+            let sy = source(y, height, image.height);
+            spectrum.data[sy * image.width + sx].scale(scale)
+        })
+        .collect();
+    Spectrum {
+        data,
+        width,
+        height,
+    }
+    .into_image()
+}
+
+/// Logarithm of the power spectrum of `image`, averaged over the tiles of
+/// `size` x `size` pixels that fit into it, with the zero frequency in the
+/// center. Smaller images are transformed as a whole.
+pub fn log_power_spectrum(image: &Image, size: usize) -> Image {
+    let (tile_w, tile_h) = (size.min(image.width), size.min(image.height));
+    let mut power = vec![0.0f32; tile_w * tile_h];
+    let mut num_tiles = 0;
+    for y0 in (0..=image.height - tile_h).step_by(tile_h.max(1)) {
+        for x0 in (0..=image.width - tile_w).step_by(tile_w.max(1)) {
+            let data: Vec<f32> = (y0..y0 + tile_h)
+                .flat_map(|y| &image.data[y * image.width + x0..][..tile_w])
+                .copied()
+                .collect();
+            // without the mean, the zero frequency doesn't dominate the display:
+            let mean = data.iter().sum::<f32>() / data.len().max(1) as f32;
+            let tile = Image {
+                data: data.into_iter().map(|v| v - mean).collect(),
+                width: tile_w,
+                height: tile_h,
+            };
+            let spectrum = Spectrum::of(&tile);
+            power
+                .iter_mut()
+                .zip(&spectrum.data)
+                .for_each(|(p, c)| *p += c.norm_sqr());
+            num_tiles += 1;
+        }
+    }
+    let data = (0..tile_h)
+        .flat_map(|y| (0..tile_w).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let x = (x + tile_w - tile_w / 2) % tile_w;
+            let y = (y + tile_h - tile_h / 2) % tile_h;
+            (power[y * tile_w + x] / num_tiles.max(1) as f32).ln_1p()
+        })
+        .collect();
+    Image {
+        data,
+        width: tile_w,
+        height: tile_h,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic test signal, with values in -1..1
+    fn signal(len: usize) -> Vec<Complex> {
+        (0..len)
+            .map(|k| {
+                let re = ((k * 7 + 3) % 11) as f32 / 5.5 - 1.0;
+                let im = ((k * 5) % 13) as f32 / 13.0;
+                Complex::new(re, im)
+            })
+            .collect()
+    }
+
+    fn naive_dft(data: &[Complex]) -> Vec<Complex> {
+        let n = data.len();
+        (0..n)
+            .map(|k| {
+                let (mut re, mut im) = (0.0f64, 0.0f64);
+                for (j, x) in data.iter().enumerate() {
+                    let angle = -2.0 * PI * ((j * k) % n) as f64 / n as f64;
+                    re += x.re as f64 * angle.cos() - x.im as f64 * angle.sin();
+                    im += x.re as f64 * angle.sin() + x.im as f64 * angle.cos();
+                }
+                Complex::new(re as f32, im as f32)
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[Complex], expected: &[Complex], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (k, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (*a - *e).norm_sqr().sqrt() <= tolerance,
+                "index {k}: {a:?} != {e:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_the_naive_dft() {
+        for len in [1, 2, 4, 8, 64, 256, 3, 5, 7, 12, 97, 100, 127] {
+            let input = signal(len);
+            let mut data = input.clone();
+            Plan::new(len).process(&mut data, false, &mut Vec::new());
+            assert_close(&data, &naive_dft(&input), 1e-3 * len as f32);
+        }
+    }
+
+    #[test]
+    fn forward_and_inverse_round_trip() {
+        for len in [16, 17, 30, 31] {
+            let input = signal(len);
+            let mut data = input.clone();
+            let plan = Plan::new(len);
+            let mut scratch = Vec::new();
+            plan.process(&mut data, false, &mut scratch);
+            plan.process(&mut data, true, &mut scratch);
+            let data: Vec<Complex> = data.iter().map(|x| x.scale(1.0 / len as f32)).collect();
+            assert_close(&data, &input, 1e-4);
+        }
+    }
+
+    #[test]
+    fn spectrum_round_trip() {
+        for (width, height) in [(8, 4), (5, 7), (12, 9)] {
+            let data: Vec<f32> = signal(width * height).iter().map(|c| c.re).collect();
+            let image = Image {
+                data: data.clone(),
+                width,
+                height,
+            };
+            let back = Spectrum::of(&image).into_image();
+            assert_eq!((back.width, back.height), (width, height));
+            for (a, b) in back.data.iter().zip(&data) {
+                assert!((a - b).abs() < 1e-4, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn fourier_crop_keeps_the_mean() {
+        let image = Image {
+            data: vec![3.0; 12 * 10],
+            width: 12,
+            height: 10,
+        };
+        let cropped = fourier_crop(&image, 5, 4);
+        assert_eq!((cropped.width, cropped.height), (5, 4));
+        assert!(cropped.data.iter().all(|v| (v - 3.0).abs() < 1e-4));
+    }
+
+    #[test]
+    fn centered_power_spectrum() {
+        // two periods per 8 pixels along x, in two 8x8 tiles:
+        let wave = (0..16 * 8).map(|i| (PI * (i % 16) as f64 / 2.0).cos() as f32);
+        let image = Image {
+            data: wave.collect(),
+            width: 16,
+            height: 8,
+        };
+        let spectrum = log_power_spectrum(&image, 8);
+        assert_eq!((spectrum.width, spectrum.height), (8, 8));
+        for (idx, v) in spectrum.data.iter().enumerate() {
+            let peak = idx == 4 * 8 + 2 || idx == 4 * 8 + 6;
+            assert!(if peak { *v > 6.0 } else { *v < 1e-3 }, "index {idx}: {v}");
+        }
+        let small = Image {
+            data: vec![1.0; 5 * 3],
+            width: 5,
+            height: 3,
+        };
+        let spectrum = log_power_spectrum(&small, 8);
+        assert_eq!((spectrum.width, spectrum.height), (5, 3));
+    }
+}
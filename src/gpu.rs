@@ -0,0 +1,276 @@
+use std::sync::Arc;
+
+use eframe::{
+    egui,
+    egui_wgpu::RenderState,
+    wgpu::{self, util::DeviceExt},
+};
+
+use crate::render::DisplayScaling;
+
+/// Format of the rendered preview, the same as egui's own textures
+const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Maps the values of the preview to gray values on the GPU, so that changing
+/// the display range or scaling only runs a shader instead of rendering the
+/// whole slice again on the CPU. The values are only uploaded when another
+/// slice is shown.
+pub struct GpuPreview {
+    render_state: RenderState,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// The `Params` of `preview.wgsl`
+    params: wgpu::Buffer,
+    target: Option<Target>,
+}
+
+/// The textures for slices of one size.
+struct Target {
+    width: usize,
+    height: usize,
+    values: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    view: wgpu::TextureView,
+    texture_id: egui::TextureId,
+    /// The values in `values`, to only upload them again for another slice
+    uploaded: Option<Arc<[f32]>>,
+    /// Display range and scaling `view` was rendered with
+    rendered_with: Option<((f32, f32), DisplayScaling)>,
+}
+
+impl GpuPreview {
+    pub fn new(render_state: RenderState) -> Self {
+        let device = &render_state.device;
+        let shader = device.create_shader_module(wgpu::include_wgsl!("preview.wgsl"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("preview"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("preview"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("preview"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(TARGET_FORMAT.into())],
+            }),
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("preview params"),
+            contents: &[0; 16],
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            render_state,
+            pipeline,
+            bind_group_layout,
+            params,
+            target: None,
+        }
+    }
+
+    /// Longest side of the slices that fit into a texture
+    pub fn max_size(&self) -> usize {
+        self.render_state.device.limits().max_texture_dimension_2d as usize
+    }
+
+    /// The texture showing the `width` x `height` `values` in the display
+    /// `range` with `scaling`, which is only rendered again if one of them
+    /// has changed.
+    pub fn texture(
+        &mut self,
+        values: &Arc<[f32]>,
+        width: usize,
+        height: usize,
+        range: (f32, f32),
+        scaling: DisplayScaling,
+    ) -> egui::TextureId {
+        let target = match self.target.take() {
+            Some(target) if (target.width, target.height) == (width, height) => target,
+            old => self.create_target(width, height, old.map(|old| old.texture_id)),
+        };
+        let target = self.target.insert(target);
+        let queue = &self.render_state.queue;
+        if !target
+            .uploaded
+            .as_ref()
+            .is_some_and(|uploaded| Arc::ptr_eq(uploaded, values))
+        {
+            queue.write_texture(
+                target.values.as_image_copy(),
+                bytemuck::cast_slice(values),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some((width * size_of::<f32>()) as u32),
+                    rows_per_image: None,
+                },
+                extent(width, height),
+            );
+            target.uploaded = Some(Arc::clone(values));
+            target.rendered_with = None;
+        }
+        if target.rendered_with != Some((range, scaling)) {
+            let scaling_idx: u32 = match scaling {
+                DisplayScaling::Linear => 0,
+                DisplayScaling::Log => 1,
+                DisplayScaling::Sqrt => 2,
+            };
+            let params = [
+                range.0.to_ne_bytes(),
+                range.1.to_ne_bytes(),
+                scaling_idx.to_ne_bytes(),
+            ];
+            queue.write_buffer(&self.params, 0, params.as_flattened());
+            render(&self.render_state, &self.pipeline, target);
+            target.rendered_with = Some((range, scaling));
+        }
+        target.texture_id
+    }
+
+    /// Textures for slices of `width` x `height`, shown as the egui texture
+    /// `texture_id` if there already is one.
+    fn create_target(
+        &self,
+        width: usize,
+        height: usize,
+        texture_id: Option<egui::TextureId>,
+    ) -> Target {
+        let device = &self.render_state.device;
+        let values = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("preview values"),
+            size: extent(width, height),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let output = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("preview"),
+            size: extent(width, height),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TARGET_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let values_view = values.create_view(&Default::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("preview"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&values_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.params.as_entire_binding(),
+                },
+            ],
+        });
+        let view = output.create_view(&Default::default());
+        let mut renderer = self.render_state.renderer.write();
+        let texture_id = match texture_id {
+            Some(id) => {
+                renderer.update_egui_texture_from_wgpu_texture(
+                    device,
+                    &view,
+                    wgpu::FilterMode::Linear,
+                    id,
+                );
+                id
+            }
+            None => renderer.register_native_texture(device, &view, wgpu::FilterMode::Linear),
+        };
+        Target {
+            width,
+            height,
+            values,
+            bind_group,
+            view,
+            texture_id,
+            uploaded: None,
+            rendered_with: None,
+        }
+    }
+}
+
+fn extent(width: usize, height: usize) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: width as u32,
+        height: height as u32,
+        depth_or_array_layers: 1,
+    }
+}
+
+/// Run the shader over the whole `target`, which egui then draws like any
+/// other texture.
+fn render(render_state: &RenderState, pipeline: &wgpu::RenderPipeline, target: &Target) {
+    let mut encoder = render_state
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("preview"),
+        });
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("preview"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target.view,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &target.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+    render_state.queue.submit([encoder.finish()]);
+}
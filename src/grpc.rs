@@ -0,0 +1,198 @@
+use std::{
+    error::Error,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use log::info;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, transport::Server};
+
+use crate::jobs::{
+    self, JobError, JobQueue, JobStatus, OptionsParser, check_inside, check_options_inside,
+};
+
+mod pb {
+    tonic::include_proto!("mrc2tiff");
+}
+
+use pb::converter_server::{Converter, ConverterServer};
+
+/// How often a followed job is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+impl From<JobError> for Status {
+    fn from(err: JobError) -> Self {
+        let msg = err.to_string();
+        match err {
+            JobError::NotFound(_) => Status::not_found(msg),
+            JobError::Finished(_) => Status::failed_precondition(msg),
+            JobError::Forbidden(_) => Status::permission_denied(msg),
+            JobError::InvalidPath { .. } => Status::invalid_argument(msg),
+        }
+    }
+}
+
+impl From<JobStatus> for pb::JobStatus {
+    fn from(status: JobStatus) -> Self {
+        match status {
+            JobStatus::Queued => pb::JobStatus::Queued,
+            JobStatus::Running => pb::JobStatus::Running,
+            JobStatus::Done => pb::JobStatus::Done,
+            JobStatus::Failed => pb::JobStatus::Failed,
+            JobStatus::Cancelled => pb::JobStatus::Cancelled,
+        }
+    }
+}
+
+impl From<jobs::Job> for pb::Job {
+    fn from(job: jobs::Job) -> Self {
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        pb::Job {
+            id: job.id as u64,
+            mrc_path: path(&job.mrc_path),
+            dest_path: path(&job.dest_path),
+            status: pb::JobStatus::from(job.status).into(),
+            num_done: job.num_done as u64,
+            total: job.total as u64,
+            error: job.error,
+            files: job.files.iter().map(|file| path(file)).collect(),
+        }
+    }
+}
+
+type JobStream = ReceiverStream<Result<pb::Job, Status>>;
+
+struct ConverterService {
+    jobs: Arc<JobQueue>,
+    root: Option<PathBuf>,
+    parse_options: Box<OptionsParser>,
+}
+
+impl ConverterService {
+    fn submit(&self, request: pb::JobRequest) -> Result<jobs::Job, Status> {
+        let mrc_path = PathBuf::from(request.mrc_path);
+        let dest_path = PathBuf::from(request.dest_path);
+        let options = if request.options_json.is_empty() {
+            serde_json::Value::Object(Default::default())
+        } else {
+            serde_json::from_str(&request.options_json)
+                .map_err(|e| Status::invalid_argument(format!("invalid options: {e}")))?
+        };
+        if let Some(root) = &self.root {
+            check_inside(&mrc_path, root)?;
+            check_inside(&dest_path, root)?;
+            check_options_inside(&options, &dest_path, root)?;
+        }
+        let options =
+            (self.parse_options)(options).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(self.jobs.submit(mrc_path, dest_path, options))
+    }
+
+    /// The state of job `id` now and after each change, until it has finished.
+    fn watch(&self, id: usize) -> Result<JobStream, Status> {
+        self.jobs.get(id)?;
+        let jobs = Arc::clone(&self.jobs);
+        let (updates, stream) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            let mut last = None;
+            loop {
+                interval.tick().await;
+                let job = jobs.get(id).expect("jobs are never removed");
+                let finished = job.status.is_finished();
+                if last != Some((job.status, job.num_done)) {
+                    last = Some((job.status, job.num_done));
+                    if updates.send(Ok(job.into())).await.is_err() {
+                        // the client has stopped following the job
+                        break;
+                    }
+                }
+                if finished {
+                    break;
+                }
+            }
+        });
+        Ok(ReceiverStream::new(stream))
+    }
+}
+
+fn job_id(request: Request<pb::JobId>) -> Result<usize, Status> {
+    let id = request.into_inner().id;
+    usize::try_from(id).map_err(|_| Status::not_found(format!("job {id} not found")))
+}
+
+#[tonic::async_trait]
+impl Converter for ConverterService {
+    type ConvertStream = JobStream;
+    type WatchJobStream = JobStream;
+
+    async fn convert(
+        &self,
+        request: Request<pb::JobRequest>,
+    ) -> Result<Response<JobStream>, Status> {
+        let job = self.submit(request.into_inner())?;
+        Ok(Response::new(self.watch(job.id)?))
+    }
+
+    async fn submit_job(
+        &self,
+        request: Request<pb::JobRequest>,
+    ) -> Result<Response<pb::Job>, Status> {
+        let job = self.submit(request.into_inner())?;
+        Ok(Response::new(job.into()))
+    }
+
+    async fn get_job(&self, request: Request<pb::JobId>) -> Result<Response<pb::Job>, Status> {
+        let job = self.jobs.get(job_id(request)?)?;
+        Ok(Response::new(job.into()))
+    }
+
+    async fn list_jobs(
+        &self,
+        _request: Request<pb::ListJobsRequest>,
+    ) -> Result<Response<pb::JobList>, Status> {
+        let jobs = self.jobs.list().into_iter().map(pb::Job::from).collect();
+        Ok(Response::new(pb::JobList { jobs }))
+    }
+
+    async fn watch_job(&self, request: Request<pb::JobId>) -> Result<Response<JobStream>, Status> {
+        Ok(Response::new(self.watch(job_id(request)?)?))
+    }
+
+    async fn cancel_job(&self, request: Request<pb::JobId>) -> Result<Response<pb::Job>, Status> {
+        let job = self.jobs.cancel(job_id(request)?)?;
+        Ok(Response::new(job.into()))
+    }
+}
+
+/// Serve the `Converter` service of `proto/mrc2tiff.proto`, with the same
+/// jobs as the REST API of `serve::serve`, but with streamed progress.
+///
+/// Jobs are converted one after the other. If `root` is given, all paths
+/// need to be inside of it.
+pub fn serve(
+    listen: SocketAddr,
+    root: Option<&Path>,
+    parse_options: Box<OptionsParser>,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let root = root.map(|root| root.canonicalize()).transpose()?;
+    let service = ConverterService {
+        jobs: Arc::new(JobQueue::start()),
+        root,
+        parse_options,
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    info!("listening for gRPC requests on {listen}");
+    runtime.block_on(
+        Server::builder()
+            .add_service(ConverterServer::new(service))
+            .serve(listen),
+    )?;
+    Ok(())
+}
@@ -1,41 +1,340 @@
 use std::{
+    collections::BTreeSet,
     error::Error,
     path::{Path, PathBuf},
-    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+    },
     time::Duration,
 };
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use eframe::egui::{self, DragValue, RichText, Slider, Spacing, Style, vec2};
-use egui_plot::{Plot, PlotImage, PlotPoint};
-use indicatif::MultiProgress;
-use indicatif_log_bridge::LogWrapper;
-use log::{error, info};
+use egui_plot::{
+    Bar, BarChart, HLine, Line, Plot, PlotImage, PlotPoint, PlotUi, Points, Polygon, Text, VLine,
+};
+use indicatif::{HumanBytes, MultiProgress};
+use log::{debug, error, info};
 use mrc::MrcMmap;
+use serde::{Deserialize, Serialize};
+
+use mrc_to_tiff::{
+    align::{AlignReference, Alignment, DEFAULT_MAX_SHIFT},
+    common::{ArgEndianess, available_space, error_chain},
+    convert::{self, CancelToken, ConvertOptions, OutputNaming},
+    dose::DoseWeighting,
+    process::{
+        BinMode, Clip, Crop, Dither, Equalize, Image, Normalize, Processing, Projection, StatsScope,
+    },
+    progress::{ProgressBars, ProgressMessage, ProgressSink},
+    read::{Axis, Volume3D, check_file_size},
+    reference::{Dark, Gain},
+    stats::{Histogram, Stats},
+    tilt,
+    write::SampleType,
+};
+
+#[cfg(feature = "gpu")]
+use crate::gpu::GpuPreview;
+use crate::{
+    i18n::{Language, tr},
+    logging::LogBuffer,
+    preview::{
+        PREVIEW_SIZE, PreviewCache, PreviewRequest, PreviewResult, PreviewTexture,
+        ProcessedPreview, RenderedPreview, Thumbnail,
+    },
+    queue::ExportQueue,
+    recent::{FileSettings, RecentFiles},
+    render::{DisplayScaling, render_to_rgb, robust_range},
+    script::Command,
+};
 
-use crate::{convert::ProgressMessage, read::Volume3D, render::render_to_rgb};
-mod common;
-mod convert;
-mod read;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod i18n;
+mod instance;
+mod logging;
+mod preview;
+mod queue;
+mod recent;
 mod render;
-mod write;
+mod script;
 
 #[derive(Parser, Debug)]
 struct Args {
     /// Path to the input .mrc file. Must be a 3D stack in 16bit format.
     mrc_path: Option<PathBuf>,
+
+    /// Open a new window, instead of the file in an already running one
+    #[arg(long)]
+    new_instance: bool,
+
+    /// Run the commands in this file without a window, for testing
+    #[arg(long, hide = true)]
+    script: Option<PathBuf>,
 }
 
 const H: f32 = 15.0;
 const V: f32 = 10.0;
 
+const WINDOW_TITLE: &str = "MRC to TIFF converter";
+
+/// Touchpad scroll distance, in points, that moves the preview by one slice
+const SCROLL_POINTS_PER_SLICE: f32 = 40.0;
+
+/// Quantiles of the shown values used as the display range by "auto contrast"
+const AUTO_CONTRAST: (f32, f32) = (0.001, 0.999);
+
+/// Number of evenly spaced slices shown in the thumbnail strip
+const NUM_THUMBNAILS: usize = 32;
+const THUMBNAIL_HEIGHT: f32 = 48.0;
+
 #[derive(Default)]
 struct ConverterApp {
     dest_directory: Option<PathBuf>,
     input_data: Option<WithInputData>,
     quantile: f32,
+    scaling: DisplayScaling,
+    /// Number of slices skipped by Page Up/Down
+    jump_size: usize,
+    /// Don't bin large slices for the preview
+    full_resolution: bool,
+    /// Overlay a scale bar on the preview, if the pixel size is known
+    preview_scalebar: bool,
+    /// Show the slice as processed for the export next to the preview
+    compare_processed: bool,
+    /// Show the power spectrum of the previewed slice
+    show_spectrum: bool,
+    export: ExportSettings,
+    theme: UiTheme,
+    /// egui's zoom factor, which can also be changed with Ctrl +/-
+    ui_scale: f32,
     multi: MultiProgress,
     error_state: Option<String>,
+    /// Shown as a notification until it's closed
+    finished_export: Option<FinishedExport>,
+    log: LogBuffer,
+    queue: ExportQueue,
+    recent: RecentFiles,
+    /// As last set, to only update it on changes
+    window_title: String,
+    /// Files opened by instances started later, see `instance::listen`
+    forwarded: Option<Receiver<PathBuf>>,
+    /// Renders the preview if the window runs on the GPU
+    #[cfg(feature = "gpu")]
+    gpu: Option<GpuPreview>,
+}
+
+/// Output options of the export section, shared by all opened files
+#[derive(Debug, Clone)]
+struct ExportSettings {
+    endianess: ArgEndianess,
+    /// `None` keeps the type of the input, or what the processing requires
+    output_type: Option<SampleType>,
+    normalize: Normalize,
+    normalize_stats: StatsScope,
+    equalize: Option<Equalize>,
+    dither: Option<Dither>,
+    /// Write a single projection of the selected frames instead of each frame
+    projection: Option<Projection>,
+    dark: Option<Dark>,
+    gain: Option<Gain>,
+    fix_outliers: Option<f32>,
+    fix_temporal_outliers: Option<f32>,
+    bin: Option<usize>,
+    bin_mode: BinMode,
+    fourier_crop: Option<f32>,
+    subtract_plane: bool,
+    dose_weighting: Option<DoseWeighting>,
+    /// Drift correction of the exported slices
+    align: Option<Alignment>,
+    naming: OutputNaming,
+    /// The name template as typed, only applied to `naming` when it's valid
+    template: String,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        let naming = OutputNaming::default();
+        Self {
+            endianess: ArgEndianess::default(),
+            output_type: None,
+            normalize: Normalize::None,
+            normalize_stats: StatsScope::default(),
+            equalize: None,
+            dither: None,
+            projection: None,
+            dark: None,
+            gain: None,
+            fix_outliers: None,
+            fix_temporal_outliers: None,
+            bin: None,
+            bin_mode: BinMode::default(),
+            fourier_crop: None,
+            subtract_plane: false,
+            dose_weighting: None,
+            align: None,
+            template: naming.template.to_string(),
+            naming,
+        }
+    }
+}
+
+impl ExportSettings {
+    /// For exporting all frames of a file, without the settings made on its preview.
+    fn options(&self) -> ConvertOptions {
+        ConvertOptions {
+            endianess: self.endianess.clone(),
+            processing: Processing {
+                dark: self.dark.clone(),
+                gain: self.gain.clone(),
+                fix_outliers: self.fix_outliers,
+                bin: self.bin,
+                bin_mode: self.bin_mode,
+                fourier_crop: self.fourier_crop,
+                subtract_plane: self.subtract_plane,
+                normalize: self.normalize,
+                normalize_stats: self.normalize_stats,
+                equalize: self.equalize,
+                dither: self.dither,
+                output_type: self.output_type,
+                ..Default::default()
+            },
+            projection: self.projection,
+            fix_temporal_outliers: self.fix_temporal_outliers,
+            dose_weighting: self.dose_weighting,
+            align: self.align,
+            naming: self.naming.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Name of a variant as given on the command line
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_owned())
+        .unwrap_or_default()
+}
+
+/// A combo box listing all variants of a command line option.
+fn value_enum_combo<T: ValueEnum + PartialEq>(ui: &mut egui::Ui, id: &str, value: &mut T) {
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(value_name(value))
+        .show_ui(ui, |ui| {
+            for option in T::value_variants() {
+                let name = value_name(option);
+                ui.selectable_value(value, option.clone(), name);
+            }
+        });
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum UiTheme {
+    /// Follow the theme of the operating system
+    #[default]
+    System,
+    Dark,
+    Light,
+}
+
+impl UiTheme {
+    const ALL: [UiTheme; 3] = [Self::System, Self::Dark, Self::Light];
+
+    fn name(&self) -> &'static str {
+        match self {
+            UiTheme::System => "system",
+            UiTheme::Dark => "dark",
+            UiTheme::Light => "light",
+        }
+    }
+
+    fn preference(&self) -> egui::ThemePreference {
+        match self {
+            UiTheme::System => egui::ThemePreference::System,
+            UiTheme::Dark => egui::ThemePreference::Dark,
+            UiTheme::Light => egui::ThemePreference::Light,
+        }
+    }
+}
+
+/// Settings that are remembered between sessions, in eframe's storage
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct Settings {
+    dest_directory: Option<PathBuf>,
+    quantile: f32,
+    scaling: DisplayScaling,
+    jump_size: usize,
+    endianess: ArgEndianess,
+    theme: UiTheme,
+    ui_scale: f32,
+    language: Language,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            dest_directory: None,
+            quantile: 0.999,
+            scaling: DisplayScaling::default(),
+            jump_size: 10,
+            endianess: ArgEndianess::default(),
+            theme: UiTheme::default(),
+            ui_scale: 1.0,
+            language: Language::default(),
+        }
+    }
+}
+
+const SETTINGS_KEY: &str = "settings";
+
+impl Settings {
+    fn load(storage: Option<&dyn eframe::Storage>) -> Self {
+        storage
+            .and_then(|storage| storage.get_string(SETTINGS_KEY))
+            .and_then(|value| {
+                serde_json::from_str(&value)
+                    .inspect_err(|e| debug!("ignoring the stored settings: {e}"))
+                    .ok()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// What dragging and clicking on the preview does
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum PreviewTool {
+    /// Dragging moves the view, clicking sets the position of the orthogonal views
+    #[default]
+    Cursor,
+    /// Dragging draws or resizes the crop region
+    Crop,
+    /// Dragging draws the line of the intensity profile
+    Profile,
+    /// Dragging measures the distance between two points
+    Measure,
+}
+
+/// Start and end of a line drawn on the preview, in pixel coordinates
+type PreviewLine = ((f64, f64), (f64, f64));
+
+/// The end of the display range that is being dragged in the histogram
+#[derive(Debug, Clone, Copy)]
+enum RangeHandle {
+    Min,
+    Max,
+}
+
+/// The XZ and YZ cross-sections through the cursor position, together with
+/// the cursor position and display settings they were rendered with.
+struct OrthoViews {
+    key: ((usize, usize), (f32, f32), DisplayScaling),
+    xz: egui::TextureHandle,
+    yz: egui::TextureHandle,
 }
 
 #[derive(Debug)]
@@ -44,72 +343,736 @@ struct BgProgress {
     total: usize,
 }
 
+struct FinishedExport {
+    dest_directory: PathBuf,
+    num_frames: usize,
+}
+
 struct WithInputData {
     source_path: PathBuf,
     mmap: MrcMmap,
     slice_position: usize,
     num_frames: usize,
+    // fraction of a slice scrolled on a touchpad, kept for the next scroll event:
+    scroll_remainder: f32,
 
     export_start: usize,
     export_end: usize,
+    // slices marked as worth keeping while browsing, exported instead of the
+    // range if `export_bookmarked` is set:
+    bookmarks: BTreeSet<usize>,
+    export_bookmarked: bool,
+    // in degrees, if the stack is a tilt series:
+    tilt_angles: Option<Vec<f32>>,
+    export_by_angle: bool,
+    crop: Option<Crop>,
+    tool: PreviewTool,
+    // dragging on the preview edits the crop region, from this fixed corner:
+    crop_anchor: Option<(f64, f64)>,
+    profile: Option<PreviewLine>,
+    measurement: Option<PreviewLine>,
+
+    // the last rendered preview, shown until a new one is ready:
+    texture: Option<PreviewTexture>,
+    preview_outdated: bool,
+    pending_preview: Option<Receiver<PreviewResult>>,
+    // the cache key of the pending preview:
+    pending_key: String,
+    preview_cache: PreviewCache,
+    // every n-th slice for quick navigation, with the quantile and scaling
+    // they were rendered with:
+    thumbnails: Vec<Thumbnail>,
+    pending_thumbnails: Option<Receiver<Thumbnail>>,
+    thumbnails_rendered_with: Option<(f32, DisplayScaling)>,
+    processed_preview: Option<ProcessedPreview>,
+    spectrum_preview: Option<ProcessedPreview>,
+    // the settings the processed preview was requested with:
+    compared: Option<String>,
+    histogram: Option<Histogram>,
+    // of the previewed slice or projection, to help spot bad frames:
+    preview_stats: Option<Stats>,
+    // the values shown in the preview, for the readout under the mouse pointer:
+    preview_values: Arc<[f32]>,
+    // in Ångström, from the header:
+    pixel_size: Option<f32>,
+    // preview a projection over the export range instead of the current slice:
+    preview_projection: Option<Projection>,
 
-    texture: Option<egui::TextureHandle>,
+    // display range of the preview, chosen from the histogram, or the
+    // automatic range of the current slice if `None`:
+    display_range: Option<(f32, f32)>,
+    auto_range: (f32, f32),
+    // from dmin and dmax of the header, the initial display range if they are set:
+    header_range: Option<(f32, f32)>,
+    dragged_handle: Option<RangeHandle>,
+    clip_to_display_range: bool,
+
+    // position (x, y) selected in the preview, and the cross-sections through it:
+    cursor: Option<(usize, usize)>,
+    ortho_views: Option<OrthoViews>,
 
     // data for tracking the ongoing export operation (running in a background thread)
     background_progress: Option<Receiver<ProgressMessage>>,
     background_progress_nums: Option<BgProgress>,
+    cancel_export: Option<CancelToken>,
+    export_destination: Option<PathBuf>,
+}
+
+impl WithInputData {
+    /// Convert the export range in a background thread, which reports its
+    /// progress through `background_progress`.
+    fn start_export(
+        &mut self,
+        dest_directory: &Path,
+        export: &ExportSettings,
+        multi_progress: &MultiProgress,
+    ) {
+        info!(
+            "converting frames {} to {} to tiff...",
+            self.export_start + 1,
+            self.export_end + 1
+        );
+        let (snd, rcv) = mpsc::channel::<ProgressMessage>();
+        self.background_progress = Some(rcv);
+        let cancel = CancelToken::new();
+        self.cancel_export = Some(cancel.clone());
+        self.export_destination = Some(dest_directory.to_owned());
+
+        let source_path = self.source_path.clone();
+        let dest_directory = dest_directory.to_owned();
+        let options = ConvertOptions {
+            cancel: Some(cancel),
+            ..self.export_options(export)
+        };
+        let bars = ProgressBars::new(multi_progress.clone());
+
+        std::thread::spawn(move || {
+            let progress = (snd, bars);
+            if let Err(e) = convert::convert(source_path, dest_directory, &options, &progress) {
+                progress.error(&error_chain(&e));
+            }
+        });
+    }
+
+    fn show_preview(&mut self, rendered: RenderedPreview) {
+        self.texture = Some(rendered.texture);
+        self.preview_values = rendered.values;
+        self.preview_stats = Some(rendered.stats);
+        self.histogram = Some(rendered.histogram);
+        self.auto_range = rendered.auto_range;
+        self.processed_preview = rendered.processed;
+        self.spectrum_preview = rendered.spectrum;
+    }
+
+    /// Number of slices between two thumbnails
+    fn thumbnail_step(&self) -> usize {
+        self.num_frames.div_ceil(NUM_THUMBNAILS).max(1)
+    }
+
+    /// A strip of thumbnails of every n-th slice; clicking one shows that slice.
+    fn show_thumbnails(&mut self, ui: &mut egui::Ui, quantile: f32, scaling: DisplayScaling) {
+        let step = self.thumbnail_step();
+        if self.thumbnails_rendered_with != Some((quantile, scaling)) {
+            self.thumbnails_rendered_with = Some((quantile, scaling));
+            self.thumbnails.clear();
+            self.pending_thumbnails = Some(preview::spawn_thumbnails(
+                self.source_path.clone(),
+                (0..self.num_frames).step_by(step).collect(),
+                quantile,
+                scaling,
+                ui.ctx(),
+            ));
+        }
+        if let Some(pending) = &self.pending_thumbnails {
+            let mut finished = false;
+            loop {
+                match pending.try_recv() {
+                    Ok(thumbnail) => self.thumbnails.push(thumbnail),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        finished = true;
+                        break;
+                    }
+                }
+            }
+            if finished {
+                self.pending_thumbnails = None;
+            }
+        }
+
+        let mut clicked = None;
+        egui::ScrollArea::horizontal()
+            .id_salt("thumbnails")
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    for thumbnail in &self.thumbnails {
+                        let position = thumbnail.slice_position;
+                        let selected = (position..position + step).contains(&self.slice_position);
+                        let image =
+                            egui::Image::new(&thumbnail.texture).max_height(THUMBNAIL_HEIGHT);
+                        let btn = ui
+                            .add(egui::Button::image(image).selected(selected))
+                            .on_hover_text(format!("{} {}", tr("slice"), position + 1));
+                        if btn.clicked() {
+                            clicked = Some(position);
+                        }
+                    }
+                });
+            });
+        if let Some(position) = clicked {
+            self.slice_position = position;
+            self.preview_outdated = true;
+        }
+    }
+
+    /// The displayed value range, typed in or taken from the data.
+    fn show_display_range(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let (mut vmin, mut vmax) = self.display_range.unwrap_or(self.auto_range);
+            ui.label("vmin");
+            let vmin_resp = ui.add(DragValue::new(&mut vmin).range(f32::MIN..=vmax));
+            ui.label("vmax");
+            let vmax_resp = ui.add(DragValue::new(&mut vmax).range(vmin..=f32::MAX));
+            if vmin_resp.changed() || vmax_resp.changed() {
+                self.display_range = Some((vmin, vmax));
+                self.preview_outdated = true;
+            }
+            let auto_btn = ui.add_enabled(self.display_range.is_some(), egui::Button::new("auto"));
+            let auto_tip = tr("Display from the minimum up to the quantile of each slice");
+            if auto_btn.on_hover_text(auto_tip).clicked() {
+                self.display_range = None;
+                self.preview_outdated = true;
+            }
+            let contrast_btn = ui.add_enabled(
+                !self.preview_values.is_empty(),
+                egui::Button::new(tr("auto contrast")),
+            );
+            let contrast_tip = tr("From the 0.1 % to the 99.9 % quantile of the preview");
+            if contrast_btn.on_hover_text(contrast_tip).clicked() {
+                let (low, high) = AUTO_CONTRAST;
+                self.display_range = Some(robust_range(&self.preview_values, low, high));
+                self.preview_outdated = true;
+            }
+            let header_btn =
+                ui.add_enabled(self.header_range.is_some(), egui::Button::new(tr("header")));
+            let header_tip = tr("From dmin to dmax of the MRC header, as IMOD shows the file");
+            if header_btn.on_hover_text(header_tip).clicked() {
+                self.display_range = self.header_range;
+                self.preview_outdated = true;
+            }
+        });
+    }
+
+    /// The frames to export: their range, bookmarks and crop region.
+    fn show_export_range(&mut self, ui: &mut egui::Ui) {
+        let (nx, ny, _) = self.mmap.read_view().unwrap().dimensions();
+        let export_range = (self.export_start, self.export_end);
+        ui.label(tr("Start frame number"));
+        ui.horizontal(|ui| {
+            let mut export_start_drag = self.export_start + 1;
+            ui.add(DragValue::new(&mut export_start_drag).range(1..=self.export_end));
+            self.export_start = export_start_drag - 1;
+
+            if ui
+                .button(format!(
+                    "{} ({})",
+                    tr("from current preview"),
+                    self.slice_position + 1
+                ))
+                .clicked()
+            {
+                self.export_start = self.slice_position;
+            }
+        });
+        ui.end_row();
+
+        ui.label(tr("End frame number (inclusive)"));
+        ui.horizontal(|ui| {
+            let mut export_end_drag = self.export_end + 1;
+            ui.add(DragValue::new(&mut export_end_drag).range(1..=self.num_frames));
+            self.export_end = export_end_drag - 1;
+            if ui
+                .button(format!(
+                    "{} ({})",
+                    tr("from current preview"),
+                    self.slice_position + 1
+                ))
+                .clicked()
+            {
+                self.export_end = self.slice_position;
+            }
+        });
+        ui.end_row();
+
+        if self.preview_projection.is_some() && export_range != (self.export_start, self.export_end)
+        {
+            self.preview_outdated = true;
+        }
+
+        ui.label(tr("Bookmarked slices"));
+        ui.horizontal(|ui| {
+            let marked = self.bookmarks.contains(&self.slice_position);
+            let mut keep = marked;
+            let keep_text = format!("{} ({})", tr("keep current"), self.slice_position + 1);
+            ui.checkbox(&mut keep, keep_text)
+                .on_hover_text(tr("Toggle with B while browsing"));
+            if keep != marked {
+                self.toggle_bookmark();
+            }
+            ui.label(format!("{} {}", self.bookmarks.len(), tr("marked")));
+            let any_marked = !self.bookmarks.is_empty();
+            let only_marked =
+                egui::Checkbox::new(&mut self.export_bookmarked, tr("export only these"));
+            ui.add_enabled(any_marked, only_marked)
+                .on_hover_text(tr("Instead of the range between the start and end frame"));
+            let clear_btn = egui::Button::new(tr("Clear"));
+            if ui.add_enabled(any_marked, clear_btn).clicked() {
+                self.bookmarks.clear();
+            }
+        });
+        ui.end_row();
+
+        if self.tilt_angles.is_some() {
+            ui.label(tr("Tilt series"));
+            ui.checkbox(&mut self.export_by_angle, tr("ordered by tilt angle"))
+                .on_hover_text(tr("The angle is added to the file names"));
+            ui.end_row();
+        }
+
+        ui.label(tr("Crop region"));
+        ui.horizontal(|ui| {
+            let mut crop_enabled = self.crop.is_some();
+            ui.checkbox(&mut crop_enabled, "");
+            let mut selecting = self.tool == PreviewTool::Crop;
+            if ui
+                .toggle_value(&mut selecting, tr("select on preview"))
+                .on_hover_text(tr("Drag on the preview to draw or resize the region"))
+                .changed()
+            {
+                self.tool = if selecting {
+                    PreviewTool::Crop
+                } else {
+                    PreviewTool::Cursor
+                };
+            }
+            if !crop_enabled {
+                self.crop = None;
+                return;
+            }
+            let crop = self.crop.get_or_insert(Crop {
+                x: 0,
+                y: 0,
+                width: nx,
+                height: ny,
+            });
+            ui.label("x");
+            ui.add(DragValue::new(&mut crop.x).range(0..=nx - 1));
+            ui.label("y");
+            ui.add(DragValue::new(&mut crop.y).range(0..=ny - 1));
+            ui.label(tr("width"));
+            ui.add(DragValue::new(&mut crop.width).range(1..=nx - crop.x));
+            ui.label(tr("height"));
+            ui.add(DragValue::new(&mut crop.height).range(1..=ny - crop.y));
+        });
+        ui.end_row();
+    }
+
+    fn file_settings(&self) -> FileSettings {
+        FileSettings {
+            slice_position: self.slice_position,
+            export_start: self.export_start,
+            export_end: self.export_end,
+            display_range: self.display_range,
+            clip_to_display_range: self.clip_to_display_range,
+        }
+    }
+
+    /// Apply the settings of an earlier session, unless the file has changed
+    /// so that they don't fit anymore.
+    fn restore(&mut self, settings: FileSettings) {
+        if settings.slice_position >= self.num_frames
+            || settings.export_end > self.num_frames
+            || settings.export_start > settings.export_end
+        {
+            return;
+        }
+        self.slice_position = settings.slice_position;
+        self.export_start = settings.export_start;
+        self.export_end = settings.export_end;
+        self.display_range = settings.display_range;
+        self.clip_to_display_range = settings.clip_to_display_range;
+    }
+
+    /// Number of slices the export reads
+    fn num_export_frames(&self) -> usize {
+        if self.export_bookmarked && !self.bookmarks.is_empty() {
+            self.bookmarks.len()
+        } else {
+            self.export_end + 1 - self.export_start
+        }
+    }
+
+    /// Move to the slice with the next higher or lower tilt angle.
+    fn step_by_angle(&mut self, higher: bool) {
+        let Some(angles) = &self.tilt_angles else {
+            return;
+        };
+        let mut order: Vec<usize> = (0..angles.len()).collect();
+        order.sort_by(|&a, &b| angles[a].total_cmp(&angles[b]));
+        let Some(pos) = order.iter().position(|&z| z == self.slice_position) else {
+            return;
+        };
+        let next = if higher {
+            order.get(pos + 1)
+        } else {
+            pos.checked_sub(1).and_then(|pos| order.get(pos))
+        };
+        if let Some(&z) = next {
+            self.slice_position = z;
+            self.preview_outdated = true;
+        }
+    }
+
+    fn toggle_bookmark(&mut self) {
+        if !self.bookmarks.remove(&self.slice_position) {
+            self.bookmarks.insert(self.slice_position);
+        }
+    }
+
+    /// Step through the slices with the arrow keys, jump by `jump_size` with
+    /// Page Up/Down, and to the first or last slice with Home/End. B marks or
+    /// unmarks the current slice.
+    fn navigate_by_keys(&mut self, input: &egui::InputState, jump_size: usize) {
+        if input.key_pressed(egui::Key::B) {
+            self.toggle_bookmark();
+        }
+        let last = self.num_frames - 1;
+        let position = self.slice_position;
+        let new_position = if input.key_pressed(egui::Key::ArrowLeft) {
+            position.saturating_sub(1)
+        } else if input.key_pressed(egui::Key::ArrowRight) {
+            position.saturating_add(1).min(last)
+        } else if input.key_pressed(egui::Key::PageUp) {
+            position.saturating_sub(jump_size)
+        } else if input.key_pressed(egui::Key::PageDown) {
+            position.saturating_add(jump_size).min(last)
+        } else if input.key_pressed(egui::Key::Home) {
+            0
+        } else if input.key_pressed(egui::Key::End) {
+            last
+        } else {
+            position
+        };
+        if new_position != position {
+            self.slice_position = new_position;
+            self.preview_outdated = true;
+        }
+    }
+
+    /// Step through the slices with the mouse wheel, scrolling down moving
+    /// forward. Ctrl + wheel is left to the plot, for zooming.
+    fn scroll_slices(&mut self, input: &egui::InputState, jump_size: usize) {
+        for event in &input.events {
+            if let egui::Event::MouseWheel {
+                unit,
+                delta,
+                modifiers,
+            } = event
+                && !(modifiers.ctrl || modifiers.command)
+            {
+                self.scroll_remainder -= match unit {
+                    egui::MouseWheelUnit::Point => delta.y / SCROLL_POINTS_PER_SLICE,
+                    egui::MouseWheelUnit::Line => delta.y,
+                    egui::MouseWheelUnit::Page => delta.y * jump_size as f32,
+                };
+            }
+        }
+        let steps = self.scroll_remainder.trunc();
+        if steps == 0.0 {
+            return;
+        }
+        self.scroll_remainder -= steps;
+        let last = self.num_frames - 1;
+        let new_position = (self.slice_position as f32 + steps).clamp(0.0, last as f32) as usize;
+        if new_position != self.slice_position {
+            self.slice_position = new_position;
+            self.preview_outdated = true;
+        }
+    }
+
+    /// Options for exporting with the current settings.
+    fn export_options(&self, export: &ExportSettings) -> ConvertOptions {
+        let clip = self.clip_to_display_range.then(|| {
+            let (min, max) = self.display_range.unwrap_or(self.auto_range);
+            Clip { min, max }
+        });
+        let mut options = export.options();
+        let bookmarked = self.export_bookmarked && !self.bookmarks.is_empty();
+        let mut frames: Option<Vec<usize>> =
+            bookmarked.then(|| self.bookmarks.iter().copied().collect());
+        if self.export_by_angle
+            && let Some(angles) = &self.tilt_angles
+        {
+            let mut idxs =
+                frames.unwrap_or_else(|| (self.export_start..=self.export_end).collect());
+            idxs.retain(|&z| z < angles.len());
+            idxs.sort_by(|&a, &b| angles[a].total_cmp(&angles[b]));
+            options.naming.angles = Some(idxs.iter().map(|&z| angles[z]).collect());
+            frames = Some(idxs);
+        }
+        ConvertOptions {
+            start_at_frame: self.export_start + 1,
+            stop_at_frame: Some(self.export_end + 1),
+            frames: frames.map(|idxs| idxs.iter().map(|z| z + 1).collect()),
+            processing: Processing {
+                crop: self.crop,
+                clip,
+                ..options.processing
+            },
+            ..options
+        }
+    }
 }
 
 fn load_data(path: &Path) -> Result<WithInputData, Box<dyn Error>> {
     let mmap = MrcMmap::open(path)?;
-    let view = mmap.read_view()?;
-    let num_frames = view.dimensions().2;
+    check_file_size(path, mmap.header())?;
+    let volume = Volume3D::new(mmap.read_view()?);
+    let num_frames = volume.dimensions().2;
+    let header = volume.header();
+    let pixel_size = (header.mx > 0 && header.xlen > 0.0).then(|| header.xlen / header.mx as f32);
+    let tilt_angles = tilt::tilt_angles(path, header, num_frames);
+    let header_range = volume.header_stats().map(|stats| (stats.min, stats.max));
     Ok(WithInputData {
         source_path: path.to_owned(),
         slice_position: 0,
         num_frames,
+        scroll_remainder: 0.0,
         mmap,
         texture: None,
+        preview_outdated: true,
+        pending_preview: None,
+        pending_key: String::new(),
+        preview_cache: PreviewCache::default(),
+        thumbnails: Vec::new(),
+        pending_thumbnails: None,
+        thumbnails_rendered_with: None,
+        processed_preview: None,
+        spectrum_preview: None,
+        compared: None,
+        histogram: None,
+        preview_stats: None,
+        preview_values: Arc::from([]),
+        pixel_size,
+        preview_projection: None,
+        display_range: header_range,
+        auto_range: (0.0, 0.0),
+        header_range,
+        dragged_handle: None,
+        clip_to_display_range: false,
+        cursor: None,
+        ortho_views: None,
         export_start: 0,
         export_end: num_frames,
+        bookmarks: BTreeSet::new(),
+        export_bookmarked: false,
+        tilt_angles,
+        export_by_angle: false,
+        crop: None,
+        tool: PreviewTool::default(),
+        profile: None,
+        measurement: None,
+        crop_anchor: None,
         background_progress: None,
         background_progress_nums: None,
+        cancel_export: None,
+        export_destination: None,
     })
 }
 
+/// Apply the `commands` to the app state without a window, with a frame
+/// drawn after each of them, like after clicking through the GUI.
+fn run_script(app: &mut ConverterApp, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    let ctx = egui::Context::default();
+    let run_frame = |app: &mut ConverterApp| {
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                vec2(1024.0, 1024.0),
+            )),
+            ..Default::default()
+        };
+        let _ = ctx.run(input, |ctx| app.show(ctx));
+    };
+    run_frame(app);
+    for command in commands {
+        info!("script: {command:?}");
+        match command {
+            Command::Open(path) => app.open(path),
+            Command::Destination(path) => app.dest_directory = Some(path.clone()),
+            Command::Range(start, end) => {
+                let data = app.input_data.as_mut().ok_or("no file is open")?;
+                if *start < 1 || start > end || *end > data.num_frames {
+                    return Err(format!("invalid range {start} to {end}").into());
+                }
+                data.export_start = start - 1;
+                data.export_end = end - 1;
+            }
+            Command::Slice(position) => {
+                let data = app.input_data.as_mut().ok_or("no file is open")?;
+                if *position < 1 || *position > data.num_frames {
+                    return Err(format!("invalid slice {position}").into());
+                }
+                data.slice_position = position - 1;
+                data.preview_outdated = true;
+            }
+            Command::Export => {
+                let dest_directory = app.dest_directory.clone().ok_or("no destination")?;
+                let data = app.input_data.as_mut().ok_or("no file is open")?;
+                data.start_export(&dest_directory, &app.export, &app.multi);
+                // the progress is received while drawing, like in the window:
+                while app
+                    .input_data
+                    .as_ref()
+                    .is_some_and(|data| data.background_progress.is_some())
+                {
+                    run_frame(app);
+                    std::thread::sleep(Duration::from_millis(16));
+                }
+            }
+        }
+        run_frame(app);
+        if let Some(err) = &app.error_state {
+            return Err(err.clone().into());
+        }
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
-    let logger = env_logger::Builder::from_env(env).build();
     let multi = MultiProgress::new();
-    LogWrapper::new(multi.clone(), logger).try_init()?;
+    let log = LogBuffer::default();
+    logging::init(&multi, None, Some(log.clone()))?;
 
     let args = Args::parse();
-    let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([1024.0, 1024.0]),
+    if let Some(script_path) = &args.script {
+        let commands = script::parse(&std::fs::read_to_string(script_path)?)?;
+        // neither the stored settings nor the recent files, to be reproducible:
+        let mut app = ConverterApp::new(Settings::default(), multi, log, RecentFiles::default());
+        if let Some(path) = &args.mrc_path {
+            app.open(path);
+        }
+        return run_script(&mut app, &commands);
+    }
+    if !args.new_instance
+        && let Some(path) = &args.mrc_path
+        && instance::forward(path)
+    {
+        return Ok(());
+    }
+    let options = || eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([1024.0, 1024.0])
+            .with_app_id("mrc-to-tiff"),
         ..Default::default()
     };
 
-    eframe::run_native(
-        "MRC to TIFF converter",
-        options,
-        Box::new(|_cc| {
-            let input_data = args.mrc_path.map(|path| load_data(&path).unwrap());
-            let app = ConverterApp {
-                dest_directory: None,
-                input_data,
-                quantile: 0.999,
-                multi,
-                error_state: None,
-            };
+    let args = &args;
+    let create_app = || -> eframe::AppCreator<'_> {
+        let (multi, log) = (multi.clone(), log.clone());
+        Box::new(move |cc| {
+            let settings = Settings::load(cc.storage);
+            cc.egui_ctx.set_theme(settings.theme.preference());
+            cc.egui_ctx.set_zoom_factor(settings.ui_scale);
+            i18n::set_language(settings.language);
+            let mut app = ConverterApp::new(settings, multi, log, RecentFiles::load());
+            app.forwarded = instance::listen(&cc.egui_ctx);
+            #[cfg(feature = "gpu")]
+            {
+                app.gpu = cc.wgpu_render_state.clone().map(GpuPreview::new);
+            }
+            if let Some(path) = &args.mrc_path {
+                app.open(path);
+            }
             Ok(Box::new(app))
-        }),
-    )?;
+        })
+    };
+    let res = eframe::run_native(WINDOW_TITLE, options(), create_app());
+    // without a usable GPU, the preview is rendered on the CPU instead:
+    #[cfg(feature = "gpu")]
+    let res = match res {
+        Err(eframe::Error::Wgpu(e)) => {
+            log::warn!("failed to set up the GPU, rendering the preview on the CPU: {e}");
+            let options = eframe::NativeOptions {
+                renderer: eframe::Renderer::Glow,
+                ..options()
+            };
+            eframe::run_native(WINDOW_TITLE, options, create_app())
+        }
+        res => res,
+    };
+    res?;
 
     Ok(())
 }
 
 impl eframe::App for ConverterApp {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.remember_file_settings();
+        let settings = Settings {
+            dest_directory: self.dest_directory.clone(),
+            quantile: self.quantile,
+            scaling: self.scaling,
+            jump_size: self.jump_size,
+            endianess: self.export.endianess.clone(),
+            theme: self.theme,
+            ui_scale: self.ui_scale,
+            language: i18n::language(),
+        };
+        match serde_json::to_string(&settings) {
+            Ok(value) => storage.set_string(SETTINGS_KEY, value),
+            Err(e) => error!("failed to store the settings: {e}"),
+        }
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.show(ctx);
+    }
+}
+
+impl ConverterApp {
+    fn new(settings: Settings, multi: MultiProgress, log: LogBuffer, recent: RecentFiles) -> Self {
+        ConverterApp {
+            dest_directory: settings.dest_directory,
+            input_data: None,
+            quantile: settings.quantile,
+            scaling: settings.scaling,
+            jump_size: settings.jump_size,
+            full_resolution: false,
+            preview_scalebar: true,
+            compare_processed: false,
+            show_spectrum: false,
+            export: ExportSettings {
+                endianess: settings.endianess,
+                ..Default::default()
+            },
+            theme: settings.theme,
+            ui_scale: settings.ui_scale,
+            multi,
+            error_state: None,
+            finished_export: None,
+            log,
+            queue: ExportQueue::default(),
+            recent,
+            window_title: String::new(),
+            forwarded: None,
+            #[cfg(feature = "gpu")]
+            gpu: None,
+        }
+    }
+
+    /// Everything drawn in a frame, independent of the window it's shown in.
+    fn show(&mut self, ctx: &egui::Context) {
         let mut style = Style::default();
         let spacing = Spacing {
             button_padding: vec2(H, V),
@@ -120,12 +1083,39 @@ impl eframe::App for ConverterApp {
         ctx.set_style_of(egui::Theme::Dark, style.clone());
         ctx.set_style_of(egui::Theme::Light, style);
 
+        egui::TopBottomPanel::top("view settings").show(ctx, |ui| self.show_view_settings(ui));
+        egui::TopBottomPanel::bottom("log").show(ctx, |ui| self.show_log(ui));
+        self.queue.show(ctx, &self.multi);
+        self.show_finished_export(ctx);
+        self.update_window_title(ctx);
+
+        let mut dropped: Vec<PathBuf> = ctx.input(|input| {
+            input
+                .raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+        // files opened with this program while it's running are handled the same:
+        if let Some(forwarded) = &self.forwarded {
+            dropped.extend(forwarded.try_iter());
+        }
+        match dropped.as_slice() {
+            [] => {}
+            [path] => self.open(path),
+            paths => self.queue_files(paths),
+        }
+
         if let Some(err) = self.error_state.clone() {
             egui::CentralPanel::default().show(ctx, |ui| {
                 ui.vertical(|ui| {
-                    ui.label(err.to_string());
+                    ui.monospace(&err);
+                    if ui.button(tr("Copy details")).clicked() {
+                        ui.ctx().copy_text(err.clone());
+                    }
 
-                    let continue_btn = egui::Button::new(RichText::new("Continue").strong());
+                    let continue_btn = egui::Button::new(RichText::new(tr("Continue")).strong());
                     let continue_btn = continue_btn.fill(egui::Color32::from_rgb(0, 90, 230));
 
                     if ui.add(continue_btn).clicked() {
@@ -135,25 +1125,22 @@ impl eframe::App for ConverterApp {
             });
         } else {
             if self.input_data.is_some() {
-                self.render_with_data(ctx, frame);
+                self.render_with_data(ctx);
             } else {
                 egui::CentralPanel::default().show(ctx, |ui| {
                     ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
                         let load_btn =
-                            egui::Button::new(RichText::new("Load 3D MRC stack...").strong());
+                            egui::Button::new(RichText::new(tr("Load 3D MRC stack...")).strong());
                         let load_btn = load_btn.fill(egui::Color32::from_rgb(0, 90, 230));
 
                         if ui.add(load_btn).clicked()
                             && let Some(new_path) = rfd::FileDialog::new().pick_file()
                         {
-                            self.input_data = match load_data(&new_path) {
-                                Ok(data) => Some(data),
-                                Err(err) => {
-                                    self.error_state = Some(format!("Error loading data: {}", err));
-                                    None
-                                }
-                            }
+                            self.open(&new_path);
                         }
+                        self.show_batch_button(ui);
+
+                        self.show_recent(ui);
                     })
                 });
             }
@@ -162,273 +1149,1696 @@ impl eframe::App for ConverterApp {
 }
 
 impl ConverterApp {
-    fn render_with_data(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::TopBottomPanel::new(
-            egui::panel::TopBottomSide::Bottom,
-            "bottom panel view options",
-        )
-        .frame(egui::containers::Frame::new().inner_margin(vec2(H, V)))
-        .show(ctx, |ui| {
-            if let Some(data) = &mut self.input_data {
-                ui.set_min_width(256.0);
-                // 1-indexed position in the UI:
-                let mut slider_value = data.slice_position + 1;
-                ui.add(
-                    Slider::new(&mut slider_value, 1..=data.num_frames)
-                        .text("Slice preview")
-                        .drag_value_speed(0.1),
-                );
-                let new_slice_position = slider_value - 1;
-                // slider change detected:
-                if data.slice_position != new_slice_position {
-                    data.texture = None;
-                }
-                data.slice_position = new_slice_position;
-
-                let mut slider_quantile = self.quantile;
-                let q_slider = Slider::new(&mut slider_quantile, 0.0..=1.0)
-                    .text("Quantile")
-                    .drag_value_speed(0.0001);
-                ui.add(q_slider);
-                if self.quantile != slider_quantile {
-                    data.texture = None;
-                }
-                self.quantile = slider_quantile;
+    /// The recent log lines, collapsed by default.
+    fn show_log(&self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new(tr("Log")).show(ui, |ui| {
+            let lines = self.log.lines();
+            if ui.button(tr("Copy")).clicked() {
+                ui.ctx().copy_text(lines.join("\n"));
             }
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in &lines {
+                        ui.monospace(line);
+                    }
+                });
         });
-        egui::CentralPanel::default().show(ctx, |ui| {
-            if ui.button("Load 3D MRC Stack...").clicked()
-                && let Some(new_path) = rfd::FileDialog::new().pick_file()
-            {
-                self.input_data = match load_data(&new_path) {
-                    Ok(data) => Some(data),
-                    Err(err) => {
-                        self.error_state = Some(err.to_string());
-                        None
+    }
+
+    fn show_view_settings(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut theme = self.theme;
+            egui::ComboBox::from_label(tr("Theme"))
+                .selected_text(theme.name())
+                .show_ui(ui, |ui| {
+                    for option in UiTheme::ALL {
+                        ui.selectable_value(&mut theme, option, option.name());
                     }
-                }
+                });
+            if theme != self.theme {
+                ui.ctx().set_theme(theme.preference());
+                self.theme = theme;
             }
-            egui::Grid::new("parameter grid")
-                .num_columns(2)
-                .striped(true)
-                .show(ui, |ui| {
-                    if let Some(data) = &mut self.input_data {
-                        let view = data.mmap.read_view().unwrap();
-                        let (nx, ny, nz) = view.dimensions();
-                        ui.label("Input path");
-                        ui.monospace(data.source_path.to_string_lossy());
-                        ui.end_row();
-                        ui.label("Input size");
-                        ui.monospace(format!("{nz}x{ny}x{nx}"));
-                        ui.end_row();
-
-                        ui.separator();
-                        ui.separator();
-                        ui.end_row();
-
-                        let dest_btn =
-                            egui::Button::new(RichText::new("Destination directory...").strong());
-                        let dest_btn = dest_btn.fill(egui::Color32::from_rgb(0, 90, 230));
-
-                        if ui.add(dest_btn).clicked()
-                            && let Some(new_path) = rfd::FileDialog::new().pick_folder()
-                        {
-                            self.dest_directory = Some(new_path);
-                        }
-                        ui.end_row();
 
-                        ui.label("Destination directory");
-                        if let Some(dest_path) = &self.dest_directory {
-                            ui.monospace(dest_path.to_string_lossy());
-                        } else {
-                            ui.label(RichText::new("not set").italics());
-                        }
-                        ui.end_row();
-
-                        if ui.input(|i| i.key_pressed(egui::Key::ArrowLeft)) {
-                            data.slice_position = data.slice_position.saturating_sub(1);
-                            data.texture = None;
-                        };
-                        if ui.input(|i| i.key_pressed(egui::Key::ArrowRight)) {
-                            data.slice_position = data
-                                .slice_position
-                                .saturating_add(1)
-                                .min(data.num_frames - 1);
-                            data.texture = None;
-                        };
-
-                        ui.separator();
-                        ui.separator();
-                        ui.end_row();
-
-                        ui.label("Start frame number");
-                        ui.horizontal(|ui| {
-                            let mut export_start_drag = data.export_start + 1;
-                            ui.add(
-                                DragValue::new(&mut export_start_drag).range(1..=data.export_end),
-                            );
-                            data.export_start = export_start_drag - 1;
-
-                            if ui
-                                .button(format!(
-                                    "from current preview ({})",
-                                    data.slice_position + 1
-                                ))
-                                .clicked()
-                            {
-                                data.export_start = data.slice_position;
-                            }
-                        });
-                        ui.end_row();
-
-                        ui.label("End frame number (inclusive)");
-                        ui.horizontal(|ui| {
-                            let mut export_end_drag = data.export_end + 1;
-                            ui.add(DragValue::new(&mut export_end_drag).range(1..=data.num_frames));
-                            data.export_end = export_end_drag - 1;
-                            if ui
-                                .button(format!(
-                                    "from current preview ({})",
-                                    data.slice_position + 1
-                                ))
-                                .clicked()
-                            {
-                                data.export_end = data.slice_position;
-                            }
-                        });
-                        ui.end_row();
-
-                        let export_enabled =
-                            self.dest_directory.is_some() && data.background_progress.is_none();
-                        let multi_progress = self.multi.clone();
-                        ui.add_enabled_ui(export_enabled, |ui| {
-                            let export_btn =
-                                egui::Button::new(RichText::new("Export to tiff").strong());
-                            let export_btn = export_btn.fill(egui::Color32::from_rgb(0, 90, 230));
-                            let mut export_btn_resp = ui.add(export_btn);
-                            if self.dest_directory.is_none() {
-                                export_btn_resp = export_btn_resp
-                                    .on_hover_text("Please select a destination directory first");
-                            }
-                            if export_btn_resp.clicked()
-                                && let Some(dest_directory) = &self.dest_directory
-                            {
-                                info!(
-                                    "converting frames {} to {} to tiff...",
-                                    data.export_start + 1,
-                                    data.export_end + 1
-                                );
-                                let (snd, rcv) = mpsc::channel::<ProgressMessage>();
-                                data.background_progress = Some(rcv);
-
-                                let source_path = data.source_path.clone();
-                                let dest_directory = dest_directory.clone();
-                                let export_start = data.export_start;
-                                let export_end = data.export_end;
-
-                                std::thread::spawn(move || {
-                                    if let Err(e) = convert::convert(
-                                        source_path,
-                                        dest_directory,
-                                        common::ArgEndianess::Big,
-                                        export_start + 1,
-                                        Some(export_end + 1),
-                                        &multi_progress,
-                                        Some(snd.clone()),
-                                    ) {
-                                        snd.send(ProgressMessage::Error { msg: e.to_string() })
-                                            .unwrap();
-                                    }
-                                });
-                            }
-                        });
-                        ui.end_row();
-
-                        ui.label("");
-                        ui.label(format!(
-                            "Note: output frames will be labeled from 1 to {}",
-                            data.export_end + 1 - data.export_start
-                        ));
-                        ui.end_row();
-
-                        if let Some(recv) = &data.background_progress {
-                            'multi_messages: loop {
-                                match recv.recv_timeout(Duration::from_millis(4)) {
-                                    Ok(ProgressMessage::InProgress { num_done, total }) => {
-                                        data.background_progress_nums = Some(BgProgress {
-                                            done: num_done,
-                                            total,
-                                        });
-                                    }
-                                    Ok(ProgressMessage::Done { total: _ }) => {
-                                        data.background_progress = None;
-                                        data.background_progress_nums = None;
-                                        break 'multi_messages;
-                                    }
-                                    Ok(ProgressMessage::Error { msg }) => {
-                                        let err = format!("Error while converting: {msg}");
-                                        error!("{err}");
-                                        self.error_state = Some(err);
-                                        break 'multi_messages;
-                                    }
-                                    Err(RecvTimeoutError::Timeout) => {
-                                        // this is fine.
-                                        break 'multi_messages;
-                                    }
-                                    Err(RecvTimeoutError::Disconnected) => {
-                                        error!("background thread disconnected");
-                                        // this should only happen if the thread errs out, but that should also
-                                        // give us a proper ProgressMessage::Error, so don't try to show this
-                                        // in the GUI.
-                                        data.background_progress = None;
-                                        data.background_progress_nums = None;
-                                        break 'multi_messages;
-                                    }
-                                }
-                            }
-                            if let Some(prog) = &data.background_progress_nums {
-                                ui.label("");
-                                ui.add(egui::ProgressBar::new(
-                                    prog.done as f32 / prog.total as f32,
-                                ));
-                                ui.end_row();
-                            }
-                            // if we expect some progress, we need to redraw:
-                            ctx.request_repaint_after(Duration::from_millis(16));
-                        }
+            let mut language = i18n::language();
+            egui::ComboBox::from_label(tr("Language"))
+                .selected_text(language.name())
+                .show_ui(ui, |ui| {
+                    for option in Language::ALL {
+                        ui.selectable_value(&mut language, option, option.name());
                     }
                 });
+            i18n::set_language(language);
 
-            if let Some(data) = &mut self.input_data {
-                let view = data.mmap.read_view().unwrap();
-                let (nx, ny, _nz) = view.dimensions();
-
-                let texture: &egui::TextureHandle = data.texture.get_or_insert_with(|| {
-                    let view = data.mmap.read_view().unwrap();
-                    let volume = Volume3D::new(view);
-                    info!("loading slice {}", data.slice_position);
-                    let img = render_to_rgb(
-                        volume.get_slice(data.slice_position).unwrap(),
-                        nx,
-                        ny,
-                        self.quantile,
-                    );
-                    ui.ctx()
-                        .load_texture("preview_texture", img, Default::default())
-                });
-                let plot = Plot::new("preview").data_aspect(1.0);
-                plot.show(ui, |plot_ui| {
-                    let center_position = PlotPoint::new(0.5, 0.5);
-                    let aspect_ratio = ny as f32 / nx as f32;
-                    let image = PlotImage::new(
-                        "preview_image",
-                        texture,
-                        center_position,
-                        vec2(aspect_ratio, 1.0),
-                    );
-                    plot_ui.image(image);
-                });
+            let resp = ui.add(
+                Slider::new(&mut self.ui_scale, 0.5..=3.0)
+                    .step_by(0.05)
+                    .text(tr("UI scale")),
+            );
+            // rescaling while dragging would move the slider away from the pointer:
+            if resp.dragged() {
+                return;
+            }
+            if resp.drag_stopped() || resp.changed() {
+                ui.ctx().set_zoom_factor(self.ui_scale);
+            } else {
+                self.ui_scale = ui.ctx().zoom_factor();
             }
         });
     }
+
+    /// Select several files at once for the export queue.
+    fn show_batch_button(&mut self, ui: &mut egui::Ui) {
+        let enabled = self.dest_directory.is_some();
+        let batch_btn = ui
+            .add_enabled(enabled, egui::Button::new(tr("Add files to queue...")))
+            .on_hover_text(tr(
+                "All frames of each file, with the current export settings",
+            ))
+            .on_disabled_hover_text(tr("Please select a destination directory first"));
+        if batch_btn.clicked()
+            && let Some(paths) = rfd::FileDialog::new().pick_files()
+        {
+            self.queue_files(&paths);
+        }
+    }
+
+    /// Queue all frames of `paths` with the current export settings, each into
+    /// a sub-directory of the destination named after the file.
+    fn queue_files(&mut self, paths: &[PathBuf]) {
+        let Some(dest_directory) = &self.dest_directory else {
+            self.error_state = Some(tr("Please select a destination directory first").to_owned());
+            return;
+        };
+        info!("adding {} files to the export queue", paths.len());
+        for path in paths {
+            let stem = path.file_stem().unwrap_or_default();
+            self.queue.push(
+                path.clone(),
+                dest_directory.join(stem),
+                self.export.options(),
+            );
+        }
+    }
+
+    /// The open file and the progress of a running export in the window title,
+    /// to keep an eye on it while the window is minimized.
+    fn update_window_title(&mut self, ctx: &egui::Context) {
+        let mut parts = vec![];
+        if let Some(data) = &self.input_data {
+            let name = data.source_path.file_name().unwrap_or_default();
+            parts.push(name.to_string_lossy().into_owned());
+        }
+        let export_progress = self
+            .input_data
+            .as_ref()
+            .and_then(|data| data.background_progress_nums.as_ref())
+            .map(|prog| prog.done as f32 / prog.total.max(1) as f32);
+        if let Some(progress) = export_progress.or(self.queue.progress()) {
+            parts.push(format!("{:.0}%", progress * 100.0));
+        }
+        parts.push(WINDOW_TITLE.to_owned());
+        let title = parts.join(" - ");
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+
+    /// A notification about the last export, with a shortcut to its output.
+    fn show_finished_export(&mut self, ctx: &egui::Context) {
+        let Some(finished) = &self.finished_export else {
+            return;
+        };
+        let mut close = false;
+        egui::Window::new(tr("Export finished"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, vec2(-H, -V))
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} {} {}",
+                    finished.num_frames,
+                    tr("frames exported to"),
+                    finished.dest_directory.display()
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button(tr("Open folder")).clicked() {
+                        if let Err(e) = open_directory(&finished.dest_directory) {
+                            error!("failed to open {:?}: {e}", finished.dest_directory);
+                        }
+                        close = true;
+                    }
+                    if ui.button(tr("Close")).clicked() {
+                        close = true;
+                    }
+                });
+            });
+        if close {
+            self.finished_export = None;
+        }
+    }
+
+    /// Keep the settings of the open file, for when it's opened again.
+    fn remember_file_settings(&mut self) {
+        if let Some(data) = &self.input_data {
+            self.recent
+                .set_file_settings(&data.source_path, data.file_settings());
+        }
+    }
+
+    /// Load `path` as the new input, and remember it as recently opened,
+    /// restoring the settings it had the last time.
+    fn open(&mut self, path: &Path) {
+        self.remember_file_settings();
+        self.input_data = match load_data(path) {
+            Ok(mut data) => {
+                self.recent.add_input(path);
+                if let Some(settings) = self.recent.file_settings(path) {
+                    data.restore(settings);
+                }
+                Some(data)
+            }
+            Err(err) => {
+                self.error_state = Some(format!("Error loading data: {}", error_chain(&*err)));
+                None
+            }
+        }
+    }
+
+    /// Recently opened files and destination directories, for one-click reopening.
+    fn show_recent(&mut self, ui: &mut egui::Ui) {
+        let mut open = None;
+        let mut destination = None;
+        if !self.recent.inputs.is_empty() {
+            ui.add_space(V);
+            ui.label(RichText::new(tr("Recent files")).strong());
+            for path in &self.recent.inputs {
+                if ui.link(path.to_string_lossy()).clicked() {
+                    open = Some(path.clone());
+                }
+            }
+        }
+        if !self.recent.destinations.is_empty() {
+            ui.add_space(V);
+            ui.label(RichText::new(tr("Recent destination directories")).strong());
+            for path in &self.recent.destinations {
+                let selected = self.dest_directory.as_ref() == Some(path);
+                if ui
+                    .selectable_label(selected, path.to_string_lossy())
+                    .clicked()
+                {
+                    destination = Some(path.clone());
+                }
+            }
+        }
+        if let Some(path) = destination {
+            self.recent.add_destination(&path);
+            self.dest_directory = Some(path);
+        }
+        if let Some(path) = open {
+            self.open(&path);
+        }
+    }
+    fn render_with_data(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::new(
+            egui::panel::TopBottomSide::Bottom,
+            "bottom panel view options",
+        )
+        .frame(egui::containers::Frame::new().inner_margin(vec2(H, V)))
+        .show(ctx, |ui| self.show_preview_options(ui));
+        let scaling = self.scaling;
+        if let Some(data) = &mut self.input_data {
+            let (nx, ny, _) = data.mmap.read_view().unwrap().dimensions();
+            show_profile(ctx, data, nx, ny);
+        }
+        if let Some(data) = &mut self.input_data
+            && let Some(cursor) = data.cursor
+        {
+            egui::SidePanel::right("orthogonal views")
+                .default_width(320.0)
+                .show(ctx, |ui| show_ortho_views(ui, data, cursor, scaling));
+        }
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button(tr("Load 3D MRC stack...")).clicked()
+                    && let Some(new_path) = rfd::FileDialog::new().pick_file()
+                {
+                    self.open(&new_path);
+                }
+                self.show_batch_button(ui);
+            });
+            egui::Grid::new("parameter grid")
+                .num_columns(2)
+                .striped(true)
+                .show(ui, |ui| self.show_parameters(ui));
+            self.show_preview_image(ui);
+        });
+    }
+
+    /// The slice selection and display settings of the preview, in the bottom panel.
+    fn show_preview_options(&mut self, ui: &mut egui::Ui) {
+        let Some(data) = &mut self.input_data else {
+            return;
+        };
+        ui.set_min_width(256.0);
+        show_histogram(ui, data);
+        if let Some(stats) = &data.preview_stats {
+            ui.monospace(format!(
+                "min {}  max {}  mean {:.2}  std {:.2}",
+                stats.min, stats.max, stats.mean, stats.std
+            ));
+        }
+        if let Some(angle) = data
+            .tilt_angles
+            .as_ref()
+            .and_then(|angles| angles.get(data.slice_position))
+        {
+            let angle = *angle;
+            ui.horizontal(|ui| {
+                if ui
+                    .button("◀")
+                    .on_hover_text(tr("Next lower tilt angle"))
+                    .clicked()
+                {
+                    data.step_by_angle(false);
+                }
+                ui.monospace(format!("{} {angle:+.1}°", tr("tilt angle")));
+                if ui
+                    .button("▶")
+                    .on_hover_text(tr("Next higher tilt angle"))
+                    .clicked()
+                {
+                    data.step_by_angle(true);
+                }
+            });
+        }
+        // 1-indexed position in the UI:
+        let mut slider_value = data.slice_position + 1;
+        ui.horizontal(|ui| {
+            ui.add(
+                Slider::new(&mut slider_value, 1..=data.num_frames)
+                    .text(tr("Slice preview"))
+                    .drag_value_speed(0.1),
+            );
+            ui.label(tr("jump by"));
+            let jump = DragValue::new(&mut self.jump_size).range(1..=data.num_frames);
+            ui.add(jump).on_hover_text(tr(
+                "Slices skipped with Page Up/Down; Home/End jump to the ends",
+            ));
+        });
+        let new_slice_position = slider_value - 1;
+        // slider change detected:
+        if data.slice_position != new_slice_position {
+            data.preview_outdated = true;
+        }
+        data.slice_position = new_slice_position;
+        data.show_thumbnails(ui, self.quantile, self.scaling);
+
+        let mut slider_quantile = self.quantile;
+        let q_slider = Slider::new(&mut slider_quantile, 0.0..=1.0)
+            .text(tr("Quantile"))
+            .drag_value_speed(0.0001);
+        ui.add(q_slider);
+        if self.quantile != slider_quantile {
+            data.preview_outdated = true;
+            data.display_range = None;
+        }
+        self.quantile = slider_quantile;
+
+        let mut scaling = self.scaling;
+        egui::ComboBox::from_label(tr("Display scaling"))
+            .selected_text(scaling.name())
+            .show_ui(ui, |ui| {
+                for option in DisplayScaling::ALL {
+                    ui.selectable_value(&mut scaling, option, option.name());
+                }
+            });
+        if self.scaling != scaling {
+            data.preview_outdated = true;
+        }
+        self.scaling = scaling;
+
+        let mut preview_projection = data.preview_projection;
+        let preview_name = |projection: Option<Projection>| match projection {
+            Some(projection) => format!("{} {}", projection.name(), tr("projection")),
+            None => tr("slice").to_owned(),
+        };
+        egui::ComboBox::from_label(tr("Preview"))
+            .selected_text(preview_name(preview_projection))
+            .show_ui(ui, |ui| {
+                for option in [None, Some(Projection::Max), Some(Projection::Mean)] {
+                    ui.selectable_value(&mut preview_projection, option, preview_name(option));
+                }
+            });
+        if data.preview_projection != preview_projection {
+            data.preview_outdated = true;
+        }
+        data.preview_projection = preview_projection;
+
+        let (nx, ny, _) = data.mmap.read_view().unwrap().dimensions();
+        if nx.max(ny) > PREVIEW_SIZE
+            && ui
+                .checkbox(&mut self.full_resolution, tr("Full resolution preview"))
+                .on_hover_text(tr(
+                    "Large slices are binned for display, to keep it responsive",
+                ))
+                .changed()
+        {
+            data.preview_outdated = true;
+        }
+
+        data.show_display_range(ui);
+        self.show_preview_tools(ui);
+    }
+
+    fn show_preview_tools(&mut self, ui: &mut egui::Ui) {
+        let Some(data) = &mut self.input_data else {
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label(tr("Preview tool"));
+            ui.selectable_value(&mut data.tool, PreviewTool::Cursor, tr("cursor"));
+            ui.selectable_value(&mut data.tool, PreviewTool::Crop, tr("crop region"));
+            ui.selectable_value(&mut data.tool, PreviewTool::Profile, tr("line profile"))
+                .on_hover_text(tr("Drag on the preview to plot the values along a line"));
+            ui.selectable_value(&mut data.tool, PreviewTool::Measure, tr("measure"))
+                .on_hover_text(tr("Drag on the preview to measure a distance"));
+            if data.pixel_size.is_some() {
+                ui.checkbox(&mut self.preview_scalebar, tr("scale bar"));
+            }
+            ui.add_enabled(
+                data.preview_projection.is_none(),
+                egui::Checkbox::new(&mut self.compare_processed, tr("compare with processed")),
+            )
+            .on_hover_text(tr(
+                "Show the slice as it will be exported, in a separate window",
+            ));
+            let spectrum = ui
+                .checkbox(&mut self.show_spectrum, tr("power spectrum"))
+                .on_hover_text(tr(
+                    "Show the log power spectrum, to judge astigmatism, drift and \
+                    Thon rings",
+                ));
+            if spectrum.changed() && self.show_spectrum {
+                data.preview_outdated = true;
+            }
+        });
+    }
+
+    /// The rows of the parameter grid, from the input file to the export progress.
+    fn show_parameters(&mut self, ui: &mut egui::Ui) {
+        let Some(data) = &mut self.input_data else {
+            return;
+        };
+        let (nx, ny, nz) = data.mmap.read_view().unwrap().dimensions();
+        ui.label(tr("Input path"));
+        ui.monospace(data.source_path.to_string_lossy());
+        ui.end_row();
+        ui.label(tr("Input size"));
+        ui.monospace(format!("{nz}x{ny}x{nx}"));
+        ui.end_row();
+
+        ui.separator();
+        ui.separator();
+        ui.end_row();
+
+        let dest_btn = egui::Button::new(RichText::new(tr("Destination directory...")).strong());
+        let dest_btn = dest_btn.fill(egui::Color32::from_rgb(0, 90, 230));
+
+        if ui.add(dest_btn).clicked()
+            && let Some(new_path) = rfd::FileDialog::new().pick_folder()
+        {
+            self.recent.add_destination(&new_path);
+            self.dest_directory = Some(new_path);
+        }
+        ui.end_row();
+
+        ui.label(tr("Destination directory"));
+        if let Some(dest_path) = &self.dest_directory {
+            ui.monospace(dest_path.to_string_lossy());
+        } else {
+            ui.label(RichText::new(tr("not set")).italics());
+        }
+        ui.end_row();
+
+        // the keys also move the cursor in text fields:
+        if !ui.ctx().wants_keyboard_input() {
+            ui.input(|i| data.navigate_by_keys(i, self.jump_size));
+        }
+
+        ui.separator();
+        ui.separator();
+        ui.end_row();
+
+        data.show_export_range(ui);
+        self.show_references(ui);
+        self.show_filters(ui, nx, ny);
+        self.show_output_format(ui);
+        self.show_naming(ui);
+        self.show_export_button(ui);
+        self.show_export_progress(ui);
+    }
+
+    fn show_references(&mut self, ui: &mut egui::Ui) {
+        ui.label(tr("Dark reference"));
+        ui.horizontal(|ui| {
+            if ui.button(tr("Select...")).clicked()
+                && let Some(path) = rfd::FileDialog::new().pick_file()
+            {
+                match Dark::load(&path, false, None, None, false) {
+                    Ok(dark) => self.export.dark = Some(dark),
+                    Err(e) => {
+                        self.error_state = Some(format!(
+                            "Error loading the dark reference: {}",
+                            error_chain(&e)
+                        ))
+                    }
+                }
+            }
+            if let Some(dark) = &mut self.export.dark {
+                ui.monospace(dark.path.to_string_lossy());
+                ui.checkbox(&mut dark.clamp, tr("clamp to zero"))
+                    .on_hover_text(tr("Set negative values after the subtraction to zero"));
+                if ui.button(tr("Remove")).clicked() {
+                    self.export.dark = None;
+                }
+            }
+        });
+        ui.end_row();
+
+        ui.label(tr("Gain reference"));
+        ui.horizontal(|ui| {
+            if ui.button(tr("Select...")).clicked()
+                && let Some(path) = rfd::FileDialog::new().pick_file()
+            {
+                match Gain::load(&path, Default::default(), None, None, false) {
+                    Ok(gain) => self.export.gain = Some(gain),
+                    Err(e) => {
+                        self.error_state = Some(format!(
+                            "Error loading the gain reference: {}",
+                            error_chain(&e)
+                        ))
+                    }
+                }
+            }
+            if let Some(gain) = &mut self.export.gain {
+                ui.monospace(gain.path.to_string_lossy());
+                value_enum_combo(ui, "gain mode", &mut gain.mode);
+                if ui.button(tr("Remove")).clicked() {
+                    self.export.gain = None;
+                }
+            }
+        });
+        ui.end_row();
+    }
+
+    /// The processing steps, in the order they are applied.
+    fn show_filters(&mut self, ui: &mut egui::Ui, nx: usize, ny: usize) {
+        ui.label(tr("Fix outliers"));
+        ui.horizontal(|ui| {
+            let mut enabled = self.export.fix_outliers.is_some();
+            ui.checkbox(&mut enabled, "");
+            if !enabled {
+                self.export.fix_outliers = None;
+                return;
+            }
+            let sigma = self.export.fix_outliers.get_or_insert(5.0);
+            let sigma = DragValue::new(sigma).range(0.5..=100.0).speed(0.1);
+            ui.add(sigma.suffix(" σ")).on_hover_text(tr(
+                "Replace pixels deviating from the median of their neighbors by \
+                more than this many standard deviations",
+            ));
+        });
+        ui.end_row();
+
+        ui.label(tr("Fix temporal outliers"));
+        ui.horizontal(|ui| {
+            let mut enabled = self.export.fix_temporal_outliers.is_some();
+            ui.checkbox(&mut enabled, "");
+            if !enabled {
+                self.export.fix_temporal_outliers = None;
+                return;
+            }
+            let sigma = self.export.fix_temporal_outliers.get_or_insert(5.0);
+            let sigma = DragValue::new(sigma).range(0.5..=100.0).speed(0.1);
+            ui.add(sigma.suffix(" σ")).on_hover_text(tr(
+                "Replace pixels much brighter than in the adjacent slices, \
+                like cosmic ray hits",
+            ));
+        });
+        ui.end_row();
+
+        ui.label(tr("Binning"));
+        ui.horizontal(|ui| {
+            let mut enabled = self.export.bin.is_some();
+            ui.checkbox(&mut enabled, "");
+            if !enabled {
+                self.export.bin = None;
+                return;
+            }
+            let factor = self.export.bin.get_or_insert(2);
+            let factor = DragValue::new(factor).range(2..=nx.min(ny).max(2));
+            ui.add(factor.prefix("N = "))
+                .on_hover_text(tr("Combine NxN pixels, after cropping"));
+            value_enum_combo(ui, "bin mode", &mut self.export.bin_mode);
+        });
+        ui.end_row();
+
+        ui.label(tr("Fourier cropping"));
+        ui.horizontal(|ui| {
+            let mut enabled = self.export.fourier_crop.is_some();
+            ui.checkbox(&mut enabled, "");
+            if !enabled {
+                self.export.fourier_crop = None;
+                return;
+            }
+            let factor = self.export.fourier_crop.get_or_insert(2.0);
+            let factor = DragValue::new(factor).range(1.0..=16.0).speed(0.05);
+            ui.add(factor.prefix(format!("{} ", tr("factor"))))
+                .on_hover_text(tr("Downsample without aliasing by cropping the Fourier \
+                    transform, after binning"));
+        });
+        ui.end_row();
+
+        ui.label(tr("Background ramp"));
+        ui.checkbox(&mut self.export.subtract_plane, tr("subtract plane"))
+            .on_hover_text(tr(
+                "Remove a linear intensity gradient fitted to each slice",
+            ));
+        ui.end_row();
+
+        ui.label(tr("Dose weighting"));
+        ui.horizontal(|ui| {
+            let mut enabled = self.export.dose_weighting.is_some();
+            ui.checkbox(&mut enabled, "").on_hover_text(tr(
+                "Damp the high frequencies of later frames by their exposure",
+            ));
+            if !enabled {
+                self.export.dose_weighting = None;
+                return;
+            }
+            let weighting = self.export.dose_weighting.get_or_insert_default();
+            let mut given = weighting.dose_per_frame.is_some();
+            ui.checkbox(&mut given, tr("dose per frame"))
+                .on_hover_text(tr(
+                    "Otherwise taken from the .mdoc file or the extended header",
+                ));
+            if !given {
+                weighting.dose_per_frame = None;
+                return;
+            }
+            let dose = weighting.dose_per_frame.get_or_insert(1.0);
+            let dose = DragValue::new(dose).range(0.01..=100.0).speed(0.01);
+            ui.add(dose.suffix(" e/Å²"));
+        });
+        ui.end_row();
+
+        ui.label(tr("Drift correction"));
+        ui.horizontal(|ui| {
+            let mut enabled = self.export.align.is_some();
+            ui.checkbox(&mut enabled, "").on_hover_text(tr(
+                "Shift the slices onto each other, found by cross-correlation",
+            ));
+            if !enabled {
+                self.export.align = None;
+                return;
+            }
+            let align = self.export.align.get_or_insert(Alignment {
+                reference: AlignReference::default(),
+                max_shift: DEFAULT_MAX_SHIFT,
+            });
+            ui.label(tr("reference"));
+            value_enum_combo(ui, "align reference", &mut align.reference);
+            ui.label(tr("max. shift"));
+            let max_shift = &mut align.max_shift;
+            let max_shift = DragValue::new(max_shift).range(1..=nx.max(ny));
+            ui.add(max_shift.suffix(" px"));
+        });
+        ui.end_row();
+    }
+
+    fn show_output_format(&mut self, ui: &mut egui::Ui) {
+        ui.label(tr("Byte order"));
+        let endianess_name = |endianess: &ArgEndianess| match endianess {
+            ArgEndianess::Big => "big endian",
+            ArgEndianess::Native if cfg!(target_endian = "little") => "native (little endian)",
+            ArgEndianess::Native => "native (big endian)",
+        };
+        egui::ComboBox::from_id_salt("byte order")
+            .selected_text(endianess_name(&self.export.endianess))
+            .show_ui(ui, |ui| {
+                for option in ArgEndianess::value_variants() {
+                    ui.selectable_value(
+                        &mut self.export.endianess,
+                        option.clone(),
+                        endianess_name(option),
+                    );
+                }
+            })
+            .response
+            .on_hover_text(tr("Native byte order is faster to write and to read back"));
+        ui.end_row();
+
+        ui.label(tr("Output"));
+        let output_name = |projection: Option<Projection>| match projection {
+            Some(projection) => format!("{} projection", projection.name()),
+            None => tr("each frame").to_owned(),
+        };
+        egui::ComboBox::from_id_salt("output")
+            .selected_text(output_name(self.export.projection))
+            .show_ui(ui, |ui| {
+                let options = std::iter::once(None)
+                    .chain(Projection::value_variants().iter().copied().map(Some));
+                for option in options {
+                    ui.selectable_value(&mut self.export.projection, option, output_name(option));
+                }
+            });
+        ui.end_row();
+
+        ui.label(tr("Sample type"));
+        let type_name = |output_type: Option<SampleType>| {
+            output_type
+                .as_ref()
+                .map_or(tr("same as input").to_owned(), value_name)
+        };
+        egui::ComboBox::from_id_salt("sample type")
+            .selected_text(type_name(self.export.output_type))
+            .show_ui(ui, |ui| {
+                let options = std::iter::once(None)
+                    .chain(SampleType::value_variants().iter().copied().map(Some));
+                for option in options {
+                    ui.selectable_value(&mut self.export.output_type, option, type_name(option));
+                }
+            });
+        ui.end_row();
+
+        ui.label(tr("Normalization"));
+        ui.horizontal(|ui| {
+            let normalize = &mut self.export.normalize;
+            let percentile = match normalize {
+                Normalize::Percentile(p) => *p,
+                _ => 1.0,
+            };
+            egui::ComboBox::from_id_salt("normalize")
+                .selected_text(normalize.name())
+                .show_ui(ui, |ui| {
+                    for option in [
+                        Normalize::None,
+                        Normalize::MinMax,
+                        Normalize::Percentile(percentile),
+                        Normalize::ZScore,
+                    ] {
+                        ui.selectable_value(normalize, option, option.name());
+                    }
+                });
+            if let Normalize::Percentile(p) = normalize {
+                ui.add(DragValue::new(p).range(0.0..=49.9).speed(0.1).suffix(" %"));
+            }
+            if *normalize != Normalize::None {
+                ui.label(tr("per"));
+                let stats = &mut self.export.normalize_stats;
+                value_enum_combo(ui, "stats scope", stats);
+            }
+        });
+        ui.end_row();
+
+        ui.label(tr("Histogram equalization"));
+        ui.horizontal(|ui| {
+            let equalize = &mut self.export.equalize;
+            let equalize_name =
+                |e: &Option<Equalize>| e.as_ref().map_or_else(|| "none".to_owned(), value_name);
+            egui::ComboBox::from_id_salt("equalize")
+                .selected_text(equalize_name(equalize))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(equalize, None, "none");
+                    for option in Equalize::value_variants() {
+                        let name = value_name(option);
+                        ui.selectable_value(equalize, Some(*option), name);
+                    }
+                })
+                .response
+                .on_hover_text(tr("Flatten the histogram instead of normalizing, for low \
+                    contrast data in 8-bit exports"));
+            // CLAHE works on tiles of each slice, so it's always per slice:
+            if *equalize == Some(Equalize::Global) {
+                ui.label(tr("per"));
+                let stats = &mut self.export.normalize_stats;
+                value_enum_combo(ui, "equalize stats scope", stats);
+            }
+        });
+        ui.end_row();
+
+        ui.label(tr("Dithering"));
+        let dither = &mut self.export.dither;
+        let dither_name =
+            |d: &Option<Dither>| d.as_ref().map_or_else(|| "none".to_owned(), value_name);
+        egui::ComboBox::from_id_salt("dither")
+            .selected_text(dither_name(dither))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(dither, None, "none");
+                for option in Dither::value_variants() {
+                    let name = value_name(option);
+                    ui.selectable_value(dither, Some(*option), name);
+                }
+            })
+            .response
+            .on_hover_text(tr(
+                "Avoid banding when rounding to integers, mostly for 8-bit exports",
+            ));
+        ui.end_row();
+    }
+
+    fn show_naming(&mut self, ui: &mut egui::Ui) {
+        ui.label(tr("File names"));
+        ui.horizontal(|ui| {
+            let naming = &mut self.export.naming;
+            let template =
+                egui::TextEdit::singleline(&mut self.export.template).desired_width(120.0);
+            let template_resp = ui.add(template).on_hover_text(tr(
+                "{index} is replaced by the number of each written frame",
+            ));
+            if template_resp.changed() {
+                match self.export.template.parse() {
+                    Ok(template) => naming.template = template,
+                    Err(e) => debug!("{e}"),
+                }
+            }
+            if naming.template.to_string() != self.export.template {
+                ui.colored_label(ui.visuals().error_fg_color, tr("needs {index}"))
+                    .on_hover_text(tr("path separators are not allowed either"));
+            }
+            ui.label(tr("suffix"));
+            let suffix = egui::TextEdit::singleline(&mut naming.suffix);
+            ui.add(suffix.desired_width(80.0));
+            value_enum_combo(ui, "extension", &mut naming.extension);
+        });
+        ui.end_row();
+    }
+
+    /// The export and queue buttons, with the files and the size they will write.
+    fn show_export_button(&mut self, ui: &mut egui::Ui) {
+        let Some(data) = &mut self.input_data else {
+            return;
+        };
+        let (nx, ny, _) = data.mmap.read_view().unwrap().dimensions();
+
+        ui.label(tr("Clip to display range"));
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut data.clip_to_display_range, "");
+            let (vmin, vmax) = data.display_range.unwrap_or(data.auto_range);
+            ui.monospace(format!("{vmin:.1} to {vmax:.1}"));
+        });
+        ui.end_row();
+
+        let export_enabled = self.dest_directory.is_some() && data.background_progress.is_none();
+        ui.add_enabled_ui(export_enabled, |ui| {
+            let export_btn = egui::Button::new(RichText::new(tr("Export to tiff")).strong());
+            let export_btn = export_btn.fill(egui::Color32::from_rgb(0, 90, 230));
+            let mut export_btn_resp = ui.add(export_btn);
+            if self.dest_directory.is_none() {
+                export_btn_resp = export_btn_resp
+                    .on_hover_text(tr("Please select a destination directory first"));
+            }
+            if export_btn_resp.clicked()
+                && let Some(dest_directory) = &self.dest_directory
+            {
+                data.start_export(dest_directory, &self.export, &self.multi);
+                self.finished_export = None;
+            }
+        });
+        ui.add_enabled_ui(self.dest_directory.is_some(), |ui| {
+            let queue_btn = ui
+                .button(tr("Add to queue"))
+                .on_hover_text(tr("Export into a sub-directory named after the file"));
+            if queue_btn.clicked()
+                && let Some(dest_directory) = &self.dest_directory
+            {
+                let stem = data.source_path.file_stem().unwrap_or_default();
+                self.queue.push(
+                    data.source_path.clone(),
+                    dest_directory.join(stem),
+                    data.export_options(&self.export),
+                );
+            }
+        });
+        ui.end_row();
+
+        ui.label("");
+        // with the tilt angles, if they are added:
+        let naming = data.export_options(&self.export).naming;
+        ui.label(match self.export.projection {
+            Some(projection) => format!(
+                "Note: writes a single file {}",
+                naming.projection_filename(projection)
+            ),
+            None => format!(
+                "Note: output frames will be written as {} to {}",
+                naming.slice_filename(1),
+                naming.slice_filename(data.num_export_frames())
+            ),
+        });
+        ui.end_row();
+
+        ui.label(tr("Output size"));
+        ui.horizontal(|ui| {
+            let num_frames = data.num_export_frames();
+            let size = data
+                .export_options(&self.export)
+                .estimated_output_bytes(nx, ny, num_frames);
+            ui.monospace(format!("about {}", HumanBytes(size)));
+            // the destination may not exist yet, like for queued files:
+            let available = self
+                .dest_directory
+                .as_deref()
+                .and_then(|dest| dest.ancestors().find_map(available_space));
+            if let Some(available) = available
+                && available < size
+            {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!("only {} free on the destination", HumanBytes(available)),
+                );
+            }
+        });
+        ui.end_row();
+    }
+
+    /// Picks up the messages of a running export, with a button to cancel it.
+    fn show_export_progress(&mut self, ui: &mut egui::Ui) {
+        let Some(data) = &mut self.input_data else {
+            return;
+        };
+        if let Some(recv) = &data.background_progress {
+            'multi_messages: loop {
+                match recv.recv_timeout(Duration::from_millis(4)) {
+                    Ok(ProgressMessage::InProgress {
+                        num_done, total, ..
+                    }) => {
+                        data.background_progress_nums = Some(BgProgress {
+                            done: num_done,
+                            total,
+                        });
+                    }
+                    Ok(ProgressMessage::Done { total }) => {
+                        self.finished_export =
+                            data.export_destination
+                                .take()
+                                .map(|dest_directory| FinishedExport {
+                                    dest_directory,
+                                    num_frames: total,
+                                });
+                        data.background_progress = None;
+                        data.background_progress_nums = None;
+                        data.cancel_export = None;
+                        break 'multi_messages;
+                    }
+                    Ok(ProgressMessage::Error { msg }) => {
+                        let cancelled = data
+                            .cancel_export
+                            .as_ref()
+                            .is_some_and(CancelToken::is_cancelled);
+                        if cancelled {
+                            info!("export cancelled");
+                        } else {
+                            let err = format!("Error while converting: {msg}");
+                            error!("{err}");
+                            self.error_state = Some(err);
+                        }
+                        data.background_progress = None;
+                        data.background_progress_nums = None;
+                        data.cancel_export = None;
+                        break 'multi_messages;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        // this is fine.
+                        break 'multi_messages;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        error!("background thread disconnected");
+                        // this should only happen if the thread errs out, but that should also
+                        // give us a proper ProgressMessage::Error, so don't try to show this
+                        // in the GUI.
+                        data.background_progress = None;
+                        data.background_progress_nums = None;
+                        data.cancel_export = None;
+                        break 'multi_messages;
+                    }
+                }
+            }
+            if let Some(cancel) = &data.cancel_export {
+                ui.label("");
+                ui.horizontal(|ui| {
+                    // the button first, the progress bar fills the rest of the row:
+                    let cancelling = cancel.is_cancelled();
+                    let cancel_btn = egui::Button::new(tr("Cancel"));
+                    if ui.add_enabled(!cancelling, cancel_btn).clicked() {
+                        info!("cancelling the export...");
+                        cancel.cancel();
+                    }
+                    if let Some(prog) = &data.background_progress_nums {
+                        ui.add(egui::ProgressBar::new(prog.done as f32 / prog.total as f32));
+                    }
+                });
+                ui.end_row();
+            }
+            // if we expect some progress, we need to redraw:
+            ui.ctx().request_repaint_after(Duration::from_millis(16));
+        }
+    }
+
+    /// The header of the file, and the preview with the selected tool.
+    fn show_preview_image(&mut self, ui: &mut egui::Ui) {
+        let Some(data) = &mut self.input_data else {
+            return;
+        };
+        let view = data.mmap.read_view().unwrap();
+        let (nx, ny, _nz) = view.dimensions();
+        egui::CollapsingHeader::new(tr("MRC header")).show(ui, |ui| show_header(ui, view.header()));
+
+        let processing = self
+            .compare_processed
+            .then(|| data.export_options(&self.export).processing);
+        let compared = processing.as_ref().map(comparison_key);
+        if compared != data.compared {
+            data.preview_outdated = true;
+        }
+        if data.preview_outdated && data.pending_preview.is_none() {
+            data.compared = compared;
+            // unless the slice doesn't fit into a texture, the GPU
+            // applies the display range and scaling, so the request
+            // doesn't depend on them:
+            #[cfg(feature = "gpu")]
+            let gpu = self
+                .gpu
+                .as_ref()
+                .is_some_and(|gpu| !self.full_resolution || nx.max(ny) <= gpu.max_size());
+            #[cfg(not(feature = "gpu"))]
+            let gpu = false;
+            let request = PreviewRequest {
+                source_path: data.source_path.clone(),
+                slice_position: data.slice_position,
+                projection: data
+                    .preview_projection
+                    .map(|projection| (projection, data.export_start..=data.export_end)),
+                quantile: self.quantile,
+                display_range: if gpu { None } else { data.display_range },
+                scaling: if gpu {
+                    DisplayScaling::Linear
+                } else {
+                    self.scaling
+                },
+                full_resolution: self.full_resolution,
+                processing,
+                power_spectrum: self.show_spectrum,
+                #[cfg(feature = "gpu")]
+                gpu,
+            };
+            let key = request.cache_key();
+            if let Some(rendered) = data.preview_cache.get(&key) {
+                data.show_preview(rendered);
+            } else {
+                data.pending_preview = Some(preview::spawn(request, ui.ctx()));
+                data.pending_key = key;
+            }
+            data.preview_outdated = false;
+        }
+        if let Some(pending) = &data.pending_preview {
+            match pending.try_recv() {
+                Ok(Ok(rendered)) => {
+                    let key = std::mem::take(&mut data.pending_key);
+                    data.preview_cache.insert(key, rendered.clone());
+                    data.show_preview(rendered);
+                    data.pending_preview = None;
+                }
+                Ok(Err(e)) => {
+                    error!("failed to render the preview: {e}");
+                    data.pending_preview = None;
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => data.pending_preview = None,
+            }
+        }
+        if self.compare_processed
+            && let Some(processed) = &data.processed_preview
+        {
+            egui::Window::new(tr("Processed preview"))
+                .open(&mut self.compare_processed)
+                .show(ui.ctx(), |ui| show_processed(ui, processed));
+        }
+        if self.show_spectrum
+            && let Some(spectrum) = &data.spectrum_preview
+        {
+            egui::Window::new(tr("Power spectrum"))
+                .open(&mut self.show_spectrum)
+                .show(ui.ctx(), |ui| show_spectrum(ui, spectrum, data.pixel_size));
+        }
+        let Some(texture) = &data.texture else {
+            ui.spinner();
+            return;
+        };
+        let texture = match texture {
+            PreviewTexture::Cpu(texture) => texture.id(),
+            #[cfg(feature = "gpu")]
+            PreviewTexture::Gpu {
+                values,
+                width,
+                height,
+            } => {
+                let gpu = self.gpu.as_mut().expect("only requested with a GPU");
+                let range = data.display_range.unwrap_or(data.auto_range);
+                gpu.texture(values, *width, *height, range, self.scaling)
+            }
+        };
+        let tool = data.tool;
+        let plot = Plot::new("preview")
+            .data_aspect(1.0)
+            .allow_drag(tool == PreviewTool::Cursor)
+            .allow_boxed_zoom(tool == PreviewTool::Cursor)
+            // the wheel changes the slice instead:
+            .allow_scroll(false);
+        let shown = plot.show(ui, |plot_ui| {
+            let center_position = PlotPoint::new(0.5, 0.5);
+            let image = PlotImage::new(
+                "preview_image",
+                texture,
+                center_position,
+                image_size(nx, ny),
+            );
+            plot_ui.image(image);
+            if let Some((x, y)) = data.cursor {
+                let pos = to_plot_point(x, y, nx, ny);
+                plot_ui.vline(VLine::new("cursor x", pos.x).color(CURSOR_COLOR));
+                plot_ui.hline(HLine::new("cursor y", pos.y).color(CURSOR_COLOR));
+            }
+            if let Some(crop) = &data.crop {
+                show_crop(plot_ui, crop, nx, ny);
+            }
+            if let Some(line) = data.profile {
+                let points = line_points(line, nx, ny);
+                plot_ui.line(Line::new("line profile", points).color(PROFILE_COLOR));
+            }
+            if let Some(line) = data.measurement {
+                show_measurement(plot_ui, line, data.pixel_size, nx, ny);
+            }
+            if self.preview_scalebar
+                && let Some(pixel_size) = data.pixel_size
+            {
+                show_scalebar(plot_ui, pixel_size, ny);
+            }
+            plot_ui.pointer_coordinate()
+        });
+        let pointer = shown.inner;
+        if data.pending_preview.is_some() || data.preview_outdated {
+            // the previous image stays visible until the new one is ready:
+            let corner = shown.response.rect.right_top() + vec2(-32.0, 8.0);
+            let rect = egui::Rect::from_min_size(corner, vec2(24.0, 24.0));
+            ui.put(rect, egui::Spinner::new());
+        }
+        if shown.response.hovered() {
+            ui.input(|i| data.scroll_slices(i, self.jump_size));
+        }
+        match tool {
+            PreviewTool::Crop => drag_crop(data, &shown.response, pointer, nx, ny),
+            PreviewTool::Profile => drag_line(&mut data.profile, &shown.response, pointer, nx, ny),
+            PreviewTool::Measure => {
+                drag_line(&mut data.measurement, &shown.response, pointer, nx, ny)
+            }
+            PreviewTool::Cursor => {
+                if shown.response.clicked()
+                    && let Some(cursor) = pointer.and_then(|pos| from_plot_point(pos, nx, ny))
+                {
+                    // selects the position of the orthogonal views:
+                    data.cursor = Some(cursor);
+                }
+            }
+        }
+        if let Some((x, y)) = pointer.and_then(|pos| from_plot_point(pos, nx, ny))
+            && let Some(value) = data.preview_values.get(y * nx + x)
+        {
+            let mut readout = format!("x = {x}, y = {y}\nvalue = {value}");
+            if let Some(pixel_size) = data.pixel_size {
+                readout += &format!(
+                    "\nposition = {}, {}",
+                    format_length(x as f32 * pixel_size),
+                    format_length(y as f32 * pixel_size)
+                );
+            }
+            shown.response.on_hover_text_at_pointer(readout);
+        }
+    }
+}
+
+/// Draw `line` by dragging from its start to its end.
+fn drag_line(
+    line: &mut Option<PreviewLine>,
+    response: &egui::Response,
+    pointer: Option<PlotPoint>,
+    width: usize,
+    height: usize,
+) {
+    let Some(pos) = pointer else {
+        return;
+    };
+    let (x, y) = to_pixel(pos, width, height);
+    let (x, y) = (x.clamp(0.0, width as f64), y.clamp(0.0, height as f64));
+    if response.drag_started() {
+        *line = Some(((x, y), (x, y)));
+    } else if response.dragged()
+        && let Some((_, end)) = line
+    {
+        *end = (x, y);
+    }
+}
+
+fn line_points((start, end): PreviewLine, width: usize, height: usize) -> Vec<[f64; 2]> {
+    [start, end]
+        .into_iter()
+        .map(|(x, y)| {
+            let pos = edge_to_plot(x, y, width, height);
+            [pos.x, pos.y]
+        })
+        .collect()
+}
+
+/// The measured line, labeled with its length in pixels and, if the pixel
+/// size is known, in physical units.
+fn show_measurement(
+    plot_ui: &mut PlotUi,
+    line: PreviewLine,
+    pixel_size: Option<f32>,
+    width: usize,
+    height: usize,
+) {
+    let ((x0, y0), (x1, y1)) = line;
+    let length = (x1 - x0).hypot(y1 - y0);
+    let mut label = format!("{length:.1} px");
+    if let Some(pixel_size) = pixel_size {
+        label += &format!(" ({})", format_length(length as f32 * pixel_size));
+    }
+    let points = line_points(line, width, height);
+    let center = PlotPoint::new(
+        (points[0][0] + points[1][0]) / 2.0,
+        (points[0][1] + points[1][1]) / 2.0,
+    );
+    plot_ui.line(Line::new("measurement", points.clone()).color(MEASURE_COLOR));
+    plot_ui.points(
+        Points::new("measurement ends", points)
+            .radius(3.0)
+            .color(MEASURE_COLOR),
+    );
+    plot_ui.text(
+        Text::new("measurement length", center, label)
+            .color(MEASURE_COLOR)
+            .anchor(egui::Align2::LEFT_BOTTOM),
+    );
+}
+
+/// What the processed preview depends on, besides the slice and display
+/// settings; the references only show their path and mode in the `Debug` output.
+fn comparison_key(processing: &Processing) -> String {
+    format!("{processing:?}")
+}
+
+/// The slice as processed for the export; stack-wide normalization is
+/// approximated by normalizing the slice on its own.
+fn show_processed(ui: &mut egui::Ui, processed: &ProcessedPreview) {
+    ui.label(format!("{}x{} pixels", processed.width, processed.height));
+    Plot::new("processed preview")
+        .data_aspect(1.0)
+        .show(ui, |plot_ui| {
+            plot_ui.image(PlotImage::new(
+                "processed preview image",
+                &processed.texture,
+                PlotPoint::new(0.5, 0.5),
+                image_size(processed.width, processed.height),
+            ));
+        });
+}
+
+/// The log power spectrum, with the zero frequency in the center.
+fn show_spectrum(ui: &mut egui::Ui, spectrum: &ProcessedPreview, pixel_size: Option<f32>) {
+    let mut label = format!("{}x{} pixels", spectrum.width, spectrum.height);
+    if let Some(pixel_size) = pixel_size {
+        let nyquist = format_length(2.0 * pixel_size);
+        label += &format!(", {} {nyquist}", tr("Nyquist at the edges:"));
+    }
+    ui.label(label);
+    Plot::new("power spectrum")
+        .data_aspect(1.0)
+        .show(ui, |plot_ui| {
+            plot_ui.image(PlotImage::new(
+                "power spectrum image",
+                &spectrum.texture,
+                PlotPoint::new(0.5, 0.5),
+                image_size(spectrum.width, spectrum.height),
+            ));
+        });
+}
+
+/// A scale bar in the bottom right corner of the visible part of the preview,
+/// about a fifth of its width, rounded to 1, 2 or 5 times a power of ten.
+fn show_scalebar(plot_ui: &mut PlotUi, pixel_size: f32, height: usize) {
+    let bounds = plot_ui.plot_bounds();
+    // the image is one plot unit high:
+    let length_per_unit = pixel_size as f64 * height as f64;
+    let target = bounds.width() / 5.0 * length_per_unit;
+    let magnitude = 10f64.powf(target.log10().floor());
+    let length = [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|&length| length <= target)
+        .unwrap_or(magnitude);
+    let bar_width = length / length_per_unit;
+    let right = bounds.max()[0] - bounds.width() * 0.05;
+    let y = bounds.min()[1] + bounds.height() * 0.05;
+    let points = vec![[right - bar_width, y], [right, y]];
+    plot_ui.line(
+        Line::new("scale bar", points)
+            .color(SCALEBAR_COLOR)
+            .width(4.0),
+    );
+    plot_ui.text(
+        Text::new(
+            "scale bar length",
+            PlotPoint::new(right - bar_width / 2.0, y),
+            format_length(length as f32),
+        )
+        .color(SCALEBAR_COLOR)
+        .anchor(egui::Align2::CENTER_BOTTOM),
+    );
+}
+
+/// Values along the line from `start` to `end`, in pixel coordinates, sampled
+/// once per pixel of length with bilinear interpolation. Returns pairs of the
+/// distance from `start`, in pixels, and the value.
+fn line_profile(
+    values: &[f32],
+    width: usize,
+    height: usize,
+    start: (f64, f64),
+    end: (f64, f64),
+) -> Vec<[f64; 2]> {
+    let length = (end.0 - start.0).hypot(end.1 - start.1);
+    let num_samples = length.ceil() as usize + 1;
+    let value_at = |x: usize, y: usize| values[y * width + x] as f64;
+    (0..num_samples)
+        .map(|idx| {
+            let t = if num_samples > 1 {
+                idx as f64 / (num_samples - 1) as f64
+            } else {
+                0.0
+            };
+            // relative to the pixel centers:
+            let x = (start.0 + t * (end.0 - start.0) - 0.5).clamp(0.0, (width - 1) as f64);
+            let y = (start.1 + t * (end.1 - start.1) - 0.5).clamp(0.0, (height - 1) as f64);
+            let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+            let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+            let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+            let top = value_at(x0, y0) * (1.0 - fx) + value_at(x1, y0) * fx;
+            let bottom = value_at(x0, y1) * (1.0 - fx) + value_at(x1, y1) * fx;
+            [t * length, top * (1.0 - fy) + bottom * fy]
+        })
+        .collect()
+}
+
+/// Plot of the intensities along the line drawn on the preview, in its own window.
+fn show_profile(ctx: &egui::Context, data: &mut WithInputData, width: usize, height: usize) {
+    let Some((start, end)) = data.profile else {
+        return;
+    };
+    if data.preview_values.len() != width * height {
+        return;
+    }
+    let (scale, unit) = match data.pixel_size {
+        Some(pixel_size) => (pixel_size as f64 / 10.0, "nm"),
+        None => (1.0, "px"),
+    };
+    let points: Vec<[f64; 2]> = line_profile(&data.preview_values, width, height, start, end)
+        .into_iter()
+        .map(|[distance, value]| [distance * scale, value])
+        .collect();
+    let mut open = true;
+    egui::Window::new(tr("Line profile"))
+        .open(&mut open)
+        .show(ctx, |ui| {
+            Plot::new("line profile plot")
+                .height(200.0)
+                .x_axis_label(format!("distance [{unit}]"))
+                .y_axis_label("value")
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new("profile", points).color(PROFILE_COLOR));
+                });
+        });
+    if !open {
+        data.profile = None;
+    }
+}
+
+/// All fields of the parsed header, with the labels and a summary of the
+/// extended header.
+fn show_header(ui: &mut egui::Ui, header: &mrc::Header) {
+    let mode = match mrc::Mode::from_i32(header.mode) {
+        Some(mode) => format!("{} ({mode:?})", header.mode),
+        None => format!("{} (unknown)", header.mode),
+    };
+    // EXTTYP and NVERSION are stored in the words 27 and 28, inside of `extra`:
+    let ext_type = String::from_utf8_lossy(&header.extra[8..12])
+        .trim_matches('\0')
+        .to_owned();
+    let version = i32::from_le_bytes(header.extra[12..16].try_into().expect("4 bytes"));
+    let rows = [
+        (
+            "Size (nx, ny, nz)",
+            format!("{}, {}, {}", header.nx, header.ny, header.nz),
+        ),
+        ("Mode", mode),
+        (
+            "Start (nxstart, nystart, nzstart)",
+            format!("{}, {}, {}", header.nxstart, header.nystart, header.nzstart),
+        ),
+        (
+            "Sampling (mx, my, mz)",
+            format!("{}, {}, {}", header.mx, header.my, header.mz),
+        ),
+        (
+            "Cell size (Å)",
+            format!("{}, {}, {}", header.xlen, header.ylen, header.zlen),
+        ),
+        (
+            "Cell angles (°)",
+            format!("{}, {}, {}", header.alpha, header.beta, header.gamma),
+        ),
+        (
+            "Axis order (mapc, mapr, maps)",
+            format!("{}, {}, {}", header.mapc, header.mapr, header.maps),
+        ),
+        (
+            "Origin (Å)",
+            format!(
+                "{}, {}, {}",
+                header.origin[0], header.origin[1], header.origin[2]
+            ),
+        ),
+        (
+            "dmin / dmax / dmean",
+            format!("{} / {} / {}", header.dmin, header.dmax, header.dmean),
+        ),
+        ("RMS", header.rms.to_string()),
+        ("Space group", header.ispg.to_string()),
+        ("Format version", version.to_string()),
+        (
+            "Extended header",
+            if header.nsymbt > 0 {
+                format!("{} bytes, type {ext_type:?}", header.nsymbt)
+            } else {
+                "none".to_owned()
+            },
+        ),
+        ("Machine stamp", format!("{:02x?}", header.machst)),
+    ];
+    egui::Grid::new("header grid")
+        .num_columns(2)
+        .striped(true)
+        .show(ui, |ui| {
+            for (name, value) in rows {
+                ui.label(name);
+                ui.monospace(value);
+                ui.end_row();
+            }
+            let num_labels = header.nlabl.clamp(0, 10) as usize;
+            for (idx, label) in header.label.chunks_exact(80).take(num_labels).enumerate() {
+                ui.label(format!("Label {}", idx + 1));
+                ui.monospace(String::from_utf8_lossy(label).trim_end_matches(['\0', ' ']));
+                ui.end_row();
+            }
+        });
+}
+
+/// Show `path` in the system's file manager.
+fn open_directory(path: &Path) -> std::io::Result<()> {
+    let program = if cfg!(target_os = "windows") {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    std::process::Command::new(program).arg(path).spawn()?;
+    Ok(())
+}
+
+/// A length given in Ångström, in nm or µm if it is at least 1 nm or 1 µm.
+fn format_length(length: f32) -> String {
+    if length >= 10_000.0 {
+        format!("{:.2} µm", length / 10_000.0)
+    } else if length >= 10.0 {
+        format!("{:.1} nm", length / 10.0)
+    } else {
+        format!("{length:.1} Å")
+    }
+}
+
+const CURSOR_COLOR: egui::Color32 = egui::Color32::from_rgb(230, 140, 0);
+const CROP_COLOR: egui::Color32 = egui::Color32::from_rgb(0, 200, 120);
+const PROFILE_COLOR: egui::Color32 = egui::Color32::from_rgb(230, 60, 60);
+const MEASURE_COLOR: egui::Color32 = egui::Color32::from_rgb(230, 230, 0);
+const SCALEBAR_COLOR: egui::Color32 = egui::Color32::WHITE;
+
+/// Size in plot coordinates of an image of `width`x`height` pixels, which is
+/// placed around (0.5, 0.5) with a height of one.
+fn image_size(width: usize, height: usize) -> egui::Vec2 {
+    vec2(width as f32 / height as f32, 1.0)
+}
+
+/// Plot coordinates of the fractional pixel position (`x`, `y`), with (0, 0)
+/// being the top left corner of the image
+fn edge_to_plot(x: f64, y: f64, width: usize, height: usize) -> PlotPoint {
+    let size = image_size(width, height);
+    let left = 0.5 - size.x as f64 / 2.0;
+    PlotPoint::new(
+        left + x / width as f64 * size.x as f64,
+        1.0 - y / height as f64,
+    )
+}
+
+/// Plot coordinates of the center of pixel (`x`, `y`)
+fn to_plot_point(x: usize, y: usize, width: usize, height: usize) -> PlotPoint {
+    edge_to_plot(x as f64 + 0.5, y as f64 + 0.5, width, height)
+}
+
+/// The fractional pixel position at plot coordinates `pos`, the inverse of `edge_to_plot`
+fn to_pixel(pos: PlotPoint, width: usize, height: usize) -> (f64, f64) {
+    let size = image_size(width, height);
+    let left = 0.5 - size.x as f64 / 2.0;
+    (
+        (pos.x - left) / size.x as f64 * width as f64,
+        (1.0 - pos.y) * height as f64,
+    )
+}
+
+/// The pixel at plot coordinates `pos`, if it is inside of the image
+fn from_plot_point(pos: PlotPoint, width: usize, height: usize) -> Option<(usize, usize)> {
+    let (x, y) = to_pixel(pos, width, height);
+    (x >= 0.0 && y >= 0.0 && x < width as f64 && y < height as f64)
+        .then_some((x as usize, y as usize))
+}
+
+/// Outline of the crop region, with its corners as handles and its size.
+fn show_crop(plot_ui: &mut PlotUi, crop: &Crop, width: usize, height: usize) {
+    let (x0, y0) = (crop.x as f64, crop.y as f64);
+    let (x1, y1) = (x0 + crop.width as f64, y0 + crop.height as f64);
+    let corners: Vec<[f64; 2]> = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+        .into_iter()
+        .map(|(x, y)| {
+            let pos = edge_to_plot(x, y, width, height);
+            [pos.x, pos.y]
+        })
+        .collect();
+    let top_left = PlotPoint::new(corners[0][0], corners[0][1]);
+    plot_ui.polygon(
+        Polygon::new("crop region", corners.clone())
+            .stroke(egui::Stroke::new(1.5, CROP_COLOR))
+            .fill_color(egui::Color32::TRANSPARENT),
+    );
+    plot_ui.points(
+        Points::new("crop handles", corners)
+            .radius(4.0)
+            .color(CROP_COLOR),
+    );
+    plot_ui.text(
+        Text::new(
+            "crop size",
+            top_left,
+            format!("{}x{}", crop.width, crop.height),
+        )
+        .color(CROP_COLOR)
+        .anchor(egui::Align2::LEFT_BOTTOM),
+    );
+}
+
+/// Draw a new crop region by dragging on the preview, or resize the current
+/// one by dragging one of its corners.
+fn drag_crop(
+    data: &mut WithInputData,
+    response: &egui::Response,
+    pointer: Option<PlotPoint>,
+    width: usize,
+    height: usize,
+) {
+    let Some(pos) = pointer else {
+        return;
+    };
+    let (x, y) = to_pixel(pos, width, height);
+    let (x, y) = (x.clamp(0.0, width as f64), y.clamp(0.0, height as f64));
+    if response.drag_started() {
+        let tolerance = 0.02 * width.max(height) as f64;
+        let opposite_corner = data.crop.and_then(|crop| {
+            let (x0, y0) = (crop.x as f64, crop.y as f64);
+            let (x1, y1) = (x0 + crop.width as f64, y0 + crop.height as f64);
+            [
+                ((x0, y0), (x1, y1)),
+                ((x1, y0), (x0, y1)),
+                ((x1, y1), (x0, y0)),
+                ((x0, y1), (x1, y0)),
+            ]
+            .into_iter()
+            .find(|((cx, cy), _)| (cx - x).abs() < tolerance && (cy - y).abs() < tolerance)
+            .map(|(_, opposite)| opposite)
+        });
+        data.crop_anchor = Some(opposite_corner.unwrap_or((x, y)));
+    }
+    if response.dragged()
+        && let Some((anchor_x, anchor_y)) = data.crop_anchor
+    {
+        let x0 = (anchor_x.min(x).floor() as usize).min(width - 1);
+        let y0 = (anchor_y.min(y).floor() as usize).min(height - 1);
+        let x1 = (anchor_x.max(x).ceil() as usize).clamp(x0 + 1, width);
+        let y1 = (anchor_y.max(y).ceil() as usize).clamp(y0 + 1, height);
+        data.crop = Some(Crop {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        });
+    }
+    if response.drag_stopped() {
+        data.crop_anchor = None;
+    }
+}
+
+/// The XZ and YZ cross-sections through the cursor, with the current slice
+/// marked, using the same display range as the preview.
+fn show_ortho_views(
+    ui: &mut egui::Ui,
+    data: &mut WithInputData,
+    (x, y): (usize, usize),
+    scaling: DisplayScaling,
+) {
+    let view = data.mmap.read_view().unwrap();
+    let (nx, ny, nz) = view.dimensions();
+    let range = data.display_range.unwrap_or(data.auto_range);
+    let key = ((x, y), range, scaling);
+    if data
+        .ortho_views
+        .as_ref()
+        .is_none_or(|views| views.key != key)
+    {
+        let render = |axis: Axis, idx: usize, name: &str| {
+            let volume = Volume3D::new(data.mmap.read_view().unwrap()).along(axis);
+            let (width, height, _) = volume.dimensions();
+            let slice = Image::from_slice(&volume.get_slice(idx).unwrap(), width, height);
+            let img = render_to_rgb(&slice.data, width, height, range, scaling);
+            ui.ctx().load_texture(name, img, Default::default())
+        };
+        data.ortho_views = Some(OrthoViews {
+            key,
+            xz: render(Axis::Y, y, "xz_texture"),
+            yz: render(Axis::X, x, "yz_texture"),
+        });
+    }
+    let Some(views) = &data.ortho_views else {
+        return;
+    };
+
+    let plot_height = (ui.available_height() - 4.0 * V) / 2.0;
+    let panes = [
+        ("XZ", format!("y = {}", y + 1), &views.xz, x, nx),
+        ("YZ", format!("x = {}", x + 1), &views.yz, y, ny),
+    ];
+    for (name, position, texture, cursor, width) in panes {
+        ui.label(format!("{name} at {position}"));
+        Plot::new(name)
+            .data_aspect(1.0)
+            .height(plot_height)
+            .show(ui, |plot_ui| {
+                let image = PlotImage::new(
+                    name,
+                    texture,
+                    PlotPoint::new(0.5, 0.5),
+                    image_size(width, nz),
+                );
+                plot_ui.image(image);
+                let pos = to_plot_point(cursor, data.slice_position, width, nz);
+                plot_ui.vline(VLine::new("cursor", pos.x).color(CURSOR_COLOR));
+                plot_ui.hline(HLine::new("current slice", pos.y).color(CURSOR_COLOR));
+            });
+    }
+    if ui.button(tr("Close")).clicked() {
+        data.cursor = None;
+        data.ortho_views = None;
+    }
+}
+
+/// Histogram of the current slice, with the ends of the display range as
+/// handles that can be dragged.
+fn show_histogram(ui: &mut egui::Ui, data: &mut WithInputData) {
+    let Some(histogram) = &data.histogram else {
+        return;
+    };
+    let (vmin, vmax) = data.display_range.unwrap_or(data.auto_range);
+    let bars = histogram
+        .bins()
+        .map(|(center, count)| Bar::new(center as f64, count as f64))
+        .collect();
+    let chart = BarChart::new("histogram", bars).width(histogram.bin_width() as f64);
+    let response = Plot::new("histogram")
+        .height(120.0)
+        .show_y(false)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .allow_boxed_zoom(false)
+        .show(ui, |plot_ui| {
+            plot_ui.bar_chart(chart);
+            plot_ui.vline(VLine::new("vmin", vmin).color(egui::Color32::from_rgb(0, 90, 230)));
+            plot_ui.vline(VLine::new("vmax", vmax).color(egui::Color32::from_rgb(0, 90, 230)));
+            plot_ui.pointer_coordinate()
+        });
+    let pointer = response.inner.map(|p| p.x as f32);
+    let response = response.response;
+
+    if response.drag_started()
+        && let Some(x) = pointer
+    {
+        data.dragged_handle = Some(if (x - vmin).abs() < (x - vmax).abs() {
+            RangeHandle::Min
+        } else {
+            RangeHandle::Max
+        });
+    }
+    if response.dragged()
+        && let (Some(handle), Some(x)) = (data.dragged_handle, pointer)
+    {
+        data.display_range = Some(match handle {
+            RangeHandle::Min => (x.min(vmax), vmax),
+            RangeHandle::Max => (vmin, x.max(vmin)),
+        });
+        data.preview_outdated = true;
+    }
+    if response.drag_stopped() {
+        data.dragged_handle = None;
+    }
 }
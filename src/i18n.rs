@@ -0,0 +1,251 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// Language of the GUI texts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    English,
+    German,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Self::English, Self::German];
+
+    /// In the language itself, so it can be found without understanding the current one
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::German => "Deutsch",
+        }
+    }
+}
+
+/// The language used by `tr`, as the index into `Language::ALL`
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_language(language: Language) {
+    let idx = Language::ALL
+        .iter()
+        .position(|&l| l == language)
+        .unwrap_or(0);
+    CURRENT.store(idx as u8, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    Language::ALL
+        .get(CURRENT.load(Ordering::Relaxed) as usize)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Translate the English `text` into the current language. Texts without a
+/// translation are shown in English.
+pub fn tr(text: &'static str) -> &'static str {
+    match language() {
+        Language::English => text,
+        Language::German => german(text).unwrap_or(text),
+    }
+}
+
+fn german(text: &str) -> Option<&'static str> {
+    Some(match text {
+        // window, panels and general actions
+        "Theme" => "Design",
+        "UI scale" => "Skalierung",
+        "Language" => "Sprache",
+        "Log" => "Protokoll",
+        "Copy" => "Kopieren",
+        "Copy details" => "Details kopieren",
+        "Continue" => "Weiter",
+        "Close" => "Schließen",
+        "Remove" => "Entfernen",
+        "Select..." => "Auswählen...",
+        "Cancel" => "Abbrechen",
+        "Start" => "Starten",
+        "Load 3D MRC stack..." => "3D-MRC-Stapel laden...",
+        "Recent files" => "Zuletzt geöffnete Dateien",
+        "Recent destination directories" => "Zuletzt verwendete Zielverzeichnisse",
+        "MRC header" => "MRC-Header",
+        "not set" => "nicht gesetzt",
+
+        // preview
+        "Slice preview" => "Schnittvorschau",
+        "jump by" => "springen um",
+        "slice" => "Schnitt",
+        "tilt angle" => "Kippwinkel",
+        "Next lower tilt angle" => "Nächstkleinerer Kippwinkel",
+        "Next higher tilt angle" => "Nächstgrößerer Kippwinkel",
+        "Slices skipped with Page Up/Down; Home/End jump to the ends" => {
+            "Mit Bild auf/ab übersprungene Schnitte; Pos1/Ende springen an die Enden"
+        }
+        "Quantile" => "Quantil",
+        "Display scaling" => "Anzeigeskalierung",
+        "Preview" => "Vorschau",
+        "projection" => "Projektion",
+        "Full resolution preview" => "Vorschau in voller Auflösung",
+        "Large slices are binned for display, to keep it responsive" => {
+            "Große Schnitte werden für die Anzeige gebinnt, damit sie flüssig bleibt"
+        }
+        "Display from the minimum up to the quantile of each slice" => {
+            "Anzeige vom Minimum bis zum Quantil des jeweiligen Schnitts"
+        }
+        "auto contrast" => "Auto-Kontrast",
+        "From the 0.1 % to the 99.9 % quantile of the preview" => {
+            "Vom 0,1-%- bis zum 99,9-%-Quantil der Vorschau"
+        }
+        "header" => "Header",
+        "From dmin to dmax of the MRC header, as IMOD shows the file" => {
+            "Von dmin bis dmax des MRC-Headers, wie IMOD die Datei zeigt"
+        }
+        "Preview tool" => "Werkzeug",
+        "cursor" => "Cursor",
+        "crop region" => "Ausschnitt",
+        "line profile" => "Linienprofil",
+        "measure" => "Messen",
+        "scale bar" => "Maßstab",
+        "compare with processed" => "mit Verarbeitung vergleichen",
+        "Drag on the preview to draw or resize the region" => {
+            "Auf der Vorschau ziehen, um den Bereich zu zeichnen oder zu ändern"
+        }
+        "Drag on the preview to plot the values along a line" => {
+            "Auf der Vorschau ziehen, um die Werte entlang einer Linie darzustellen"
+        }
+        "Drag on the preview to measure a distance" => {
+            "Auf der Vorschau ziehen, um einen Abstand zu messen"
+        }
+        "Show the slice as it will be exported, in a separate window" => {
+            "Den Schnitt so zeigen, wie er exportiert wird, in einem eigenen Fenster"
+        }
+        "Processed preview" => "Verarbeitete Vorschau",
+        "power spectrum" => "Leistungsspektrum",
+        "Show the log power spectrum, to judge astigmatism, drift and Thon rings" => {
+            "Das logarithmische Leistungsspektrum zeigen, um Astigmatismus, Drift und \
+            Thon-Ringe zu beurteilen"
+        }
+        "Power spectrum" => "Leistungsspektrum",
+        "Nyquist at the edges:" => "Nyquist an den Rändern:",
+        "Line profile" => "Linienprofil",
+
+        // export settings
+        "Input path" => "Eingabedatei",
+        "Input size" => "Eingabegröße",
+        "Destination directory..." => "Zielverzeichnis...",
+        "Destination directory" => "Zielverzeichnis",
+        "Start frame number" => "Erster Frame",
+        "End frame number (inclusive)" => "Letzter Frame (inklusive)",
+        "from current preview" => "aus der aktuellen Vorschau",
+        "Bookmarked slices" => "Markierte Schnitte",
+        "keep current" => "aktuellen behalten",
+        "Toggle with B while browsing" => "Beim Durchblättern mit B umschalten",
+        "marked" => "markiert",
+        "export only these" => "nur diese exportieren",
+        "Instead of the range between the start and end frame" => {
+            "Statt des Bereichs zwischen erstem und letztem Frame"
+        }
+        "Clear" => "Leeren",
+        "Tilt series" => "Kippserie",
+        "ordered by tilt angle" => "nach Kippwinkel sortiert",
+        "The angle is added to the file names" => "Der Winkel wird an die Dateinamen angehängt",
+        "Crop region" => "Ausschnitt",
+        "select on preview" => "auf der Vorschau wählen",
+        "width" => "Breite",
+        "height" => "Höhe",
+        "Dark reference" => "Dunkelreferenz",
+        "Gain reference" => "Gain-Referenz",
+        "clamp to zero" => "auf null begrenzen",
+        "Set negative values after the subtraction to zero" => {
+            "Negative Werte nach der Subtraktion auf null setzen"
+        }
+        "Fix outliers" => "Ausreißer korrigieren",
+        "Replace pixels deviating from the median of their neighbors by more than this many \
+        standard deviations" => {
+            "Pixel ersetzen, die um mehr als so viele Standardabweichungen vom Median ihrer \
+            Nachbarn abweichen"
+        }
+        "Fix temporal outliers" => "Zeitliche Ausreißer korrigieren",
+        "Replace pixels much brighter than in the adjacent slices, like cosmic ray hits" => {
+            "Pixel ersetzen, die viel heller als in den benachbarten Schnitten sind, wie Treffer \
+            kosmischer Strahlung"
+        }
+        "Binning" => "Binning",
+        "Combine NxN pixels, after cropping" => "NxN Pixel zusammenfassen, nach dem Zuschneiden",
+        "Fourier cropping" => "Fourier-Beschneidung",
+        "factor" => "Faktor",
+        "Downsample without aliasing by cropping the Fourier transform, after binning" => {
+            "Ohne Aliasing verkleinern, durch Beschneiden der Fourier-Transformierten, nach dem \
+            Binning"
+        }
+        "Background ramp" => "Hintergrundgradient",
+        "subtract plane" => "Ebene abziehen",
+        "Remove a linear intensity gradient fitted to each slice" => {
+            "Einen an jeden Schnitt angepassten linearen Intensitätsverlauf entfernen"
+        }
+        "Dose weighting" => "Dosisgewichtung",
+        "Damp the high frequencies of later frames by their exposure" => {
+            "Hohe Frequenzen späterer Frames gemäß ihrer Belichtung dämpfen"
+        }
+        "dose per frame" => "Dosis pro Frame",
+        "Otherwise taken from the .mdoc file or the extended header" => {
+            "Sonst aus der .mdoc-Datei oder dem erweiterten Header"
+        }
+        "Drift correction" => "Driftkorrektur",
+        "Shift the slices onto each other, found by cross-correlation" => {
+            "Die Schnitte aufeinander verschieben, per Kreuzkorrelation bestimmt"
+        }
+        "reference" => "Referenz",
+        "max. shift" => "max. Verschiebung",
+        "Byte order" => "Byte-Reihenfolge",
+        "Native byte order is faster to write and to read back" => {
+            "Die native Byte-Reihenfolge ist schneller zu schreiben und zu lesen"
+        }
+        "Output" => "Ausgabe",
+        "each frame" => "jeder Frame",
+        "Sample type" => "Datentyp",
+        "same as input" => "wie die Eingabe",
+        "Normalization" => "Normierung",
+        "per" => "pro",
+        "Histogram equalization" => "Histogrammausgleich",
+        "Flatten the histogram instead of normalizing, for low contrast data in 8-bit exports" => {
+            "Das Histogramm statt einer Normierung ausgleichen, für kontrastarme Daten in \
+            8-Bit-Exporten"
+        }
+        "Dithering" => "Dithering",
+        "Avoid banding when rounding to integers, mostly for 8-bit exports" => {
+            "Stufenbildung beim Runden auf ganze Zahlen vermeiden, vor allem für 8-Bit-Exporte"
+        }
+        "File names" => "Dateinamen",
+        "{index} is replaced by the number of each written frame" => {
+            "{index} wird durch die Nummer des jeweils geschriebenen Frames ersetzt"
+        }
+        "needs {index}" => "benötigt {index}",
+        "path separators are not allowed either" => "Pfadtrenner sind auch nicht erlaubt",
+        "suffix" => "Suffix",
+        "Clip to display range" => "Auf Anzeigebereich begrenzen",
+        "Export to tiff" => "Als TIFF exportieren",
+        "Please select a destination directory first" => "Bitte zuerst ein Zielverzeichnis wählen",
+        "Add to queue" => "Zur Warteschlange",
+        "Add files to queue..." => "Dateien zur Warteschlange...",
+        "All frames of each file, with the current export settings" => {
+            "Alle Frames jeder Datei, mit den aktuellen Export-Einstellungen"
+        }
+        "Export into a sub-directory named after the file" => {
+            "In ein nach der Datei benanntes Unterverzeichnis exportieren"
+        }
+        "Output size" => "Ausgabegröße",
+        "Export finished" => "Export abgeschlossen",
+        "frames exported to" => "Frames exportiert nach",
+        "Open folder" => "Ordner öffnen",
+
+        // export queue
+        "Export queue" => "Export-Warteschlange",
+        "Clear finished" => "Fertige entfernen",
+        "waiting" => "wartet",
+        "done" => "fertig",
+        "failed" => "fehlgeschlagen",
+        "cancelled" => "abgebrochen",
+        _ => return None,
+    })
+}
@@ -0,0 +1,95 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{Error, read::Axis, tilt};
+
+/// Relates the written files back to the sections of the input stack, for
+/// databases that keep track of where each file comes from.
+#[derive(Debug, Serialize)]
+pub struct DatasetIndex {
+    pub input: PathBuf,
+    /// Perpendicular to the written slices
+    pub axis: Axis,
+    /// Of the written files, in Å per pixel, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_size: Option<f32>,
+    pub files: Vec<IndexEntry>,
+}
+
+/// A written file and the sections it was computed from, which are several
+/// for projections, groups and montages.
+#[derive(Debug, Serialize)]
+pub struct IndexEntry {
+    pub file: String,
+    pub sections: Vec<IndexedSection>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexedSection {
+    /// 1-indexed, along the sliced axis
+    pub section: usize,
+    /// In degrees, from the .mdoc file or the extended header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tilt_angle: Option<f32>,
+    /// Along the sliced axis in Å, including the origin of the header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<f32>,
+}
+
+/// Where the sections of a stack are along the sliced axis.
+#[derive(Debug, Clone)]
+pub struct SectionGeometry {
+    angles: Option<Vec<f32>>,
+    origin: f32,
+    /// Å per section
+    spacing: Option<f32>,
+}
+
+impl SectionGeometry {
+    /// From the header of the stack at `path`; tilt angles only apply to
+    /// sections along Z.
+    pub fn new(path: &Path, header: &mrc::Header, axis: Axis) -> Self {
+        let (sampling, length, origin, num_sections) = match axis {
+            Axis::X => (header.mx, header.xlen, header.origin[0], header.nx),
+            Axis::Y => (header.my, header.ylen, header.origin[1], header.ny),
+            Axis::Z => (header.mz, header.zlen, header.origin[2], header.nz),
+        };
+        let angles = match axis {
+            Axis::Z => tilt::tilt_angles(path, header, num_sections.max(0) as usize),
+            _ => None,
+        };
+        Self {
+            angles,
+            origin,
+            spacing: (sampling > 0 && length > 0.0).then(|| length / sampling as f32),
+        }
+    }
+
+    /// The section `z` (0-indexed).
+    pub fn section(&self, z: usize) -> IndexedSection {
+        IndexedSection {
+            section: z + 1,
+            tilt_angle: self
+                .angles
+                .as_ref()
+                .and_then(|angles| angles.get(z).copied()),
+            position: self.spacing.map(|spacing| self.origin + z as f32 * spacing),
+        }
+    }
+}
+
+impl DatasetIndex {
+    /// Write as pretty-printed JSON to `path`, which must not exist yet.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut out = BufWriter::new(File::create_new(path)?);
+        serde_json::to_writer_pretty(&mut out, self).map_err(std::io::Error::from)?;
+        writeln!(out)?;
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+use eframe::egui;
+use log::{debug, info, warn};
+
+/// How long to wait for a running instance to take over a file, and for
+/// another instance to send its path
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The running instance listens on a socket in the config directory, which
+/// only the user can connect to.
+#[cfg(unix)]
+mod transport {
+    use std::{
+        fs, io,
+        os::unix::{
+            fs::{DirBuilderExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+        path::PathBuf,
+    };
+
+    use crate::recent::config_dir;
+
+    pub type Listener = UnixListener;
+    pub type Stream = UnixStream;
+
+    fn socket_path() -> io::Result<PathBuf> {
+        config_dir()
+            .map(|dir| dir.join("instance.sock"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))
+    }
+
+    pub fn connect() -> io::Result<Stream> {
+        UnixStream::connect(socket_path()?)
+    }
+
+    pub fn bind() -> io::Result<Listener> {
+        let path = socket_path()?;
+        if let Some(dir) = path.parent() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)?;
+        }
+        // left behind by an instance that crashed, or taken over from one that
+        // is still running, like the port file of other platforms:
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+}
+
+/// Without Unix sockets, the running instance listens on a localhost port,
+/// which it writes to a file in the config directory.
+#[cfg(not(unix))]
+mod transport {
+    use std::{
+        fs, io,
+        net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+        path::PathBuf,
+    };
+
+    use super::TIMEOUT;
+    use crate::recent::config_dir;
+
+    pub type Listener = TcpListener;
+    pub type Stream = TcpStream;
+
+    fn port_file() -> io::Result<PathBuf> {
+        config_dir()
+            .map(|dir| dir.join("instance.port"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))
+    }
+
+    pub fn connect() -> io::Result<Stream> {
+        let port = fs::read_to_string(port_file()?)?;
+        let port: u16 = port.trim().parse().map_err(io::Error::other)?;
+        TcpStream::connect_timeout(&SocketAddr::from((Ipv4Addr::LOCALHOST, port)), TIMEOUT)
+    }
+
+    pub fn bind() -> io::Result<Listener> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let port = listener.local_addr()?.port();
+        let port_file = port_file()?;
+        port_file.parent().map_or(Ok(()), fs::create_dir_all)?;
+        fs::write(&port_file, port.to_string())?;
+        Ok(listener)
+    }
+}
+
+/// Ask an already running instance to open `path`; `false` if there is none,
+/// or it didn't confirm.
+pub fn forward(path: &Path) -> bool {
+    // the other instance may run in a different working directory:
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    match send_path(&path) {
+        Ok(reply) if reply.trim() == "ok" => {
+            info!("opened {path:?} in the running instance");
+            true
+        }
+        Ok(_) => false,
+        Err(e) => {
+            debug!("no running instance: {e}");
+            false
+        }
+    }
+}
+
+/// Send `path` on its own line, returning the reply.
+fn send_path(path: &Path) -> io::Result<String> {
+    let mut stream = transport::connect()?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    writeln!(stream, "{}", path.to_string_lossy())?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply)
+}
+
+/// The path sent by another instance on `stream`, which has to send it
+/// quickly, so it can't hold up the ones after it.
+fn receive_path(stream: &transport::Stream) -> io::Result<PathBuf> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(PathBuf::from(line.trim_end_matches(['\r', '\n'])))
+}
+
+/// Accept files from instances started later, in a background thread. The
+/// window is brought to the front for each of them.
+pub fn listen(ctx: &egui::Context) -> Option<Receiver<PathBuf>> {
+    let listener = transport::bind()
+        .inspect_err(|e| warn!("can't accept files from other instances: {e}"))
+        .ok()?;
+
+    let (snd, rcv) = mpsc::channel();
+    let ctx = ctx.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let path = match receive_path(&stream) {
+                Ok(path) => path,
+                Err(e) => {
+                    debug!("failed to receive a path from another instance: {e}");
+                    continue;
+                }
+            };
+            // only files, as nothing else can be opened:
+            if !path.is_file() {
+                warn!("ignoring {path:?} sent by another instance");
+                continue;
+            }
+            if snd.send(path).is_err() {
+                break;
+            }
+            let _ = (&stream).write_all(b"ok\n");
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            ctx.request_repaint();
+        }
+    });
+    Some(rcv)
+}
@@ -0,0 +1,292 @@
+use std::{
+    error::Error,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use log::{error, info};
+use serde::Serialize;
+
+use mrc_to_tiff::{
+    Error as LibraryError,
+    convert::{self, CancelToken, ConvertOptions},
+    progress::ProgressSink,
+};
+
+/// Parses the `options` of a submitted job, using the same keys as the config file.
+pub type OptionsParser =
+    dyn Fn(serde_json::Value) -> Result<ConvertOptions, Box<dyn Error + Sync + Send>> + Send + Sync;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: usize,
+    pub mrc_path: PathBuf,
+    pub dest_path: PathBuf,
+    pub status: JobStatus,
+    pub num_done: usize,
+    pub total: usize,
+    pub error: Option<String>,
+    /// files written so far
+    pub files: Vec<PathBuf>,
+    #[serde(skip)]
+    cancel: CancelToken,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JobError {
+    #[error("job {0} not found")]
+    NotFound(usize),
+    #[error("job {0} has already finished")]
+    Finished(usize),
+    #[error("path {0:?} is outside of the served root directory")]
+    Forbidden(PathBuf),
+    #[error("{path:?}: {source}")]
+    InvalidPath { path: PathBuf, source: io::Error },
+}
+
+/// Fails unless `path` exists and is inside of the canonical `root`.
+pub fn check_inside(path: &Path, root: &Path) -> Result<(), JobError> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|source| JobError::InvalidPath {
+            path: path.to_owned(),
+            source,
+        })?;
+    if !canonical.starts_with(root) {
+        return Err(JobError::Forbidden(path.to_owned()));
+    }
+    Ok(())
+}
+
+/// Options of a job with paths of files that are read.
+const INPUT_PATH_OPTIONS: [&str; 2] = ["dark", "gain"];
+/// Options of a job with paths of files that are written, relative to the destination.
+const OUTPUT_PATH_OPTIONS: [&str; 1] = ["stats"];
+
+/// Fails unless all paths in the job `options` are inside of the canonical
+/// `root`, like the input and the destination.
+pub fn check_options_inside(
+    options: &serde_json::Value,
+    dest_path: &Path,
+    root: &Path,
+) -> Result<(), JobError> {
+    let path = |key: &str| {
+        options
+            .get(key)
+            .and_then(|value| value.as_str())
+            .map(Path::new)
+    };
+    for path in INPUT_PATH_OPTIONS.into_iter().filter_map(path) {
+        check_inside(path, root)?;
+    }
+    for path in OUTPUT_PATH_OPTIONS.into_iter().filter_map(path) {
+        let path = dest_path.join(path);
+        // the file itself doesn't exist yet:
+        check_inside(path.parent().unwrap_or(&path), root)?;
+    }
+    Ok(())
+}
+
+type JobList = Arc<Mutex<Vec<Job>>>;
+
+/// Conversion jobs submitted by the servers, which are converted one after
+/// the other in a worker thread.
+pub struct JobQueue {
+    jobs: JobList,
+    queue: Sender<(usize, ConvertOptions)>,
+    worker: JoinHandle<()>,
+}
+
+impl JobQueue {
+    pub fn start() -> Self {
+        let jobs: JobList = Arc::new(Mutex::new(Vec::new()));
+        let (queue, queue_r) = mpsc::channel();
+        let worker = {
+            let jobs = Arc::clone(&jobs);
+            thread::spawn(move || run_jobs(&jobs, queue_r))
+        };
+        Self {
+            jobs,
+            queue,
+            worker,
+        }
+    }
+
+    /// Queue the conversion of `mrc_path` into `dest_path`.
+    pub fn submit(&self, mrc_path: PathBuf, dest_path: PathBuf, options: ConvertOptions) -> Job {
+        let mut jobs = self.jobs.lock().expect("job list lock poisoned");
+        let job = Job {
+            id: jobs.len(),
+            mrc_path,
+            dest_path,
+            status: JobStatus::Queued,
+            num_done: 0,
+            total: 0,
+            error: None,
+            files: Vec::new(),
+            cancel: CancelToken::new(),
+        };
+        info!(
+            "queued job {}: {:?} to {:?}",
+            job.id, job.mrc_path, job.dest_path
+        );
+        jobs.push(job.clone());
+        self.queue
+            .send((job.id, options))
+            .expect("the job worker runs as long as the queue exists");
+        job
+    }
+
+    pub fn get(&self, id: usize) -> Result<Job, JobError> {
+        let jobs = self.jobs.lock().expect("job list lock poisoned");
+        jobs.get(id).cloned().ok_or(JobError::NotFound(id))
+    }
+
+    pub fn list(&self) -> Vec<Job> {
+        self.jobs.lock().expect("job list lock poisoned").clone()
+    }
+
+    /// Cancel a queued or running job, removing the files it has written.
+    pub fn cancel(&self, id: usize) -> Result<Job, JobError> {
+        let mut jobs = self.jobs.lock().expect("job list lock poisoned");
+        let job = jobs.get_mut(id).ok_or(JobError::NotFound(id))?;
+        if job.status.is_finished() {
+            return Err(JobError::Finished(job.id));
+        }
+        // a queued job is skipped by the worker, which updates the status of
+        // a running one once the conversion has stopped:
+        if job.status == JobStatus::Queued {
+            job.status = JobStatus::Cancelled;
+        }
+        info!("cancelling job {}", job.id);
+        job.cancel.cancel();
+        Ok(job.clone())
+    }
+
+    /// Wait for the queued jobs to finish.
+    pub fn join(self) {
+        drop(self.queue);
+        self.worker.join().expect("job worker thread panicked");
+    }
+}
+
+fn update_job(jobs: &JobList, id: usize, update: impl FnOnce(&mut Job)) {
+    let mut jobs = jobs.lock().expect("job list lock poisoned");
+    update(&mut jobs[id]);
+}
+
+/// Records the progress of job `id` in the job list.
+struct JobProgress<'a> {
+    jobs: &'a JobList,
+    id: usize,
+}
+
+impl ProgressSink for JobProgress<'_> {
+    fn advance(
+        &self,
+        _input: &Path,
+        num_done: usize,
+        total: usize,
+        output: &Path,
+    ) -> Result<(), LibraryError> {
+        update_job(self.jobs, self.id, |job| {
+            job.num_done = num_done;
+            job.total = total;
+            // a projection reports the same file for each slice:
+            if job.files.last().map(PathBuf::as_path) != Some(output) {
+                job.files.push(output.to_owned());
+            }
+        });
+        Ok(())
+    }
+}
+
+fn run_jobs(jobs: &JobList, queue: Receiver<(usize, ConvertOptions)>) {
+    for (id, options) in queue {
+        let (mrc_path, dest_path, cancel) = {
+            let mut jobs = jobs.lock().expect("job list lock poisoned");
+            let job = &mut jobs[id];
+            if job.status == JobStatus::Cancelled {
+                continue;
+            }
+            job.status = JobStatus::Running;
+            (
+                job.mrc_path.clone(),
+                job.dest_path.clone(),
+                job.cancel.clone(),
+            )
+        };
+
+        let options = ConvertOptions {
+            cancel: Some(cancel),
+            ..options
+        };
+        let progress = JobProgress { jobs, id };
+        let res = convert::convert(mrc_path, dest_path, &options, &progress);
+
+        update_job(jobs, id, |job| match res {
+            Ok(()) => job.status = JobStatus::Done,
+            Err(e) if e.is_cancelled() => {
+                info!("job {id} cancelled");
+                job.status = JobStatus::Cancelled;
+                // removed by `convert`:
+                job.files.clear();
+            }
+            Err(e) => {
+                error!("job {id} failed: {e}");
+                job.status = JobStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_in_the_options() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        let outside = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(root.join("gain.mrc"), []).unwrap();
+        let options = |options: serde_json::Value| check_options_inside(&options, &root, &root);
+
+        assert!(options(serde_json::json!({ "bin": 2 })).is_ok());
+        assert!(options(serde_json::json!({ "gain": root.join("gain.mrc") })).is_ok());
+        assert!(options(serde_json::json!({ "stats": "stats.csv" })).is_ok());
+        assert!(matches!(
+            options(serde_json::json!({ "dark": outside.path() })),
+            Err(JobError::Forbidden(_))
+        ));
+        assert!(matches!(
+            options(serde_json::json!({ "stats": "../stats.csv" })),
+            Err(JobError::Forbidden(_))
+        ));
+    }
+}
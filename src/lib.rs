@@ -0,0 +1,81 @@
+//! Conversion of 16bit 3D MRC stacks to TIFF files, as used by the
+//! `mrc-to-tiff` command line tool and the `mrc-to-tiff-gui` viewer.
+//!
+//! The whole conversion, including the processing, projections and drift
+//! correction, is available as [`convert::convert`], configured by
+//! [`convert::ConvertOptions`]:
+//!
+//! ```no_run
+//! use mrc_to_tiff::{
+//!     convert::{ConvertOptions, convert},
+//!     process::{Normalize, Processing},
+//!     progress::NoProgress,
+//!     write::SampleType,
+//! };
+//!
+//! let options = ConvertOptions {
+//!     processing: Processing {
+//!         bin: Some(2),
+//!         normalize: Normalize::MinMax,
+//!         output_type: Some(SampleType::U8),
+//!         ..Default::default()
+//!     },
+//!     ..Default::default()
+//! };
+//! convert("stack.mrc".into(), "out/".into(), &options, &NoProgress)?;
+//! # Ok::<(), mrc_to_tiff::Error>(())
+//! ```
+//!
+//! The building blocks can also be used on their own: [`read::Volume3D`]
+//! gives access to the slices of a stack along any axis,
+//! [`process::Processing`] turns them into processed [`process::Image`]s,
+//! and [`write::write_image`] writes those as TIFF files.
+//!
+//! The progress is reported to a [`progress::ProgressSink`]; besides
+//! [`progress::NoProgress`], there are sinks for terminal progress bars,
+//! JSON lines and channels, or it can be implemented to follow the progress
+//! in any other way.
+//!
+//! Custom filters and corrections can be added to a conversion as
+//! [`processor::SliceProcessor`]s, which are applied to each slice before
+//! the built-in processing.
+//!
+//! Failures are reported as [`Error`], which keeps the errors of the
+//! individual modules, like [`convert::ConvertError`], as its variants.
+
+#[cfg(feature = "native")]
+pub mod align;
+#[cfg(feature = "native")]
+pub mod batch;
+#[cfg(feature = "native")]
+pub mod buffer_pool;
+pub mod common;
+#[cfg(feature = "native")]
+pub mod convert;
+pub mod dose;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fft;
+pub mod index;
+pub mod manifest;
+pub mod montage;
+pub mod process;
+pub mod processor;
+#[cfg(feature = "native")]
+pub mod progress;
+#[cfg(feature = "python")]
+mod python;
+pub mod read;
+pub mod reference;
+pub mod scalebar;
+pub mod stats;
+#[cfg(feature = "native")]
+pub mod throttle;
+pub mod tilt;
+pub mod timings;
+#[cfg(feature = "wasm")]
+mod wasm;
+pub mod write;
+
+pub use error::Error;
@@ -0,0 +1,242 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use indicatif::MultiProgress;
+use indicatif_log_bridge::LogWrapper;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One line of plain text per record
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Where and how to write the log file, in addition to the terminal output.
+#[derive(Debug, Clone)]
+pub struct LogFileOptions {
+    pub path: PathBuf,
+    pub format: LogFormat,
+    /// Rotate the file once it would grow beyond this many bytes
+    pub max_size: u64,
+    /// Number of rotated files to keep, as `<path>.1` (the newest) to `<path>.<keep>`
+    pub keep: usize,
+}
+
+/// A log file that is rotated once it reaches its maximum size.
+struct RotatingFile {
+    options: LogFileOptions,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(options: LogFileOptions) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&options.path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            options,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, idx: usize) -> PathBuf {
+        let mut name = self.options.path.clone().into_os_string();
+        name.push(format!(".{idx}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = &self.options.path;
+        if self.options.keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for idx in (1..self.options.keep).rev() {
+                let from = self.rotated_path(idx);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(idx + 1))?;
+                }
+            }
+            fs::rename(path, self.rotated_path(1))?;
+        }
+        self.file = File::create(path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.options.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+/// Number of lines kept by a `LogBuffer`
+const MAX_BUFFERED_LINES: usize = 1000;
+
+/// The most recent lines shown on the terminal, for displaying them in the GUI.
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<String>>>);
+
+impl LogBuffer {
+    fn push(&self, line: String) {
+        let mut lines = self.0.lock().expect("log buffer lock poisoned");
+        if lines.len() == MAX_BUFFERED_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Oldest first
+    // only the GUI shows them, the command line binary includes this module too:
+    #[allow(dead_code)]
+    pub fn lines(&self) -> Vec<String> {
+        let lines = self.0.lock().expect("log buffer lock poisoned");
+        lines.iter().cloned().collect()
+    }
+}
+
+/// Sends every record to the terminal logger, and additionally writes records
+/// of the library and the binaries down to `Debug` (and of other crates down
+/// to `Info`) into the log file.
+struct TeeLogger {
+    terminal: env_logger::Logger,
+    file: Option<(Mutex<RotatingFile>, LogFormat)>,
+    buffer: Option<LogBuffer>,
+}
+
+/// Prefix of the log targets of the library and of both binaries.
+const OWN_TARGETS: &str = "mrc_to_tiff";
+
+fn file_enabled(metadata: &Metadata) -> bool {
+    let max_level = if metadata.target().starts_with(OWN_TARGETS) {
+        Level::Debug
+    } else {
+        Level::Info
+    };
+    metadata.level() <= max_level
+}
+
+fn format_record(record: &Record, format: LogFormat) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    match format {
+        LogFormat::Text => format!(
+            "{timestamp:.3} {} {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        ),
+        LogFormat::Json => serde_json::json!({
+            "time": timestamp,
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "msg": record.args().to_string(),
+        })
+        .to_string(),
+    }
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.terminal.enabled(metadata) || (self.file.is_some() && file_enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        // the terminal logger filters by itself:
+        self.terminal.log(record);
+        if let Some(buffer) = &self.buffer
+            && self.terminal.enabled(record.metadata())
+        {
+            buffer.push(format!(
+                "{} {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ));
+        }
+        if let Some((file, format)) = &self.file
+            && file_enabled(record.metadata())
+        {
+            let line = format_record(record, *format);
+            let mut file = file.lock().expect("log file lock poisoned");
+            if let Err(e) = file.write_line(&line) {
+                eprintln!("failed to write to the log file: {e}");
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+        if let Some((file, _)) = &self.file {
+            let _ = file.lock().expect("log file lock poisoned").file.flush();
+        }
+    }
+}
+
+/// Install the global logger, writing to the terminal without interfering
+/// with the progress bars of `multi`, to a log file if given, and keeping the
+/// terminal output in `buffer`.
+pub fn init(
+    multi: &MultiProgress,
+    log_file: Option<LogFileOptions>,
+    buffer: Option<LogBuffer>,
+) -> io::Result<()> {
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    let terminal = env_logger::Builder::from_env(env).build();
+    let terminal_level = terminal.filter();
+    let file = log_file
+        .map(|options| -> io::Result<_> {
+            let format = options.format;
+            Ok((Mutex::new(RotatingFile::open(options)?), format))
+        })
+        .transpose()?;
+    let logger = TeeLogger {
+        terminal,
+        file,
+        buffer,
+    };
+    let has_file = logger.file.is_some();
+    LogWrapper::new(multi.clone(), logger)
+        .try_init()
+        .expect("no other logger should be installed");
+    // `try_init` only takes the terminal into account:
+    if has_file {
+        log::set_max_level(terminal_level.max(LevelFilter::Debug));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_levels() {
+        let enabled =
+            |level, target| file_enabled(&Metadata::builder().level(level).target(target).build());
+        assert!(enabled(Level::Debug, "mrc_to_tiff::convert"));
+        assert!(enabled(Level::Debug, "mrc_to_tiff_gui::preview"));
+        assert!(!enabled(Level::Trace, "mrc_to_tiff::convert"));
+        assert!(enabled(Level::Info, "eframe"));
+        assert!(!enabled(Level::Debug, "eframe"));
+    }
+}
@@ -1,55 +1,1373 @@
-mod convert;
-mod read;
-mod write;
-mod render;
-mod common;
+mod bench;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod jobs;
+mod logging;
+mod serve;
+mod verify;
+mod watch;
 
-use std::{error::Error, path::PathBuf};
+use std::{
+    error::Error,
+    path::{Component, Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
 use indicatif::MultiProgress;
-use indicatif_log_bridge::LogWrapper;
+use log::{error, info};
+use serde::Deserialize;
 
-use crate::common::ArgEndianess;
+use mrc_to_tiff::{
+    Error as LibraryError,
+    align::{AlignReference, Alignment, DEFAULT_MAX_SHIFT},
+    batch::{self, BatchError},
+    common::{ArgEndianess, MemorySize},
+    convert::{
+        self, ConvertError, ConvertOptions, Extension, Grouping, NameTemplate, OutputNaming,
+        StatsExport,
+    },
+    dose::DoseWeighting,
+    montage::Montage,
+    process::{
+        BinMode, Clip, Crop, Dither, Equalize, Flip, Invert, Normalize, Processing, Projection,
+        Rotation, Size, StatsScope,
+    },
+    processor::SliceProcessors,
+    progress::ProgressFormat,
+    read::Axis,
+    reference::{Dark, Gain, GainMode, ReferenceError},
+    scalebar::ScaleBar,
+    throttle::{self, IoLimiter},
+    timings::StageTimings,
+    write::{SampleType, WriteBackend, WriteError},
+};
 
+use crate::{
+    logging::{LogFileOptions, LogFormat},
+    verify::VerifyError,
+};
+
+/// Convert 16bit 3D MRC stacks to a folder of TIFF files.
 #[derive(Parser, Debug)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    convert: Args,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct LogArgs {
+    /// Also write the log, including the per-slice messages, to this file.
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Format of the log file.
+    #[arg(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Rotate the log file once it grows beyond this size.
+    #[arg(long, global = true, value_name = "SIZE", default_value = "10M")]
+    log_max_size: MemorySize,
+
+    /// How many rotated log files to keep.
+    #[arg(long, global = true, value_name = "N", default_value = "3")]
+    log_keep: usize,
+}
+
+impl LogArgs {
+    fn file_options(&self) -> Option<LogFileOptions> {
+        let path = self.log_file.clone()?;
+        Some(LogFileOptions {
+            path,
+            format: self.log_format,
+            max_size: self.log_max_size.0,
+            keep: self.log_keep,
+        })
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Re-read previously written tiff files and compare them against the source .mrc file.
+    ///
+    /// Only conversions that wrote the slices as they are, one file per slice,
+    /// can be verified: output with any processing, projections, groups,
+    /// montages or `--single-file` stacks is reported as mismatched or missing.
+    Verify(VerifyArgs),
+
+    /// Run an HTTP server for submitting conversion jobs and polling their progress.
+    Serve(ServeArgs),
+
+    /// Run a gRPC server for submitting conversion jobs and streaming their progress.
+    #[cfg(feature = "grpc")]
+    Grpc(GrpcArgs),
+
+    /// Measure read, processing and write throughput for different thread counts.
+    Bench(BenchArgs),
+
+    /// Print a shell completion script to stdout.
+    #[command(hide = true)]
+    Completions { shell: clap_complete::Shell },
+
+    /// Print a man page in roff format to stdout.
+    #[command(hide = true)]
+    Manpage,
+}
+
+#[derive(clap::Args, Debug, Default, Deserialize)]
+#[command(group = clap::ArgGroup::new("batch").args(["files_from", "watch"]))]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
 struct Args {
-    /// Path to the input .mrc file. Must be a 3D stack in 16bit format.
+    /// Path to the input .mrc file, which must be a 3D stack in 16bit format,
+    /// followed by the destination path, which should be an existing directory.
+    /// With `--files-from` or `--watch`, only the destination is given.
+    #[arg(required = true, num_args = 1..=2, value_names = ["MRC_PATH", "DEST_PATH"])]
+    #[serde(skip)]
+    paths: Vec<PathBuf>,
+
+    /// Read newline-separated input paths from a file, or from stdin if given as `-`.
+    /// Then the only positional argument is the destination directory, and each
+    /// input is converted into a sub-directory of it, see `--dest-template`.
+    #[arg(long, value_name = "FILE")]
+    #[serde(skip)]
+    files_from: Option<PathBuf>,
+
+    /// Convert the inputs of a batch one after the other, instead of sharing the
+    /// threads between multiple inputs at the same time. Implied by `--max-memory`.
+    #[arg(long, requires = "files_from")]
+    #[serde(skip)]
+    sequential: bool,
+
+    /// Watch a directory and convert new .mrc files as they appear, until interrupted.
+    /// Like with `--files-from`, the only positional argument is the destination directory.
+    #[arg(long, value_name = "DIR", conflicts_with = "files_from")]
+    #[serde(skip)]
+    watch: Option<PathBuf>,
+
+    /// When watching, wait until a new file hasn't changed for this many seconds
+    /// before converting it. [default: 5]
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        requires = "watch",
+        value_parser = |value: &str| SETTLE_TIME.parse(value)
+    )]
+    settle_time: Option<f64>,
+
+    /// Destination of each input of a batch, relative to the destination directory.
+    /// Can contain the placeholders {stem}, {name} and {parent} (the name of the
+    /// directory containing the input). [default: {stem}]
+    #[arg(long, value_name = "TEMPLATE", requires = "batch")]
+    dest_template: Option<String>,
+
+    /// TOML file with conversion options, using the long flag names as keys.
+    /// Options given on the command line take precedence, like `--index=false`
+    /// to turn off a flag that the file sets.
+    #[arg(long)]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
+    /// Which frame number should be the first to include? Starts at 1. [default: 1]
+    #[arg(short, long)]
+    start_at_frame: Option<usize>,
+
+    /// Which frame number should be the last to include? Starts at 1.
+    #[arg(long)]
+    stop_at_frame: Option<usize>,
+
+    /// The endianess of the tiff files that are written. [default: big]
+    #[arg(short, long)]
+    endianess: Option<ArgEndianess>,
+
+    /// How the tiff files are written. `preallocated` reserves each file at its final
+    /// size up front, and writes the pages of `--single-file` stacks from all threads
+    /// at once. `direct` also bypasses the page cache, which is much faster on
+    /// parallel file systems like BeeGFS or Lustre. [default: buffered]
+    #[arg(long)]
+    write_backend: Option<WriteBackend>,
+
+    /// Export slices perpendicular to this axis. Frame numbers count along this axis. [default: z]
+    #[arg(long)]
+    axis: Option<Axis>,
+
+    /// Read 16bit data of mode 1 as unsigned instead of signed values, for
+    /// files written by software that doesn't mark them with mode 6. Otherwise
+    /// the upper half of the range ends up as negative values.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    assume_unsigned: Option<bool>,
+
+    /// Dark reference to subtract from each slice, before applying the gain.
+    /// References with multiple slices are averaged. Without other processing,
+    /// the result is written as 32bit integers.
+    #[arg(long, value_name = "DARK.MRC")]
+    dark: Option<PathBuf>,
+
+    /// Clamp negative values after subtracting the dark reference to zero.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    dark_clamp: Option<bool>,
+
+    /// Rotate the dark reference clockwise to match the orientation of the slices.
+    #[arg(long)]
+    dark_rotate: Option<Rotation>,
+
+    /// Flip the dark reference, after rotating it.
+    #[arg(long)]
+    dark_flip: Option<Flip>,
+
+    /// Gain reference to apply to each slice. References with multiple slices are averaged.
+    #[arg(long, value_name = "GAINREF.MRC")]
+    gain: Option<PathBuf>,
+
+    /// Whether to multiply or divide the slices by the gain reference. [default: multiply]
+    #[arg(long)]
+    gain_mode: Option<GainMode>,
+
+    /// Rotate the gain reference clockwise to match the orientation of the slices.
+    #[arg(long)]
+    gain_rotate: Option<Rotation>,
+
+    /// Flip the gain reference, after rotating it.
+    #[arg(long)]
+    gain_flip: Option<Flip>,
+
+    /// Replace pixels deviating from the median of their neighbors by more than SIGMA
+    /// standard deviations with that median. Applied after the dark and gain references.
+    #[arg(long, value_name = "SIGMA")]
+    fix_outliers: Option<f32>,
+
+    /// Replace pixels brighter than the median of the same pixel in the two slices
+    /// before and after by more than SIGMA standard deviations, removing cosmic ray
+    /// hits. Applied before all other processing.
+    #[arg(long, value_name = "SIGMA")]
+    fix_temporal_outliers: Option<f32>,
+
+    /// Only export the region starting at pixel X,Y with size W,H.
+    #[arg(long, value_name = "X,Y,W,H")]
+    crop: Option<Crop>,
+
+    /// Bin each slice NxN before writing. Applied after cropping.
+    #[arg(long, value_name = "N")]
+    bin: Option<usize>,
+
+    /// How to combine the binned pixels: the mean, the sum for counting data, written
+    /// as 32bit integers, or the median. [default: mean]
+    #[arg(long)]
+    bin_mode: Option<BinMode>,
+
+    /// Downsample each slice by FACTOR by cropping its Fourier transform, which
+    /// avoids the aliasing of binning. The factor doesn't need to be an integer.
+    /// Applied after binning.
+    #[arg(long, value_name = "FACTOR")]
+    fourier_crop: Option<f32>,
+
+    /// Resample each slice to a pixel size of A Ångström, so that exports of different
+    /// magnifications share a common sampling. Downsampling crops the Fourier transform,
+    /// upsampling interpolates bilinearly. Applied after Fourier cropping; the pixel size
+    /// is written into the resolution tags.
+    #[arg(long, value_name = "A")]
+    target_pixel_size: Option<f32>,
+
+    /// Rotate each slice clockwise by the given angle, after cropping and binning.
+    #[arg(long)]
+    rotate: Option<Rotation>,
+
+    /// Mirror each slice horizontally or vertically, after rotating.
+    #[arg(long)]
+    flip: Option<Flip>,
+
+    /// Blur each slice with a gaussian of standard deviation SIGMA, in pixels after binning.
+    #[arg(long, value_name = "SIGMA")]
+    gaussian: Option<f32>,
+
+    /// Apply a KxK median filter to each slice, after the gaussian blur.
+    #[arg(long, value_name = "K")]
+    median: Option<usize>,
+
+    /// Fit a plane to each slice and subtract it, except for its mean, removing
+    /// illumination gradients. Applied after the median filter.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    subtract_plane: Option<bool>,
+
+    /// Crop each slice to at most WxH pixels around its center, as the last step of
+    /// the geometric processing, for fixed-size outputs together with `--pad-to`.
+    #[arg(long, value_name = "WxH")]
+    crop_to: Option<Size>,
+
+    /// Pad each slice to at least WxH pixels, keeping it centered. Applied after
+    /// `--crop-to`.
+    #[arg(long, value_name = "WxH")]
+    pad_to: Option<Size>,
+
+    /// Value of the pixels added by `--pad-to`. [default: the mean of each slice]
+    #[arg(long, value_name = "V")]
+    pad_value: Option<f32>,
+
+    /// Weight each frame of a movie by its accumulated exposure, damping the
+    /// high frequencies of later frames. The exposure is taken from the .mdoc
+    /// file or the extended header. Applied after the median filter.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    dose_weight: Option<bool>,
+
+    /// Exposure of each frame in e/Å², instead of the one from the metadata.
+    #[arg(long, value_name = "E/A2")]
+    dose_per_frame: Option<f32>,
+
+    /// Acceleration voltage in kV for the dose weighting. [default: from the
+    /// extended header, or 300]
+    #[arg(long, value_name = "KV")]
+    voltage: Option<f32>,
+
+    /// Correct the drift between the slices with rigid shifts found by
+    /// cross-correlation, against the first slice or the running average of the
+    /// aligned ones. Applied after the dose weighting.
+    #[arg(long, value_name = "REFERENCE")]
+    align: Option<AlignReference>,
+
+    /// Largest shift searched for when aligning, in pixels after binning. [default: 32]
+    #[arg(long, value_name = "PIXELS")]
+    max_shift: Option<usize>,
+
+    /// Clamp the values to the range MIN..=MAX, before inverting and normalizing.
+    #[arg(long, value_name = "MIN,MAX", allow_hyphen_values = true)]
+    clip: Option<Clip>,
+
+    /// Invert the contrast, either by negating the data (the default; unsigned data
+    /// is subtracted from 65535 instead) or by marking the files as WhiteIsZero
+    /// with `--invert=photometric`.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "data")]
+    invert: Option<Invert>,
+
+    /// Rescale intensities after the geometric transforms: none, minmax,
+    /// percentile:P (P-th to (100-P)-th percentile, clipped) or zscore. [default: none]
+    #[arg(long, value_name = "MODE")]
+    normalize: Option<Normalize>,
+
+    /// Equalize the histogram of each slice instead of normalizing it, over the
+    /// whole slice or with CLAHE (contrast-limited adaptive histogram equalization)
+    /// per region of it. [default output type: uint8]
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "global")]
+    equalize: Option<Equalize>,
+
+    /// Dither when rounding to an integer output type, to avoid banding in smooth
+    /// gradients of 8-bit exports.
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "floyd-steinberg"
+    )]
+    dither: Option<Dither>,
+
+    /// Compute the normalization statistics, or the histogram for `--equalize=global`,
+    /// per slice or once over all selected slices. [default: slice]
+    #[arg(long)]
+    normalize_stats: Option<StatsScope>,
+
+    /// With `--normalize minmax`, normalize the whole stack to the dmin..dmax
+    /// range of the MRC header, like IMOD displays it, instead of scanning all
+    /// slices for their range first. Falls back to scanning if the header has
+    /// no statistics, if the processing changes the range of the values, or
+    /// with `--assume-unsigned`, as the header describes signed values then.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    header_stats: Option<bool>,
+
+    /// Sample type of the written files. Normalized data is scaled to the full
+    /// range of integer types. [default: int16, or float32 when normalizing]
+    #[arg(long)]
+    output_type: Option<SampleType>,
+
+    /// Instead of one file per slice, write a single projection over all selected slices.
+    #[arg(long)]
+    projection: Option<Projection>,
+
+    /// Average every N consecutive slices into one output frame, written as 32bit floats.
+    #[arg(long, value_name = "N")]
+    average: Option<usize>,
+
+    /// Sum every N consecutive slices into one output frame, written as 32bit integers.
+    #[arg(long, value_name = "N")]
+    sum: Option<usize>,
+
+    /// Write each frame as the average of the N frames around it, keeping the number
+    /// of frames. An even N is rounded up to the next odd one, to center the window.
+    #[arg(long, value_name = "N")]
+    running_average: Option<usize>,
+
+    /// Instead of one file per slice, write overview images with COLSxROWS
+    /// downsampled slices each, like `8x6`.
+    #[arg(long, value_name = "COLSxROWS")]
+    montage: Option<Montage>,
+
+    /// Write the log power spectrum of each slice, or of each group with `--average`
+    /// or `--sum`, instead of the slice itself. Averaged over 1024x1024 tiles, with
+    /// the zero frequency in the center. [default output type: float32]
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    export_fft: Option<bool>,
+
+    /// Write all slices as the pages of a single `stack.tif` (with the suffix),
+    /// instead of one file per slice. Large stacks are written as BigTIFF. The
+    /// ImageDescription of each page lists the number of its section and, where
+    /// the extended header or the .mdoc file has them, its tilt angle, defocus
+    /// (µm) and dose (e/Å²) as key=value lines.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    single_file: Option<bool>,
+
+    /// Limit the memory used for slices that are processed at the same time,
+    /// like `8G` or `512M`, by using fewer threads. The inputs of a batch are
+    /// then converted one after the other.
+    #[arg(long, value_name = "SIZE")]
+    max_memory: Option<MemorySize>,
+
+    /// Limit the combined read and write rate, in MB/s, to leave bandwidth for other
+    /// processes using the same disk.
+    #[arg(long, value_name = "MB/S", value_parser = |value: &str| IO_LIMIT.parse(value))]
+    io_limit: Option<f64>,
+
+    /// Run with the lowest CPU and I/O priority.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    nice: Option<bool>,
+
+    /// Draw a scale bar of this length, like `50nm`, `500A` or `1um`, using the pixel
+    /// size from the header. Needs `--output-type uint8`.
+    #[arg(long, value_name = "LENGTH")]
+    scalebar: Option<ScaleBar>,
+
+    /// Name of the written slices, without the extension; `{index}` is replaced
+    /// by the zero-padded slice number. [default: slice_{index}]
+    #[arg(long, value_name = "TEMPLATE")]
+    name_template: Option<NameTemplate>,
+
+    /// Extension of the written files. [default: tif]
+    #[arg(long)]
+    ext: Option<Extension>,
+
+    /// Append this to the name of each written file, before the extension.
+    #[arg(long)]
+    suffix: Option<String>,
+
+    /// Also write `index.json` (with the suffix), relating each written file to
+    /// the input sections, their tilt angles and their positions in Å.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    index: Option<bool>,
+
+    /// Also write `manifest.json` (with the suffix), with the offset, shape and
+    /// NumPy dtype of the pixel data of each written file, for distributed readers.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    manifest: Option<bool>,
+
+    /// Write the min, max, mean and standard deviation of each converted input
+    /// slice to this CSV file, relative to the output directory.
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_parser = |value: &str| parse_relative_path("stats", value)
+    )]
+    stats: Option<PathBuf>,
+
+    /// Also include these percentiles in the `--stats`, like `1,50,99`.
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    stats_percentiles: Option<Vec<f32>>,
+
+    /// How to report progress. [default: bar]
+    #[arg(long)]
+    progress: Option<ProgressFormat>,
+
+    /// Print how long reading, processing, encoding and writing the slices took
+    /// to stderr, to tell whether the conversion is limited by the I/O or by the CPU.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    timings: Option<bool>,
+}
+
+impl Args {
+    /// Fill in all options that were not given on the command line from `config`.
+    fn or(self, config: Args) -> Args {
+        Args {
+            paths: self.paths,
+            files_from: self.files_from,
+            sequential: self.sequential,
+            watch: self.watch,
+            settle_time: self.settle_time.or(config.settle_time),
+            dest_template: self.dest_template.or(config.dest_template),
+            config: self.config,
+            start_at_frame: self.start_at_frame.or(config.start_at_frame),
+            stop_at_frame: self.stop_at_frame.or(config.stop_at_frame),
+            endianess: self.endianess.or(config.endianess),
+            write_backend: self.write_backend.or(config.write_backend),
+            axis: self.axis.or(config.axis),
+            assume_unsigned: self.assume_unsigned.or(config.assume_unsigned),
+            dark: self.dark.or(config.dark),
+            dark_clamp: self.dark_clamp.or(config.dark_clamp),
+            dark_rotate: self.dark_rotate.or(config.dark_rotate),
+            dark_flip: self.dark_flip.or(config.dark_flip),
+            gain: self.gain.or(config.gain),
+            gain_mode: self.gain_mode.or(config.gain_mode),
+            gain_rotate: self.gain_rotate.or(config.gain_rotate),
+            gain_flip: self.gain_flip.or(config.gain_flip),
+            fix_outliers: self.fix_outliers.or(config.fix_outliers),
+            fix_temporal_outliers: self.fix_temporal_outliers.or(config.fix_temporal_outliers),
+            crop: self.crop.or(config.crop),
+            bin: self.bin.or(config.bin),
+            bin_mode: self.bin_mode.or(config.bin_mode),
+            fourier_crop: self.fourier_crop.or(config.fourier_crop),
+            target_pixel_size: self.target_pixel_size.or(config.target_pixel_size),
+            rotate: self.rotate.or(config.rotate),
+            flip: self.flip.or(config.flip),
+            gaussian: self.gaussian.or(config.gaussian),
+            median: self.median.or(config.median),
+            subtract_plane: self.subtract_plane.or(config.subtract_plane),
+            crop_to: self.crop_to.or(config.crop_to),
+            pad_to: self.pad_to.or(config.pad_to),
+            pad_value: self.pad_value.or(config.pad_value),
+            dose_weight: self.dose_weight.or(config.dose_weight),
+            dose_per_frame: self.dose_per_frame.or(config.dose_per_frame),
+            voltage: self.voltage.or(config.voltage),
+            align: self.align.or(config.align),
+            max_shift: self.max_shift.or(config.max_shift),
+            clip: self.clip.or(config.clip),
+            invert: self.invert.or(config.invert),
+            normalize: self.normalize.or(config.normalize),
+            normalize_stats: self.normalize_stats.or(config.normalize_stats),
+            header_stats: self.header_stats.or(config.header_stats),
+            equalize: self.equalize.or(config.equalize),
+            dither: self.dither.or(config.dither),
+            output_type: self.output_type.or(config.output_type),
+            projection: self.projection.or(config.projection),
+            average: self.average.or(config.average),
+            sum: self.sum.or(config.sum),
+            running_average: self.running_average.or(config.running_average),
+            montage: self.montage.or(config.montage),
+            export_fft: self.export_fft.or(config.export_fft),
+            single_file: self.single_file.or(config.single_file),
+            max_memory: self.max_memory.or(config.max_memory),
+            io_limit: self.io_limit.or(config.io_limit),
+            nice: self.nice.or(config.nice),
+            scalebar: self.scalebar.or(config.scalebar),
+            ext: self.ext.or(config.ext),
+            name_template: self.name_template.or(config.name_template),
+            suffix: self.suffix.or(config.suffix),
+            index: self.index.or(config.index),
+            manifest: self.manifest.or(config.manifest),
+            stats: self.stats.or(config.stats),
+            stats_percentiles: self.stats_percentiles.or(config.stats_percentiles),
+            progress: self.progress.or(config.progress),
+            timings: self.timings.or(config.timings),
+        }
+    }
+
+    fn with_config_file(self) -> Result<Args, Box<dyn Error + Sync + Send>> {
+        let Some(config_path) = &self.config else {
+            return Ok(self);
+        };
+        info!("reading options from {config_path:?}");
+        let config: Args = toml::from_str(&std::fs::read_to_string(config_path)?)?;
+        Ok(self.or(config))
+    }
+
+    /// Load the references and collect the options for `convert::convert`.
+    fn convert_options(&self) -> Result<ConvertOptions, Box<dyn Error + Sync + Send>> {
+        self.check_options()?;
+        let dark = self
+            .dark
+            .as_deref()
+            .map(|path| {
+                Dark::load(
+                    path,
+                    self.dark_clamp.unwrap_or_default(),
+                    self.dark_rotate,
+                    self.dark_flip,
+                    self.assume_unsigned.unwrap_or_default(),
+                )
+            })
+            .transpose()?;
+        let gain = self
+            .gain
+            .as_deref()
+            .map(|path| {
+                Gain::load(
+                    path,
+                    self.gain_mode.unwrap_or_default(),
+                    self.gain_rotate,
+                    self.gain_flip,
+                    self.assume_unsigned.unwrap_or_default(),
+                )
+            })
+            .transpose()?;
+        Ok(ConvertOptions {
+            endianess: self.endianess.clone().unwrap_or(ArgEndianess::Big),
+            write_backend: self.write_backend.unwrap_or_default(),
+            start_at_frame: self.start_at_frame.unwrap_or(1),
+            stop_at_frame: self.stop_at_frame,
+            frames: None,
+            axis: self.axis.unwrap_or_default(),
+            assume_unsigned: self.assume_unsigned.unwrap_or_default(),
+            processors: SliceProcessors::default(),
+            processing: Processing {
+                dark,
+                gain,
+                fix_outliers: self.fix_outliers,
+                crop: self.crop,
+                bin: self.bin,
+                bin_mode: self.bin_mode.unwrap_or_default(),
+                fourier_crop: self.fourier_crop,
+                target_pixel_size: self.target_pixel_size,
+                rotate: self.rotate,
+                flip: self.flip,
+                gaussian: self.gaussian,
+                median: self.median,
+                subtract_plane: self.subtract_plane.unwrap_or_default(),
+                crop_to: self.crop_to,
+                pad_to: self.pad_to,
+                pad_value: self.pad_value,
+                clip: self.clip,
+                invert: self.invert,
+                normalize: self.normalize.unwrap_or_default(),
+                normalize_stats: self.normalize_stats.unwrap_or_default(),
+                header_stats: self.header_stats.unwrap_or_default(),
+                equalize: self.equalize,
+                dither: self.dither,
+                output_type: self.output_type,
+                stack_levels: None,
+                stack_histogram: None,
+                scalebar: self.scalebar,
+                pixel_size: None,
+                unsigned_input: false,
+                dose_weighted: false,
+            },
+            fix_temporal_outliers: self.fix_temporal_outliers,
+            dose_weighting: self
+                .dose_weight
+                .unwrap_or_default()
+                .then_some(DoseWeighting {
+                    dose_per_frame: self.dose_per_frame,
+                    voltage: self.voltage,
+                }),
+            align: self.align.map(|reference| Alignment {
+                reference,
+                max_shift: self.max_shift.unwrap_or(DEFAULT_MAX_SHIFT),
+            }),
+            projection: self.projection,
+            grouping: match (self.average, self.sum) {
+                (Some(size), _) => Some(Grouping {
+                    size,
+                    mode: Projection::Mean,
+                }),
+                (None, Some(size)) => Some(Grouping {
+                    size,
+                    mode: Projection::Sum,
+                }),
+                (None, None) => None,
+            },
+            running_average: self.running_average,
+            montage: self.montage,
+            export_fft: self.export_fft.unwrap_or_default(),
+            single_file: self.single_file.unwrap_or_default(),
+            max_memory: self.max_memory.map(|MemorySize(bytes)| bytes),
+            io_limit: self
+                .io_limit
+                .map(|mb_per_sec| Arc::new(IoLimiter::new(mb_per_sec * 1e6))),
+            timings: self
+                .timings
+                .unwrap_or_default()
+                .then(|| Arc::new(StageTimings::default())),
+            naming: OutputNaming {
+                template: self.name_template.clone().unwrap_or_default(),
+                extension: self.ext.unwrap_or_default(),
+                suffix: self.suffix.clone().unwrap_or_default(),
+                angles: None,
+            },
+            stats: self.stats.clone().map(|path| StatsExport {
+                path,
+                percentiles: self.stats_percentiles.clone().unwrap_or_default(),
+            }),
+            write_index: self.index.unwrap_or_default(),
+            write_manifest: self.manifest.unwrap_or_default(),
+            cancel: None,
+        })
+    }
+
+    /// Check the combinations of the options, and the values that come from the
+    /// config file. This happens after merging it, so clap can't do it while parsing.
+    fn check_options(&self) -> Result<(), OptionsError> {
+        for &((option, is_set), (required, has_required)) in REQUIRED_OPTIONS {
+            if is_set(self) && !has_required(self) {
+                return Err(OptionsError::Requires { option, required });
+            }
+        }
+        let mut exclusive = EXCLUSIVE_OPTIONS.iter().filter(|(_, is_set)| is_set(self));
+        if let (Some(&(first, _)), Some(&(second, _))) = (exclusive.next(), exclusive.next()) {
+            return Err(OptionsError::Conflict(first, second));
+        }
+        for &((first, first_set), (second, second_set)) in CONFLICTING_OPTIONS {
+            if first_set(self) && second_set(self) {
+                return Err(OptionsError::Conflict(first, second));
+            }
+        }
+        // the values from the config file aren't checked while parsing:
+        if let Some(seconds) = self.settle_time {
+            SETTLE_TIME.check(seconds)?;
+        }
+        if let Some(mb_per_sec) = self.io_limit {
+            IO_LIMIT.check(mb_per_sec)?;
+        }
+        if let Some(path) = &self.stats {
+            check_relative_path("stats", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// The long name of an option, and whether it is set.
+type IsSet = (&'static str, fn(&Args) -> bool);
+
+const DARK: IsSet = ("dark", |args| args.dark.is_some());
+const GAIN: IsSet = ("gain", |args| args.gain.is_some());
+const DOSE_WEIGHT: IsSet = ("dose-weight", |args| args.dose_weight == Some(true));
+const EXPORT_FFT: IsSet = ("export-fft", |args| args.export_fft == Some(true));
+const SINGLE_FILE: IsSet = ("single-file", |args| args.single_file == Some(true));
+
+/// Options that only have an effect together with another one.
+const REQUIRED_OPTIONS: &[(IsSet, IsSet)] = &[
+    (("dark-clamp", |args| args.dark_clamp == Some(true)), DARK),
+    (("dark-rotate", |args| args.dark_rotate.is_some()), DARK),
+    (("dark-flip", |args| args.dark_flip.is_some()), DARK),
+    (("gain-mode", |args| args.gain_mode.is_some()), GAIN),
+    (("gain-rotate", |args| args.gain_rotate.is_some()), GAIN),
+    (("gain-flip", |args| args.gain_flip.is_some()), GAIN),
+    (
+        ("pad-value", |args| args.pad_value.is_some()),
+        ("pad-to", |args| args.pad_to.is_some()),
+    ),
+    (
+        ("dose-per-frame", |args| args.dose_per_frame.is_some()),
+        DOSE_WEIGHT,
+    ),
+    (("voltage", |args| args.voltage.is_some()), DOSE_WEIGHT),
+    (
+        ("max-shift", |args| args.max_shift.is_some()),
+        ("align", |args| args.align.is_some()),
+    ),
+    (
+        ("stats-percentiles", |args| args.stats_percentiles.is_some()),
+        ("stats", |args| args.stats.is_some()),
+    ),
+];
+
+/// Ways of combining the slices, of which only one can be used at a time.
+const EXCLUSIVE_OPTIONS: &[IsSet] = &[
+    ("projection", |args| args.projection.is_some()),
+    ("average", |args| args.average.is_some()),
+    ("sum", |args| args.sum.is_some()),
+    ("running-average", |args| args.running_average.is_some()),
+    ("montage", |args| args.montage.is_some()),
+];
+
+/// Pairs of options that can't be used together.
+const CONFLICTING_OPTIONS: &[(IsSet, IsSet)] = &[
+    (
+        ("montage", |args| args.montage.is_some()),
+        ("scalebar", |args| args.scalebar.is_some()),
+    ),
+    (
+        ("equalize", |args| args.equalize.is_some()),
+        ("normalize", |args| args.normalize.is_some()),
+    ),
+    (EXPORT_FFT, ("projection", |args| args.projection.is_some())),
+    (EXPORT_FFT, ("montage", |args| args.montage.is_some())),
+    (
+        SINGLE_FILE,
+        ("projection", |args| args.projection.is_some()),
+    ),
+    (SINGLE_FILE, ("montage", |args| args.montage.is_some())),
+    (SINGLE_FILE, ("average", |args| args.average.is_some())),
+    (SINGLE_FILE, ("sum", |args| args.sum.is_some())),
+];
+
+/// A numeric option that doesn't have a type of its own, and its valid range.
+struct NumberOption {
+    name: &'static str,
+    valid: fn(f64) -> bool,
+    expected: &'static str,
+}
+
+impl NumberOption {
+    fn check(&self, value: f64) -> Result<f64, OptionsError> {
+        if !(self.valid)(value) {
+            return Err(OptionsError::InvalidValue {
+                option: self.name,
+                value,
+                expected: self.expected,
+            });
+        }
+        Ok(value)
+    }
+
+    fn parse(&self, value: &str) -> Result<f64, Box<dyn Error + Sync + Send>> {
+        Ok(self.check(value.parse()?)?)
+    }
+}
+
+const SETTLE_TIME: NumberOption = NumberOption {
+    name: "settle-time",
+    valid: |value| Duration::try_from_secs_f64(value).is_ok(),
+    expected: "a number of seconds",
+};
+
+const IO_LIMIT: NumberOption = NumberOption {
+    name: "io-limit",
+    valid: |value| value.is_finite() && value > 0.0,
+    expected: "a positive rate",
+};
+
+/// Check that an output file stays inside of the output directory.
+fn check_relative_path(option: &'static str, path: &Path) -> Result<(), OptionsError> {
+    let mut components = path.components();
+    let valid = path.file_name().is_some()
+        && components.all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !valid {
+        return Err(OptionsError::InvalidPath {
+            option,
+            path: path.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+fn parse_relative_path(option: &'static str, value: &str) -> Result<PathBuf, OptionsError> {
+    let path = PathBuf::from(value);
+    check_relative_path(option, &path)?;
+    Ok(path)
+}
+
+#[derive(Debug, thiserror::Error)]
+enum OptionsError {
+    #[error("`--{option}` requires `--{required}`")]
+    Requires {
+        option: &'static str,
+        required: &'static str,
+    },
+    #[error("`--{0}` can't be used together with `--{1}`")]
+    Conflict(&'static str, &'static str),
+    #[error("missing the destination path after the input .mrc file")]
+    MissingDestination,
+    #[error("invalid value {value} for `--{option}`, expected {expected}")]
+    InvalidValue {
+        option: &'static str,
+        value: f64,
+        expected: &'static str,
+    },
+    #[error("invalid path {path:?} for `--{option}`, expected a file in the output directory")]
+    InvalidPath { option: &'static str, path: PathBuf },
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    /// Path to the original .mrc file.
     mrc_path: PathBuf,
 
-    /// Destination path, should be an existing directory.
+    /// Directory containing the converted tiff files.
     dest_path: PathBuf,
 
-    /// Which frame number should be the first to include? Starts at 1.
+    /// The first frame number that was included in the conversion. Starts at 1.
     #[arg(short, long, default_value = "1")]
     start_at_frame: usize,
 
-    /// Which frame number should be the last to include? Starts at 1.
-    #[arg(short, long)]
+    /// The last frame number that was included in the conversion. Starts at 1.
+    #[arg(long)]
     stop_at_frame: Option<usize>,
 
-    /// The endianess of the tiff files that are written.
-    #[arg(short, long, default_value = "big")]
-    endianess: ArgEndianess,
+    /// Name template of the converted files, as used for the conversion.
+    #[arg(long, value_name = "TEMPLATE", default_value_t)]
+    name_template: NameTemplate,
+
+    /// Extension of the converted files.
+    #[arg(long, default_value = "tif")]
+    ext: Extension,
+
+    /// Suffix of the converted files, before the extension.
+    #[arg(long, default_value = "")]
+    suffix: String,
+
+    /// Axis perpendicular to the converted slices, as used for the conversion.
+    #[arg(long, default_value = "z")]
+    axis: Axis,
+
+    /// Read mode 1 data as unsigned, as used for the conversion.
+    #[arg(long)]
+    assume_unsigned: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// Path to an .mrc file to read the slices from.
+    mrc_path: PathBuf,
+
+    /// Directory for the files written during the benchmark, ideally on the
+    /// storage that is going to be used for the real conversions.
+    #[arg(long, default_value_os_t = std::env::temp_dir())]
+    scratch_dir: PathBuf,
+
+    /// Only use the first N slices.
+    #[arg(long, value_name = "N", default_value = "100")]
+    max_slices: usize,
+
+    /// Comma-separated thread counts to measure. [default: powers of two up to the number of CPUs]
+    #[arg(long, value_delimiter = ',')]
+    threads: Vec<usize>,
 }
 
-fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
-    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
-    let logger = env_logger::Builder::from_env(env).build();
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Only accept jobs with input, destination and reference paths inside of this directory.
+    #[arg(long)]
+    root: Option<PathBuf>,
+}
+
+#[cfg(feature = "grpc")]
+#[derive(clap::Args, Debug)]
+struct GrpcArgs {
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    listen: std::net::SocketAddr,
+
+    /// Only accept jobs with input, destination and reference paths inside of this directory.
+    #[arg(long)]
+    root: Option<PathBuf>,
+}
+
+/// Errors reported by the CLI, categorized so that each category maps to its
+/// own exit code.
+#[derive(Debug, thiserror::Error)]
+enum CliError {
+    #[error("invalid input: {0}")]
+    BadInput(Box<dyn Error + Sync + Send>),
+    #[error("unsupported input: {0}")]
+    UnsupportedMode(Box<dyn Error + Sync + Send>),
+    #[error("I/O error: {0}")]
+    Io(Box<dyn Error + Sync + Send>),
+    #[error("{0}")]
+    PartialFailure(Box<dyn Error + Sync + Send>),
+    #[error("{0}")]
+    Other(Box<dyn Error + Sync + Send>),
+}
+
+impl CliError {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            CliError::Other(_) => ExitCode::from(1),
+            // same as clap uses for usage errors:
+            CliError::BadInput(_) => ExitCode::from(2),
+            CliError::UnsupportedMode(_) => ExitCode::from(3),
+            CliError::Io(_) => ExitCode::from(4),
+            CliError::PartialFailure(_) => ExitCode::from(5),
+        }
+    }
+
+    fn inner(&self) -> &(dyn Error + Sync + Send + 'static) {
+        match self {
+            CliError::BadInput(err)
+            | CliError::UnsupportedMode(err)
+            | CliError::Io(err)
+            | CliError::PartialFailure(err)
+            | CliError::Other(err) => err.as_ref(),
+        }
+    }
+}
+
+impl From<Box<dyn Error + Sync + Send>> for CliError {
+    fn from(err: Box<dyn Error + Sync + Send>) -> Self {
+        category(err.as_ref())(err)
+    }
+}
+
+/// The variant of `CliError` that `err` belongs to.
+fn category(err: &(dyn Error + 'static)) -> fn(Box<dyn Error + Sync + Send>) -> CliError {
+    if let Some(err) = err.downcast_ref::<LibraryError>() {
+        // the slice an error happened in doesn't matter for the category:
+        match err.kind() {
+            LibraryError::Convert(err) => category(err),
+            LibraryError::Reference(err) => category(err),
+            LibraryError::Batch(err) => category(err),
+            LibraryError::Header(_) | LibraryError::Read(_) => CliError::BadInput,
+            LibraryError::Write(_) | LibraryError::Io(_) => CliError::Io,
+            err if caused_by_io(err) => CliError::Io,
+            _ => CliError::Other,
+        }
+    } else if let Some(err) = err.downcast_ref::<ConvertError>() {
+        match err {
+            ConvertError::UnsupportedMode { .. } => CliError::UnsupportedMode,
+            ConvertError::InvalidFrameRange { .. }
+            | ConvertError::InvalidFrame { .. }
+            | ConvertError::StatsFileExists { .. }
+            | ConvertError::InvalidCrop { .. }
+            | ConvertError::ScalebarNeeds8Bit
+            | ConvertError::UnknownPixelSize { .. }
+            | ConvertError::UnknownDose
+            | ConvertError::EmptyProjection => CliError::BadInput,
+            ConvertError::StackWriterStopped => CliError::Io,
+            ConvertError::Cancelled => CliError::Other,
+        }
+    } else if let Some(err) = err.downcast_ref::<ReferenceError>() {
+        match err {
+            ReferenceError::UnsupportedMode { .. } => CliError::UnsupportedMode,
+            ReferenceError::Empty { .. } | ReferenceError::DimensionMismatch { .. } => {
+                CliError::BadInput
+            }
+        }
+    } else if let Some(err) = err.downcast_ref::<BatchError>() {
+        match err {
+            BatchError::Failed { .. } => CliError::PartialFailure,
+            BatchError::UnexpectedInput
+            | BatchError::InvalidTemplate { .. }
+            | BatchError::DestinationCollision { .. } => CliError::BadInput,
+        }
+    } else if err.is::<OptionsError>() {
+        CliError::BadInput
+    } else if err.is::<VerifyError>() {
+        CliError::PartialFailure
+    } else if err.is::<WriteError>() || caused_by_io(err) {
+        CliError::Io
+    } else if err.is::<mrc::Error>() || err.is::<toml::de::Error>() {
+        CliError::BadInput
+    } else {
+        CliError::Other
+    }
+}
+
+/// Check the whole error chain, as the reading and writing libraries wrap
+/// `std::io::Error`s into their own error types.
+fn caused_by_io(err: &(dyn Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(e) = current {
+        if e.is::<std::io::Error>() {
+            return true;
+        }
+        current = e.source();
+    }
+    false
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
     let multi = MultiProgress::new();
-    LogWrapper::new(multi.clone(), logger).try_init()?;
+    if let Err(err) = logging::init(&multi, cli.log.file_options(), None) {
+        eprintln!("failed to open the log file: {err}");
+        return CliError::Io(Box::new(err)).exit_code();
+    }
 
-    let args = Args::parse();
+    match run(cli, &multi) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            let err = CliError::from(err);
+            error!("{err}");
+            let mut source = err.inner().source();
+            while let Some(cause) = source {
+                error!("caused by: {cause}");
+                source = cause.source();
+            }
+            err.exit_code()
+        }
+    }
+}
+
+/// What to convert, depending on the given arguments.
+enum Inputs {
+    Single {
+        mrc_path: PathBuf,
+        dest_path: PathBuf,
+    },
+    Batch {
+        inputs: Vec<PathBuf>,
+        dests: Vec<PathBuf>,
+    },
+    Watch {
+        dir: PathBuf,
+        dest_path: PathBuf,
+    },
+}
 
-    convert::convert(
-        args.mrc_path,
-        args.dest_path,
-        args.endianess,
-        args.start_at_frame,
-        args.stop_at_frame,
-        &multi,
-        None,
-    )?;
+fn run(cli: Cli, multi: &MultiProgress) -> Result<(), Box<dyn Error + Sync + Send>> {
+    match cli.command {
+        Some(Command::Verify(args)) => {
+            let report = verify::verify(
+                args.mrc_path,
+                args.dest_path,
+                args.start_at_frame,
+                args.stop_at_frame,
+                &OutputNaming {
+                    template: args.name_template,
+                    extension: args.ext,
+                    suffix: args.suffix,
+                    angles: None,
+                },
+                args.axis,
+                args.assume_unsigned,
+                multi,
+            )?;
+            info!("verified {} files", report.num_checked);
+            report.into_result()?;
+        }
+        Some(Command::Bench(args)) => {
+            let thread_counts = if args.threads.is_empty() {
+                bench::default_thread_counts()
+            } else {
+                args.threads
+            };
+            bench::bench(
+                &args.mrc_path,
+                &args.scratch_dir,
+                args.max_slices,
+                &thread_counts,
+            )?;
+        }
+        Some(Command::Serve(args)) => {
+            serve::serve(
+                &args.listen,
+                args.root.as_deref(),
+                Box::new(|options| {
+                    let args: Args = serde_json::from_value(options)?;
+                    args.convert_options()
+                }),
+            )?;
+        }
+        #[cfg(feature = "grpc")]
+        Some(Command::Grpc(args)) => {
+            grpc::serve(
+                args.listen,
+                args.root.as_deref(),
+                Box::new(|options| {
+                    let args: Args = serde_json::from_value(options)?;
+                    args.convert_options()
+                }),
+            )?;
+        }
+        Some(Command::Completions { shell }) => {
+            let mut cmd = Cli::command();
+            let name = cmd.get_name().to_owned();
+            clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+        }
+        Some(Command::Manpage) => {
+            clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
+        }
+        None => {
+            let args = cli.convert.with_config_file()?;
+            if args.nice.unwrap_or_default() {
+                throttle::lower_priority();
+            }
+            let template = args
+                .dest_template
+                .as_deref()
+                .unwrap_or(batch::DEFAULT_DEST_TEMPLATE);
+            let inputs = match (&args.files_from, &args.watch, args.paths.as_slice()) {
+                (Some(list), _, [dest_path]) => {
+                    let inputs = batch::read_file_list(list)?;
+                    let dests = batch::destinations(&inputs, dest_path, template)?;
+                    Inputs::Batch { inputs, dests }
+                }
+                (None, Some(dir), [dest_path]) => Inputs::Watch {
+                    dir: dir.clone(),
+                    dest_path: dest_path.clone(),
+                },
+                (None, None, [mrc_path, dest_path]) => Inputs::Single {
+                    mrc_path: mrc_path.clone(),
+                    dest_path: dest_path.clone(),
+                },
+                (None, None, _) => return Err(OptionsError::MissingDestination.into()),
+                _ => return Err(BatchError::UnexpectedInput.into()),
+            };
+            let progress = args.progress.clone().unwrap_or_default().sink(multi);
+            let options = args.convert_options()?;
+            let res = match inputs {
+                Inputs::Single {
+                    mrc_path,
+                    dest_path,
+                } => convert::convert(mrc_path, dest_path, &options, &*progress),
+                Inputs::Batch { inputs, dests } => {
+                    batch::convert_batch(&inputs, &dests, args.sequential, &options, &*progress)
+                }
+                Inputs::Watch { dir, dest_path } => watch::watch(
+                    &dir,
+                    &dest_path,
+                    template,
+                    Duration::from_secs_f64(args.settle_time.unwrap_or(5.0)),
+                    &options,
+                    &*progress,
+                ),
+            };
+            if let Err(e) = &res {
+                progress.error(&e.to_string());
+            }
+            // stdout is reserved for the progress, which may be parsed as JSON:
+            if let Some(timings) = &options.timings {
+                eprint!("{timings}");
+            }
+            res?;
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cli: &[&str], config: &str) -> Args {
+        let cli = Cli::try_parse_from([&["mrc-to-tiff"], cli].concat()).unwrap();
+        cli.convert.or(toml::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn positional_paths() {
+        use std::path::Path;
+        assert_eq!(
+            args(&["in.mrc", "out"], "").paths,
+            [Path::new("in.mrc"), Path::new("out")]
+        );
+        assert_eq!(
+            args(&["--files-from", "-", "out"], "").paths,
+            [Path::new("out")]
+        );
+        assert_eq!(
+            args(&["--watch", "data", "out"], "").paths,
+            [Path::new("out")]
+        );
+        assert!(Cli::try_parse_from(["mrc-to-tiff", "in.mrc", "out", "extra"]).is_err());
+    }
+
+    #[test]
+    fn batch_options() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["mrc-to-tiff"], args].concat());
+        assert!(parse(&["--dest-template", "{name}", "in.mrc", "out"]).is_err());
+        assert!(parse(&["--files-from", "-", "--dest-template", "{name}", "out"]).is_ok());
+        assert!(parse(&["--watch", "data", "--dest-template", "{name}", "out"]).is_ok());
+        assert!(parse(&["--watch", "data", "--settle-time", "-1", "out"]).is_err());
+        assert!(parse(&["--watch", "data", "--settle-time", "NaN", "out"]).is_err());
+        assert!(parse(&["--watch", "data", "--settle-time", "0.5", "out"]).is_ok());
+        assert!(matches!(
+            args(&["--watch", "data", "out"], "settle-time = -1.0").check_options(),
+            Err(OptionsError::InvalidValue {
+                option: "settle-time",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn io_limit() {
+        let parse = |limit| Cli::try_parse_from(["mrc-to-tiff", "--io-limit", limit, "in", "out"]);
+        assert!(parse("0").is_err());
+        assert!(parse("-5").is_err());
+        assert!(parse("inf").is_err());
+        assert!(parse("12.5").is_ok());
+        assert!(matches!(
+            args(&["in.mrc", "out"], "io-limit = 0.0").check_options(),
+            Err(OptionsError::InvalidValue {
+                option: "io-limit",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn stats_path() {
+        let parse = |path| Cli::try_parse_from(["mrc-to-tiff", "--stats", path, "in", "out"]);
+        assert!(parse("stats.csv").is_ok());
+        assert!(parse("./logs/stats.csv").is_ok());
+        assert!(parse("/tmp/stats.csv").is_err());
+        assert!(parse("../stats.csv").is_err());
+        assert!(parse("logs/../../stats.csv").is_err());
+        assert!(parse("").is_err());
+        assert!(matches!(
+            args(&["in.mrc", "out"], "stats = \"../stats.csv\"").check_options(),
+            Err(OptionsError::InvalidPath {
+                option: "stats",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn flags_from_the_config_file() {
+        assert_eq!(args(&["in.mrc", "out"], "index = true").index, Some(true));
+        assert_eq!(
+            args(&["in.mrc", "out", "--index=false"], "index = true").index,
+            Some(false)
+        );
+        assert_eq!(
+            args(&["in.mrc", "out", "--index"], "index = false").index,
+            Some(true)
+        );
+        assert!(
+            args(
+                &["in.mrc", "out", "--dose-weight=false", "--voltage", "200"],
+                ""
+            )
+            .check_options()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn combinations_from_the_config_file() {
+        assert!(
+            args(&["in.mrc", "out", "--gain-flip", "h"], "")
+                .check_options()
+                .is_err()
+        );
+        assert!(
+            args(
+                &["in.mrc", "out", "--gain-flip", "h"],
+                "gain = \"gain.mrc\""
+            )
+            .check_options()
+            .is_ok()
+        );
+        assert!(matches!(
+            args(&["in.mrc", "out", "--dark-clamp"], "").check_options(),
+            Err(OptionsError::Requires {
+                option: "dark-clamp",
+                required: "dark"
+            })
+        ));
+        assert!(
+            args(&["in.mrc", "out", "--dark-clamp"], "dark = \"dark.mrc\"")
+                .check_options()
+                .is_ok()
+        );
+        assert!(
+            args(&["in.mrc", "out", "--sum", "4"], "")
+                .check_options()
+                .is_ok()
+        );
+        assert!(matches!(
+            args(&["in.mrc", "out", "--sum", "4"], "projection = \"max\"").check_options(),
+            Err(OptionsError::Conflict("projection", "sum"))
+        ));
+        assert!(matches!(
+            args(&["in.mrc", "out", "--average", "2", "--sum", "4"], "").check_options(),
+            Err(OptionsError::Conflict("average", "sum"))
+        ));
+        assert!(matches!(
+            args(&["in.mrc", "out"], "average = 2\nmontage = \"4x4\"").check_options(),
+            Err(OptionsError::Conflict("average", "montage"))
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tiff::{
+    decoder::Decoder,
+    tags::{ByteOrder, Tag},
+};
+
+use crate::Error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestError {
+    #[error("the pixel data of {path:?} is not stored in one piece")]
+    NotContiguous { path: PathBuf },
+    #[error("the pages of {path:?} differ in size")]
+    MixedPages { path: PathBuf },
+    #[error("{path:?} has an unsupported sample format {format} with {bits} bits")]
+    UnsupportedSampleFormat {
+        path: PathBuf,
+        format: u16,
+        bits: u16,
+    },
+}
+
+/// Where the pixels of each written file are, so distributed readers like
+/// LiberTEM or Dask can map them directly, without decoding the tiff headers.
+#[derive(Debug, Serialize)]
+pub struct ChunkManifest {
+    pub files: Vec<Chunk>,
+}
+
+/// The uncompressed pixel data of one file.
+#[derive(Debug, Serialize)]
+pub struct Chunk {
+    pub file: String,
+    /// Of the first pixel, in bytes from the start of the file
+    pub offset: u64,
+    /// In bytes
+    pub size: u64,
+    /// Height and width in row-major order, preceded by the number of pages
+    /// for files with several
+    pub shape: Vec<usize>,
+    /// NumPy type string, like `>u2` for big endian 16 bit unsigned integers
+    pub dtype: String,
+}
+
+impl Chunk {
+    /// Read the layout of the written file `file` in `dir` from its headers.
+    pub fn read(dir: &Path, file: &str) -> Result<Self, Error> {
+        let path = dir.join(file);
+        let mut decoder = Decoder::new(BufReader::new(File::open(&path)?))?;
+        let (width, height) = decoder.dimensions()?;
+        let mut offsets = Vec::new();
+        let mut counts = Vec::new();
+        let mut num_pages = 0;
+        loop {
+            if decoder.dimensions()? != (width, height) {
+                return Err(ManifestError::MixedPages { path }.into());
+            }
+            offsets.extend(decoder.get_tag_u64_vec(Tag::StripOffsets)?);
+            counts.extend(decoder.get_tag_u64_vec(Tag::StripByteCounts)?);
+            num_pages += 1;
+            if !decoder.more_images() {
+                break;
+            }
+            decoder.next_image()?;
+        }
+        let contiguous = offsets
+            .windows(2)
+            .zip(&counts)
+            .all(|(pair, count)| pair[0] + count == pair[1]);
+        let (Some(&offset), true) = (offsets.first(), contiguous) else {
+            return Err(ManifestError::NotContiguous { path }.into());
+        };
+        let bits = decoder.get_tag_unsigned::<u16>(Tag::BitsPerSample)?;
+        // unsigned integers, unless specified otherwise:
+        let format = decoder
+            .find_tag_unsigned::<u16>(Tag::SampleFormat)?
+            .unwrap_or(1);
+        let kind = match format {
+            1 => 'u',
+            2 => 'i',
+            3 => 'f',
+            _ => return Err(ManifestError::UnsupportedSampleFormat { path, format, bits }.into()),
+        };
+        let byte_order = match (bits, decoder.byte_order()) {
+            (8, _) => '|',
+            (_, ByteOrder::BigEndian) => '>',
+            (_, ByteOrder::LittleEndian) => '<',
+        };
+        Ok(Self {
+            file: file.to_owned(),
+            offset,
+            size: counts.iter().sum(),
+            shape: match num_pages {
+                1 => vec![height as usize, width as usize],
+                _ => vec![num_pages, height as usize, width as usize],
+            },
+            dtype: format!("{byte_order}{kind}{}", bits / 8),
+        })
+    }
+}
+
+impl ChunkManifest {
+    /// Write as pretty-printed JSON to `path`, which must not exist yet.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut out = BufWriter::new(File::create_new(path)?);
+        serde_json::to_writer_pretty(&mut out, self).map_err(std::io::Error::from)?;
+        writeln!(out)?;
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::process::{BinMode, Image, bin};
+
+/// Longest side of a single tile, slices are downsampled until they fit.
+const TILE_SIZE: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid montage layout {0:?}, expected COLSxROWS, like 8x6")]
+pub struct ParseMontageError(String);
+
+/// Layout of the overview images, each showing `cols * rows` slices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Montage {
+    pub cols: usize,
+    pub rows: usize,
+}
+
+impl Montage {
+    pub fn tiles(&self) -> usize {
+        self.cols * self.rows
+    }
+
+    /// Width and height of a montage of slices with the given dimensions.
+    pub fn dimensions(&self, width: usize, height: usize) -> (usize, usize) {
+        let factor = width.max(height).div_ceil(TILE_SIZE).max(1);
+        (width / factor * self.cols, height / factor * self.rows)
+    }
+
+    /// Downsample a processed slice so that it fits into a tile.
+    pub fn downsample(&self, image: &Image) -> Image {
+        let factor = image.width.max(image.height).div_ceil(TILE_SIZE);
+        if factor > 1 {
+            bin(image, factor, BinMode::Mean)
+        } else {
+            image.clone()
+        }
+    }
+
+    /// Place the `tiles` row by row; the remaining space of the last montage
+    /// is filled with the lowest value, so it doesn't affect the normalization.
+    pub fn compose(&self, tiles: &[Image]) -> Image {
+        let tile_width = tiles.iter().map(|t| t.width).max().unwrap_or(0);
+        let tile_height = tiles.iter().map(|t| t.height).max().unwrap_or(0);
+        let width = tile_width * self.cols;
+        let height = tile_height * self.rows;
+        let background = tiles
+            .iter()
+            .flat_map(|t| t.data.iter().copied())
+            .reduce(f32::min)
+            .unwrap_or(0.0);
+        let mut data = vec![background; width * height];
+        for (idx, tile) in tiles.iter().enumerate() {
+            let x0 = (idx % self.cols) * tile_width;
+            let y0 = (idx / self.cols) * tile_height;
+            for (y, row) in tile.data.chunks_exact(tile.width).enumerate() {
+                data[(y0 + y) * width + x0..][..tile.width].copy_from_slice(row);
+            }
+        }
+        Image {
+            data,
+            width,
+            height,
+        }
+    }
+}
+
+impl FromStr for Montage {
+    type Err = ParseMontageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseMontageError(s.to_owned());
+        let (cols, rows) = s.split_once(['x', 'X']).ok_or_else(err)?;
+        let cols: usize = cols.trim().parse().map_err(|_| err())?;
+        let rows: usize = rows.trim().parse().map_err(|_| err())?;
+        if cols == 0 || rows == 0 {
+            return Err(err());
+        }
+        Ok(Montage { cols, rows })
+    }
+}
+
+impl TryFrom<String> for Montage {
+    type Error = ParseMontageError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Montage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.cols, self.rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts() {
+        assert_eq!(
+            "8x6".parse::<Montage>().ok(),
+            Some(Montage { cols: 8, rows: 6 })
+        );
+        assert_eq!(
+            "4 X 2".parse::<Montage>().ok(),
+            Some(Montage { cols: 4, rows: 2 })
+        );
+        for invalid in ["8", "0x4", "4x0", "ax2", "4x-1", ""] {
+            assert!(invalid.parse::<Montage>().is_err(), "{invalid}");
+        }
+        assert_eq!(Montage { cols: 3, rows: 2 }.to_string(), "3x2");
+    }
+
+    #[test]
+    fn compose_fills_the_remaining_tiles() {
+        let montage = Montage { cols: 2, rows: 2 };
+        let tile = |v: f32| Image {
+            data: vec![v; 4],
+            width: 2,
+            height: 2,
+        };
+        let image = montage.compose(&[tile(1.0), tile(2.0), tile(3.0)]);
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!(&image.data[..4], [1.0, 1.0, 2.0, 2.0]);
+        assert_eq!(&image.data[12..], [3.0, 3.0, 1.0, 1.0]);
+        assert_eq!(montage.dimensions(1024, 512), (512, 256));
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+};
+
+use eframe::egui;
+use log::{info, warn};
+use mrc::MrcMmap;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use mrc_to_tiff::{
+    fft::{SPECTRUM_SIZE, log_power_spectrum},
+    process::{BinMode, Image, Partial, Processing, Projection, bin},
+    read::Volume3D,
+    stats::{Histogram, Stats},
+};
+
+use crate::render::{DisplayScaling, auto_range, render_to_rgb, robust_range};
+
+/// Number of bins of the preview histogram
+const HISTOGRAM_BINS: usize = 256;
+
+/// Longest side of the preview texture; larger slices are binned for
+/// display, unless shown at full resolution
+pub const PREVIEW_SIZE: usize = 2048;
+
+/// Memory for the values of cached previews, in bytes
+const CACHE_BYTES: usize = 512 << 20;
+
+/// Longest side of the thumbnails used for navigating
+const THUMBNAIL_SIZE: usize = 96;
+
+/// Everything the preview image depends on.
+#[derive(Debug, Clone)]
+pub struct PreviewRequest {
+    pub source_path: PathBuf,
+    pub slice_position: usize,
+    /// Show a projection over these slices instead of the current one
+    pub projection: Option<(Projection, RangeInclusive<usize>)>,
+    pub quantile: f32,
+    /// The automatic range of the loaded data is used if `None`
+    pub display_range: Option<(f32, f32)>,
+    pub scaling: DisplayScaling,
+    pub full_resolution: bool,
+    /// Also render the slice as processed for the export, for comparing
+    pub processing: Option<Processing>,
+    /// Also compute the power spectrum of the shown data
+    pub power_spectrum: bool,
+    /// Leave the display range and scaling to `gpu::GpuPreview`, only
+    /// preparing the shown values
+    #[cfg(feature = "gpu")]
+    pub gpu: bool,
+}
+
+impl PreviewRequest {
+    /// Equal for requests that render the same preview
+    pub fn cache_key(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+/// A slice after the export processing, which can change its size.
+#[derive(Clone)]
+pub struct ProcessedPreview {
+    pub texture: egui::TextureHandle,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The preview image, rendered on the CPU, or the values to render on the GPU.
+#[derive(Clone)]
+pub enum PreviewTexture {
+    Cpu(egui::TextureHandle),
+    /// Binned like the texture would be
+    #[cfg(feature = "gpu")]
+    Gpu {
+        values: Arc<[f32]>,
+        width: usize,
+        height: usize,
+    },
+}
+
+/// The preview texture, with the data it was rendered from.
+#[derive(Clone)]
+pub struct RenderedPreview {
+    pub texture: PreviewTexture,
+    /// At full resolution, for the readout and line profiles
+    pub values: Arc<[f32]>,
+    pub stats: Stats,
+    pub histogram: Histogram,
+    pub auto_range: (f32, f32),
+    /// Only for single slices, not projections
+    pub processed: Option<ProcessedPreview>,
+    pub spectrum: Option<ProcessedPreview>,
+}
+
+/// The most recently used previews, so that going back and forth between
+/// slices doesn't render them again.
+#[derive(Default)]
+pub struct PreviewCache {
+    // most recently used first:
+    entries: VecDeque<(String, RenderedPreview)>,
+}
+
+impl PreviewCache {
+    pub fn get(&mut self, key: &str) -> Option<RenderedPreview> {
+        let idx = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(idx)?;
+        let rendered = entry.1.clone();
+        self.entries.push_front(entry);
+        Some(rendered)
+    }
+
+    /// Add a preview, dropping the least recently used ones beyond `CACHE_BYTES`.
+    pub fn insert(&mut self, key: String, rendered: RenderedPreview) {
+        self.entries.retain(|(k, _)| *k != key);
+        self.entries.push_front((key, rendered));
+        let mut total = 0;
+        let keep = self
+            .entries
+            .iter()
+            .take_while(|(_, rendered)| {
+                total += rendered.values.len() * size_of::<f32>();
+                total <= CACHE_BYTES
+            })
+            .count();
+        // the newest one stays, even if it's larger than the whole cache:
+        self.entries.truncate(keep.max(1));
+    }
+}
+
+/// A downsampled slice, for navigating through the stack.
+pub struct Thumbnail {
+    pub slice_position: usize,
+    pub texture: egui::TextureHandle,
+}
+
+pub type PreviewResult = Result<RenderedPreview, Box<dyn Error + Send + Sync>>;
+
+/// Load and render the preview in a background thread, so that the UI stays
+/// responsive for large slices; a repaint is requested once it's done.
+pub fn spawn(request: PreviewRequest, ctx: &egui::Context) -> Receiver<PreviewResult> {
+    let (snd, rcv) = mpsc::channel();
+    let ctx = ctx.clone();
+    thread::spawn(move || {
+        let result = render(&request, &ctx);
+        // the receiver is gone if the file was closed in the meantime:
+        let _ = snd.send(result);
+        ctx.request_repaint();
+    });
+    rcv
+}
+
+fn render(request: &PreviewRequest, ctx: &egui::Context) -> PreviewResult {
+    let mmap = MrcMmap::open(&request.source_path)?;
+    let volume = Volume3D::new(mmap.read_view()?);
+    let (nx, ny, _) = volume.dimensions();
+    let (slice, processed): (Vec<f32>, _) = match &request.projection {
+        Some((projection, range)) => {
+            info!(
+                "projecting slices {} to {}",
+                range.start() + 1,
+                range.end() + 1
+            );
+            (project(&volume, range.clone(), *projection), None)
+        }
+        None => {
+            info!("loading slice {}", request.slice_position);
+            let raw = volume.get_slice(request.slice_position)?;
+            let processed = request
+                .processing
+                .as_ref()
+                .map(|processing| processing.apply(&raw, nx, ny));
+            (Image::from_slice(&raw, nx, ny).data, processed)
+        }
+    };
+    // with its own display range, as the processing may have changed the values:
+    let processed = processed.map(|image| {
+        let range = auto_range(&image.data, request.quantile);
+        let img = render_to_rgb(
+            &image.data,
+            image.width,
+            image.height,
+            range,
+            request.scaling,
+        );
+        ProcessedPreview {
+            texture: ctx.load_texture("processed_preview_texture", img, Default::default()),
+            width: image.width,
+            height: image.height,
+        }
+    });
+    let auto_range = auto_range(&slice, request.quantile);
+    let stats = Stats::of(&slice);
+    let mut histogram = Histogram::new(stats.min, stats.max, HISTOGRAM_BINS);
+    histogram.add(&slice);
+
+    let image = Image {
+        data: slice,
+        width: nx,
+        height: ny,
+    };
+    let factor = if request.full_resolution {
+        1
+    } else {
+        nx.max(ny).div_ceil(PREVIEW_SIZE)
+    };
+    let spectrum = request.power_spectrum.then(|| {
+        let spectrum = log_power_spectrum(&image, SPECTRUM_SIZE);
+        let range = robust_range(&spectrum.data, 0.01, 0.999);
+        let img = render_to_rgb(
+            &spectrum.data,
+            spectrum.width,
+            spectrum.height,
+            range,
+            DisplayScaling::Linear,
+        );
+        ProcessedPreview {
+            texture: ctx.load_texture("spectrum_texture", img, Default::default()),
+            width: spectrum.width,
+            height: spectrum.height,
+        }
+    });
+    let binned = (factor > 1).then(|| bin(&image, factor, BinMode::Mean));
+    let values: Arc<[f32]> = image.data.into();
+    let texture = match binned {
+        #[cfg(feature = "gpu")]
+        Some(binned) if request.gpu => PreviewTexture::Gpu {
+            values: binned.data.into(),
+            width: binned.width,
+            height: binned.height,
+        },
+        #[cfg(feature = "gpu")]
+        None if request.gpu => PreviewTexture::Gpu {
+            values: Arc::clone(&values),
+            width: nx,
+            height: ny,
+        },
+        binned => {
+            let (shown, width, height) = match &binned {
+                Some(binned) => (&binned.data[..], binned.width, binned.height),
+                None => (&values[..], nx, ny),
+            };
+            let range = request.display_range.unwrap_or(auto_range);
+            let img = render_to_rgb(shown, width, height, range, request.scaling);
+            PreviewTexture::Cpu(ctx.load_texture("preview_texture", img, Default::default()))
+        }
+    };
+    Ok(RenderedPreview {
+        texture,
+        values,
+        stats,
+        histogram,
+        auto_range,
+        processed,
+        spectrum,
+    })
+}
+
+/// Render thumbnails of the slices at `positions` in a background thread,
+/// sending each one as soon as it's ready. Dropping the receiver stops it.
+pub fn spawn_thumbnails(
+    source_path: PathBuf,
+    positions: Vec<usize>,
+    quantile: f32,
+    scaling: DisplayScaling,
+    ctx: &egui::Context,
+) -> Receiver<Thumbnail> {
+    let (snd, rcv) = mpsc::channel();
+    let ctx = ctx.clone();
+    thread::spawn(move || {
+        if let Err(e) = render_thumbnails(&source_path, positions, quantile, scaling, &ctx, &snd) {
+            warn!("failed to render the thumbnails of {source_path:?}: {e}");
+        }
+    });
+    rcv
+}
+
+fn render_thumbnails(
+    source_path: &Path,
+    positions: Vec<usize>,
+    quantile: f32,
+    scaling: DisplayScaling,
+    ctx: &egui::Context,
+    snd: &Sender<Thumbnail>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mmap = MrcMmap::open(source_path)?;
+    let volume = Volume3D::new(mmap.read_view()?);
+    let (nx, ny, _) = volume.dimensions();
+    let factor = nx.max(ny).div_ceil(THUMBNAIL_SIZE);
+    for slice_position in positions {
+        let image = Image::from_slice(&volume.get_slice(slice_position)?, nx, ny);
+        let image = if factor > 1 {
+            bin(&image, factor, BinMode::Mean)
+        } else {
+            image
+        };
+        let range = auto_range(&image.data, quantile);
+        let img = render_to_rgb(&image.data, image.width, image.height, range, scaling);
+        let name = format!("thumbnail {slice_position}");
+        let texture = ctx.load_texture(name, img, Default::default());
+        if snd
+            .send(Thumbnail {
+                slice_position,
+                texture,
+            })
+            .is_err()
+        {
+            break;
+        }
+        ctx.request_repaint();
+    }
+    Ok(())
+}
+
+/// Projection of the slices in `range`, for previewing. An empty range gives
+/// an all-zero image.
+fn project(volume: &Volume3D, range: RangeInclusive<usize>, projection: Projection) -> Vec<f32> {
+    let (nx, ny, _) = volume.dimensions();
+    let num_slices = range.clone().count();
+    range
+        .into_par_iter()
+        .map(|z| Partial::from(Image::from_slice(&volume.get_slice(z).unwrap(), nx, ny)))
+        .reduce_with(|a, b| projection.combine(a, b))
+        .map(|image| projection.finish(image, num_slices).data)
+        .unwrap_or_else(|| vec![0.0; nx * ny])
+}
@@ -0,0 +1,50 @@
+// Maps the values of the preview to gray values, like `render::render_to_rgb`.
+
+struct Params {
+    vmin: f32,
+    vmax: f32,
+    // 0: linear, 1: log, 2: sqrt, see `gpu::GpuPreview::render`
+    scaling: u32,
+}
+
+@group(0) @binding(0) var values: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+// how many orders of magnitude the log scaling spans, as in `DisplayScaling::apply`:
+const LOG_RANGE: f32 = 1000.0;
+
+// a single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> @builtin(position) vec4<f32> {
+    let corner = vec2<f32>(f32((idx << 1u) & 2u), f32(idx & 2u));
+    return vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// the target is sRGB like the textures of egui, so the gray value is
+// converted to linear light here and back when it's stored:
+fn to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        return c / 12.92;
+    }
+    return pow((c + 0.055) / 1.055, 2.4);
+}
+
+@fragment
+fn fs_main(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    if params.vmax <= params.vmin {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let value = textureLoad(values, vec2<i32>(pos.xy), 0).r;
+    var c = clamp((value - params.vmin) / (params.vmax - params.vmin), 0.0, 1.0);
+    switch params.scaling {
+        case 1u: {
+            c = log(1.0 + LOG_RANGE * c) / log(1.0 + LOG_RANGE);
+        }
+        case 2u: {
+            c = sqrt(c);
+        }
+        default: {}
+    }
+    let gray = to_linear(c);
+    return vec4<f32>(gray, gray, gray, 1.0);
+}
@@ -0,0 +1,1657 @@
+use std::{fmt::Display, ops::Range, str::FromStr};
+
+use log::{debug, warn};
+#[cfg(feature = "native")]
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Deserialize;
+
+use crate::{
+    Error,
+    fft::fourier_crop,
+    read::{Slice, Volume3D},
+    reference::{Dark, Gain},
+    scalebar::ScaleBar,
+    stats::{Histogram, Stats, StatsAccumulator, percentile},
+    write::{Sample, SampleType, TiffMetadata},
+};
+
+/// A single 2D image in row-major order, as it passes through the processing steps.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub data: Vec<f32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Image {
+    pub fn from_slice(data: &Slice, width: usize, height: usize) -> Self {
+        Self::from_slice_in(data, width, height, Vec::with_capacity(data.len()))
+    }
+
+    /// Like `from_slice`, reusing the allocation of `buffer`.
+    pub fn from_slice_in(data: &Slice, width: usize, height: usize, mut buffer: Vec<f32>) -> Self {
+        buffer.clear();
+        data.extend_f32(&mut buffer);
+        Self {
+            data: buffer,
+            width,
+            height,
+        }
+    }
+
+    pub fn to_samples<T: Sample>(&self) -> Vec<T> {
+        self.data.iter().map(|&v| T::from_f32(v)).collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinMode {
+    /// Average of the binned pixels
+    #[default]
+    Mean,
+    /// Sum of the binned pixels, written as 32bit integers
+    Sum,
+    /// Median of the binned pixels, which ignores hot pixels. The upper of the
+    /// two middle values for even factors, so integer data stays integer.
+    Median,
+}
+
+/// Clockwise rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(try_from = "u16")]
+pub enum Rotation {
+    #[value(name = "90")]
+    R90,
+    #[value(name = "180")]
+    R180,
+    #[value(name = "270")]
+    R270,
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            90 => Ok(Rotation::R90),
+            180 => Ok(Rotation::R180),
+            270 => Ok(Rotation::R270),
+            _ => Err(format!("invalid rotation {value}, expected 90, 180 or 270")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flip {
+    /// Mirror left and right
+    H,
+    /// Mirror top and bottom
+    V,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Invert {
+    /// Negate the pixel values, or subtract unsigned ones from their maximum
+    Data,
+    /// Keep the values, but mark the files as WhiteIsZero
+    Photometric,
+}
+
+/// A projection in progress. The values are accumulated as `f64`, so that
+/// summing many slices doesn't lose precision before the final conversion.
+pub struct Partial {
+    data: Vec<f64>,
+    width: usize,
+    height: usize,
+}
+
+impl From<Image> for Partial {
+    fn from(image: Image) -> Self {
+        let Image {
+            data,
+            width,
+            height,
+        } = image;
+        Partial {
+            data: data.into_iter().map(f64::from).collect(),
+            width,
+            height,
+        }
+    }
+}
+
+impl From<&Image> for Partial {
+    fn from(image: &Image) -> Self {
+        let data = image.data.iter().copied().map(f64::from).collect();
+        Partial {
+            data,
+            width: image.width,
+            height: image.height,
+        }
+    }
+}
+
+/// How the slices are combined into a single image by a projection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Projection {
+    /// Maximum intensity
+    Max,
+    /// Average intensity, written as 32bit floats
+    Mean,
+    /// Sum of intensities, written as 32bit integers
+    Sum,
+}
+
+impl Projection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Projection::Max => "max",
+            Projection::Mean => "mean",
+            Projection::Sum => "sum",
+        }
+    }
+
+    /// Combine two partial projections; for `Mean`, the result needs to be
+    /// divided by the number of slices at the end.
+    pub fn combine(&self, mut a: Partial, b: Partial) -> Partial {
+        match self {
+            Projection::Max => a
+                .data
+                .iter_mut()
+                .zip(b.data)
+                .for_each(|(a, b)| *a = a.max(b)),
+            Projection::Mean | Projection::Sum => {
+                a.data.iter_mut().zip(b.data).for_each(|(a, b)| *a += b)
+            }
+        }
+        a
+    }
+
+    pub fn finish(&self, partial: Partial, num_slices: usize) -> Image {
+        let Partial {
+            data,
+            width,
+            height,
+        } = partial;
+        let data = if *self == Projection::Mean {
+            data.into_iter()
+                .map(|v| (v / num_slices as f64) as f32)
+                .collect()
+        } else {
+            data.into_iter().map(|v| v as f32).collect()
+        };
+        Image {
+            data,
+            width,
+            height,
+        }
+    }
+
+    pub fn output_sample_type(&self, processing: &Processing) -> SampleType {
+        if processing.output_type.is_some()
+            || processing.normalize != Normalize::None
+            || processing.equalize.is_some()
+        {
+            return processing.output_sample_type();
+        }
+        match self {
+            Projection::Max => processing.output_sample_type(),
+            Projection::Mean => SampleType::F32,
+            Projection::Sum => SampleType::I32,
+        }
+    }
+}
+
+/// Intensity normalization, applied after the geometric transforms.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Normalize {
+    #[default]
+    None,
+    /// Rescale the range min..=max to 0..=1
+    MinMax,
+    /// Rescale the range between the P-th and (100-P)-th percentile to 0..=1,
+    /// clipping values outside of it
+    Percentile(f32),
+    /// Subtract the mean and divide by the standard deviation
+    ZScore,
+}
+
+impl Normalize {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Normalize::None => "none",
+            Normalize::MinMax => "minmax",
+            Normalize::Percentile(_) => "percentile",
+            Normalize::ZScore => "zscore",
+        }
+    }
+
+    /// Does this normalization map the data to the range 0..=1?
+    fn is_unit_range(&self) -> bool {
+        matches!(self, Normalize::MinMax | Normalize::Percentile(_))
+    }
+
+    /// Compute the levels for this normalization from the data of a single slice.
+    fn levels_of(&self, data: &[f32]) -> Levels {
+        match self {
+            Normalize::Percentile(p) => {
+                Levels::between(percentile(data, *p), percentile(data, 100.0 - p), true)
+            }
+            _ => self.levels_from_stats(&Stats::of(data)),
+        }
+    }
+
+    fn levels_from_stats(&self, stats: &Stats) -> Levels {
+        match self {
+            Normalize::None => Levels {
+                offset: 0.0,
+                scale: 1.0,
+                clip: false,
+            },
+            Normalize::MinMax => Levels::between(stats.min, stats.max, false),
+            Normalize::ZScore => Levels::between(stats.mean, stats.mean + stats.std, false),
+            Normalize::Percentile(_) => unreachable!("percentiles are not part of `Stats`"),
+        }
+    }
+}
+
+impl FromStr for Normalize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Normalize::None),
+            "minmax" => Ok(Normalize::MinMax),
+            "zscore" => Ok(Normalize::ZScore),
+            _ => {
+                let p = s
+                    .strip_prefix("percentile:")
+                    .and_then(|p| p.parse::<f32>().ok())
+                    .filter(|p| (0.0..50.0).contains(p))
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid normalization {s:?}, expected none, minmax, \
+                            percentile:P (with 0 <= P < 50) or zscore"
+                        )
+                    })?;
+                Ok(Normalize::Percentile(p))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Normalize {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Which data the normalization statistics are computed from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsScope {
+    /// Each slice is normalized on its own
+    #[default]
+    Slice,
+    /// Statistics are computed once over all selected slices
+    Stack,
+}
+
+/// Histogram equalization, replacing the normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Equalize {
+    /// Flatten the histogram of the whole slice
+    Global,
+    /// Contrast-limited adaptive histogram equalization, per region of the slice
+    Clahe,
+}
+
+/// Dithering when rounding to an integer output type, which avoids banding
+/// in smooth gradients, especially for 8-bit output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    /// Error diffusion, spreading the rounding error of each pixel to its
+    /// neighbors that weren't rounded yet
+    FloydSteinberg,
+    /// A fixed 4x4 threshold pattern, which doesn't depend on the neighbors
+    Ordered,
+}
+
+/// Linear intensity mapping `(v - offset) / scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Levels {
+    pub offset: f32,
+    pub scale: f32,
+    /// Clip the result to 0..=1
+    pub clip: bool,
+}
+
+impl Levels {
+    /// Map `low` to 0 and `high` to 1.
+    fn between(low: f32, high: f32, clip: bool) -> Self {
+        let scale = high - low;
+        Self {
+            offset: low,
+            scale: if scale > 0.0 { scale } else { 1.0 },
+            clip,
+        }
+    }
+
+    fn apply(&self, data: &mut [f32], unit_scale: f32) {
+        for v in data.iter_mut() {
+            let mut normalized = (*v - self.offset) / self.scale;
+            if self.clip {
+                normalized = normalized.clamp(0.0, 1.0);
+            }
+            *v = normalized * unit_scale;
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid region {0:?}, expected X,Y,W,H")]
+pub struct ParseCropError(String);
+
+/// A rectangular region of a slice, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Crop {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Crop {
+    /// Does the region lie completely inside of a slice of size `nx`x`ny`?
+    pub fn fits_into(&self, nx: usize, ny: usize) -> bool {
+        self.width > 0 && self.height > 0 && self.x + self.width <= nx && self.y + self.height <= ny
+    }
+}
+
+impl FromStr for Crop {
+    type Err = ParseCropError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<usize> = s
+            .split(',')
+            .map(|part| part.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| ParseCropError(s.to_owned()))?;
+        match parts[..] {
+            [x, y, width, height] => Ok(Crop {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(ParseCropError(s.to_owned())),
+        }
+    }
+}
+
+impl TryFrom<String> for Crop {
+    type Error = ParseCropError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Crop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid size {0:?}, expected WIDTHxHEIGHT, like 512x512")]
+pub struct ParseSizeError(String);
+
+/// Fixed dimensions of the output, parsed from `WIDTHxHEIGHT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Size {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl FromStr for Size {
+    type Err = ParseSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSizeError(s.to_owned());
+        let (width, height) = s.split_once(['x', 'X']).ok_or_else(err)?;
+        let width: usize = width.trim().parse().map_err(|_| err())?;
+        let height: usize = height.trim().parse().map_err(|_| err())?;
+        if width == 0 || height == 0 {
+            return Err(err());
+        }
+        Ok(Size { width, height })
+    }
+}
+
+impl TryFrom<String> for Size {
+    type Error = ParseSizeError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid range {0:?}, expected MIN,MAX with MIN <= MAX")]
+pub struct ParseClipError(String);
+
+/// A fixed range of values to clamp the data to.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Clip {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl FromStr for Clip {
+    type Err = ParseClipError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<f32> = s
+            .split(',')
+            .map(|part| part.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| ParseClipError(s.to_owned()))?;
+        match parts[..] {
+            [min, max] if min <= max => Ok(Clip { min, max }),
+            _ => Err(ParseClipError(s.to_owned())),
+        }
+    }
+}
+
+impl TryFrom<String> for Clip {
+    type Error = ParseClipError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Per-slice processing steps that are applied before writing.
+#[derive(Debug, Default, Clone)]
+pub struct Processing {
+    /// Dark reference, subtracted from the full input slices
+    pub dark: Option<Dark>,
+    /// Gain reference, applied to the full input slices
+    pub gain: Option<Gain>,
+    /// Threshold in standard deviations for replacing hot and dead pixels
+    pub fix_outliers: Option<f32>,
+    /// Region to keep, in pixels of the input slices
+    pub crop: Option<Crop>,
+    /// Bin factor N for NxN binning
+    pub bin: Option<usize>,
+    pub bin_mode: BinMode,
+    /// Downsampling factor for cropping in Fourier space, after binning
+    pub fourier_crop: Option<f32>,
+    /// Resample to this pixel size in Ångström, after Fourier cropping
+    pub target_pixel_size: Option<f32>,
+    pub rotate: Option<Rotation>,
+    pub flip: Option<Flip>,
+    /// Standard deviation of the gaussian blur, in output pixels
+    pub gaussian: Option<f32>,
+    /// Window size K of the KxK median filter
+    pub median: Option<usize>,
+    /// Remove a linear intensity ramp fitted to each slice
+    pub subtract_plane: bool,
+    /// Crop larger slices to at most this size, around their center
+    pub crop_to: Option<Size>,
+    /// Pad smaller slices to at least this size, keeping them centered
+    pub pad_to: Option<Size>,
+    /// Value of the padded pixels, the mean of each slice if not given
+    pub pad_value: Option<f32>,
+    /// Clamp the values to this range, before inverting and normalizing
+    pub clip: Option<Clip>,
+    pub invert: Option<Invert>,
+    pub normalize: Normalize,
+    pub normalize_stats: StatsScope,
+    /// Take the range of `Normalize::MinMax` for the whole stack from the
+    /// statistics in the mrc header, instead of scanning the slices for it
+    pub header_stats: bool,
+    /// Equalize the histogram of each slice instead of normalizing it
+    pub equalize: Option<Equalize>,
+    /// Dither when rounding to an integer output type
+    pub dither: Option<Dither>,
+    /// Sample type of the output, chosen automatically if not given
+    pub output_type: Option<SampleType>,
+    /// Normalization levels computed over the whole stack, filled in by `prepare`
+    pub stack_levels: Option<Levels>,
+    /// Histogram of the whole stack for global equalization, filled in by `prepare`
+    pub stack_histogram: Option<Histogram>,
+    /// Drawn onto the finished image, which needs to be 8-bit
+    pub scalebar: Option<ScaleBar>,
+    /// Size of the input pixels in Ångström, from the mrc header
+    pub pixel_size: Option<f32>,
+    /// Whether the input values are unsigned, see `Volume3D::is_unsigned`
+    pub unsigned_input: bool,
+    /// Whether the processed slices are dose weighted before the normalization,
+    /// set by `convert` from its `DoseWeighting`
+    pub dose_weighted: bool,
+}
+
+impl Processing {
+    /// No processing requested, the slices can be written as they are.
+    pub fn is_identity(&self) -> bool {
+        self.dark.is_none()
+            && self.gain.is_none()
+            && self.fix_outliers.is_none()
+            && self.crop.is_none()
+            && self.bin.is_none_or(|factor| factor == 1)
+            && self.fourier_crop.is_none_or(|factor| factor <= 1.0)
+            && self.target_pixel_size.is_none()
+            && self.rotate.is_none()
+            && self.flip.is_none()
+            && self.gaussian.is_none_or(|sigma| sigma <= 0.0)
+            && self.median.is_none_or(|size| size <= 1)
+            && !self.subtract_plane
+            && self.crop_to.is_none()
+            && self.pad_to.is_none()
+            && self.clip.is_none()
+            && self.invert != Some(Invert::Data)
+            && self.normalize == Normalize::None
+            && self.equalize.is_none()
+            && self
+                .output_type
+                .is_none_or(|t| t == self.input_sample_type())
+            && self.scalebar.is_none()
+    }
+
+    /// Width and height of the processed slices, for input slices of `nx` x `ny`.
+    pub fn output_dimensions(&self, nx: usize, ny: usize) -> (usize, usize) {
+        let (mut width, mut height) = match &self.crop {
+            Some(crop) => (crop.width, crop.height),
+            None => (nx, ny),
+        };
+        if let Some(factor) = self.bin
+            && factor > 1
+        {
+            width /= factor;
+            height /= factor;
+        }
+        if let Some(factor) = self.fourier_crop
+            && factor > 1.0
+        {
+            width = fourier_cropped_size(width, factor);
+            height = fourier_cropped_size(height, factor);
+        }
+        if let Some(factor) = self.resample_factor() {
+            width = resampled_size(width, factor);
+            height = resampled_size(height, factor);
+        }
+        if matches!(self.rotate, Some(Rotation::R90 | Rotation::R270)) {
+            (width, height) = (height, width);
+        }
+        if let Some(size) = self.crop_to {
+            (width, height) = (width.min(size.width), height.min(size.height));
+        }
+        if let Some(size) = self.pad_to {
+            (width, height) = (width.max(size.width), height.max(size.height));
+        }
+        (width, height)
+    }
+
+    /// Size of the processed pixels in Ångström, if the input pixel size is known.
+    pub fn output_pixel_size(&self) -> Option<f32> {
+        self.target_pixel_size.or_else(|| self.binned_pixel_size())
+    }
+
+    /// Size of the pixels in Ångström after binning and Fourier cropping
+    fn binned_pixel_size(&self) -> Option<f32> {
+        let fourier_crop = self.fourier_crop.unwrap_or(1.0).max(1.0);
+        Some(self.pixel_size? * self.bin.unwrap_or(1).max(1) as f32 * fourier_crop)
+    }
+
+    /// Factor by which the slices are downsampled to reach the target pixel
+    /// size, below 1 for upsampling.
+    fn resample_factor(&self) -> Option<f32> {
+        Some(self.target_pixel_size? / self.binned_pixel_size()?)
+    }
+
+    pub fn output_sample_type(&self) -> SampleType {
+        if let Some(output_type) = self.output_type {
+            return output_type;
+        }
+        if self.equalize.is_some() {
+            return SampleType::U8;
+        }
+        if self.normalize != Normalize::None || self.gain.is_some() {
+            return SampleType::F32;
+        }
+        match (self.bin, self.bin_mode) {
+            (Some(factor), BinMode::Sum) if factor > 1 => SampleType::I32,
+            // the difference of two 16bit values doesn't always fit into 16 bits
+            _ if self.dark.is_some() => SampleType::I32,
+            _ => self.input_sample_type(),
+        }
+    }
+
+    /// Sample type of the slices as they are stored in the input.
+    pub fn input_sample_type(&self) -> SampleType {
+        if self.unsigned_input {
+            SampleType::U16
+        } else {
+            SampleType::I16
+        }
+    }
+
+    pub fn apply(&self, slice: &Slice, nx: usize, ny: usize) -> Image {
+        self.adjust(self.transform(slice, nx, ny))
+    }
+
+    /// The reference corrections, the geometric part of the processing (crop,
+    /// bin, rotate and flip) and the filters.
+    pub fn transform(&self, slice: &Slice, nx: usize, ny: usize) -> Image {
+        self.transform_image(Image::from_slice(slice, nx, ny))
+    }
+
+    /// Like `transform`, for an input slice that was already converted.
+    pub fn transform_image(&self, mut image: Image) -> Image {
+        if let Some(dark) = &self.dark {
+            dark.apply(&mut image);
+        }
+        if let Some(gain) = &self.gain {
+            gain.apply(&mut image);
+        }
+        if let Some(sigma) = self.fix_outliers {
+            let num_fixed;
+            (image, num_fixed) = fix_outliers(&image, sigma);
+            if num_fixed > 0 {
+                debug!("replaced {num_fixed} outlier pixels");
+            }
+        }
+        if let Some(region) = &self.crop {
+            image = crop(&image, region);
+        }
+        if let Some(factor) = self.bin
+            && factor > 1
+        {
+            image = bin(&image, factor, self.bin_mode);
+        }
+        if let Some(factor) = self.fourier_crop
+            && factor > 1.0
+        {
+            let width = fourier_cropped_size(image.width, factor);
+            let height = fourier_cropped_size(image.height, factor);
+            image = fourier_crop(&image, width, height);
+        }
+        if let Some(factor) = self.resample_factor() {
+            let width = resampled_size(image.width, factor);
+            let height = resampled_size(image.height, factor);
+            image = resample(&image, width, height);
+        }
+        if let Some(rotation) = self.rotate {
+            image = rotate(&image, rotation);
+        }
+        if let Some(direction) = self.flip {
+            image = flip(&image, direction);
+        }
+        if let Some(sigma) = self.gaussian
+            && sigma > 0.0
+        {
+            image = gaussian(&image, sigma);
+        }
+        if let Some(size) = self.median
+            && size > 1
+        {
+            image = median(&image, size);
+        }
+        if self.subtract_plane {
+            image = subtract_plane(&image);
+        }
+        if let Some(size) = self.crop_to {
+            image = crop_center(&image, size.width, size.height);
+        }
+        if let Some(size) = self.pad_to {
+            image = pad(&image, size.width, size.height, self.pad_value);
+        }
+        image
+    }
+
+    /// The intensity part of the processing: clip, invert, normalize and
+    /// dither, and finally drawing the scale bar.
+    pub fn adjust(&self, mut image: Image) -> Image {
+        if let Some(Clip { min, max }) = self.clip {
+            image.data.iter_mut().for_each(|v| *v = v.clamp(min, max));
+        }
+        if self.invert == Some(Invert::Data) {
+            let offset = self.invert_offset();
+            image.data.iter_mut().for_each(|v| *v = offset - *v);
+        }
+        if let Some(mode) = self.equalize {
+            image = equalize(&image, mode, self.stack_histogram.as_ref());
+            let unit_scale = self.output_sample_type().unit_scale();
+            image.data.iter_mut().for_each(|v| *v *= unit_scale);
+        } else if self.normalize != Normalize::None {
+            let levels = self
+                .stack_levels
+                .unwrap_or_else(|| self.normalize.levels_of(&image.data));
+            let unit_scale = if self.normalize.is_unit_range() {
+                self.output_sample_type().unit_scale()
+            } else {
+                1.0
+            };
+            levels.apply(&mut image.data, unit_scale);
+        }
+        let output_type = self.output_sample_type();
+        if let Some(mode) = self.dither
+            && output_type != SampleType::F32
+        {
+            image = dither(&image, mode, output_type.limits());
+        }
+        if let (Some(scalebar), Some(pixel_size)) = (self.scalebar, self.output_pixel_size()) {
+            scalebar.draw(&mut image, pixel_size, SampleType::U8.unit_scale());
+        }
+        image
+    }
+
+    /// Only the intensity mapping of the output, for writing power spectra
+    /// instead of the slices. They are written as floats unless another type
+    /// is requested or implied by the equalization.
+    pub fn for_spectra(&self) -> Processing {
+        Processing {
+            normalize: self.normalize,
+            equalize: self.equalize,
+            dither: self.dither,
+            output_type: Some(match (self.output_type, self.equalize) {
+                (Some(output_type), _) => output_type,
+                (None, Some(_)) => SampleType::U8,
+                (None, None) => SampleType::F32,
+            }),
+            ..Default::default()
+        }
+    }
+
+    pub fn tiff_metadata(&self) -> TiffMetadata {
+        TiffMetadata {
+            white_is_zero: self.invert == Some(Invert::Photometric),
+            pixel_size: self.target_pixel_size,
+            page_descriptions: Vec::new(),
+        }
+    }
+
+    /// Inverting maps `v` to `offset - v`. Unsigned input is subtracted from
+    /// its maximum, so that it stays unsigned instead of being clamped to zero.
+    fn invert_offset(&self) -> f32 {
+        if self.unsigned_input {
+            f32::from(u16::MAX)
+        } else {
+            0.0
+        }
+    }
+
+    /// Range of the values right before the normalization, for input values
+    /// in `min..=max`, unless the processing can take them outside of it.
+    fn range_before_normalization(&self, min: f32, max: f32) -> Option<(f32, f32)> {
+        let summed = self.bin_mode == BinMode::Sum && self.bin.is_some_and(|factor| factor > 1);
+        // cropping the Fourier transform, also to resample, and the dose
+        // weighting overshoot at edges:
+        let fourier = self.fourier_crop.is_some_and(|factor| factor > 1.0)
+            || self.target_pixel_size.is_some()
+            || self.dose_weighted;
+        if self.dark.is_some() || self.gain.is_some() || self.subtract_plane || summed || fourier {
+            return None;
+        }
+        let (mut min, mut max) = (min, max);
+        if let Some(clip) = self.clip {
+            (min, max) = (min.clamp(clip.min, clip.max), max.clamp(clip.min, clip.max));
+        }
+        if self.invert == Some(Invert::Data) {
+            let offset = self.invert_offset();
+            (min, max) = (offset - max, offset - min);
+        }
+        Some((min, max))
+    }
+
+    /// Compute everything that depends on all of the selected slices `idxs`,
+    /// like stack-wide normalization statistics, and return the processing
+    /// ready to be `apply`ed.
+    pub fn prepare(&self, volume: &Volume3D, idxs: &[usize]) -> Result<Processing, Error> {
+        let frames: Vec<&[usize]> = idxs.chunks(1).collect();
+        self.prepare_groups(volume, &frames, Projection::Max)
+    }
+
+    /// Like `prepare`, for output frames that each combine a group of slices
+    /// with `mode`, so that the statistics are those of the combined frames.
+    pub fn prepare_groups(
+        &self,
+        volume: &Volume3D,
+        groups: &[&[usize]],
+        mode: Projection,
+    ) -> Result<Processing, Error> {
+        let mut prepared = self.clone();
+        let equalize_stack = self.equalize == Some(Equalize::Global);
+        let minmax = self.normalize == Normalize::MinMax && self.equalize.is_none();
+        let summed = mode == Projection::Sum && groups.iter().any(|group| group.len() > 1);
+        if self.header_stats && !minmax {
+            warn!("the header statistics only apply to the min-max normalization, ignoring them");
+        } else if self.header_stats {
+            let range = volume.header_stats().map(|stats| {
+                self.range_before_normalization(stats.min, stats.max)
+                    .filter(|_| !summed)
+            });
+            match range {
+                Some(Some((min, max))) => {
+                    debug!("normalizing the range {min}..={max} from the header");
+                    prepared.stack_levels = Some(Levels::between(min, max, false));
+                    return Ok(prepared);
+                }
+                Some(None) => {
+                    warn!("the processing changes the range of the values, scanning the stack")
+                }
+                None => {
+                    warn!("the header has no statistics of the values as read, scanning the stack")
+                }
+            }
+        }
+        if (self.normalize == Normalize::None && !equalize_stack)
+            || self.normalize_stats == StatsScope::Slice
+        {
+            return Ok(prepared);
+        }
+
+        let (nx, ny, _) = volume.dimensions();
+        let before_normalization = Processing {
+            normalize: Normalize::None,
+            equalize: None,
+            scalebar: None,
+            ..self.clone()
+        };
+        let transformed = |z: usize| -> Result<Partial, Error> {
+            Ok(before_normalization
+                .transform(&volume.get_slice(z)?, nx, ny)
+                .into())
+        };
+        // the output frame at position `idx`, combined from its group of slices:
+        let image = |idx: usize| -> Result<Image, Error> {
+            let group = groups[idx];
+            let mut combined = transformed(group[0])?;
+            for &z in &group[1..] {
+                combined = mode.combine(combined, transformed(z)?);
+            }
+            Ok(before_normalization.adjust(mode.finish(combined, group.len())))
+        };
+        let positions: Vec<usize> = (0..groups.len()).collect();
+
+        let acc = reduce_slices(
+            &positions,
+            |idx| {
+                let mut acc = StatsAccumulator::default();
+                acc.add(&image(idx)?.data);
+                Ok(acc)
+            },
+            StatsAccumulator::default,
+            StatsAccumulator::merge,
+        )?;
+        let stats = acc.finish();
+        let histogram = |num_bins: usize| {
+            reduce_slices(
+                &positions,
+                |idx| {
+                    let mut hist = Histogram::new(stats.min, stats.max, num_bins);
+                    hist.add(&image(idx)?.data);
+                    Ok(hist)
+                },
+                || Histogram::new(stats.min, stats.max, num_bins),
+                Histogram::merge,
+            )
+        };
+
+        if equalize_stack {
+            prepared.stack_histogram = Some(histogram(EQUALIZE_BINS)?);
+            return Ok(prepared);
+        }
+        prepared.stack_levels = Some(match self.normalize {
+            Normalize::Percentile(p) => {
+                let hist = histogram(HISTOGRAM_BINS)?;
+                Levels::between(hist.percentile(p), hist.percentile(100.0 - p), true)
+            }
+            normalize => normalize.levels_from_stats(&stats),
+        });
+        Ok(prepared)
+    }
+}
+
+/// Number of bins used for approximating stack-wide percentiles
+const HISTOGRAM_BINS: usize = 1 << 16;
+
+/// `map` each of the slices `idxs` and combine the results with `merge`, in
+/// parallel unless built without the `native` feature.
+fn reduce_slices<T: Send>(
+    idxs: &[usize],
+    map: impl Fn(usize) -> Result<T, Error> + Sync + Send,
+    identity: impl Fn() -> T + Sync + Send,
+    merge: impl Fn(T, T) -> T + Sync + Send,
+) -> Result<T, Error> {
+    #[cfg(feature = "native")]
+    {
+        idxs.par_iter()
+            .map(|&z| map(z))
+            .try_reduce(identity, |a, b| Ok(merge(a, b)))
+    }
+    #[cfg(not(feature = "native"))]
+    {
+        idxs.iter()
+            .try_fold(identity(), |acc, &z| Ok(merge(acc, map(z)?)))
+    }
+}
+
+pub fn crop(image: &Image, region: &Crop) -> Image {
+    let data = image
+        .data
+        .chunks_exact(image.width)
+        .skip(region.y)
+        .take(region.height)
+        .flat_map(|row| &row[region.x..region.x + region.width])
+        .copied()
+        .collect();
+    Image {
+        data,
+        width: region.width,
+        height: region.height,
+    }
+}
+
+/// NxN binning; rows and columns that don't fill a whole bin are dropped.
+pub fn bin(image: &Image, factor: usize, mode: BinMode) -> Image {
+    if mode == BinMode::Median {
+        return bin_median(image, factor);
+    }
+    let width = image.width / factor;
+    let height = image.height / factor;
+    let mut data = vec![0.0f32; width * height];
+    for y in 0..height * factor {
+        let in_row = &image.data[y * image.width..][..width * factor];
+        let out_row = &mut data[(y / factor) * width..][..width];
+        for (x, v) in in_row.iter().enumerate() {
+            out_row[x / factor] += v;
+        }
+    }
+    if mode == BinMode::Mean {
+        let norm = (factor * factor) as f32;
+        data.iter_mut().for_each(|v| *v /= norm);
+    }
+    Image {
+        data,
+        width,
+        height,
+    }
+}
+
+fn bin_median(image: &Image, factor: usize) -> Image {
+    let width = image.width / factor;
+    let height = image.height / factor;
+    let mut block = Vec::with_capacity(factor * factor);
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            block.clear();
+            for by in y * factor..(y + 1) * factor {
+                block.extend_from_slice(&image.data[by * image.width + x * factor..][..factor]);
+            }
+            let mid = block.len() / 2;
+            let (_, value, _) = block.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+            data.push(*value);
+        }
+    }
+    Image {
+        data,
+        width,
+        height,
+    }
+}
+
+/// Crop `image` to at most `width` x `height`, around its center.
+pub fn crop_center(image: &Image, width: usize, height: usize) -> Image {
+    let (width, height) = (width.min(image.width), height.min(image.height));
+    let region = Crop {
+        x: (image.width - width) / 2,
+        y: (image.height - height) / 2,
+        width,
+        height,
+    };
+    crop(image, &region)
+}
+
+/// Pad `image` to at least `width` x `height` with `value`, or its mean if
+/// not given, keeping it centered.
+pub fn pad(image: &Image, width: usize, height: usize, value: Option<f32>) -> Image {
+    let (width, height) = (width.max(image.width), height.max(image.height));
+    if (width, height) == (image.width, image.height) {
+        return image.clone();
+    }
+    let value =
+        value.unwrap_or_else(|| image.data.iter().sum::<f32>() / image.data.len().max(1) as f32);
+    let (x0, y0) = ((width - image.width) / 2, (height - image.height) / 2);
+    let mut data = vec![value; width * height];
+    for (y, row) in image.data.chunks_exact(image.width.max(1)).enumerate() {
+        data[(y0 + y) * width + x0..][..image.width].copy_from_slice(row);
+    }
+    Image {
+        data,
+        width,
+        height,
+    }
+}
+
+/// Length of a side of `size` pixels after resampling by `factor`
+fn resampled_size(size: usize, factor: f32) -> usize {
+    ((size as f32 / factor).round() as usize).max(1)
+}
+
+/// Scale `image` to `width` x `height`. Downsampling crops the Fourier
+/// transform, which avoids aliasing, upsampling interpolates bilinearly.
+pub fn resample(image: &Image, width: usize, height: usize) -> Image {
+    if width <= image.width && height <= image.height {
+        if (width, height) == (image.width, image.height) {
+            return image.clone();
+        }
+        return fourier_crop(image, width, height);
+    }
+    // the position of an output pixel center in input pixels:
+    let source = |i: usize, m: usize, n: usize| {
+        let pos = ((i as f32 + 0.5) * n as f32 / m as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+        let lower = (pos as usize).min(n.saturating_sub(2));
+        (lower, (lower + 1).min(n - 1), pos - lower as f32)
+    };
+    let w = image.width;
+    let data = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let (x0, x1, fx) = source(x, width, image.width);
+            let (y0, y1, fy) = source(y, height, image.height);
+            let top = image.data[y0 * w + x0] * (1.0 - fx) + image.data[y0 * w + x1] * fx;
+            let bottom = image.data[y1 * w + x0] * (1.0 - fx) + image.data[y1 * w + x1] * fx;
+            top * (1.0 - fy) + bottom * fy
+        })
+        .collect();
+    Image {
+        data,
+        width,
+        height,
+    }
+}
+
+/// Length of a side of `size` pixels after Fourier cropping by `factor`
+fn fourier_cropped_size(size: usize, factor: f32) -> usize {
+    ((size as f32 / factor).round() as usize).clamp(1, size.max(1))
+}
+
+pub fn rotate(image: &Image, rotation: Rotation) -> Image {
+    let (w, h) = (image.width, image.height);
+    let at = |x: usize, y: usize| image.data[y * w + x];
+    match rotation {
+        Rotation::R90 => Image {
+            data: (0..w)
+                .flat_map(|y_out| (0..h).map(move |x_out| at(y_out, h - 1 - x_out)))
+                .collect(),
+            width: h,
+            height: w,
+        },
+        Rotation::R180 => Image {
+            data: image.data.iter().rev().copied().collect(),
+            width: w,
+            height: h,
+        },
+        Rotation::R270 => Image {
+            data: (0..w)
+                .flat_map(|y_out| (0..h).map(move |x_out| at(w - 1 - y_out, x_out)))
+                .collect(),
+            width: h,
+            height: w,
+        },
+    }
+}
+
+pub fn flip(image: &Image, direction: Flip) -> Image {
+    let rows = image.data.chunks_exact(image.width);
+    let data = match direction {
+        Flip::H => rows.flat_map(|row| row.iter().rev()).copied().collect(),
+        Flip::V => rows.rev().flatten().copied().collect(),
+    };
+    Image {
+        data,
+        width: image.width,
+        height: image.height,
+    }
+}
+
+/// Replace pixels that deviate from the median of their 3x3 neighborhood by
+/// more than `sigma` standard deviations of all such deviations in the image
+/// with that median, to remove hot and dead pixels.
+pub fn fix_outliers(image: &Image, sigma: f32) -> (Image, usize) {
+    let (w, h) = (image.width, image.height);
+    let mut medians = Vec::with_capacity(w * h);
+    let mut neighbors = Vec::with_capacity(8);
+    for y in 0..h {
+        for x in 0..w {
+            neighbors.clear();
+            for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                    if (nx, ny) != (x, y) {
+                        neighbors.push(image.data[ny * w + nx]);
+                    }
+                }
+            }
+            neighbors.sort_unstable_by(|a, b| a.total_cmp(b));
+            medians.push(
+                neighbors
+                    .get(neighbors.len() / 2)
+                    .copied()
+                    .unwrap_or(image.data[y * w + x]),
+            );
+        }
+    }
+
+    let residuals: Vec<f32> = image
+        .data
+        .iter()
+        .zip(&medians)
+        .map(|(v, m)| v - m)
+        .collect();
+    let threshold = sigma * Stats::of(&residuals).std;
+    let mut num_fixed = 0;
+    let data = image
+        .data
+        .iter()
+        .zip(residuals)
+        .zip(medians)
+        .map(|((&v, r), m)| {
+            if r.abs() > threshold {
+                num_fixed += 1;
+                m
+            } else {
+                v
+            }
+        })
+        .collect();
+    (
+        Image {
+            data,
+            width: w,
+            height: h,
+        },
+        num_fixed,
+    )
+}
+
+/// Replace pixels that are brighter than the median of the same pixel in the
+/// `neighbors`, usually the adjacent slices, by more than `sigma` standard
+/// deviations of all such differences with that median. This removes cosmic
+/// ray and X-ray hits, which only show up in a single slice.
+pub fn fix_temporal_outliers(image: &Image, neighbors: &[Image], sigma: f32) -> (Image, usize) {
+    if neighbors.is_empty() {
+        return (image.clone(), 0);
+    }
+    let mut values = Vec::with_capacity(neighbors.len());
+    let medians: Vec<f32> = (0..image.data.len())
+        .map(|idx| {
+            values.clear();
+            values.extend(neighbors.iter().map(|n| n.data[idx]));
+            values.sort_unstable_by(|a, b| a.total_cmp(b));
+            let mid = values.len() / 2;
+            if values.len() % 2 == 0 {
+                (values[mid - 1] + values[mid]) / 2.0
+            } else {
+                values[mid]
+            }
+        })
+        .collect();
+
+    let residuals: Vec<f32> = image
+        .data
+        .iter()
+        .zip(&medians)
+        .map(|(v, m)| v - m)
+        .collect();
+    let threshold = sigma * Stats::of(&residuals).std;
+    let mut num_fixed = 0;
+    let data = image
+        .data
+        .iter()
+        .zip(residuals)
+        .zip(medians)
+        .map(|((&v, r), m)| {
+            if r > threshold {
+                num_fixed += 1;
+                m
+            } else {
+                v
+            }
+        })
+        .collect();
+    (
+        Image {
+            data,
+            width: image.width,
+            height: image.height,
+        },
+        num_fixed,
+    )
+}
+
+/// Separable gaussian blur, extending the image at the borders with the edge values.
+pub fn gaussian(image: &Image, sigma: f32) -> Image {
+    let radius = (3.0 * sigma).ceil() as usize;
+    let kernel: Vec<f32> = (0..=2 * radius)
+        .map(|i| {
+            let d = i as f32 - radius as f32;
+            (-d * d / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let norm: f32 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.iter().map(|k| k / norm).collect();
+
+    let (w, h) = (image.width, image.height);
+    let convolve = |len: usize, at: &dyn Fn(usize) -> f32, i: usize| -> f32 {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(k, weight)| {
+                let pos = (i + k).saturating_sub(radius).min(len - 1);
+                weight * at(pos)
+            })
+            .sum()
+    };
+
+    let mut rows = vec![0.0f32; w * h];
+    for y in 0..h {
+        let row = &image.data[y * w..][..w];
+        for x in 0..w {
+            rows[y * w + x] = convolve(w, &|pos| row[pos], x);
+        }
+    }
+    let mut data = vec![0.0f32; w * h];
+    for x in 0..w {
+        for y in 0..h {
+            data[y * w + x] = convolve(h, &|pos| rows[pos * w + x], y);
+        }
+    }
+    Image {
+        data,
+        width: w,
+        height: h,
+    }
+}
+
+/// KxK median filter, only using the pixels inside the image at the borders.
+pub fn median(image: &Image, size: usize) -> Image {
+    let (w, h) = (image.width, image.height);
+    let (before, after) = ((size - 1) / 2, size / 2);
+    let mut window = Vec::with_capacity(size * size);
+    let mut data = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            window.clear();
+            let columns = x.saturating_sub(before)..(x + after + 1).min(w);
+            for wy in y.saturating_sub(before)..(y + after + 1).min(h) {
+                window.extend_from_slice(&image.data[wy * w..][columns.clone()]);
+            }
+            let mid = window.len() / 2;
+            let (_, value, _) = window.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+            data.push(*value);
+        }
+    }
+    Image {
+        data,
+        width: w,
+        height: h,
+    }
+}
+
+/// Number of gray levels the equalization distinguishes
+const EQUALIZE_BINS: usize = 256;
+
+/// Number of CLAHE tiles along each side of the image
+const CLAHE_TILES: usize = 8;
+
+/// Largest count of a histogram bin for CLAHE, relative to the average count
+const CLAHE_CLIP_LIMIT: f32 = 2.0;
+
+/// Map the values to 0..=1 so that their histogram becomes flat, either over
+/// the whole image or, for CLAHE, per tile with limited contrast gain and
+/// bilinear interpolation between the tiles. Global equalization uses the
+/// `stack` histogram instead of the one of the image, if given.
+pub fn equalize(image: &Image, mode: Equalize, stack: Option<&Histogram>) -> Image {
+    let (w, h) = (image.width, image.height);
+    let min = image.data.iter().copied().fold(f32::MAX, f32::min);
+    let max = image.data.iter().copied().fold(f32::MIN, f32::max);
+    let scale = if max > min {
+        (EQUALIZE_BINS - 1) as f32 / (max - min)
+    } else {
+        0.0
+    };
+    let level = |v: f32| (((v - min) * scale).round() as usize).min(EQUALIZE_BINS - 1);
+    // the normalized cumulative histogram of the pixels in the region, as a lookup table:
+    let lookup = |xs: Range<usize>, ys: Range<usize>, clip_limit: Option<f32>| {
+        let mut counts = vec![0.0f32; EQUALIZE_BINS];
+        for y in ys {
+            for &v in &image.data[y * w..][xs.clone()] {
+                counts[level(v)] += 1.0;
+            }
+        }
+        if let Some(limit) = clip_limit {
+            let limit = limit * counts.iter().sum::<f32>() / EQUALIZE_BINS as f32;
+            let excess: f32 = counts.iter().map(|&c| (c - limit).max(0.0)).sum();
+            let share = excess / EQUALIZE_BINS as f32;
+            counts.iter_mut().for_each(|c| *c = c.min(limit) + share);
+        }
+        let total = counts.iter().sum::<f32>().max(1.0);
+        counts
+            .iter()
+            .scan(0.0, |sum, &c| {
+                *sum += c;
+                Some(*sum / total)
+            })
+            .collect::<Vec<f32>>()
+    };
+    let data = match mode {
+        Equalize::Global => match stack {
+            Some(hist) => {
+                let table = hist.cumulative();
+                image.data.iter().map(|&v| table[hist.bin_of(v)]).collect()
+            }
+            None => {
+                let table = lookup(0..w, 0..h, None);
+                image.data.iter().map(|&v| table[level(v)]).collect()
+            }
+        },
+        Equalize::Clahe => {
+            let (tile_w, tile_h) = (w.div_ceil(CLAHE_TILES), h.div_ceil(CLAHE_TILES));
+            let (tiles_x, tiles_y) = (w.div_ceil(tile_w.max(1)), h.div_ceil(tile_h.max(1)));
+            let tables: Vec<Vec<f32>> = (0..tiles_y)
+                .flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty)))
+                .map(|(tx, ty)| {
+                    let xs = tx * tile_w..((tx + 1) * tile_w).min(w);
+                    let ys = ty * tile_h..((ty + 1) * tile_h).min(h);
+                    lookup(xs, ys, Some(CLAHE_CLIP_LIMIT))
+                })
+                .collect();
+            // the two tiles whose centers enclose `pos`, and the weight of the second:
+            let neighbors = |pos: usize, size: usize, num: usize| {
+                let t = ((pos as f32 + 0.5) / size as f32 - 0.5).clamp(0.0, (num - 1) as f32);
+                let first = t.floor() as usize;
+                (first, (first + 1).min(num - 1), t - first as f32)
+            };
+            (0..h)
+                .flat_map(|y| (0..w).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let (x0, x1, fx) = neighbors(x, tile_w, tiles_x);
+                    let (y0, y1, fy) = neighbors(y, tile_h, tiles_y);
+                    let l = level(image.data[y * w + x]);
+                    let at = |tx: usize, ty: usize| tables[ty * tiles_x + tx][l];
+                    let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+                    let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+                    top * (1.0 - fy) + bottom * fy
+                })
+                .collect()
+        }
+    };
+    Image {
+        data,
+        width: w,
+        height: h,
+    }
+}
+
+/// Subtract the least squares fit of a plane `a + b x + c y`, except for its
+/// mean, so illumination gradients are removed but the intensities stay in range.
+pub fn subtract_plane(image: &Image) -> Image {
+    let (w, h) = (image.width, image.height);
+    // with centered coordinates, the x and y slopes can be fitted independently:
+    let (cx, cy) = ((w as f64 - 1.0) / 2.0, (h as f64 - 1.0) / 2.0);
+    let (mut sum_xv, mut sum_yv, mut sum_xx, mut sum_yy) = (0.0, 0.0, 0.0, 0.0);
+    for (i, &v) in image.data.iter().enumerate() {
+        let (x, y) = ((i % w) as f64 - cx, (i / w) as f64 - cy);
+        sum_xv += x * v as f64;
+        sum_yv += y * v as f64;
+        sum_xx += x * x;
+        sum_yy += y * y;
+    }
+    let slope_x = if sum_xx > 0.0 { sum_xv / sum_xx } else { 0.0 };
+    let slope_y = if sum_yy > 0.0 { sum_yv / sum_yy } else { 0.0 };
+    let data = image
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            let (x, y) = ((i % w) as f64 - cx, (i / w) as f64 - cy);
+            (v as f64 - slope_x * x - slope_y * y) as f32
+        })
+        .collect();
+    Image {
+        data,
+        width: w,
+        height: h,
+    }
+}
+
+/// Thresholds of the ordered dithering, in sixteenths
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Round the values to integers within `(min, max)`, such that the local
+/// mean is kept.
+pub fn dither(image: &Image, mode: Dither, (min, max): (f32, f32)) -> Image {
+    let w = image.width;
+    let data = match mode {
+        Dither::FloydSteinberg => {
+            let mut data = image.data.clone();
+            for i in 0..data.len() {
+                let (x, y) = (i % w, i / w);
+                let old = data[i];
+                data[i] = old.round().clamp(min, max);
+                // an infinite error would spread over the rest of the image:
+                let err = if old.is_finite() { old - data[i] } else { 0.0 };
+                if x + 1 < w {
+                    data[i + 1] += err * 7.0 / 16.0;
+                }
+                if y + 1 < image.height {
+                    if x > 0 {
+                        data[i + w - 1] += err * 3.0 / 16.0;
+                    }
+                    data[i + w] += err * 5.0 / 16.0;
+                    if x + 1 < w {
+                        data[i + w + 1] += err / 16.0;
+                    }
+                }
+            }
+            data
+        }
+        Dither::Ordered => image
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let threshold = (BAYER_4X4[(i / w) % 4][(i % w) % 4] as f32 + 0.5) / 16.0;
+                (v + threshold - 0.5).round().clamp(min, max)
+            })
+            .collect(),
+    };
+    Image {
+        data,
+        width: image.width,
+        height: image.height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(data: Vec<f32>, width: usize, height: usize) -> Image {
+        assert_eq!(data.len(), width * height);
+        Image {
+            data,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn binning() {
+        let image = grid((0..20).map(|v| v as f32).collect(), 5, 4);
+        let summed = bin(&image, 2, BinMode::Sum);
+        assert_eq!((summed.width, summed.height), (2, 2));
+        assert_eq!(summed.data, vec![12.0, 20.0, 52.0, 60.0]);
+        assert_eq!(
+            bin(&image, 2, BinMode::Mean).data,
+            vec![3.0, 5.0, 13.0, 15.0]
+        );
+        let spiky = grid(vec![1.0, 100.0, 2.0, 3.0], 2, 2);
+        assert_eq!(bin(&spiky, 2, BinMode::Median).data, vec![3.0]);
+    }
+
+    #[test]
+    fn rotations() {
+        let image = grid(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 3, 2);
+        let r90 = rotate(&image, Rotation::R90);
+        assert_eq!((r90.width, r90.height), (2, 3));
+        assert_eq!(r90.data, vec![4.0, 1.0, 5.0, 2.0, 6.0, 3.0]);
+        assert_eq!(
+            rotate(&image, Rotation::R180).data,
+            vec![6.0, 5.0, 4.0, 3.0, 2.0, 1.0]
+        );
+        assert_eq!(
+            rotate(&image, Rotation::R270).data,
+            vec![3.0, 6.0, 2.0, 5.0, 1.0, 4.0]
+        );
+        assert_eq!(
+            rotate(&rotate(&r90, Rotation::R180), Rotation::R90).data,
+            image.data
+        );
+        assert_eq!(
+            flip(&image, Flip::H).data,
+            vec![3.0, 2.0, 1.0, 6.0, 5.0, 4.0]
+        );
+        assert_eq!(
+            flip(&image, Flip::V).data,
+            vec![4.0, 5.0, 6.0, 1.0, 2.0, 3.0]
+        );
+    }
+
+    #[test]
+    fn outliers() {
+        let mut data: Vec<f32> = (0..64).map(|v| 10.0 + (v % 3) as f32).collect();
+        data[27] = 1000.0;
+        data[40] = -500.0;
+        let (fixed, num_fixed) = fix_outliers(&grid(data.clone(), 8, 8), 3.0);
+        assert_eq!(num_fixed, 2);
+        assert!((10.0..=12.0).contains(&fixed.data[27]));
+        assert!((10.0..=12.0).contains(&fixed.data[40]));
+        let unchanged = |(idx, (a, b)): (usize, (&f32, &f32))| idx == 27 || idx == 40 || a == b;
+        assert!(fixed.data.iter().zip(&data).enumerate().all(unchanged));
+    }
+
+    #[test]
+    fn sum_projection_keeps_precision() {
+        let projection = Projection::Sum;
+        let mut sum = Partial::from(grid(vec![16_777_216.0, 0.0], 2, 1));
+        for _ in 0..2 {
+            sum = projection.combine(sum, grid(vec![1.0, 0.25], 2, 1).into());
+        }
+        assert_eq!(projection.finish(sum, 3).data, vec![16_777_218.0, 0.5]);
+    }
+
+    #[test]
+    fn mean_and_max_projection() {
+        let slices =
+            || [vec![1.0, 4.0], vec![2.0, -4.0], vec![6.0, 3.0]].map(|data| grid(data, 2, 1));
+        let reduce = |projection: Projection| {
+            let partial = slices()
+                .into_iter()
+                .map(Partial::from)
+                .reduce(|a, b| projection.combine(a, b))
+                .unwrap();
+            projection.finish(partial, 3).data
+        };
+        assert_eq!(reduce(Projection::Mean), vec![3.0, 1.0]);
+        assert_eq!(reduce(Projection::Max), vec![6.0, 4.0]);
+    }
+
+    #[test]
+    fn invert_unsigned() {
+        // the values of a mode 6 stack, written as uint16 again:
+        let processing = Processing {
+            invert: Some(Invert::Data),
+            unsigned_input: true,
+            ..Default::default()
+        };
+        assert_eq!(processing.output_sample_type(), SampleType::U16);
+        let inverted = processing.adjust(grid(vec![0.0, 1000.0, 40000.0, 65535.0], 2, 2));
+        assert_eq!(inverted.data, vec![65535.0, 64535.0, 25535.0, 0.0]);
+        assert_eq!(
+            processing.range_before_normalization(0.0, 1000.0),
+            Some((64535.0, 65535.0))
+        );
+        let signed = Processing {
+            unsigned_input: false,
+            ..processing
+        };
+        assert_eq!(
+            signed.adjust(grid(vec![-5.0, 7.0], 2, 1)).data,
+            vec![5.0, -7.0]
+        );
+    }
+
+    #[test]
+    fn range_before_normalization() {
+        let clipped = Processing {
+            clip: Some(Clip {
+                min: 0.0,
+                max: 100.0,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            clipped.range_before_normalization(-5.0, 50.0),
+            Some((0.0, 50.0))
+        );
+        // these can overshoot the range of the input values:
+        let overshooting = [
+            Processing {
+                fourier_crop: Some(2.0),
+                ..clipped.clone()
+            },
+            Processing {
+                target_pixel_size: Some(4.0),
+                ..clipped.clone()
+            },
+            Processing {
+                dose_weighted: true,
+                ..clipped.clone()
+            },
+        ];
+        for processing in overshooting {
+            assert_eq!(processing.range_before_normalization(-5.0, 50.0), None);
+        }
+        let unchanged = Processing {
+            fourier_crop: Some(1.0),
+            ..clipped
+        };
+        assert_eq!(
+            unchanged.range_before_normalization(-5.0, 50.0),
+            Some((0.0, 50.0))
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    fmt::{Debug, Formatter},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{Error, process::Image};
+
+/// What a `SliceProcessor` is told about the slice it processes.
+#[derive(Debug, Clone, Copy)]
+pub struct SliceInfo<'a> {
+    /// The stack the slice is read from
+    pub input: &'a Path,
+    /// 0-indexed position of the slice along the sliced axis
+    pub index: usize,
+    /// Number of slices along the sliced axis
+    pub num_slices: usize,
+    /// Of the input, in Å per pixel, if the header specifies it
+    pub pixel_size: Option<f32>,
+}
+
+/// A custom processing step, like a filter or a correction, applied to each
+/// slice at the input resolution, before the built-in processing.
+///
+/// Slices are processed in parallel and in any order. The stack-wide
+/// statistics for the normalization and the drift correction are based on
+/// the slices without the custom steps.
+pub trait SliceProcessor: Send + Sync {
+    fn process(
+        &self,
+        image: Image,
+        info: &SliceInfo,
+    ) -> Result<Image, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Shown in the log and in errors.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// Plain functions and closures can be used as processors, too.
+impl<F> SliceProcessor for F
+where
+    F: Fn(Image, &SliceInfo) -> Result<Image, Box<dyn std::error::Error + Send + Sync>>
+        + Send
+        + Sync,
+{
+    fn process(
+        &self,
+        image: Image,
+        info: &SliceInfo,
+    ) -> Result<Image, Box<dyn std::error::Error + Send + Sync>> {
+        self(image, info)
+    }
+}
+
+/// The custom processing steps of a conversion, applied in the order they
+/// were added.
+#[derive(Clone, Default)]
+pub struct SliceProcessors(Vec<Arc<dyn SliceProcessor>>);
+
+impl SliceProcessors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, processor: impl SliceProcessor + 'static) {
+        self.0.push(Arc::new(processor));
+    }
+
+    pub fn with(mut self, processor: impl SliceProcessor + 'static) -> Self {
+        self.push(processor);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Run all steps on `image`.
+    pub fn apply(&self, image: Image, info: &SliceInfo) -> Result<Image, Error> {
+        self.0.iter().try_fold(image, |image, processor| {
+            processor
+                .process(image, info)
+                .map_err(|source| Error::Processor {
+                    name: processor.name().to_owned(),
+                    source,
+                })
+        })
+    }
+}
+
+impl Debug for SliceProcessors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|p| p.name()))
+            .finish()
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    collections::HashMap,
+    io::{Write, stdout},
+    path::{Path, PathBuf},
+    sync::{Mutex, mpsc::Sender},
+    time::Instant,
+};
+
+use indicatif::{MultiProgress, ProgressBar};
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Receives the progress of conversions. All methods have empty defaults, so
+/// implementations only need to handle the events they are interested in.
+///
+/// Conversions of several inputs may run at the same time, so the events
+/// name the `input` they belong to.
+pub trait ProgressSink: Sync {
+    /// The conversion of `input` starts, writing `total` files, or reading
+    /// `total` slices for a projection.
+    fn start(&self, _input: &Path, _total: usize) {}
+
+    /// `num_done` of the `total` steps of `input` are done, the last one
+    /// writing to `output`. An error stops the conversion.
+    fn advance(
+        &self,
+        _input: &Path,
+        _num_done: usize,
+        _total: usize,
+        _output: &Path,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The conversion of `input` has finished successfully.
+    fn finish(&self, _input: &Path, _total: usize) {}
+
+    /// The conversion of `input` has failed or was cancelled; the error itself
+    /// is reported by the caller through `error`.
+    fn abort(&self, _input: &Path) {}
+
+    /// Something went wrong, like the conversion of one of the inputs.
+    fn error(&self, _msg: &str) {}
+}
+
+/// Ignores the progress.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// The events sent by the `ProgressSink` of a `Sender`, for showing the
+/// progress in another thread.
+#[derive(Debug)]
+pub enum ProgressMessage {
+    InProgress {
+        num_done: usize,
+        total: usize,
+        path: PathBuf,
+    },
+    Done {
+        total: usize,
+    },
+    Error {
+        msg: String,
+    },
+}
+
+/// Stops the conversion once the receiving side is gone.
+impl ProgressSink for Sender<ProgressMessage> {
+    fn advance(
+        &self,
+        _input: &Path,
+        num_done: usize,
+        total: usize,
+        output: &Path,
+    ) -> Result<(), Error> {
+        self.send(ProgressMessage::InProgress {
+            num_done,
+            total,
+            path: output.to_owned(),
+        })?;
+        Ok(())
+    }
+
+    fn finish(&self, _input: &Path, total: usize) {
+        let _ = self.send(ProgressMessage::Done { total });
+    }
+
+    fn error(&self, msg: &str) {
+        let _ = self.send(ProgressMessage::Error {
+            msg: msg.to_owned(),
+        });
+    }
+}
+
+/// A terminal progress bar for each running conversion.
+#[derive(Debug, Default)]
+pub struct ProgressBars {
+    multi: MultiProgress,
+    bars: Mutex<HashMap<PathBuf, ProgressBar>>,
+}
+
+impl ProgressBars {
+    /// Shown in `multi`, which can also be used to print log messages
+    /// without disturbing the bars.
+    pub fn new(multi: MultiProgress) -> Self {
+        Self {
+            multi,
+            bars: Mutex::default(),
+        }
+    }
+
+    fn remove(&self, input: &Path) -> Option<ProgressBar> {
+        let bar = self
+            .bars
+            .lock()
+            .expect("progress bar lock poisoned")
+            .remove(input)?;
+        self.multi.remove(&bar);
+        Some(bar)
+    }
+}
+
+impl ProgressSink for ProgressBars {
+    fn start(&self, input: &Path, total: usize) {
+        let bar = self.multi.add(ProgressBar::new(total as u64));
+        let mut bars = self.bars.lock().expect("progress bar lock poisoned");
+        if let Some(previous) = bars.insert(input.to_owned(), bar) {
+            self.multi.remove(&previous);
+        }
+    }
+
+    fn advance(
+        &self,
+        input: &Path,
+        _num_done: usize,
+        _total: usize,
+        _output: &Path,
+    ) -> Result<(), Error> {
+        // the steps finish out of order, so counting is more reliable than `num_done`:
+        if let Some(bar) = self
+            .bars
+            .lock()
+            .expect("progress bar lock poisoned")
+            .get(input)
+        {
+            bar.inc(1);
+        }
+        Ok(())
+    }
+
+    fn finish(&self, input: &Path, _total: usize) {
+        if let Some(bar) = self.remove(input) {
+            bar.finish();
+        }
+    }
+
+    fn abort(&self, input: &Path) {
+        self.remove(input);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JsonEvent<'a> {
+    Progress {
+        done: usize,
+        total: usize,
+        file: &'a Path,
+        /// slices per second since the start of the conversion
+        rate: f64,
+    },
+    Done {
+        total: usize,
+        elapsed_secs: f64,
+    },
+    Error {
+        msg: &'a str,
+    },
+}
+
+/// Newline-delimited JSON events, for other programs to follow the progress.
+#[derive(Debug)]
+pub struct JsonLines<W> {
+    out: Mutex<W>,
+    t0: Instant,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            t0: Instant::now(),
+        }
+    }
+
+    fn emit(&self, event: &JsonEvent) {
+        let line = serde_json::to_string(event).expect("progress events are always serializable");
+        let mut out = self.out.lock().expect("progress output lock poisoned");
+        // if the output is gone, nobody is listening anymore, which is no
+        // reason to stop the conversion:
+        let _ = writeln!(out, "{line}").and_then(|_| out.flush());
+    }
+}
+
+impl<W: Write + Send> ProgressSink for JsonLines<W> {
+    fn advance(
+        &self,
+        _input: &Path,
+        num_done: usize,
+        total: usize,
+        output: &Path,
+    ) -> Result<(), Error> {
+        self.emit(&JsonEvent::Progress {
+            done: num_done,
+            total,
+            file: output,
+            rate: num_done as f64 / self.t0.elapsed().as_secs_f64(),
+        });
+        Ok(())
+    }
+
+    fn finish(&self, _input: &Path, total: usize) {
+        self.emit(&JsonEvent::Done {
+            total,
+            elapsed_secs: self.t0.elapsed().as_secs_f64(),
+        });
+    }
+
+    fn error(&self, msg: &str) {
+        self.emit(&JsonEvent::Error { msg });
+    }
+}
+
+impl<T: ProgressSink + ?Sized> ProgressSink for &T {
+    fn start(&self, input: &Path, total: usize) {
+        (**self).start(input, total)
+    }
+
+    fn advance(
+        &self,
+        input: &Path,
+        num_done: usize,
+        total: usize,
+        output: &Path,
+    ) -> Result<(), Error> {
+        (**self).advance(input, num_done, total, output)
+    }
+
+    fn finish(&self, input: &Path, total: usize) {
+        (**self).finish(input, total)
+    }
+
+    fn abort(&self, input: &Path) {
+        (**self).abort(input)
+    }
+
+    fn error(&self, msg: &str) {
+        (**self).error(msg)
+    }
+}
+
+/// Reports to both sinks, stopping the conversion if either of them fails.
+impl<A: ProgressSink, B: ProgressSink> ProgressSink for (A, B) {
+    fn start(&self, input: &Path, total: usize) {
+        self.0.start(input, total);
+        self.1.start(input, total);
+    }
+
+    fn advance(
+        &self,
+        input: &Path,
+        num_done: usize,
+        total: usize,
+        output: &Path,
+    ) -> Result<(), Error> {
+        self.0.advance(input, num_done, total, output)?;
+        self.1.advance(input, num_done, total, output)
+    }
+
+    fn finish(&self, input: &Path, total: usize) {
+        self.0.finish(input, total);
+        self.1.finish(input, total);
+    }
+
+    fn abort(&self, input: &Path) {
+        self.0.abort(input);
+        self.1.abort(input);
+    }
+
+    fn error(&self, msg: &str) {
+        self.0.error(msg);
+        self.1.error(msg);
+    }
+}
+
+/// How the command line tool reports progress
+#[derive(Debug, Default, Clone, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressFormat {
+    /// Interactive progress bar on the terminal
+    #[default]
+    Bar,
+    /// Newline-delimited JSON events on stdout
+    Json,
+}
+
+impl ProgressFormat {
+    /// The bars are shown in `multi`.
+    pub fn sink(&self, multi: &MultiProgress) -> Box<dyn ProgressSink> {
+        match self {
+            ProgressFormat::Bar => Box::new(ProgressBars::new(multi.clone())),
+            ProgressFormat::Json => Box::new(JsonLines::new(stdout())),
+        }
+    }
+}
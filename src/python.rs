@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use mrc::MrcMmap;
+use numpy::{IntoPyArray, ndarray::Array2};
+use pyo3::{
+    exceptions::{PyOSError, PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+
+use crate::{
+    Error,
+    common::{ArgEndianess, error_chain},
+    convert::{ConvertError, ConvertOptions},
+    process::{Normalize, Processing},
+    progress::NoProgress,
+    read::{Axis, Slice, Volume3D},
+    write::SampleType,
+};
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        let msg = error_chain(&err);
+        match err.kind() {
+            Error::Io(_) | Error::Write(_) => PyOSError::new_err(msg),
+            Error::Header(_)
+            | Error::Read(_)
+            | Error::Convert(ConvertError::UnsupportedMode { .. }) => PyValueError::new_err(msg),
+            _ => PyRuntimeError::new_err(msg),
+        }
+    }
+}
+
+/// A value of one of the command line choices, like `"uint8"` for the output type.
+fn parse_choice<T: ValueEnum>(value: &str) -> PyResult<T> {
+    T::from_str(value, true).map_err(PyValueError::new_err)
+}
+
+/// Convert the frames `start_at_frame` to `stop_at_frame` (1-indexed,
+/// inclusive) of the stack at `mrc_path` into TIFF files in the existing
+/// directory `dest_path`. The other arguments are the same as the options
+/// of the command line tool.
+#[pyfunction]
+#[pyo3(signature = (
+    mrc_path,
+    dest_path,
+    *,
+    start_at_frame = 1,
+    stop_at_frame = None,
+    axis = "z",
+    bin = None,
+    normalize = None,
+    output_type = None,
+    endianess = "big",
+))]
+#[allow(clippy::too_many_arguments)]
+fn convert(
+    py: Python<'_>,
+    mrc_path: PathBuf,
+    dest_path: PathBuf,
+    start_at_frame: usize,
+    stop_at_frame: Option<usize>,
+    axis: &str,
+    bin: Option<usize>,
+    normalize: Option<String>,
+    output_type: Option<&str>,
+    endianess: &str,
+) -> PyResult<()> {
+    let options = ConvertOptions {
+        endianess: parse_choice::<ArgEndianess>(endianess)?,
+        start_at_frame,
+        stop_at_frame,
+        axis: parse_choice(axis)?,
+        processing: Processing {
+            bin,
+            normalize: normalize
+                .map(Normalize::try_from)
+                .transpose()
+                .map_err(PyValueError::new_err)?
+                .unwrap_or_default(),
+            output_type: output_type.map(parse_choice::<SampleType>).transpose()?,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    // other Python threads can run during the conversion:
+    py.detach(|| crate::convert::convert(mrc_path, dest_path, &options, &NoProgress))?;
+    Ok(())
+}
+
+/// The main fields of the header of the MRC file at `path`.
+#[pyfunction]
+fn header(py: Python<'_>, path: PathBuf) -> PyResult<Bound<'_, PyDict>> {
+    let data = MrcMmap::open(&path).map_err(Error::from)?;
+    let view = data.read_view().map_err(Error::from)?;
+    let header = view.header();
+    let pixel_size = (header.mx > 0 && header.xlen > 0.0).then(|| header.xlen / header.mx as f32);
+    let num_labels = header.nlabl.clamp(0, 10) as usize;
+    let labels: Vec<String> = header
+        .label
+        .chunks_exact(80)
+        .take(num_labels)
+        .map(|label| String::from_utf8_lossy(label).trim_end().to_owned())
+        .collect();
+
+    let dict = PyDict::new(py);
+    dict.set_item("nx", header.nx)?;
+    dict.set_item("ny", header.ny)?;
+    dict.set_item("nz", header.nz)?;
+    dict.set_item("mode", header.mode)?;
+    dict.set_item("pixel_size", pixel_size)?;
+    dict.set_item("dmin", header.dmin)?;
+    dict.set_item("dmax", header.dmax)?;
+    dict.set_item("dmean", header.dmean)?;
+    dict.set_item("labels", labels)?;
+    Ok(dict)
+}
+
+/// The slice `index` (0-indexed) perpendicular to `axis` of the stack at
+/// `path`, as a 2D int16 array of shape (height, width), or uint16 for
+/// unsigned data.
+#[pyfunction]
+#[pyo3(signature = (path, index, axis = "z"))]
+fn read_slice<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    index: usize,
+    axis: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    let axis: Axis = parse_choice(axis)?;
+    let data = MrcMmap::open(&path).map_err(Error::from)?;
+    let view = data.read_view().map_err(Error::from)?;
+    let volume = Volume3D::new(view).along(axis);
+    let (width, height, _) = volume.dimensions();
+    let array = match volume.get_slice(index).map_err(Error::from)? {
+        Slice::Signed(values) => Array2::from_shape_vec((height, width), values.into_owned())
+            .map(|array| array.into_pyarray(py).into_any()),
+        Slice::Unsigned(values) => Array2::from_shape_vec((height, width), values.into_owned())
+            .map(|array| array.into_pyarray(py).into_any()),
+    };
+    Ok(array.expect("slices have width * height values"))
+}
+
+/// Conversion of 16bit 3D MRC stacks to TIFF files, and access to their
+/// headers and slices.
+#[pymodule]
+fn mrc_to_tiff(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(convert, m)?)?;
+    m.add_function(wrap_pyfunction!(header, m)?)?;
+    m.add_function(wrap_pyfunction!(read_slice, m)?)?;
+    Ok(())
+}
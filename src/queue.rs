@@ -0,0 +1,303 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use eframe::egui;
+use indicatif::MultiProgress;
+use log::{error, info};
+
+use mrc_to_tiff::{
+    Error,
+    common::error_chain,
+    convert::{self, CancelToken, ConvertOptions},
+    progress::{ProgressBars, ProgressSink},
+};
+
+use crate::i18n::tr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Waiting,
+    Running { done: usize, total: usize },
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+/// One input file, with the settings it was queued with.
+pub struct Job {
+    pub source_path: PathBuf,
+    pub dest_directory: PathBuf,
+    pub options: ConvertOptions,
+    pub status: JobStatus,
+}
+
+/// Sent from the background thread, `idx` being the position of the job in the queue
+enum QueueEvent {
+    Started {
+        idx: usize,
+    },
+    Progress {
+        idx: usize,
+        done: usize,
+        total: usize,
+    },
+    Finished {
+        idx: usize,
+        result: Result<(), String>,
+    },
+}
+
+struct Worker {
+    events: Receiver<QueueEvent>,
+    cancel: CancelToken,
+}
+
+/// Input files that are converted one after the other in a background thread.
+#[derive(Default)]
+pub struct ExportQueue {
+    jobs: Vec<Job>,
+    worker: Option<Worker>,
+}
+
+impl ExportQueue {
+    pub fn push(&mut self, source_path: PathBuf, dest_directory: PathBuf, options: ConvertOptions) {
+        self.jobs.push(Job {
+            source_path,
+            dest_directory,
+            options,
+            status: JobStatus::Waiting,
+        });
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.worker.is_some()
+    }
+
+    /// Fraction of the queued files that are converted, while the queue is running.
+    pub fn progress(&self) -> Option<f32> {
+        self.worker.as_ref()?;
+        let done: f32 = self
+            .jobs
+            .iter()
+            .map(|job| match job.status {
+                JobStatus::Waiting => 0.0,
+                JobStatus::Running { done, total } => done as f32 / total.max(1) as f32,
+                _ => 1.0,
+            })
+            .sum();
+        Some(done / self.jobs.len().max(1) as f32)
+    }
+
+    /// Convert all waiting jobs, in the order they were queued.
+    fn start(&mut self, multi: &MultiProgress) {
+        let cancel = CancelToken::new();
+        let jobs: Vec<_> = self
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| job.status == JobStatus::Waiting)
+            .map(|(idx, job)| {
+                let options = ConvertOptions {
+                    cancel: Some(cancel.clone()),
+                    ..job.options.clone()
+                };
+                (
+                    idx,
+                    job.source_path.clone(),
+                    job.dest_directory.clone(),
+                    options,
+                )
+            })
+            .collect();
+        info!("starting the export queue with {} files", jobs.len());
+        let (snd, rcv) = mpsc::channel();
+        let bars = ProgressBars::new(multi.clone());
+        let worker_cancel = cancel.clone();
+        thread::spawn(move || {
+            for (idx, source_path, dest_directory, options) in jobs {
+                if worker_cancel.is_cancelled() {
+                    break;
+                }
+                if snd.send(QueueEvent::Started { idx }).is_err() {
+                    break;
+                }
+                let result = run_job(idx, source_path, dest_directory, &options, &bars, &snd);
+                let result = result.map_err(|e| error_chain(&e));
+                if snd.send(QueueEvent::Finished { idx, result }).is_err() {
+                    break;
+                }
+            }
+        });
+        self.worker = Some(Worker {
+            events: rcv,
+            cancel,
+        });
+    }
+
+    /// Apply the events sent by the background thread since the last frame.
+    fn poll(&mut self) {
+        let Some(worker) = &self.worker else {
+            return;
+        };
+        loop {
+            match worker.events.try_recv() {
+                Ok(QueueEvent::Started { idx }) => {
+                    self.jobs[idx].status = JobStatus::Running { done: 0, total: 0 };
+                }
+                Ok(QueueEvent::Progress { idx, done, total }) => {
+                    self.jobs[idx].status = JobStatus::Running { done, total };
+                }
+                Ok(QueueEvent::Finished { idx, result }) => {
+                    let job = &mut self.jobs[idx];
+                    job.status = match result {
+                        Ok(()) => JobStatus::Done,
+                        Err(_) if worker.cancel.is_cancelled() => JobStatus::Cancelled,
+                        Err(msg) => {
+                            error!("failed to convert {:?}: {msg}", job.source_path);
+                            JobStatus::Failed(msg)
+                        }
+                    };
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    info!("export queue finished");
+                    self.worker = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Show the queue in its own window, as long as there are queued files.
+    pub fn show(&mut self, ctx: &egui::Context, multi: &MultiProgress) {
+        self.poll();
+        if self.jobs.is_empty() {
+            return;
+        }
+        let running = self.is_running();
+        let num_finished = self
+            .jobs
+            .iter()
+            .filter(|job| !matches!(job.status, JobStatus::Waiting | JobStatus::Running { .. }))
+            .count();
+
+        egui::Window::new(tr("Export queue")).show(ctx, |ui| {
+            ui.label(format!(
+                "{num_finished} of {} files finished",
+                self.jobs.len()
+            ));
+            ui.add(egui::ProgressBar::new(
+                num_finished as f32 / self.jobs.len() as f32,
+            ));
+
+            let mut remove = None;
+            egui::Grid::new("queue grid")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for (idx, job) in self.jobs.iter().enumerate() {
+                        let name = job.source_path.file_name().unwrap_or_default();
+                        ui.monospace(name.to_string_lossy())
+                            .on_hover_text(format!("to {}", job.dest_directory.display()));
+                        match &job.status {
+                            JobStatus::Waiting => ui.label(tr("waiting")),
+                            JobStatus::Running { done, total } => ui.add(
+                                egui::ProgressBar::new(*done as f32 / (*total).max(1) as f32)
+                                    .show_percentage(),
+                            ),
+                            JobStatus::Done => ui.label(tr("done")),
+                            JobStatus::Failed(msg) => ui.label(tr("failed")).on_hover_text(msg),
+                            JobStatus::Cancelled => ui.label(tr("cancelled")),
+                        };
+                        // removing shifts the positions the background thread refers to:
+                        if ui
+                            .add_enabled(!running, egui::Button::new(tr("Remove")))
+                            .clicked()
+                        {
+                            remove = Some(idx);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            ui.horizontal(|ui| {
+                let any_waiting = self.jobs.iter().any(|job| job.status == JobStatus::Waiting);
+                if ui
+                    .add_enabled(!running && any_waiting, egui::Button::new(tr("Start")))
+                    .clicked()
+                {
+                    self.start(multi);
+                }
+                if let Some(worker) = &self.worker
+                    && ui
+                        .add_enabled(
+                            !worker.cancel.is_cancelled(),
+                            egui::Button::new(tr("Cancel")),
+                        )
+                        .clicked()
+                {
+                    info!("cancelling the export queue...");
+                    worker.cancel.cancel();
+                }
+                let clear_btn = egui::Button::new(tr("Clear finished"));
+                if ui
+                    .add_enabled(!running && num_finished > 0, clear_btn)
+                    .clicked()
+                {
+                    self.jobs.retain(|job| {
+                        matches!(job.status, JobStatus::Waiting | JobStatus::Running { .. })
+                    });
+                }
+            });
+
+            if let Some(idx) = remove {
+                self.jobs.remove(idx);
+            }
+        });
+
+        if running {
+            ctx.request_repaint_after(Duration::from_millis(16));
+        }
+    }
+}
+
+/// Forwards the progress of job `idx` as `QueueEvent`s.
+struct JobProgress<'a> {
+    idx: usize,
+    events: &'a Sender<QueueEvent>,
+}
+
+impl ProgressSink for JobProgress<'_> {
+    fn advance(
+        &self,
+        _input: &Path,
+        done: usize,
+        total: usize,
+        _output: &Path,
+    ) -> Result<(), Error> {
+        let _ = self.events.send(QueueEvent::Progress {
+            idx: self.idx,
+            done,
+            total,
+        });
+        Ok(())
+    }
+}
+
+/// Convert a single queued file, also showing its progress in `bars`.
+fn run_job(
+    idx: usize,
+    source_path: PathBuf,
+    dest_directory: PathBuf,
+    options: &ConvertOptions,
+    bars: &ProgressBars,
+    events: &Sender<QueueEvent>,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(&dest_directory)?;
+    let progress = (JobProgress { idx, events }, bars);
+    convert::convert(source_path, dest_directory, options, &progress)
+}
@@ -1,29 +1,322 @@
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
 use mrc::MrcView;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Size of the main MRC header, the extended header follows right after it
+pub const HEADER_BYTES: u64 = 1024;
+
+/// The data modes of the MRC format, see `data_len`
+const MODES: [i32; 8] = [0, 1, 2, 3, 4, 6, 12, 101];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadError {
+    #[error("the header of {path:?} has invalid dimensions {nx}x{ny}x{nz}")]
+    InvalidDimensions {
+        path: PathBuf,
+        nx: i32,
+        ny: i32,
+        nz: i32,
+    },
+    #[error("the header of {path:?} has the unknown data mode {mode}")]
+    UnknownMode { path: PathBuf, mode: i32 },
+    #[error("{path:?} holds {actual} bytes of data, but its header describes {nx}x{ny}x{nz} values of mode {mode}, which take {expected} bytes{}", size_hint(*.expected, *.actual, *.fitting_mode))]
+    SizeMismatch {
+        path: PathBuf,
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        mode: i32,
+        expected: u64,
+        actual: u64,
+        /// Another mode that the data would fit
+        fitting_mode: Option<i32>,
+    },
+}
+
+fn size_hint(expected: u64, actual: u64, fitting_mode: Option<i32>) -> String {
+    match fitting_mode {
+        Some(mode) => format!(" (it would fit mode {mode}, maybe the header has the wrong mode?)"),
+        None if actual < expected => " (the file seems to be truncated)".to_owned(),
+        None => String::new(),
+    }
+}
+
+/// Bytes of `nx` x `ny` x `nz` values of the data `mode`, if it is known.
+fn data_len(mode: i32, nx: u64, ny: u64, nz: u64) -> Option<u64> {
+    let row_len = match mode {
+        0 => nx,
+        1 | 6 | 12 => 2 * nx,
+        2 | 3 => 4 * nx,
+        4 => 8 * nx,
+        // two 4 bit values per byte, with each row starting on a new byte:
+        101 => nx.div_ceil(2),
+        _ => return None,
+    };
+    Some(row_len * ny * nz)
+}
+
+/// Check that the file at `path` is as large as the dimensions and the data
+/// mode of its `header` say, so truncated files and wrong modes are reported
+/// before converting, instead of failing at the first slice past the end.
+/// Data beyond the expected end is only warned about.
+pub fn check_file_size(path: &Path, header: &mrc::Header) -> Result<(), Error> {
+    let (nx, ny, nz) = match (header.nx, header.ny, header.nz) {
+        (nx @ 0.., ny @ 0.., nz @ 0..) => (nx as usize, ny as usize, nz as usize),
+        (nx, ny, nz) => {
+            return Err(ReadError::InvalidDimensions {
+                path: path.to_owned(),
+                nx,
+                ny,
+                nz,
+            }
+            .into());
+        }
+    };
+    let mode = header.mode;
+    let len_of = |mode| data_len(mode, nx as u64, ny as u64, nz as u64);
+    let Some(expected) = len_of(mode) else {
+        return Err(ReadError::UnknownMode {
+            path: path.to_owned(),
+            mode,
+        }
+        .into());
+    };
+    let data_start = HEADER_BYTES + header.nsymbt.max(0) as u64;
+    let actual = std::fs::metadata(path)?.len().saturating_sub(data_start);
+    if actual == expected {
+        return Ok(());
+    }
+    let fitting_mode = MODES
+        .into_iter()
+        .find(|&other| len_of(other) == Some(actual));
+    if actual > expected && fitting_mode.is_none() {
+        warn!(
+            "{path:?} has {} bytes after the data described by its header",
+            actual - expected
+        );
+        return Ok(());
+    }
+    Err(ReadError::SizeMismatch {
+        path: path.to_owned(),
+        nx,
+        ny,
+        nz,
+        mode,
+        expected,
+        actual,
+        fitting_mode,
+    }
+    .into())
+}
+
+/// Statistics of the whole stack, as stored in its header by IMOD and most
+/// other software that writes MRC files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeaderStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: Option<f32>,
+}
+
+/// The `dmin`, `dmax` and `dmean` of `header`, unless they weren't computed.
+/// MRC2014 marks that with a `dmax` below `dmin` and a `dmean` below both;
+/// files whose writer doesn't set them at all have zeros in their place.
+pub fn header_stats(header: &mrc::Header) -> Option<HeaderStats> {
+    let (min, max, mean) = (header.dmin, header.dmax, header.dmean);
+    if !(min.is_finite() && max.is_finite() && min < max) {
+        return None;
+    }
+    Some(HeaderStats {
+        min,
+        max,
+        mean: (min..=max).contains(&mean).then_some(mean),
+    })
+}
+
+/// Whether the values of a stack with `header` are unsigned. IMOD and the
+/// MRC2014 standard store unsigned 16 bit values as mode 6; the IMOD flags
+/// after the `imodStamp` only say whether mode 0 bytes are signed, so mode 1
+/// is always signed, unless the caller knows better, see
+/// `Volume3D::assume_unsigned`.
+pub fn is_unsigned(header: &mrc::Header) -> bool {
+    header.mode == 6
+}
+
+/// Fails unless `view` holds 16 bit values that `Volume3D` can read.
+pub fn check_mode(view: &MrcView) -> Result<(), mrc::Error> {
+    if is_unsigned(view.header()) {
+        view.data.as_u16_slice().map(drop)
+    } else {
+        view.data.as_i16_slice().map(drop)
+    }
+}
+
+/// The 16 bit values of a slice as they are stored in the file.
+#[derive(Debug, Clone)]
+pub enum Slice<'a> {
+    Signed(Cow<'a, [i16]>),
+    Unsigned(Cow<'a, [u16]>),
+}
+
+impl Slice<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            Slice::Signed(values) => values.len(),
+            Slice::Unsigned(values) => values.len(),
+        }
+    }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append the values to `out`, as floats hold any 16 bit value exactly.
+    pub fn extend_f32(&self, out: &mut Vec<f32>) {
+        match self {
+            Slice::Signed(values) => out.extend(values.iter().map(|&v| f32::from(v))),
+            Slice::Unsigned(values) => out.extend(values.iter().map(|&v| f32::from(v))),
+        }
+    }
+
+    pub fn into_owned(self) -> Slice<'static> {
+        match self {
+            Slice::Signed(values) => Slice::Signed(Cow::Owned(values.into_owned())),
+            Slice::Unsigned(values) => Slice::Unsigned(Cow::Owned(values.into_owned())),
+        }
+    }
+}
+
+/// The axis perpendicular to the exported slices.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis {
+    /// YZ planes, with Y along the width and Z along the height
+    X,
+    /// XZ planes, with X along the width and Z along the height
+    Y,
+    /// XY planes, as stored in the file
+    #[default]
+    Z,
+}
+
+/// Read access to the slices of a 3D stack of signed or unsigned 16bit
+/// values, along any of its axes.
 // adapted from the docs of the mrc crate
 pub struct Volume3D<'a> {
     view: MrcView<'a>,
     nx: usize,
     ny: usize,
     nz: usize,
+    axis: Axis,
+    unsigned: bool,
 }
 
 impl<'a> Volume3D<'a> {
+    /// Slices along Z, use `along` for the other axes.
     pub fn new(view: MrcView<'a>) -> Self {
         let (nx, ny, nz) = view.dimensions();
-        Self { view, nx, ny, nz }
+        let unsigned = is_unsigned(view.header());
+        Self {
+            view,
+            nx,
+            ny,
+            nz,
+            axis: Axis::Z,
+            unsigned,
+        }
+    }
+
+    /// Slice the volume perpendicular to `axis` instead of along Z.
+    pub fn along(self, axis: Axis) -> Self {
+        Self { axis, ..self }
+    }
+
+    /// Read the values of mode 1 as unsigned if `assume` is set, for data
+    /// written by software that doesn't use mode 6 for them. Their bits are
+    /// taken as they are, so negative values become the upper half of the
+    /// unsigned range instead.
+    pub fn assume_unsigned(self, assume: bool) -> Self {
+        Self {
+            unsigned: self.unsigned || assume,
+            ..self
+        }
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        self.unsigned
+    }
+
+    /// The `header_stats`, unless they describe the values differently than
+    /// they are read, like the signed values of mode 1 with `assume_unsigned`.
+    pub fn header_stats(&self) -> Option<HeaderStats> {
+        header_stats(self.header()).filter(|_| self.unsigned == is_unsigned(self.header()))
+    }
+
+    pub fn header(&self) -> &mrc::Header {
+        self.view.header()
+    }
+
+    /// Width and height of a slice, and the number of slices along the current axis.
+    pub fn dimensions(&self) -> (usize, usize, usize) {
+        match self.axis {
+            Axis::X => (self.ny, self.nz, self.nx),
+            Axis::Y => (self.nx, self.nz, self.ny),
+            Axis::Z => (self.nx, self.ny, self.nz),
+        }
+    }
+
+    /// Get the `idx`-th slice along the current axis. Z slices are borrowed
+    /// from the file, X and Y slices have to be gathered from all Z slices.
+    pub fn get_slice(&self, idx: usize) -> Result<Slice<'_>, mrc::Error> {
+        let data = &self.view.data;
+        Ok(match (is_unsigned(self.view.header()), self.unsigned) {
+            (true, _) => Slice::Unsigned(self.gather(data.as_u16_slice()?, idx)?),
+            (false, true) => {
+                Slice::Unsigned(self.gather(bytemuck::cast_slice(data.as_i16_slice()?), idx)?)
+            }
+            (false, false) => Slice::Signed(self.gather(data.as_i16_slice()?, idx)?),
+        })
     }
 
-    pub fn get_slice(&self, z: usize) -> Result<&[i16], mrc::Error> {
-        if z >= self.nz {
+    fn gather<'b, T: Copy>(&self, values: &'b [T], idx: usize) -> Result<Cow<'b, [T]>, mrc::Error> {
+        let (_, _, num_slices) = self.dimensions();
+        if idx >= num_slices {
             return Err(mrc::Error::InvalidDimensions);
         }
 
         let slice_size = self.nx * self.ny;
-        let start = z * slice_size;
-        let ints = self.view.data.as_i16_slice()?;
+        if values.len() < slice_size * self.nz {
+            return Err(mrc::Error::InvalidDimensions);
+        }
 
-        ints.get(start..start + slice_size)
-            .ok_or(mrc::Error::InvalidDimensions)
+        match self.axis {
+            Axis::Z => {
+                let start = idx * slice_size;
+                Ok(Cow::Borrowed(&values[start..start + slice_size]))
+            }
+            Axis::Y => {
+                let mut out = Vec::with_capacity(self.nx * self.nz);
+                for z in 0..self.nz {
+                    let start = z * slice_size + idx * self.nx;
+                    out.extend_from_slice(&values[start..start + self.nx]);
+                }
+                Ok(Cow::Owned(out))
+            }
+            Axis::X => {
+                let mut out = Vec::with_capacity(self.ny * self.nz);
+                for z in 0..self.nz {
+                    let plane = &values[z * slice_size..(z + 1) * slice_size];
+                    out.extend(plane.iter().skip(idx).step_by(self.nx));
+                }
+                Ok(Cow::Owned(out))
+            }
+        }
     }
-}
\ No newline at end of file
+}
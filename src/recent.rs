@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Number of entries kept in each list
+const MAX_RECENT: usize = 10;
+
+/// Recently opened input files and destination directories, newest first,
+/// persisted in the user's configuration directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecentFiles {
+    pub inputs: Vec<PathBuf>,
+    pub destinations: Vec<PathBuf>,
+    /// Of the recent inputs, restored when one of them is opened again
+    pub files: HashMap<PathBuf, FileSettings>,
+    /// Only the loaded lists are saved, not the ones that start out empty
+    #[serde(skip)]
+    persistent: bool,
+}
+
+/// What was chosen for an input file the last time it was open.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FileSettings {
+    pub slice_position: usize,
+    pub export_start: usize,
+    pub export_end: usize,
+    pub display_range: Option<(f32, f32)>,
+    pub clip_to_display_range: bool,
+}
+
+/// Where the GUI keeps its files, like the list of recent files
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("mrc-to-tiff"))
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    // relative paths from the command line wouldn't work from elsewhere:
+    std::path::absolute(path).unwrap_or_else(|_| path.to_owned())
+}
+
+fn push_front(list: &mut Vec<PathBuf>, path: &Path) {
+    let path = absolute(path);
+    list.retain(|p| *p != path);
+    list.insert(0, path);
+    list.truncate(MAX_RECENT);
+}
+
+impl RecentFiles {
+    fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("recent.json"))
+    }
+
+    /// Load the lists, starting out empty if they were never saved or can't be read.
+    pub fn load() -> Self {
+        let recent = Self::path().and_then(|path| {
+            let contents = fs::read_to_string(&path).ok()?;
+            serde_json::from_str(&contents)
+                .inspect_err(|e| warn!("ignoring the list of recent files in {path:?}: {e}"))
+                .ok()
+        });
+        Self {
+            persistent: true,
+            ..recent.unwrap_or_default()
+        }
+    }
+
+    fn save(&self) {
+        let Some(path) = Self::path().filter(|_| self.persistent) else {
+            return;
+        };
+        let res = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_string_pretty(self)?));
+        if let Err(e) = res {
+            warn!("failed to save the list of recent files to {path:?}: {e}");
+        }
+    }
+
+    pub fn add_input(&mut self, path: &Path) {
+        push_front(&mut self.inputs, path);
+        self.save();
+    }
+
+    pub fn file_settings(&self, path: &Path) -> Option<FileSettings> {
+        self.files.get(&absolute(path)).copied()
+    }
+
+    /// Remember the settings of `path`, forgetting those of files that
+    /// dropped out of the recent inputs.
+    pub fn set_file_settings(&mut self, path: &Path, settings: FileSettings) {
+        let path = absolute(path);
+        if self.files.get(&path) == Some(&settings) {
+            return;
+        }
+        self.files.insert(path, settings);
+        self.files.retain(|p, _| self.inputs.contains(p));
+        self.save();
+    }
+
+    pub fn add_destination(&mut self, path: &Path) {
+        push_front(&mut self.destinations, path);
+        self.save();
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::info;
+#[cfg(feature = "native")]
+use mrc::MrcMmap;
+use serde::Deserialize;
+
+use crate::{
+    Error,
+    process::{Flip, Image, Rotation, flip, rotate},
+    read,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReferenceError {
+    #[error("reference {path:?} can't be read as 16bit integer or 32bit float data")]
+    UnsupportedMode { path: PathBuf, source: mrc::Error },
+    #[error("reference {path:?} doesn't contain any data")]
+    Empty { path: PathBuf },
+    #[error("reference {path:?} has size {width}x{height}, but the slices have size {nx}x{ny}{}", hint(*.width, *.height, *.nx, *.ny))]
+    DimensionMismatch {
+        path: PathBuf,
+        width: usize,
+        height: usize,
+        nx: usize,
+        ny: usize,
+    },
+}
+
+fn hint(width: usize, height: usize, nx: usize, ny: usize) -> &'static str {
+    if width == ny && height == nx {
+        " (maybe it needs to be rotated by 90 or 270 degrees?)"
+    } else {
+        ""
+    }
+}
+
+/// Load a reference image, like a gain or dark reference. References with
+/// multiple slices are averaged into one image. The optional rotation and
+/// flip are applied to bring the reference into the orientation of the data.
+/// Like for the data, `assume_unsigned` reads mode 1 values as unsigned.
+pub fn load_reference(
+    path: &Path,
+    rotation: Option<Rotation>,
+    direction: Option<Flip>,
+    assume_unsigned: bool,
+) -> Result<Image, Error> {
+    // without memory mapping, the reference is read into memory:
+    #[cfg(feature = "native")]
+    let data = MrcMmap::open(path)?;
+    #[cfg(feature = "native")]
+    let view = data.read_view()?;
+    #[cfg(not(feature = "native"))]
+    let data = std::fs::read(path)?;
+    #[cfg(not(feature = "native"))]
+    let view = mrc::MrcView::new(&data)?;
+    let (nx, ny, nz) = view.dimensions();
+    let slice_size = nx * ny;
+    if slice_size == 0 || nz == 0 {
+        return Err(ReferenceError::Empty {
+            path: path.to_owned(),
+        }
+        .into());
+    }
+
+    let values: Vec<f32> = if read::is_unsigned(view.header()) {
+        let ints = view
+            .data
+            .as_u16_slice()
+            .map_err(|source| ReferenceError::UnsupportedMode {
+                path: path.to_owned(),
+                source,
+            })?;
+        ints.iter().map(|&v| f32::from(v)).collect()
+    } else if let Ok(ints) = view.data.as_i16_slice() {
+        if assume_unsigned {
+            ints.iter().map(|&v| f32::from(v as u16)).collect()
+        } else {
+            ints.iter().map(|&v| f32::from(v)).collect()
+        }
+    } else {
+        view.data
+            .as_f32_slice()
+            .map_err(|source| ReferenceError::UnsupportedMode {
+                path: path.to_owned(),
+                source,
+            })?
+            .to_vec()
+    };
+
+    let mut sum = vec![0.0f32; slice_size];
+    for slice in values.chunks_exact(slice_size) {
+        sum.iter_mut().zip(slice).for_each(|(a, b)| *a += b);
+    }
+    sum.iter_mut().for_each(|v| *v /= nz as f32);
+    info!("loaded reference {path:?} of size {nx}x{ny}, averaged over {nz} slices");
+
+    let mut image = Image {
+        data: sum,
+        width: nx,
+        height: ny,
+    };
+    if let Some(rotation) = rotation {
+        image = rotate(&image, rotation);
+    }
+    if let Some(direction) = direction {
+        image = flip(&image, direction);
+    }
+    Ok(image)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GainMode {
+    /// Multiply each slice by the reference
+    #[default]
+    Multiply,
+    /// Divide each slice by the reference, pixels with a gain of zero are set to zero
+    Divide,
+}
+
+/// A gain reference, applied to the full slices before any other processing.
+#[derive(Clone)]
+pub struct Gain {
+    pub path: PathBuf,
+    pub image: Arc<Image>,
+    pub mode: GainMode,
+}
+
+// don't dump all the pixels into the log
+impl Debug for Gain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Gain")
+            .field("path", &self.path)
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+impl Gain {
+    pub fn load(
+        path: &Path,
+        mode: GainMode,
+        rotation: Option<Rotation>,
+        direction: Option<Flip>,
+        assume_unsigned: bool,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.to_owned(),
+            image: Arc::new(load_reference(path, rotation, direction, assume_unsigned)?),
+            mode,
+        })
+    }
+
+    pub fn check_dimensions(&self, nx: usize, ny: usize) -> Result<(), ReferenceError> {
+        check_dimensions(&self.path, &self.image, nx, ny)
+    }
+
+    pub fn apply(&self, image: &mut Image) {
+        let gain = &self.image.data;
+        match self.mode {
+            GainMode::Multiply => image.data.iter_mut().zip(gain).for_each(|(v, g)| *v *= g),
+            GainMode::Divide => image.data.iter_mut().zip(gain).for_each(|(v, &g)| {
+                *v = if g == 0.0 { 0.0 } else { *v / g };
+            }),
+        }
+    }
+}
+
+/// A dark reference, subtracted from the full slices before applying the gain.
+#[derive(Clone)]
+pub struct Dark {
+    pub path: PathBuf,
+    pub image: Arc<Image>,
+    /// Clamp negative values after the subtraction to zero
+    pub clamp: bool,
+}
+
+impl Debug for Dark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dark")
+            .field("path", &self.path)
+            .field("clamp", &self.clamp)
+            .finish()
+    }
+}
+
+impl Dark {
+    pub fn load(
+        path: &Path,
+        clamp: bool,
+        rotation: Option<Rotation>,
+        direction: Option<Flip>,
+        assume_unsigned: bool,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.to_owned(),
+            image: Arc::new(load_reference(path, rotation, direction, assume_unsigned)?),
+            clamp,
+        })
+    }
+
+    pub fn check_dimensions(&self, nx: usize, ny: usize) -> Result<(), ReferenceError> {
+        check_dimensions(&self.path, &self.image, nx, ny)
+    }
+
+    pub fn apply(&self, image: &mut Image) {
+        let dark = &self.image.data;
+        image.data.iter_mut().zip(dark).for_each(|(v, d)| {
+            *v -= d;
+            if self.clamp {
+                *v = v.max(0.0);
+            }
+        });
+    }
+}
+
+fn check_dimensions(
+    path: &Path,
+    reference: &Image,
+    nx: usize,
+    ny: usize,
+) -> Result<(), ReferenceError> {
+    if reference.width != nx || reference.height != ny {
+        return Err(ReferenceError::DimensionMismatch {
+            path: path.to_owned(),
+            width: reference.width,
+            height: reference.height,
+            nx,
+            ny,
+        });
+    }
+    Ok(())
+}
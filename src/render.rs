@@ -1,46 +1,116 @@
 use eframe::egui::ColorImage;
+use serde::{Deserialize, Serialize};
 
-fn get_quantile(data: &[f32], q: f32) -> f32 {
-    let mut data: Vec<f32> = data.to_vec();
-    data.sort_by(|a, b| a.partial_cmp(b).unwrap());
+/// Slices with at least this many pixels are subsampled for the display range
+const SUBSAMPLE_MIN_LEN: usize = 1 << 20;
 
-    let idx_for_q: usize = ((data.len() as f32 * q) as usize).min(data.len() - 1).max(0);
+/// Only every n-th pixel of large slices is used for the display range; not a
+/// power of two, so it doesn't line up with the column structure of detectors
+const SUBSAMPLE_STRIDE: usize = 7;
 
-    data[idx_for_q]
+/// The pixels of `data` that the display range is computed from: all of them
+/// for small slices, a strided subsample of large ones. That keeps the contrast
+/// controls interactive on large detectors, without a visible difference.
+fn range_sample(data: &[f32]) -> Vec<f32> {
+    if data.len() < SUBSAMPLE_MIN_LEN {
+        data.to_vec()
+    } else {
+        data.iter().step_by(SUBSAMPLE_STRIDE).copied().collect()
+    }
+}
+
+/// Index of the quantile `q` in the sorted `len` values.
+fn quantile_index(len: usize, q: f32) -> usize {
+    ((len as f32 * q) as usize).min(len - 1)
+}
+
+/// Only partially sorts `data`, which is much faster than a full sort on
+/// large slices.
+fn get_quantile(data: &mut [f32], q: f32) -> f32 {
+    let idx = quantile_index(data.len(), q);
+    *data.select_nth_unstable_by(idx, f32::total_cmp).1
+}
+
+/// Default display range of a slice: from its minimum up to the given quantile.
+pub fn auto_range(data: &[f32], quantile: f32) -> (f32, f32) {
+    let mut sample = range_sample(data);
+    let vmin = sample.iter().copied().fold(f32::MAX, f32::min);
+    (vmin, get_quantile(&mut sample, quantile))
 }
 
-pub fn render_to_rgb(data: &[i16], nx: usize, ny: usize, quantile: f32) -> ColorImage {
-    let (vmin, vmax) = &data.iter().fold((i16::MAX, i16::MIN), |a, &b| {
-        (a.0.min(b), a.1.max(b))
-    });
+/// Display range from the `low` up to the `high` quantile, so that a few hot
+/// or dead pixels don't wash out the contrast.
+pub fn robust_range(data: &[f32], low: f32, high: f32) -> (f32, f32) {
+    let mut data = range_sample(data);
+    let low = quantile_index(data.len(), low);
+    let high = quantile_index(data.len(), high);
+    let (below, vmax, _) = data.select_nth_unstable_by(high, f32::total_cmp);
+    let vmax = *vmax;
+    // the values below the high quantile are the smallest ones, which contain the low quantile:
+    let vmin = if low < high {
+        *below.select_nth_unstable_by(low, f32::total_cmp).1
+    } else {
+        *data.select_nth_unstable_by(low, f32::total_cmp).1
+    };
+    (vmin, vmax)
+}
 
-    let vmin = *vmin as f32;
-    let vmax = *vmax as f32;
-    
-    let data: Vec<f32> = data.iter().map(|v| *v as f32).collect();
+/// How the display range is mapped to gray values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayScaling {
+    #[default]
+    Linear,
+    /// For data with extreme dynamic range, like diffraction patterns
+    Log,
+    Sqrt,
+}
 
-    let vmax_quantiled = get_quantile(&data, quantile);
+impl DisplayScaling {
+    pub const ALL: [DisplayScaling; 3] = [Self::Linear, Self::Log, Self::Sqrt];
 
-    let normalizer = |(idx, v): (usize, &f32)| (idx, (v - vmin) / (vmax_quantiled - vmin));
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayScaling::Linear => "linear",
+            DisplayScaling::Log => "log",
+            DisplayScaling::Sqrt => "sqrt",
+        }
+    }
 
-    let to_rgba = |(idx, value): (usize, &f32)| {
-        let c = 255.0 * *value;
-        // let x = (idx % width) as u16;
-        // let y = (idx / width) as u16;
+    /// Map a value in the range 0..=1 onto itself.
+    fn apply(&self, value: f32) -> f32 {
+        // how many orders of magnitude the log scaling spans:
+        const LOG_RANGE: f32 = 1000.0;
+        let value = value.clamp(0.0, 1.0);
+        match self {
+            DisplayScaling::Linear => value,
+            DisplayScaling::Log => (1.0 + LOG_RANGE * value).ln() / (1.0 + LOG_RANGE).ln(),
+            DisplayScaling::Sqrt => value.sqrt(),
+        }
+    }
+}
+
+/// Map `vmin..=vmax` to gray values, clamping everything outside.
+pub fn render_to_rgb(
+    data: &[f32],
+    nx: usize,
+    ny: usize,
+    (vmin, vmax): (f32, f32),
+    scaling: DisplayScaling,
+) -> ColorImage {
+    let to_rgba = |value: f32| {
+        let c = 255.0 * scaling.apply(value);
         let a = 255;
         [c as u8, c as u8, c as u8, a]
     };
 
-    let iter_flat = (0..).zip(data.iter());
-
-    let mapped: Vec<u8> = if vmax_quantiled == vmin {
-        iter_flat.flat_map(to_rgba).collect()
+    let mapped: Vec<u8> = if vmax <= vmin {
+        data.iter().flat_map(|_| to_rgba(0.0)).collect()
     } else {
-        iter_flat
-            .map(normalizer)
-            .flat_map(|(idx, v)| to_rgba((idx, &v)))
+        data.iter()
+            .flat_map(|v| to_rgba((v - vmin) / (vmax - vmin)))
             .collect()
     };
 
-    ColorImage::from_rgba_unmultiplied([ny, nx], &mapped)
+    ColorImage::from_rgba_unmultiplied([nx, ny], &mapped)
 }
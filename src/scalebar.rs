@@ -0,0 +1,151 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::process::Image;
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid length {0:?}, expected a number with a unit of A, nm or um, like 50nm")]
+pub struct ParseLengthError(String);
+
+/// A scale bar of a fixed physical length, drawn into the bottom right corner.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ScaleBar {
+    /// in Ångström, like the pixel size in the mrc header
+    pub length: f32,
+}
+
+impl FromStr for ScaleBar {
+    type Err = ParseLengthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseLengthError(s.to_owned());
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(err)?;
+        let (number, unit) = s.split_at(split);
+        let number: f32 = number.parse().map_err(|_| err())?;
+        let factor = match unit.trim() {
+            "A" | "Å" => 1.0,
+            "nm" => 10.0,
+            "um" | "µm" => 10_000.0,
+            _ => return Err(err()),
+        };
+        if number <= 0.0 {
+            return Err(err());
+        }
+        Ok(ScaleBar {
+            length: number * factor,
+        })
+    }
+}
+
+impl TryFrom<String> for ScaleBar {
+    type Error = ParseLengthError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for ScaleBar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // `u` and `A`, as the font doesn't have µ or Å:
+        if self.length >= 10_000.0 {
+            write!(f, "{} um", self.length / 10_000.0)
+        } else if self.length >= 10.0 {
+            write!(f, "{} nm", self.length / 10.0)
+        } else {
+            write!(f, "{} A", self.length)
+        }
+    }
+}
+
+/// 3x5 pixel glyphs, one row per entry, the highest bit being the leftmost column.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'n' => [0b000, 0b000, 0b110, 0b101, 0b101],
+        'm' => [0b000, 0b000, 0b111, 0b111, 0b101],
+        'u' => [0b000, 0b000, 0b101, 0b101, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        _ => [0; 5],
+    }
+}
+
+fn fill_rect(image: &mut Image, x: usize, y: usize, width: usize, height: usize, value: f32) {
+    for row in y.min(image.height)..(y + height).min(image.height) {
+        let row_data = &mut image.data[row * image.width..][..image.width];
+        for v in &mut row_data[x.min(image.width)..(x + width).min(image.width)] {
+            *v = value;
+        }
+    }
+}
+
+impl ScaleBar {
+    /// Draw the bar and its label with `value`, for an image with square
+    /// pixels of `pixel_size` Ångström.
+    pub fn draw(&self, image: &mut Image, pixel_size: f32, value: f32) {
+        let scale = (image.height / 200).max(1);
+        let margin = 4 * scale;
+        let bar_width = ((self.length / pixel_size).round() as usize).min(image.width);
+        let bar_height = 2 * scale;
+        let bar_x = image.width.saturating_sub(bar_width + margin);
+        let bar_y = image.height.saturating_sub(bar_height + margin);
+        fill_rect(image, bar_x, bar_y, bar_width, bar_height, value);
+
+        let label = self.to_string();
+        let char_width = 4 * scale;
+        let label_width = label.chars().count() * char_width - scale;
+        let label_x = (bar_x + bar_width / 2).saturating_sub(label_width / 2);
+        let label_y = bar_y.saturating_sub(7 * scale);
+        for (idx, c) in label.chars().enumerate() {
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) != 0 {
+                        let x = label_x + idx * char_width + col * scale;
+                        let y = label_y + row * scale;
+                        fill_rect(image, x, y, scale, scale, value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths() {
+        let length = |s: &str| s.parse::<ScaleBar>().ok().map(|bar| bar.length);
+        assert_eq!(length("500A"), Some(500.0));
+        assert_eq!(length("50nm"), Some(500.0));
+        assert_eq!(length("1.5 um"), Some(15_000.0));
+        assert_eq!(length("2µm"), Some(20_000.0));
+        assert_eq!(length("50"), None);
+        assert_eq!(length("50mm"), None);
+        assert_eq!(length("0nm"), None);
+        assert_eq!(length("nm"), None);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(ScaleBar { length: 15_000.0 }.to_string(), "1.5 um");
+        assert_eq!(ScaleBar { length: 500.0 }.to_string(), "50 nm");
+        assert_eq!(ScaleBar { length: 5.0 }.to_string(), "5 A");
+    }
+}
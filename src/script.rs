@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+#[error("line {line} of the script: {msg}")]
+pub struct ParseScriptError {
+    line: usize,
+    msg: String,
+}
+
+/// One step of a GUI script, doing what the corresponding control does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Load an input file
+    Open(PathBuf),
+    Destination(PathBuf),
+    /// First and last frame to export, 1-indexed
+    Range(usize, usize),
+    /// The previewed slice, 1-indexed
+    Slice(usize),
+    /// Export the range and wait until it's done
+    Export,
+}
+
+/// Parse a script with one command per line, like `range 1 100`. Empty
+/// lines and lines starting with `#` are skipped.
+pub fn parse(script: &str) -> Result<Vec<Command>, ParseScriptError> {
+    script
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, text)| parse_command(text).map_err(|msg| ParseScriptError { line, msg }))
+        .collect()
+}
+
+fn parse_command(text: &str) -> Result<Command, String> {
+    let (name, arg) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let arg = arg.trim();
+    let number = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("expected a number, got {s:?}"))
+    };
+    let path = || {
+        if arg.is_empty() {
+            Err(format!("{name} needs a path"))
+        } else {
+            Ok(PathBuf::from(arg))
+        }
+    };
+    Ok(match name {
+        "open" => Command::Open(path()?),
+        "destination" => Command::Destination(path()?),
+        "range" => {
+            let (start, end) = arg
+                .split_once(char::is_whitespace)
+                .ok_or("range needs the first and last frame")?;
+            Command::Range(number(start)?, number(end.trim())?)
+        }
+        "slice" => Command::Slice(number(arg)?),
+        "export" => Command::Export,
+        _ => return Err(format!("unknown command {name:?}")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let script = "# comment\nopen /data/a b.mrc\n\n  range 2  10\nslice 3\nexport\n";
+        assert_eq!(
+            parse(script).unwrap(),
+            [
+                Command::Open(PathBuf::from("/data/a b.mrc")),
+                Command::Range(2, 10),
+                Command::Slice(3),
+                Command::Export,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = parse("open a.mrc\nrange 2\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(parse("slice x").is_err());
+        assert!(parse("open").is_err());
+        assert!(parse("zoom 2").is_err());
+    }
+}
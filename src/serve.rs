@@ -0,0 +1,132 @@
+use std::{
+    error::Error,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::jobs::{JobError, JobQueue, OptionsParser, check_inside, check_options_inside};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobRequest {
+    mrc_path: PathBuf,
+    dest_path: PathBuf,
+    #[serde(default)]
+    options: Option<serde_json::Value>,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ApiError {
+    #[error("{0}")]
+    BadRequest(Box<dyn Error + Sync + Send>),
+    #[error("not found")]
+    NotFound,
+    #[error("method not allowed")]
+    MethodNotAllowed,
+    #[error(transparent)]
+    Job(#[from] JobError),
+}
+
+impl ApiError {
+    fn status_code(&self) -> u16 {
+        match self {
+            ApiError::BadRequest(_) | ApiError::Job(JobError::InvalidPath { .. }) => 400,
+            ApiError::Job(JobError::Forbidden(_)) => 403,
+            ApiError::NotFound | ApiError::Job(JobError::NotFound(_)) => 404,
+            ApiError::MethodNotAllowed => 405,
+            ApiError::Job(JobError::Finished(_)) => 409,
+        }
+    }
+}
+
+fn json_response<T: Serialize>(status: u16, body: &T) -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("static header is valid");
+    Response::from_data(serde_json::to_vec(body).expect("serializing to memory can't fail"))
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+/// Serve a small REST API for submitting conversion jobs and polling their progress:
+///
+/// - `POST /jobs` with `{"mrc_path": ..., "dest_path": ..., "options": {...}}` queues a job
+/// - `GET /jobs` lists all jobs
+/// - `GET /jobs/<id>` returns the status, progress and written files of one job
+/// - `DELETE /jobs/<id>` cancels a queued or running job, removing the files it has written
+///
+/// Jobs are converted one after the other. If `root` is given, all paths
+/// need to be inside of it.
+pub fn serve(
+    listen: &str,
+    root: Option<&Path>,
+    parse_options: Box<OptionsParser>,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let root = root.map(|root| root.canonicalize()).transpose()?;
+    let server = Server::http(listen)?;
+    info!("listening on http://{listen}");
+
+    let jobs = JobQueue::start();
+
+    for mut request in server.incoming_requests() {
+        debug!("{} {}", request.method(), request.url());
+        let response = match handle(&mut request, &jobs, root.as_deref(), &parse_options) {
+            Ok((status, body)) => json_response(status, &body),
+            Err(e) => {
+                let body = serde_json::json!({ "error": e.to_string() });
+                json_response(e.status_code(), &body)
+            }
+        };
+        if let Err(e) = request.respond(response) {
+            error!("failed to send response: {e}");
+        }
+    }
+
+    jobs.join();
+    Ok(())
+}
+
+fn handle(
+    request: &mut Request,
+    jobs: &JobQueue,
+    root: Option<&Path>,
+    parse_options: &OptionsParser,
+) -> Result<(u16, serde_json::Value), ApiError> {
+    let url = request.url().trim_end_matches('/').to_owned();
+    let segments: Vec<&str> = url.trim_start_matches('/').split('/').collect();
+    let job_id = |id: &str| id.parse::<usize>().map_err(|_| ApiError::NotFound);
+    match (request.method(), &segments[..]) {
+        (Method::Get, ["jobs"]) => Ok((200, to_value(&jobs.list()))),
+        (Method::Get, ["jobs", id]) => Ok((200, to_value(&jobs.get(job_id(id)?)?))),
+        (Method::Post, ["jobs"]) => {
+            let mut body = String::new();
+            request
+                .as_reader()
+                .read_to_string(&mut body)
+                .map_err(|e| ApiError::BadRequest(Box::new(e)))?;
+            let job_request: JobRequest =
+                serde_json::from_str(&body).map_err(|e| ApiError::BadRequest(Box::new(e)))?;
+            let options = job_request
+                .options
+                .unwrap_or_else(|| serde_json::Value::Object(Default::default()));
+            if let Some(root) = root {
+                check_inside(&job_request.mrc_path, root)?;
+                check_inside(&job_request.dest_path, root)?;
+                check_options_inside(&options, &job_request.dest_path, root)?;
+            }
+            let options = parse_options(options).map_err(ApiError::BadRequest)?;
+            let job = jobs.submit(job_request.mrc_path, job_request.dest_path, options);
+            Ok((201, to_value(&job)))
+        }
+        (Method::Delete, ["jobs", id]) => Ok((200, to_value(&jobs.cancel(job_id(id)?)?))),
+        (_, ["jobs"] | ["jobs", _]) => Err(ApiError::MethodNotAllowed),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).expect("jobs can always be serialized")
+}
@@ -0,0 +1,298 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Summary statistics of a slice or of a whole stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub std: f32,
+}
+
+impl Stats {
+    pub fn of(data: &[f32]) -> Self {
+        let mut acc = StatsAccumulator::default();
+        acc.add(data);
+        acc.finish()
+    }
+}
+
+/// Incrementally computes `Stats` over multiple chunks of data, which can
+/// also be computed independently and merged afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct StatsAccumulator {
+    min: f32,
+    max: f32,
+    sum: f64,
+    sum_sq: f64,
+    count: usize,
+}
+
+impl Default for StatsAccumulator {
+    fn default() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+            sum_sq: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl StatsAccumulator {
+    pub fn add(&mut self, data: &[f32]) {
+        for &v in data {
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+            self.sum += v as f64;
+            self.sum_sq += (v as f64) * (v as f64);
+        }
+        self.count += data.len();
+    }
+
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            sum: self.sum + other.sum,
+            sum_sq: self.sum_sq + other.sum_sq,
+            count: self.count + other.count,
+        }
+    }
+
+    pub fn finish(&self) -> Stats {
+        if self.count == 0 {
+            return Stats {
+                min: 0.0,
+                max: 0.0,
+                mean: 0.0,
+                std: 0.0,
+            };
+        }
+        let n = self.count as f64;
+        let mean = self.sum / n;
+        let var = (self.sum_sq / n - mean * mean).max(0.0);
+        Stats {
+            min: self.min,
+            max: self.max,
+            mean: mean as f32,
+            std: var.sqrt() as f32,
+        }
+    }
+}
+
+/// Exact percentile of `data`, with `p` in the range 0..=100.
+pub fn percentile(data: &[f32], p: f32) -> f32 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut data = data.to_vec();
+    let idx = ((data.len() - 1) as f32 * p / 100.0).round() as usize;
+    let (_, value, _) = data.select_nth_unstable_by(idx, |a, b| a.total_cmp(b));
+    *value
+}
+
+/// The `Stats` of a single slice, and some of its percentiles.
+#[derive(Debug, Clone)]
+pub struct SliceStats {
+    pub stats: Stats,
+    pub percentiles: Vec<f32>,
+}
+
+impl SliceStats {
+    /// With the exact percentiles `ps`, which are clamped to the range 0..=100.
+    pub fn of(data: &[f32], ps: &[f32]) -> Self {
+        Self {
+            stats: Stats::of(data),
+            percentiles: ps
+                .iter()
+                .map(|&p| percentile(data, p.clamp(0.0, 100.0)))
+                .collect(),
+        }
+    }
+}
+
+/// Write one row for each `(file, section, stats)`, with the columns `file`,
+/// `section`, `min`, `max`, `mean`, `std` and `p<P>` for each of the
+/// percentiles `ps`.
+pub fn write_csv<'a>(
+    path: &Path,
+    ps: &[f32],
+    rows: impl IntoIterator<Item = (&'a str, usize, &'a SliceStats)>,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create_new(path)?);
+    write!(out, "file,section,min,max,mean,std")?;
+    for p in ps {
+        write!(out, ",p{p}")?;
+    }
+    writeln!(out)?;
+    for (file, section, slice) in rows {
+        let Stats {
+            min,
+            max,
+            mean,
+            std,
+        } = slice.stats;
+        write!(
+            out,
+            "{},{section},{min},{max},{mean},{std}",
+            csv_field(file)
+        )?;
+        for value in &slice.percentiles {
+            write!(out, ",{value}")?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// Quoted if it contains a separator or a quote.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Fixed-range histogram, used to approximate percentiles over data that is
+/// too large to be sorted as a whole.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    min: f32,
+    max: f32,
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn new(min: f32, max: f32, num_bins: usize) -> Self {
+        Self {
+            min,
+            max,
+            counts: vec![0; num_bins],
+        }
+    }
+
+    pub fn bin_width(&self) -> f32 {
+        (self.max - self.min) / self.counts.len() as f32
+    }
+
+    /// Center and count of each bin.
+    pub fn bins(&self) -> impl Iterator<Item = (f32, u64)> + '_ {
+        let width = self.bin_width();
+        self.counts
+            .iter()
+            .enumerate()
+            .map(move |(bin, &count)| (self.min + (bin as f32 + 0.5) * width, count))
+    }
+
+    pub fn add(&mut self, data: &[f32]) {
+        for &v in data {
+            let bin = self.bin_of(v);
+            self.counts[bin] += 1;
+        }
+    }
+
+    /// Index of the bin containing `v`; values outside of the range are
+    /// counted in the first or last bin.
+    pub fn bin_of(&self, v: f32) -> usize {
+        let width = self.bin_width();
+        if width > 0.0 {
+            (((v - self.min) / width) as usize).min(self.counts.len() - 1)
+        } else {
+            0
+        }
+    }
+
+    /// Fraction of the values in each bin or below it.
+    pub fn cumulative(&self) -> Vec<f32> {
+        let total = self.counts.iter().sum::<u64>().max(1) as f64;
+        self.counts
+            .iter()
+            .scan(0, |seen, &count| {
+                *seen += count;
+                Some((*seen as f64 / total) as f32)
+            })
+            .collect()
+    }
+
+    pub fn merge(mut self, other: Self) -> Self {
+        for (a, b) in self.counts.iter_mut().zip(other.counts) {
+            *a += b;
+        }
+        self
+    }
+
+    /// Approximate percentile, with `p` in the range 0..=100. Accurate up to
+    /// the width of one bin.
+    pub fn percentile(&self, p: f32) -> f32 {
+        let total: u64 = self.counts.iter().sum();
+        let target = (total as f64 * p as f64 / 100.0).round() as u64;
+        let mut seen = 0;
+        for (bin, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target.max(1) {
+                return self.min + (bin as f32 + 0.5) * self.bin_width();
+            }
+        }
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_percentiles() {
+        let data: Vec<f32> = (0..=100).rev().map(|v| v as f32).collect();
+        assert_eq!(percentile(&data, 0.0), 0.0);
+        assert_eq!(percentile(&data, 50.0), 50.0);
+        assert_eq!(percentile(&data, 99.0), 99.0);
+        assert_eq!(percentile(&data, 100.0), 100.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+
+    #[test]
+    fn stats() {
+        let stats = Stats::of(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(
+            (stats.min, stats.max, stats.mean, stats.std),
+            (2.0, 9.0, 5.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn histogram_percentiles() {
+        let data: Vec<f32> = (0..1000).map(|v| v as f32).collect();
+        let mut hist = Histogram::new(0.0, 1000.0, 100);
+        hist.add(&data[..400]);
+        let mut other = Histogram::new(0.0, 1000.0, 100);
+        other.add(&data[400..]);
+        let hist = hist.merge(other);
+        for p in [1.0, 25.0, 50.0, 90.0] {
+            let exact = percentile(&data, p);
+            assert!(
+                (hist.percentile(p) - exact).abs() <= hist.bin_width(),
+                "p{p}"
+            );
+        }
+        assert_eq!(hist.cumulative().last(), Some(&1.0));
+    }
+
+    #[test]
+    fn histogram_range() {
+        let hist = Histogram::new(0.0, 10.0, 10);
+        assert_eq!(hist.bin_of(-5.0), 0);
+        assert_eq!(hist.bin_of(0.0), 0);
+        assert_eq!(hist.bin_of(9.99), 9);
+        assert_eq!(hist.bin_of(10.0), 9);
+        assert_eq!(hist.bin_of(50.0), 9);
+        assert_eq!(Histogram::new(3.0, 3.0, 10).bin_of(3.0), 0);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+/// Limits the average rate of I/O, shared between all threads (and, in batch
+/// mode, all inputs) that are reading and writing at the same time.
+#[derive(Debug)]
+pub struct IoLimiter {
+    bytes_per_sec: f64,
+    /// The point in time at which the bandwidth is free again
+    next_free: Mutex<Instant>,
+}
+
+impl IoLimiter {
+    pub fn new(bytes_per_sec: f64) -> Self {
+        Self {
+            bytes_per_sec,
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserve the bandwidth for transferring `bytes`, blocking until the
+    /// transfers reserved before it would be done at the limited rate.
+    pub fn acquire(&self, bytes: usize) {
+        let duration = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec);
+        let start = {
+            let mut next_free = self.next_free.lock().expect("limiter lock poisoned");
+            let start = (*next_free).max(Instant::now());
+            *next_free = start + duration;
+            start
+        };
+        thread::sleep(start.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Lower the CPU and, on Linux, the I/O scheduling priority of this process,
+/// so it only uses resources that other processes leave idle.
+pub fn lower_priority() {
+    #[cfg(unix)]
+    {
+        // SAFETY: only changes the scheduling priority of the calling process
+        let res = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) };
+        if res != 0 {
+            warn!(
+                "failed to lower the process priority: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        // SAFETY: only changes the I/O priority of the calling process
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        };
+        if res != 0 {
+            warn!(
+                "failed to lower the I/O priority: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    warn!("lowering the process priority is not supported on this platform");
+}
@@ -0,0 +1,222 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use log::debug;
+
+use crate::read::HEADER_BYTES;
+
+/// Offsets of fields (all f64) in each section of an FEI extended header: the
+/// acceleration voltage in V, the dose in e/m², the alpha tilt in degrees and
+/// the defocus in m
+const FEI_HT: usize = 84;
+const FEI_DOSE: usize = 92;
+const FEI_ALPHA_TILT: usize = 100;
+const FEI_DEFOCUS: usize = 220;
+
+/// Tilt angles of the slices in degrees, from the SerialEM .mdoc file next to
+/// the stack, or else from its extended header. `None` if neither has an
+/// angle for each of the `nz` slices.
+pub fn tilt_angles(path: &Path, header: &mrc::Header, nz: usize) -> Option<Vec<f32>> {
+    let angles = read_mdoc(path)
+        .and_then(|contents| from_mdoc(&contents, "TiltAngle", nz))
+        .or_else(|| from_extended_header(path, header, nz));
+    if angles.is_none() {
+        debug!("no tilt angles found for {path:?}");
+    }
+    angles
+}
+
+/// Exposure of each of the `nz` slices in e/Å², from the .mdoc file or the
+/// FEI extended header.
+pub fn exposure_doses(path: &Path, header: &mrc::Header, nz: usize) -> Option<Vec<f32>> {
+    let doses = read_mdoc(path)
+        .and_then(|contents| from_mdoc(&contents, "ExposureDose", nz))
+        .or_else(|| {
+            let values = fei_values(&extended_header(path, header)?, nz, FEI_DOSE)?;
+            Some(
+                values
+                    .into_iter()
+                    .map(|dose| (dose * 1e-20) as f32)
+                    .collect(),
+            )
+        });
+    if doses.is_none() {
+        debug!("no exposure doses found for {path:?}");
+    }
+    doses
+}
+
+/// Defocus of each of the `nz` slices in µm, from the .mdoc file or the FEI
+/// extended header.
+pub fn defocus(path: &Path, header: &mrc::Header, nz: usize) -> Option<Vec<f32>> {
+    let defocus = read_mdoc(path)
+        .and_then(|contents| from_mdoc(&contents, "Defocus", nz))
+        .or_else(|| {
+            let values = fei_values(&extended_header(path, header)?, nz, FEI_DEFOCUS)?;
+            Some(
+                values
+                    .into_iter()
+                    .map(|defocus| (defocus * 1e6) as f32)
+                    .collect(),
+            )
+        });
+    if defocus.is_none() {
+        debug!("no defocus found for {path:?}");
+    }
+    defocus
+}
+
+/// The acquisition parameters of the sections of a stack, as far as they
+/// are known.
+#[derive(Debug, Clone, Default)]
+pub struct Acquisition {
+    /// In degrees
+    pub tilt_angles: Option<Vec<f32>>,
+    /// In µm
+    pub defocus: Option<Vec<f32>>,
+    /// Exposure of each section in e/Å²
+    pub doses: Option<Vec<f32>>,
+}
+
+impl Acquisition {
+    /// The parameters of the `nz` sections of the stack at `path`.
+    pub fn read(path: &Path, header: &mrc::Header, nz: usize) -> Self {
+        Self {
+            tilt_angles: tilt_angles(path, header, nz),
+            defocus: defocus(path, header, nz),
+            doses: exposure_doses(path, header, nz),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tilt_angles.is_none() && self.defocus.is_none() && self.doses.is_none()
+    }
+
+    /// The parameters of section `z` (0-indexed) as `key=value` lines,
+    /// starting with its 1-indexed number.
+    pub fn describe(&self, z: usize) -> String {
+        let mut lines = vec![format!("section={}", z + 1)];
+        let values = [
+            ("tilt_angle", &self.tilt_angles),
+            ("defocus", &self.defocus),
+            ("dose", &self.doses),
+        ];
+        for (key, values) in values {
+            if let Some(value) = values.as_ref().and_then(|values| values.get(z)) {
+                lines.push(format!("{key}={value}"));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Acceleration voltage in kV, from the FEI extended header.
+pub fn acceleration_voltage(path: &Path, header: &mrc::Header) -> Option<f32> {
+    let values = fei_values(&extended_header(path, header)?, 1, FEI_HT)?;
+    values
+        .first()
+        .map(|&ht| (ht / 1000.0) as f32)
+        .filter(|&kv| kv > 0.0)
+}
+
+/// The SerialEM .mdoc file next to the stack
+fn read_mdoc(path: &Path) -> Option<String> {
+    let mut mdoc_path = path.as_os_str().to_owned();
+    mdoc_path.push(".mdoc");
+    fs::read_to_string(&mdoc_path).ok()
+}
+
+/// The `key` entries of the `[ZValue = n]` sections.
+fn from_mdoc(contents: &str, key: &str, nz: usize) -> Option<Vec<f32>> {
+    let mut values = vec![None; nz];
+    let mut z = None;
+    for line in contents.lines() {
+        let Some((name, value)) = line.trim().trim_matches(['[', ']']).split_once('=') else {
+            continue;
+        };
+        match name.trim() {
+            "ZValue" => z = value.trim().parse::<usize>().ok(),
+            name if name == key => {
+                if let Some(entry) = z.and_then(|z| values.get_mut(z)) {
+                    *entry = value.trim().parse::<f32>().ok();
+                }
+            }
+            _ => {}
+        }
+    }
+    values.into_iter().collect()
+}
+
+/// The tilt angles stored in FEI and SerialEM extended headers.
+fn from_extended_header(path: &Path, header: &mrc::Header, nz: usize) -> Option<Vec<f32>> {
+    let ext = extended_header(path, header).filter(|_| nz > 0)?;
+    // NINT and NREAL are words 33 and 34, inside of `extra`:
+    let nint = i16::from_le_bytes([header.extra[32], header.extra[33]]);
+    let nreal = i16::from_le_bytes([header.extra[34], header.extra[35]]);
+    let angles: Vec<f32> = match ext.kind {
+        ExtendedHeaderKind::Fei => fei_values(&ext, nz, FEI_ALPHA_TILT)?
+            .into_iter()
+            .map(|angle| angle as f32)
+            .collect(),
+        // the tilt angle times 100 comes first, if the first flag is set:
+        ExtendedHeaderKind::SerialEm if nreal & 1 != 0 && nint >= 2 => ext
+            .data
+            .chunks_exact(nint as usize)
+            .take(nz)
+            .map(|s| f32::from(i16::from_le_bytes([s[0], s[1]])) / 100.0)
+            .collect(),
+        _ => return None,
+    };
+    (angles.len() == nz).then_some(angles)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExtendedHeaderKind {
+    Fei,
+    SerialEm,
+    Other,
+}
+
+struct ExtendedHeader {
+    kind: ExtendedHeaderKind,
+    data: Vec<u8>,
+}
+
+/// Read the extended header following the main header, if there is one.
+fn extended_header(path: &Path, header: &mrc::Header) -> Option<ExtendedHeader> {
+    if header.nsymbt <= 0 {
+        return None;
+    }
+    // EXTTYP is word 27, inside of `extra`:
+    let kind = match &header.extra[8..12] {
+        b"FEI1" | b"FEI2" => ExtendedHeaderKind::Fei,
+        b"SERI" => ExtendedHeaderKind::SerialEm,
+        _ => ExtendedHeaderKind::Other,
+    };
+    let mut data = vec![0; header.nsymbt as usize];
+    let mut file = File::open(path).ok()?;
+    file.seek(SeekFrom::Start(HEADER_BYTES)).ok()?;
+    file.read_exact(&mut data).ok()?;
+    Some(ExtendedHeader { kind, data })
+}
+
+/// The f64 field at `offset` of the first `nz` sections of an FEI extended
+/// header; `None` if it has fewer sections.
+fn fei_values(ext: &ExtendedHeader, nz: usize, offset: usize) -> Option<Vec<f64>> {
+    if ext.kind != ExtendedHeaderKind::Fei || nz == 0 {
+        return None;
+    }
+    // the size of each section is given at its start:
+    let section = i32::from_le_bytes(ext.data.get(0..4)?.try_into().ok()?);
+    let section = usize::try_from(section).ok().filter(|&s| s >= offset + 8)?;
+    let values: Vec<f64> = ext
+        .data
+        .chunks_exact(section)
+        .take(nz)
+        .map(|s| f64::from_le_bytes(s[offset..offset + 8].try_into().expect("8 bytes")))
+        .collect();
+    (values.len() == nz).then_some(values)
+}
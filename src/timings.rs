@@ -0,0 +1,207 @@
+use std::{
+    cell::Cell,
+    fmt,
+    io::{Seek, SeekFrom, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The parts of converting a slice that are timed separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Copying the slice out of the input, which includes the page faults of
+    /// memory mapped files. Slices that are written as they are, without any
+    /// processing, are only read from the mapping while they are encoded.
+    Read,
+    /// Everything between reading and encoding
+    Process,
+    /// Converting to the output sample type and byte order, and building the
+    /// tiff file
+    Encode,
+    /// Creating and writing the output files
+    Write,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Self::Read, Self::Process, Self::Encode, Self::Write];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Process => "process",
+            Stage::Encode => "encode",
+            Stage::Write => "write",
+        }
+    }
+}
+
+thread_local! {
+    /// Time of the stages measured on this thread so far, which doesn't count
+    /// for the stages around them.
+    static MEASURED: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+    /// Time spent in the file I/O of `io` on this thread so far
+    static IO: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Durations of the stages of each slice, recorded by all threads of one or
+/// more conversions, for telling whether they are limited by the I/O or by
+/// the CPU.
+#[derive(Debug, Default)]
+pub struct StageTimings {
+    durations: Mutex<[Vec<Duration>; 4]>,
+}
+
+/// Aggregated durations of one stage.
+#[derive(Debug, Clone)]
+pub struct StageSummary {
+    pub stage: Stage,
+    /// Number of measurements, like the number of slices
+    pub count: usize,
+    pub total: Duration,
+    pub median: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl StageTimings {
+    fn record(&self, stage: Stage, duration: Duration) {
+        let mut durations = self.durations.lock().expect("timings lock poisoned");
+        durations[stage as usize].push(duration);
+    }
+
+    /// Of the stages that were measured, in the order of `Stage::ALL`.
+    pub fn summaries(&self) -> Vec<StageSummary> {
+        let durations = self.durations.lock().expect("timings lock poisoned");
+        Stage::ALL
+            .into_iter()
+            .filter(|&stage| !durations[stage as usize].is_empty())
+            .map(|stage| {
+                let mut sorted = durations[stage as usize].clone();
+                sorted.sort_unstable();
+                let percentile = |p: f64| sorted[(p * (sorted.len() - 1) as f64).round() as usize];
+                StageSummary {
+                    stage,
+                    count: sorted.len(),
+                    total: sorted.iter().sum(),
+                    median: percentile(0.5),
+                    p90: percentile(0.9),
+                    p99: percentile(0.99),
+                    max: sorted[sorted.len() - 1],
+                }
+            })
+            .collect()
+    }
+}
+
+/// A table of the summaries, followed by how the time is split between the
+/// I/O and the CPU.
+impl fmt::Display for StageTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let summaries = self.summaries();
+        let ms = |duration: Duration| duration.as_secs_f64() * 1e3;
+        writeln!(
+            f,
+            "{:<8} {:>7} {:>10} {:>9} {:>9} {:>9} {:>9} {:>6}",
+            "stage", "count", "total s", "p50 ms", "p90 ms", "p99 ms", "max ms", "share"
+        )?;
+        let total: Duration = summaries.iter().map(|summary| summary.total).sum();
+        let share = |duration: Duration| 100.0 * duration.as_secs_f64() / total.as_secs_f64();
+        for summary in &summaries {
+            writeln!(
+                f,
+                "{:<8} {:>7} {:>10.2} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>5.0}%",
+                summary.stage.name(),
+                summary.count,
+                summary.total.as_secs_f64(),
+                ms(summary.median),
+                ms(summary.p90),
+                ms(summary.p99),
+                ms(summary.max),
+                share(summary.total),
+            )?;
+        }
+        if total.is_zero() {
+            return Ok(());
+        }
+        let io: Duration = summaries
+            .iter()
+            .filter(|summary| matches!(summary.stage, Stage::Read | Stage::Write))
+            .map(|summary| summary.total)
+            .sum();
+        writeln!(
+            f,
+            "I/O (read and write): {:.0}%, CPU (process and encode): {:.0}%",
+            share(io),
+            share(total - io),
+        )
+    }
+}
+
+/// Run `f` and return its result, with its duration, the part of it spent in
+/// stages measured within it, and the part spent in file I/O.
+fn measure<R>(f: impl FnOnce() -> R) -> (R, Duration, Duration, Duration) {
+    let (measured, io) = (MEASURED.get(), IO.get());
+    let t0 = Instant::now();
+    let res = f();
+    let elapsed = t0.elapsed();
+    let inner = MEASURED.get().saturating_sub(measured);
+    MEASURED.set(measured + elapsed);
+    (res, elapsed, inner, IO.get().saturating_sub(io))
+}
+
+/// Run `f` as `stage` of a slice, recording its duration if `timings` are
+/// given. The stages measured within it don't count, like the reading of
+/// the neighbors a processing step loads.
+pub fn time<R>(timings: Option<&StageTimings>, stage: Stage, f: impl FnOnce() -> R) -> R {
+    let Some(timings) = timings else {
+        return f();
+    };
+    let (res, elapsed, inner, _) = measure(f);
+    timings.record(stage, elapsed.saturating_sub(inner));
+    res
+}
+
+/// Like `time`, for writing a file with the functions of `write`, which
+/// encode it while writing: the file I/O is recorded as `Stage::Write`, and
+/// the rest as `Stage::Encode`.
+pub fn time_write<R>(timings: Option<&StageTimings>, f: impl FnOnce() -> R) -> R {
+    let Some(timings) = timings else {
+        return f();
+    };
+    let (res, elapsed, inner, io) = measure(f);
+    timings.record(Stage::Encode, elapsed.saturating_sub(inner + io));
+    timings.record(Stage::Write, io);
+    res
+}
+
+/// Run the file I/O `f`, which counts as `Stage::Write` for `time_write`.
+pub(crate) fn io<R>(f: impl FnOnce() -> R) -> R {
+    let t0 = Instant::now();
+    let res = f();
+    IO.set(IO.get() + t0.elapsed());
+    res
+}
+
+/// Counts the time spent writing to and seeking in `W` as file I/O.
+pub(crate) struct TimedWriter<W>(pub W);
+
+impl<W: Write> Write for TimedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        io(|| self.0.write(buf))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        io(|| self.0.write_all(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        io(|| self.0.flush())
+    }
+}
+
+impl<W: Seek> Seek for TimedWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        io(|| self.0.seek(pos))
+    }
+}
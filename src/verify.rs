@@ -0,0 +1,166 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use indicatif::{MultiProgress, ParallelProgressIterator, ProgressBar};
+use log::{debug, info, warn};
+use mrc::MrcMmap;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use tiff::decoder::{Decoder, DecodingResult};
+
+use mrc_to_tiff::{
+    convert::{OutputNaming, frame_range},
+    read::{Axis, Slice, Volume3D, check_file_size},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    #[error("verification failed: {num_missing} missing and {num_mismatched} mismatching files")]
+    Failed {
+        num_missing: usize,
+        num_mismatched: usize,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub num_checked: usize,
+    pub missing: Vec<PathBuf>,
+    pub mismatched: Vec<(PathBuf, String)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+
+    pub fn into_result(self) -> Result<(), VerifyError> {
+        if self.is_ok() {
+            Ok(())
+        } else {
+            Err(VerifyError::Failed {
+                num_missing: self.missing.len(),
+                num_mismatched: self.mismatched.len(),
+            })
+        }
+    }
+}
+
+enum SliceCheck {
+    Ok,
+    Missing,
+    Mismatch(String),
+}
+
+fn check_slice(
+    path: &Path,
+    expected: &Slice,
+    nx: usize,
+    ny: usize,
+) -> Result<SliceCheck, Box<dyn Error + Sync + Send>> {
+    if !path.exists() {
+        return Ok(SliceCheck::Missing);
+    }
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    let (width, height) = decoder.dimensions()?;
+    if (width as usize, height as usize) != (nx, ny) {
+        return Ok(SliceCheck::Mismatch(format!(
+            "dimensions {width}x{height} don't match source {nx}x{ny}"
+        )));
+    }
+    let num_different = match (decoder.read_image()?, expected) {
+        (DecodingResult::I16(actual), Slice::Signed(expected)) => {
+            count_different(&actual, expected)
+        }
+        (DecodingResult::U16(actual), Slice::Unsigned(expected)) => {
+            count_different(&actual, expected)
+        }
+        (_, Slice::Signed(_)) => {
+            return Ok(SliceCheck::Mismatch(
+                "unexpected sample type, expected signed 16bit integers".to_owned(),
+            ));
+        }
+        (_, Slice::Unsigned(_)) => {
+            return Ok(SliceCheck::Mismatch(
+                "unexpected sample type, expected unsigned 16bit integers".to_owned(),
+            ));
+        }
+    };
+    if num_different > 0 {
+        return Ok(SliceCheck::Mismatch(format!(
+            "{num_different} pixels differ from the source"
+        )));
+    }
+    Ok(SliceCheck::Ok)
+}
+
+fn count_different<T: PartialEq>(actual: &[T], expected: &[T]) -> usize {
+    actual.iter().zip(expected).filter(|(a, b)| a != b).count()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn verify(
+    mrc_path: PathBuf,            // 3d, 16bit
+    dest_path: PathBuf,           // directory with converted tiffs
+    start_at_frame: usize,        // 1-indexed
+    stop_at_frame: Option<usize>, // 1-indexed, last frame if not given
+    naming: &OutputNaming,        // as used for the conversion
+    axis: Axis,                   // as used for the conversion
+    assume_unsigned: bool,        // as used for the conversion
+    multi_progress: &MultiProgress,
+) -> Result<VerifyReport, Box<dyn Error + Sync + Send>> {
+    let t0 = Instant::now();
+
+    let data = MrcMmap::open(&mrc_path)?;
+    check_file_size(&mrc_path, data.header())?;
+    let volume = Volume3D::new(data.read_view()?)
+        .along(axis)
+        .assume_unsigned(assume_unsigned);
+    let (nx, ny, nz) = volume.dimensions();
+
+    let range = frame_range(start_at_frame, stop_at_frame, nz)?;
+    let start = range.start;
+
+    let idxs: Vec<usize> = range.collect();
+    let progress = multi_progress.add(ProgressBar::new(idxs.len() as u64));
+
+    let report = Mutex::new(VerifyReport::default());
+
+    let res: Result<Vec<()>, _> = idxs
+        .into_par_iter()
+        .progress_with(progress.clone())
+        .map(|z| -> Result<(), Box<dyn Error + Sync + Send>> {
+            let slice = volume.get_slice(z)?;
+            let idx = z + 1 - start;
+            let out_path = dest_path.join(naming.slice_filename(idx));
+            let check = check_slice(&out_path, &slice, nx, ny)?;
+            let mut report = report.lock().unwrap();
+            report.num_checked += 1;
+            match check {
+                SliceCheck::Ok => debug!("verified {out_path:?}"),
+                SliceCheck::Missing => {
+                    warn!("missing {out_path:?}");
+                    report.missing.push(out_path);
+                }
+                SliceCheck::Mismatch(msg) => {
+                    warn!("mismatch in {out_path:?}: {msg}");
+                    report.mismatched.push((out_path, msg));
+                }
+            }
+            Ok(())
+        })
+        .collect();
+    res?;
+
+    progress.finish();
+    multi_progress.remove(&progress);
+
+    info!("verification done in {:?}", t0.elapsed());
+
+    Ok(report.into_inner().unwrap())
+}
@@ -0,0 +1,111 @@
+use clap::ValueEnum;
+use mrc::MrcView;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    Error,
+    process::{Normalize, Processing},
+    read::{self, Slice, Volume3D},
+    write::{SampleType, encode_image},
+};
+
+/// An MRC stack held in memory, like a file dropped onto a web page.
+#[wasm_bindgen]
+pub struct Stack {
+    bytes: Vec<u8>,
+    width: usize,
+    height: usize,
+    num_slices: usize,
+}
+
+#[wasm_bindgen]
+impl Stack {
+    /// Check the header of the file contents `bytes`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: Vec<u8>) -> Result<Stack, JsError> {
+        let view = MrcView::new(&bytes).map_err(Error::from)?;
+        read::check_mode(&view).map_err(Error::from)?;
+        let (width, height, num_slices) = view.dimensions();
+        Ok(Stack {
+            bytes,
+            width,
+            height,
+            num_slices,
+        })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[wasm_bindgen(getter, js_name = numSlices)]
+    pub fn num_slices(&self) -> usize {
+        self.num_slices
+    }
+
+    /// The slice `index` (0-indexed) as a native endian tiff file. The
+    /// options are named like the ones of the command line tool.
+    #[wasm_bindgen(js_name = sliceTiff)]
+    pub fn slice_tiff(
+        &self,
+        index: usize,
+        bin: Option<usize>,
+        normalize: Option<String>,
+        output_type: Option<String>,
+    ) -> Result<Vec<u8>, JsError> {
+        let processing = Processing {
+            bin,
+            normalize: normalize
+                .map(Normalize::try_from)
+                .transpose()
+                .map_err(|msg| JsError::new(&msg))?
+                .unwrap_or_default(),
+            output_type: output_type
+                .map(|name| SampleType::from_str(&name, true))
+                .transpose()
+                .map_err(|msg| JsError::new(&msg))?,
+            ..Default::default()
+        };
+        let image = processing.apply(&self.slice(index)?, self.width, self.height);
+        let mut tiff = Vec::new();
+        encode_image(
+            std::io::Cursor::new(&mut tiff),
+            &image,
+            processing.output_sample_type(),
+            &processing.tiff_metadata(),
+        )?;
+        Ok(tiff)
+    }
+
+    /// The slice `index` (0-indexed), binned by `bin` and scaled from its
+    /// minimum to its maximum, as RGBA pixels for an `ImageData`. The width
+    /// and height are divided by `bin`, rounding down.
+    #[wasm_bindgen(js_name = sliceRgba)]
+    pub fn slice_rgba(&self, index: usize, bin: Option<usize>) -> Result<Vec<u8>, JsError> {
+        let processing = Processing {
+            bin,
+            normalize: Normalize::MinMax,
+            output_type: Some(SampleType::U8),
+            ..Default::default()
+        };
+        let image = processing.apply(&self.slice(index)?, self.width, self.height);
+        Ok(image
+            .to_samples::<u8>()
+            .into_iter()
+            .flat_map(|v| [v, v, v, u8::MAX])
+            .collect())
+    }
+}
+
+impl Stack {
+    fn slice(&self, index: usize) -> Result<Slice<'static>, Error> {
+        let view = MrcView::new(&self.bytes)?;
+        Ok(Volume3D::new(view).get_slice(index)?.into_owned())
+    }
+}
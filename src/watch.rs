@@ -0,0 +1,93 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{debug, error, info};
+
+use mrc_to_tiff::{
+    Error,
+    batch::{convert_one, expand_template},
+    convert::ConvertOptions,
+    progress::ProgressSink,
+};
+
+/// How often the watched directory is scanned for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size and modification time of a file, to decide whether it's still being written.
+type FileSignature = (u64, Option<SystemTime>);
+
+fn mrc_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_mrc = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mrc"));
+        if is_mrc && path.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Watch `dir` for new .mrc files and convert each of them as soon as it
+/// hasn't changed for `settle_time`, into the destination given by `template`.
+/// Files already present when starting are skipped. Runs until interrupted;
+/// failed conversions are logged and don't stop watching.
+pub fn watch(
+    dir: &Path,
+    dest_path: &Path,
+    template: &str,
+    settle_time: Duration,
+    options: &ConvertOptions,
+    progress: &dyn ProgressSink,
+) -> Result<(), Error> {
+    let mut known: HashSet<PathBuf> = mrc_files(dir)?.into_iter().collect();
+    info!(
+        "watching {dir:?} for new .mrc files, skipping {} existing ones",
+        known.len()
+    );
+    let mut pending: HashMap<PathBuf, (FileSignature, Instant)> = HashMap::new();
+
+    loop {
+        for path in mrc_files(dir)? {
+            if known.contains(&path) {
+                continue;
+            }
+            // the file may vanish between listing and reading the metadata:
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+            let signature = (meta.len(), meta.modified().ok());
+            match pending.get(&path) {
+                Some((previous, since)) if *previous == signature => {
+                    if since.elapsed() < settle_time {
+                        continue;
+                    }
+                }
+                _ => {
+                    debug!("{path:?} changed, waiting for it to settle");
+                    pending.insert(path, (signature, Instant::now()));
+                    continue;
+                }
+            }
+
+            pending.remove(&path);
+            known.insert(path.clone());
+            let dest = dest_path.join(expand_template(template, &path)?);
+            info!("converting {path:?} to {dest:?}");
+            if let Err(e) = convert_one(&path, &dest, options, progress) {
+                error!("failed to convert {path:?}: {e}");
+                progress.error(&format!("{path:?}: {e}"));
+            }
+        }
+        // forget files that were removed before they settled:
+        pending.retain(|path, _| path.exists());
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -1,66 +1,1228 @@
-use std::{error::Error, fs::File, path::{Path, PathBuf}};
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{BufWriter, Cursor, Seek, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use byteorder::{BigEndian, WriteBytesExt};
-use tiff::encoder::{TiffEncoder, colortype};
-use tiff_encoder::{LONG, RATIONAL, SHORT, TiffFile, ifd::{Ifd, tags}, write::ByteBlock};
+use tiff::{
+    encoder::{Rational, TiffEncoder, TiffValue, colortype},
+    tags::{ResolutionUnit, Tag, Type},
+};
+use tiff_encoder::{
+    LONG, RATIONAL, SHORT, TiffFile,
+    ifd::{Ifd, tags},
+    write::ByteBlock,
+};
+
+use crate::{
+    Error,
+    common::ArgEndianess,
+    process::Image,
+    timings::{self, TimedWriter},
+};
 
 #[derive(Debug, thiserror::Error)]
-enum WriteError {
+pub enum WriteError {
     #[error("file {path:?} already exists")]
     FileAlreadyExists { path: PathBuf },
+    #[error("page {page} of {path:?} doesn't match the size and type of the first page")]
+    StackPageMismatch { path: PathBuf, page: usize },
+    #[error("only {written} of the {expected} pages of {path:?} were written")]
+    StackIncomplete {
+        path: PathBuf,
+        written: usize,
+        expected: usize,
+    },
+}
+
+/// Values written into the tags of each tiff file, in addition to the ones
+/// describing the image layout.
+#[derive(Debug, Clone, Default)]
+pub struct TiffMetadata {
+    /// Set PhotometricInterpretation to WhiteIsZero instead of BlackIsZero
+    pub white_is_zero: bool,
+    /// Size of the pixels in Ångström, written as the resolution in pixels per centimeter
+    pub pixel_size: Option<f32>,
+    /// ImageDescription of each page of the stacks written by `StackWriter`
+    /// and `PreallocatedStack`, like the acquisition parameters of its section
+    pub page_descriptions: Vec<String>,
+}
+
+impl TiffMetadata {
+    fn photometric_interpretation(&self) -> u16 {
+        if self.white_is_zero { 0 } else { 1 }
+    }
+
+    /// Value of the ResolutionUnit tag and the X- and YResolution, as a fraction
+    fn resolution(&self) -> (u16, (u32, u32)) {
+        match self.pixel_size.filter(|&size| size > 0.0) {
+            Some(size) => {
+                let pixels_per_cm = 1e8 / size as f64;
+                // as precise as the 32bit numerator allows:
+                let denominator = (u32::MAX as f64 / pixels_per_cm).clamp(1.0, 1e6).floor();
+                let numerator = (pixels_per_cm * denominator).round().min(u32::MAX as f64);
+                (3, (numerator as u32, denominator as u32))
+            }
+            // no absolute unit:
+            None => (1, (1, 1)),
+        }
+    }
+
+    fn page_description(&self, idx: usize) -> Option<&str> {
+        self.page_descriptions.get(idx).map(String::as_str)
+    }
+}
+
+/// A pixel type that can be written to a tiff file.
+pub trait Sample: Copy + Send + Sync + 'static {
+    /// Value of the tiff `SampleFormat` tag: 1 = unsigned int, 2 = signed int, 3 = float
+    const SAMPLE_FORMAT: u16;
+
+    type ColorType: colortype::ColorType<Inner = Self>;
+
+    fn extend_be(self, out: &mut Vec<u8>);
+
+    /// Convert from the floating point processing representation, rounding
+    /// and saturating at the limits of the type.
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Sample for i16 {
+    const SAMPLE_FORMAT: u16 = 2;
+    type ColorType = colortype::GrayI16;
+
+    fn extend_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as i16
+    }
+}
+
+impl Sample for i32 {
+    const SAMPLE_FORMAT: u16 = 2;
+    type ColorType = colortype::GrayI32;
+
+    fn extend_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as i32
+    }
+}
+
+impl Sample for u8 {
+    const SAMPLE_FORMAT: u16 = 1;
+    type ColorType = colortype::Gray8;
+
+    fn extend_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as u8
+    }
+}
+
+impl Sample for u16 {
+    const SAMPLE_FORMAT: u16 = 1;
+    type ColorType = colortype::Gray16;
+
+    fn extend_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as u16
+    }
+}
+
+impl Sample for f32 {
+    const SAMPLE_FORMAT: u16 = 3;
+    type ColorType = colortype::Gray32Float;
+
+    fn extend_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+/// The sample type of the written tiff files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+pub enum SampleType {
+    #[value(name = "int16")]
+    #[serde(rename = "int16")]
+    I16,
+    #[value(name = "int32")]
+    #[serde(rename = "int32")]
+    I32,
+    #[value(name = "uint8")]
+    #[serde(rename = "uint8")]
+    U8,
+    #[value(name = "uint16")]
+    #[serde(rename = "uint16")]
+    U16,
+    #[value(name = "float32")]
+    #[serde(rename = "float32")]
+    F32,
+}
+
+impl SampleType {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            SampleType::I16 => size_of::<i16>(),
+            SampleType::I32 => size_of::<i32>(),
+            SampleType::U8 => size_of::<u8>(),
+            SampleType::U16 => size_of::<u16>(),
+            SampleType::F32 => size_of::<f32>(),
+        }
+    }
+
+    /// The value that 1.0 is mapped to when writing data normalized to the range 0..=1.
+    pub fn unit_scale(&self) -> f32 {
+        match self {
+            SampleType::I16 => i16::MAX as f32,
+            SampleType::I32 => i32::MAX as f32,
+            SampleType::U8 => u8::MAX as f32,
+            SampleType::U16 => u16::MAX as f32,
+            SampleType::F32 => 1.0,
+        }
+    }
+
+    /// The smallest and the largest value of the type.
+    pub fn limits(&self) -> (f32, f32) {
+        match self {
+            SampleType::I16 => (i16::MIN as f32, i16::MAX as f32),
+            SampleType::I32 => (i32::MIN as f32, i32::MAX as f32),
+            SampleType::U8 => (0.0, u8::MAX as f32),
+            SampleType::U16 => (0.0, u16::MAX as f32),
+            SampleType::F32 => (f32::MIN, f32::MAX),
+        }
+    }
 }
 
+/// How the tiff files are written to disk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteBackend {
+    /// Through the page cache
+    #[default]
+    Buffered,
+    /// Encoded in memory and written at once into a file preallocated at its
+    /// final size; the pages of `--single-file` stacks are written at their
+    /// offsets by all threads at once
+    Preallocated,
+    /// Encoded in memory and written at once with O_DIRECT into a
+    /// preallocated file, bypassing the page cache (Linux only)
+    Direct,
+}
 
-pub fn write_tiff_native_endian(
+pub fn write_tiff<T: Sample>(
     filename: &Path,
-    data: &[i16],
+    data: &[T],
     width: usize,
     height: usize,
-) -> Result<(), Box<dyn Error + Sync + Send>> {
+    endianess: &ArgEndianess,
+    backend: WriteBackend,
+    metadata: &TiffMetadata,
+) -> Result<(), Error>
+where
+    [T]: TiffValue,
+{
+    match (backend, endianess) {
+        (WriteBackend::Buffered, ArgEndianess::Big) => {
+            write_tiff_big_endian(filename, data, width, height, metadata)
+        }
+        (WriteBackend::Buffered, ArgEndianess::Native) => {
+            write_tiff_native_endian(filename, data, width, height, metadata)
+        }
+        (WriteBackend::Preallocated | WriteBackend::Direct, _) => {
+            write_tiff_preallocated(filename, data, width, height, endianess, backend, metadata)
+        }
+    }
+}
+
+/// Write a processed image, converting it to `sample_type` first.
+pub fn write_image(
+    filename: &Path,
+    image: &Image,
+    sample_type: SampleType,
+    endianess: &ArgEndianess,
+    backend: WriteBackend,
+    metadata: &TiffMetadata,
+) -> Result<(), Error> {
+    match sample_type {
+        SampleType::I16 => write_tiff(
+            filename,
+            &image.to_samples::<i16>(),
+            image.width,
+            image.height,
+            endianess,
+            backend,
+            metadata,
+        ),
+        SampleType::I32 => write_tiff(
+            filename,
+            &image.to_samples::<i32>(),
+            image.width,
+            image.height,
+            endianess,
+            backend,
+            metadata,
+        ),
+        SampleType::U8 => write_tiff(
+            filename,
+            &image.to_samples::<u8>(),
+            image.width,
+            image.height,
+            endianess,
+            backend,
+            metadata,
+        ),
+        SampleType::U16 => write_tiff(
+            filename,
+            &image.to_samples::<u16>(),
+            image.width,
+            image.height,
+            endianess,
+            backend,
+            metadata,
+        ),
+        SampleType::F32 => write_tiff(
+            filename,
+            &image.to_samples::<f32>(),
+            image.width,
+            image.height,
+            endianess,
+            backend,
+            metadata,
+        ),
+    }
+}
+
+/// Like `write_image`, encoding a native endian tiff file into `out`.
+pub fn encode_image<W: Write + Seek>(
+    out: W,
+    image: &Image,
+    sample_type: SampleType,
+    metadata: &TiffMetadata,
+) -> Result<(), Error> {
+    let (width, height) = (image.width, image.height);
+    match sample_type {
+        SampleType::I16 => encode_tiff(out, &image.to_samples::<i16>(), width, height, metadata),
+        SampleType::I32 => encode_tiff(out, &image.to_samples::<i32>(), width, height, metadata),
+        SampleType::U8 => encode_tiff(out, &image.to_samples::<u8>(), width, height, metadata),
+        SampleType::U16 => encode_tiff(out, &image.to_samples::<u16>(), width, height, metadata),
+        SampleType::F32 => encode_tiff(out, &image.to_samples::<f32>(), width, height, metadata),
+    }
+}
+
+pub fn write_tiff_native_endian<T: Sample>(
+    filename: &Path,
+    data: &[T],
+    width: usize,
+    height: usize,
+    metadata: &TiffMetadata,
+) -> Result<(), Error>
+where
+    [T]: TiffValue,
+{
     if filename.exists() {
-        return Err(Box::new(WriteError::FileAlreadyExists { path: filename.to_owned() }));
+        return Err(WriteError::FileAlreadyExists {
+            path: filename.to_owned(),
+        }
+        .into());
+    }
+    let out = TimedWriter(timings::io(|| File::create_new(filename))?);
+    encode_tiff(out, data, width, height, metadata)
+}
+
+/// Encode a native endian tiff file into `out`, which can also be in memory.
+pub fn encode_tiff<T: Sample, W: Write + Seek>(
+    mut out: W,
+    data: &[T],
+    width: usize,
+    height: usize,
+    metadata: &TiffMetadata,
+) -> Result<(), Error>
+where
+    [T]: TiffValue,
+{
+    let mut tiff = TiffEncoder::new(&mut out)?;
+    let mut image = tiff.new_image::<T::ColorType>(width as u32, height as u32)?;
+    if metadata.white_is_zero {
+        image.encoder().write_tag(
+            Tag::PhotometricInterpretation,
+            metadata.photometric_interpretation(),
+        )?;
+    }
+    if metadata.pixel_size.is_some() {
+        let (_, (n, d)) = metadata.resolution();
+        image.resolution(ResolutionUnit::Centimeter, Rational { n, d });
     }
-    let mut out_file = File::create_new(filename)?;
-    let mut tiff = TiffEncoder::new(&mut out_file)?;
-    tiff.write_image::<colortype::GrayI16>(width as u32, height as u32, data)?;
+    image.write_data(data)?;
     Ok(())
 }
 
-pub fn write_tiff_big_endian(
+/// Write a baseline tiff file in big endian byte order, with a single
+/// uncompressed strip. The samples are converted in bulk into a buffer of the
+/// final size, which is handed to the encoder.
+pub fn write_tiff_big_endian<T: Sample>(
     filename: &Path,
-    data: &[i16],
+    data: &[T],
     width: usize,
     height: usize,
-) -> Result<(), Box<dyn Error + Sync + Send>> {
+    metadata: &TiffMetadata,
+) -> Result<(), Error> {
     if filename.exists() {
-        return Err(Box::new(WriteError::FileAlreadyExists { path: filename.to_owned() }));
+        return Err(WriteError::FileAlreadyExists {
+            path: filename.to_owned(),
+        }
+        .into());
     }
-    let mut image_bytes: Vec<u8> = Vec::with_capacity(width * height * 2);
-    for value in data.iter() {
-        image_bytes.write_i16::<BigEndian>(*value)?;
+    let bytes_per_sample = size_of::<T>();
+    let mut image_bytes = Vec::with_capacity(size_of_val(data));
+    for &value in data {
+        value.extend_be(&mut image_bytes);
     }
+    let (resolution_unit, (numerator, denominator)) = metadata.resolution();
 
-    TiffFile::new(
+    let tiff = TiffFile::new(
         Ifd::new()
-            .with_entry(tags::PhotometricInterpretation, SHORT![1]) // Black is zero
+            .with_entry(
+                tags::PhotometricInterpretation,
+                SHORT![metadata.photometric_interpretation()],
+            )
             .with_entry(tags::Compression, SHORT![1]) // No compression
-
-            .with_entry(tags::BitsPerSample, SHORT![16])
+            .with_entry(tags::BitsPerSample, SHORT![(bytes_per_sample * 8) as u16])
             .with_entry(tags::SamplesPerPixel, SHORT![1])
-            .with_entry(tags::SampleFormat, SHORT![2]) // int
-
+            .with_entry(tags::SampleFormat, SHORT![T::SAMPLE_FORMAT])
             .with_entry(tags::ImageLength, LONG![height as u32])
             .with_entry(tags::ImageWidth, LONG![width as u32])
-
-            .with_entry(tags::ResolutionUnit, SHORT![1]) // No resolution unit
-            .with_entry(tags::XResolution, RATIONAL![(1, 1)])
-            .with_entry(tags::YResolution, RATIONAL![(1, 1)])
-
+            .with_entry(tags::ResolutionUnit, SHORT![resolution_unit])
+            .with_entry(tags::XResolution, RATIONAL![(numerator, denominator)])
+            .with_entry(tags::YResolution, RATIONAL![(numerator, denominator)])
             .with_entry(tags::RowsPerStrip, LONG![height as u32]) // One strip for the whole image
             .with_entry(tags::StripByteCounts, LONG![image_bytes.len() as u32])
             .with_entry(tags::StripOffsets, ByteBlock::single(image_bytes))
-            .single()
-    ).with_endianness(tiff_encoder::write::Endianness::MM).write_to(filename)?;
+            .single(),
+    )
+    .with_endianness(tiff_encoder::write::Endianness::MM);
+    timings::io(|| tiff.write_to(filename))?;
 
     Ok(())
 }
+
+/// Encode a big endian tiff file with a single page into `out`, laid out
+/// like the stacks of `StackWriter`.
+fn encode_page_big_endian<T: Sample>(
+    out: &mut Vec<u8>,
+    data: &[T],
+    width: usize,
+    height: usize,
+    metadata: &TiffMetadata,
+) {
+    let page = PageLayout::of::<T>(width, height);
+    let layout = StackLayout::new(page, 1, &ArgEndianess::Big, metadata);
+    out.extend_from_slice(&layout.header().bytes);
+    for &value in data {
+        value.extend_be(out);
+    }
+    // IFDs start on a word boundary:
+    out.resize(layout.ifd_start() as usize, 0);
+    out.extend_from_slice(&layout.ifds(metadata).bytes);
+}
+
+/// Length of the file header of classic tiff files
+const CLASSIC_HEADER_LEN: u64 = 8;
+
+/// Length of the file header of BigTIFF files
+const BIG_TIFF_HEADER_LEN: u64 = 16;
+
+/// Number of tags in each IFD written by `TiffBytes::ifd`, without an
+/// ImageDescription
+const NUM_TAGS: u64 = 13;
+
+/// Length of an IFD written by `TiffBytes::ifd`, including the X- and
+/// YResolution and the `description` that follow it.
+fn ifd_len(big_tiff: bool, description: Option<&str>) -> u64 {
+    let num_tags = NUM_TAGS + u64::from(description.is_some());
+    let len = if big_tiff {
+        8 + num_tags * 20 + 8 + 16
+    } else {
+        2 + num_tags * 12 + 4 + 16
+    };
+    len + description.map_or(0, |text| ascii_len(big_tiff, text))
+}
+
+/// Bytes of the NUL-terminated `text` after an IFD, padded to a word
+/// boundary; none if it fits into its entry.
+fn ascii_len(big_tiff: bool, text: &str) -> u64 {
+    let len = text.len() as u64 + 1;
+    let inline_len = if big_tiff { 8 } else { 4 };
+    if len <= inline_len {
+        0
+    } else {
+        len.next_multiple_of(2)
+    }
+}
+
+/// Dimensions and sample type of an uncompressed grayscale image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PageLayout {
+    width: usize,
+    height: usize,
+    bytes_per_sample: usize,
+    sample_format: u16,
+}
+
+impl PageLayout {
+    fn of<T: Sample>(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            bytes_per_sample: size_of::<T>(),
+            sample_format: T::SAMPLE_FORMAT,
+        }
+    }
+
+    fn data_len(&self) -> u64 {
+        (self.width * self.height * self.bytes_per_sample) as u64
+    }
+}
+
+/// The value of an IFD entry.
+#[derive(Debug, Clone, Copy)]
+enum IfdValue<'a> {
+    Short(u16),
+    Long(u32),
+    /// An offset or a length in bytes, which is 64 bit in BigTIFF files
+    Offset(u64),
+    /// Offset of a rational
+    Rational(u64),
+    /// Text, and its offset unless it fits into the entry
+    Ascii(&'a str, u64),
+}
+
+/// Encodes the structures of a tiff file, in either byte order and in the
+/// classic or the BigTIFF format.
+struct TiffBytes {
+    big_endian: bool,
+    big_tiff: bool,
+    bytes: Vec<u8>,
+}
+
+impl TiffBytes {
+    fn new(big_endian: bool, big_tiff: bool) -> Self {
+        Self {
+            big_endian,
+            big_tiff,
+            bytes: Vec::new(),
+        }
+    }
+
+    fn u16(&mut self, value: u16) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn u32(&mut self, value: u32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn u64(&mut self, value: u64) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn offset(&mut self, value: u64) {
+        if self.big_tiff {
+            self.u64(value);
+        } else {
+            self.u32(value as u32);
+        }
+    }
+
+    fn header(&mut self, first_ifd: u64) {
+        self.bytes
+            .extend_from_slice(if self.big_endian { b"MM" } else { b"II" });
+        if self.big_tiff {
+            self.u16(43);
+            self.u16(8); // size of the offsets
+            self.u16(0);
+        } else {
+            self.u16(42);
+        }
+        self.offset(first_ifd);
+    }
+
+    /// The IFD at `offset` of an image with a single strip at `data_offset`,
+    /// followed by its X- and YResolution and its `description`.
+    fn ifd(
+        &mut self,
+        offset: u64,
+        page: &PageLayout,
+        data_offset: u64,
+        metadata: &TiffMetadata,
+        description: Option<&str>,
+        next_ifd: u64,
+    ) {
+        let (resolution_unit, (numerator, denominator)) = metadata.resolution();
+        // the X- and YResolution come right after the IFD, followed by the description:
+        let text_len = description.map_or(0, |text| ascii_len(self.big_tiff, text));
+        let resolution_offset = offset + ifd_len(self.big_tiff, description) - text_len - 16;
+        let description_offset = resolution_offset + 16;
+        let mut entries = vec![
+            (Tag::ImageWidth, IfdValue::Long(page.width as u32)),
+            (Tag::ImageLength, IfdValue::Long(page.height as u32)),
+            (
+                Tag::BitsPerSample,
+                IfdValue::Short((page.bytes_per_sample * 8) as u16),
+            ),
+            (Tag::Compression, IfdValue::Short(1)), // no compression
+            (
+                Tag::PhotometricInterpretation,
+                IfdValue::Short(metadata.photometric_interpretation()),
+            ),
+        ];
+        if let Some(text) = description {
+            entries.push((
+                Tag::ImageDescription,
+                IfdValue::Ascii(text, description_offset),
+            ));
+        }
+        entries.extend([
+            (Tag::StripOffsets, IfdValue::Offset(data_offset)),
+            (Tag::SamplesPerPixel, IfdValue::Short(1)),
+            // one strip for the whole image:
+            (Tag::RowsPerStrip, IfdValue::Long(page.height as u32)),
+            (Tag::StripByteCounts, IfdValue::Offset(page.data_len())),
+            (Tag::XResolution, IfdValue::Rational(resolution_offset)),
+            (Tag::YResolution, IfdValue::Rational(resolution_offset + 8)),
+            (Tag::ResolutionUnit, IfdValue::Short(resolution_unit)),
+            (Tag::SampleFormat, IfdValue::Short(page.sample_format)),
+        ]);
+        if self.big_tiff {
+            self.u64(entries.len() as u64);
+        } else {
+            self.u16(entries.len() as u16);
+        }
+        let value_len = if self.big_tiff { 8 } else { 4 };
+        for (tag, value) in entries {
+            let field_type = match value {
+                IfdValue::Short(_) => Type::SHORT,
+                IfdValue::Long(_) => Type::LONG,
+                IfdValue::Offset(_) if self.big_tiff => Type::LONG8,
+                IfdValue::Offset(_) => Type::LONG,
+                IfdValue::Rational(_) => Type::RATIONAL,
+                IfdValue::Ascii(..) => Type::ASCII,
+            };
+            self.u16(tag.to_u16());
+            self.u16(field_type.to_u16());
+            match value {
+                IfdValue::Ascii(text, _) => self.offset(text.len() as u64 + 1),
+                _ => self.offset(1),
+            }
+            let start = self.bytes.len();
+            match value {
+                IfdValue::Short(value) => self.u16(value),
+                IfdValue::Long(value) => self.u32(value),
+                IfdValue::Offset(value) | IfdValue::Rational(value) => self.offset(value),
+                // the NUL terminator is added by the padding:
+                IfdValue::Ascii(text, _) if ascii_len(self.big_tiff, text) == 0 => {
+                    self.bytes.extend_from_slice(text.as_bytes());
+                }
+                IfdValue::Ascii(_, offset) => self.offset(offset),
+            }
+            // values shorter than the field are left-aligned:
+            self.bytes.resize(start + value_len, 0);
+        }
+        self.offset(next_ifd);
+        for _ in 0..2 {
+            self.u32(numerator);
+            self.u32(denominator);
+        }
+        if let Some(text) = description
+            && text_len > 0
+        {
+            let start = self.bytes.len();
+            self.bytes.extend_from_slice(text.as_bytes());
+            self.bytes.resize(start + text_len as usize, 0);
+        }
+    }
+}
+
+/// The samples of one page of a `StackWriter`, converted to the byte order
+/// of the file, which can be done in parallel for many pages.
+#[derive(Debug)]
+pub struct StackPage {
+    layout: PageLayout,
+    data: Vec<u8>,
+}
+
+impl StackPage {
+    pub fn encode<T: Sample>(
+        data: &[T],
+        width: usize,
+        height: usize,
+        endianess: &ArgEndianess,
+    ) -> Self
+    where
+        [T]: TiffValue,
+    {
+        let data = match endianess {
+            ArgEndianess::Big => {
+                let mut bytes = Vec::with_capacity(size_of_val(data));
+                for &value in data {
+                    value.extend_be(&mut bytes);
+                }
+                bytes
+            }
+            ArgEndianess::Native => data.data().into_owned(),
+        };
+        Self {
+            layout: PageLayout::of::<T>(width, height),
+            data,
+        }
+    }
+
+    /// A processed image, converted to `sample_type` first.
+    pub fn from_image(image: &Image, sample_type: SampleType, endianess: &ArgEndianess) -> Self {
+        let (width, height) = (image.width, image.height);
+        match sample_type {
+            SampleType::I16 => Self::encode(&image.to_samples::<i16>(), width, height, endianess),
+            SampleType::I32 => Self::encode(&image.to_samples::<i32>(), width, height, endianess),
+            SampleType::U8 => Self::encode(&image.to_samples::<u8>(), width, height, endianess),
+            SampleType::U16 => Self::encode(&image.to_samples::<u16>(), width, height, endianess),
+            SampleType::F32 => Self::encode(&image.to_samples::<f32>(), width, height, endianess),
+        }
+    }
+
+    /// Of the encoded samples
+    pub fn num_bytes(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// Where the pages and the IFDs of a stack go. The samples of all pages
+/// come first, in order and without gaps, and are followed by the IFDs, so
+/// each page can be written as soon as it is encoded, and the whole stack can
+/// be mapped as one array.
+#[derive(Debug, Clone, Copy)]
+struct StackLayout {
+    page: PageLayout,
+    num_pages: usize,
+    big_endian: bool,
+    big_tiff: bool,
+}
+
+impl StackLayout {
+    fn new(
+        page: PageLayout,
+        num_pages: usize,
+        endianess: &ArgEndianess,
+        metadata: &TiffMetadata,
+    ) -> Self {
+        let big_endian = match endianess {
+            ArgEndianess::Big => true,
+            ArgEndianess::Native => cfg!(target_endian = "big"),
+        };
+        let mut layout = Self {
+            page,
+            num_pages,
+            big_endian,
+            big_tiff: false,
+        };
+        // stacks that don't fit into the 32 bit offsets of classic tiff files:
+        layout.big_tiff = layout.file_len(metadata) > u64::from(u32::MAX);
+        layout
+    }
+
+    fn header_len(&self) -> u64 {
+        if self.big_tiff {
+            BIG_TIFF_HEADER_LEN
+        } else {
+            CLASSIC_HEADER_LEN
+        }
+    }
+
+    /// Of the samples of page `idx`
+    fn page_offset(&self, idx: usize) -> u64 {
+        self.header_len() + idx as u64 * self.page.data_len()
+    }
+
+    /// Of the first IFD, which starts on a word boundary
+    fn ifd_start(&self) -> u64 {
+        self.page_offset(self.num_pages).next_multiple_of(2)
+    }
+
+    fn file_len(&self, metadata: &TiffMetadata) -> u64 {
+        let ifds_len: u64 = (0..self.num_pages)
+            .map(|idx| ifd_len(self.big_tiff, metadata.page_description(idx)))
+            .sum();
+        self.ifd_start() + ifds_len
+    }
+
+    fn header(&self) -> TiffBytes {
+        let mut header = TiffBytes::new(self.big_endian, self.big_tiff);
+        header.header(self.ifd_start());
+        header
+    }
+
+    /// The IFDs of all pages, which go at `ifd_start`.
+    fn ifds(&self, metadata: &TiffMetadata) -> TiffBytes {
+        let mut ifds = TiffBytes::new(self.big_endian, self.big_tiff);
+        let mut offset = self.ifd_start();
+        for idx in 0..self.num_pages {
+            let description = metadata.page_description(idx);
+            let ifd_len = ifd_len(self.big_tiff, description);
+            let next_ifd = if idx + 1 < self.num_pages {
+                offset + ifd_len
+            } else {
+                0
+            };
+            ifds.ifd(
+                offset,
+                &self.page,
+                self.page_offset(idx),
+                metadata,
+                description,
+                next_ifd,
+            );
+            offset += ifd_len;
+        }
+        ifds
+    }
+}
+
+/// Writes slices of the same size and type as the pages of a single tiff
+/// file, one after the other. Large stacks are written as BigTIFF.
+pub struct StackWriter {
+    path: PathBuf,
+    out: BufWriter<File>,
+    layout: StackLayout,
+    metadata: TiffMetadata,
+    num_written: usize,
+}
+
+impl StackWriter {
+    /// Create `filename` for `num_pages` pages like `first`, and write that one.
+    pub fn create(
+        filename: &Path,
+        first: &StackPage,
+        num_pages: usize,
+        endianess: &ArgEndianess,
+        metadata: &TiffMetadata,
+    ) -> Result<Self, Error> {
+        if filename.exists() {
+            return Err(WriteError::FileAlreadyExists {
+                path: filename.to_owned(),
+            }
+            .into());
+        }
+        let layout = StackLayout::new(first.layout, num_pages, endianess, metadata);
+        let mut out = BufWriter::new(File::create_new(filename)?);
+        out.write_all(&layout.header().bytes)?;
+        let mut writer = Self {
+            path: filename.to_owned(),
+            out,
+            layout,
+            metadata: metadata.clone(),
+            num_written: 0,
+        };
+        writer.write_page(first)?;
+        Ok(writer)
+    }
+
+    /// Append the next page.
+    pub fn write_page(&mut self, page: &StackPage) -> Result<(), Error> {
+        if page.layout != self.layout.page || self.num_written == self.layout.num_pages {
+            return Err(WriteError::StackPageMismatch {
+                path: self.path.clone(),
+                page: self.num_written + 1,
+            }
+            .into());
+        }
+        self.out.write_all(&page.data)?;
+        self.num_written += 1;
+        Ok(())
+    }
+
+    /// Write the IFDs of all pages, once they have been written.
+    pub fn finish(mut self) -> Result<(), Error> {
+        let layout = self.layout;
+        if self.num_written != layout.num_pages {
+            return Err(WriteError::StackIncomplete {
+                path: self.path,
+                written: self.num_written,
+                expected: layout.num_pages,
+            }
+            .into());
+        }
+        let padding = layout.ifd_start() - layout.page_offset(layout.num_pages);
+        self.out.write_all(&vec![0; padding as usize])?;
+        self.out.write_all(&layout.ifds(&self.metadata).bytes)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Like `StackWriter`, but the file is preallocated at its final size, and
+/// each page is written at its own offset as soon as it is encoded, from any
+/// thread and in any order. That saves waiting for the pages in order, and
+/// opening a file for each slice when the slices are small.
+pub struct PreallocatedStack {
+    path: PathBuf,
+    file: File,
+    layout: StackLayout,
+    metadata: TiffMetadata,
+    num_written: AtomicUsize,
+}
+
+impl PreallocatedStack {
+    /// Create `filename` for `num_pages` pages like `first`, and write that one.
+    pub fn create(
+        filename: &Path,
+        first: &StackPage,
+        num_pages: usize,
+        endianess: &ArgEndianess,
+        metadata: &TiffMetadata,
+    ) -> Result<Self, Error> {
+        if filename.exists() {
+            return Err(WriteError::FileAlreadyExists {
+                path: filename.to_owned(),
+            }
+            .into());
+        }
+        let layout = StackLayout::new(first.layout, num_pages, endianess, metadata);
+        let file = File::create_new(filename)?;
+        file.set_len(layout.file_len(metadata))?;
+        preallocate(&file, layout.file_len(metadata))?;
+        write_all_at(&file, &layout.header().bytes, 0)?;
+        let stack = Self {
+            path: filename.to_owned(),
+            file,
+            layout,
+            metadata: metadata.clone(),
+            num_written: AtomicUsize::new(0),
+        };
+        stack.write_page(0, first)?;
+        Ok(stack)
+    }
+
+    /// Write page `idx` (0-indexed).
+    pub fn write_page(&self, idx: usize, page: &StackPage) -> Result<(), Error> {
+        if page.layout != self.layout.page || idx >= self.layout.num_pages {
+            return Err(WriteError::StackPageMismatch {
+                path: self.path.clone(),
+                page: idx + 1,
+            }
+            .into());
+        }
+        write_all_at(&self.file, &page.data, self.layout.page_offset(idx))?;
+        self.num_written.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Write the IFDs of all pages, once they have been written.
+    pub fn finish(self) -> Result<(), Error> {
+        let num_written = self.num_written.into_inner();
+        if num_written != self.layout.num_pages {
+            return Err(WriteError::StackIncomplete {
+                path: self.path,
+                written: num_written,
+                expected: self.layout.num_pages,
+            }
+            .into());
+        }
+        let ifds = self.layout.ifds(&self.metadata);
+        write_all_at(&self.file, &ifds.bytes, self.layout.ifd_start())?;
+        Ok(())
+    }
+}
+
+/// Write all of `buf` at `offset` into `file`, which several threads can do
+/// at the same time.
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+
+        file.write_all_at(buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+
+        let mut written = 0;
+        while written < buf.len() {
+            match file.seek_write(&buf[written..], offset + written as u64)? {
+                0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                n => written += n,
+            }
+        }
+        Ok(())
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (file, buf, offset);
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Allocate the first `len` bytes of `file` at once, instead of block by block
+/// as they are written, where that is supported.
+fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        // SAFETY: the descriptor is open as long as `file` exists
+        let res = unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) };
+        if res != 0 && res != libc::EOPNOTSUPP {
+            return Err(std::io::Error::from_raw_os_error(res));
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (file, len);
+    Ok(())
+}
+
+/// Alignment of the memory, offset and length of writes with O_DIRECT, which
+/// is a multiple of the logical block size of common devices
+const DIRECT_ALIGN: usize = 4096;
+
+thread_local! {
+    /// Reused for encoding whole files for `write_tiff_preallocated`.
+    static ENCODE_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Encode the whole tiff file in memory, and write it at once into a file
+/// preallocated at its final size. With `WriteBackend::Direct`, that is a
+/// single unbuffered write, which on parallel file systems like BeeGFS or
+/// Lustre is much faster than many small writes through the page cache.
+pub fn write_tiff_preallocated<T: Sample>(
+    filename: &Path,
+    data: &[T],
+    width: usize,
+    height: usize,
+    endianess: &ArgEndianess,
+    backend: WriteBackend,
+    metadata: &TiffMetadata,
+) -> Result<(), Error>
+where
+    [T]: TiffValue,
+{
+    if filename.exists() {
+        return Err(WriteError::FileAlreadyExists {
+            path: filename.to_owned(),
+        }
+        .into());
+    }
+    ENCODE_BUFFER.with_borrow_mut(|buffer| {
+        buffer.clear();
+        match endianess {
+            ArgEndianess::Big => encode_page_big_endian(buffer, data, width, height, metadata),
+            ArgEndianess::Native => {
+                encode_tiff(Cursor::new(&mut *buffer), data, width, height, metadata)?
+            }
+        }
+        if backend != WriteBackend::Direct {
+            timings::io(|| -> std::io::Result<()> {
+                let file = File::create_new(filename)?;
+                preallocate(&file, buffer.len() as u64)?;
+                (&file).write_all(buffer)
+            })?;
+            return Ok(());
+        }
+        // move the file to an aligned address, padded to whole blocks:
+        let len = buffer.len();
+        let padded_len = len.next_multiple_of(DIRECT_ALIGN);
+        buffer.resize(padded_len + DIRECT_ALIGN, 0);
+        let start = buffer.as_ptr().align_offset(DIRECT_ALIGN);
+        buffer.copy_within(0..len, start);
+        timings::io(|| write_direct(filename, &buffer[start..start + padded_len], len))?;
+        Ok(())
+    })
+}
+
+/// Write the first `len` bytes of the aligned and padded `blocks` into the
+/// new file `filename`, bypassing the page cache where that is supported.
+fn write_direct(filename: &Path, blocks: &[u8], len: usize) -> std::io::Result<()> {
+    let mut file = File::create_new(filename)?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let fd = file.as_raw_fd();
+        // SAFETY: `fd` is open as long as `file` exists
+        let direct = unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            flags >= 0 && libc::fcntl(fd, libc::F_SETFL, flags | libc::O_DIRECT) == 0
+        };
+        // file systems without support for O_DIRECT, like tmpfs, fall back
+        // to buffered writes:
+        if direct {
+            preallocate(&file, blocks.len() as u64)?;
+            file.write_all(blocks)?;
+            // drop the padding:
+            return file.set_len(len as u64);
+        }
+    }
+    file.write_all(&blocks[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    use super::*;
+
+    const WIDTH: usize = 3;
+    const HEIGHT: usize = 2;
+
+    fn samples(idx: usize) -> Vec<i16> {
+        (0..WIDTH * HEIGHT)
+            .map(|i| (i as i16 - 2) * 1000 + idx as i16)
+            .collect()
+    }
+
+    fn metadata() -> TiffMetadata {
+        TiffMetadata {
+            white_is_zero: false,
+            pixel_size: Some(2.5),
+            // inline in classic tiff, padded after the IFD, and no description:
+            page_descriptions: vec!["abc".into(), "section=2\ntilt_angle=-3.5".into()],
+        }
+    }
+
+    /// Check the `num_pages` pages of the tiff file in `bytes`, and their descriptions.
+    fn check_stack(bytes: Vec<u8>, num_pages: usize, metadata: &TiffMetadata) {
+        let mut decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+        for idx in 0..num_pages {
+            if idx > 0 {
+                decoder.next_image().unwrap();
+            }
+            assert_eq!(decoder.dimensions().unwrap(), (WIDTH as u32, HEIGHT as u32));
+            let description = decoder
+                .find_tag(Tag::ImageDescription)
+                .unwrap()
+                .map(|value| value.into_string().unwrap());
+            assert_eq!(description.as_deref(), metadata.page_description(idx));
+            match decoder.read_image().unwrap() {
+                DecodingResult::I16(data) => assert_eq!(data, samples(idx)),
+                other => panic!("unexpected samples {other:?}"),
+            }
+        }
+        assert!(!decoder.more_images());
+    }
+
+    fn pages(num_pages: usize, endianess: &ArgEndianess) -> Vec<StackPage> {
+        (0..num_pages)
+            .map(|idx| StackPage::encode(&samples(idx), WIDTH, HEIGHT, endianess))
+            .collect()
+    }
+
+    #[test]
+    fn stack_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stack.tif");
+        let metadata = metadata();
+        let pages = pages(3, &ArgEndianess::Big);
+        let mut writer =
+            StackWriter::create(&path, &pages[0], 3, &ArgEndianess::Big, &metadata).unwrap();
+        for page in &pages[1..] {
+            writer.write_page(page).unwrap();
+        }
+        let layout = writer.layout;
+        writer.finish().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len() as u64, layout.file_len(&metadata));
+        check_stack(bytes, 3, &metadata);
+    }
+
+    #[test]
+    fn preallocated_stack_in_any_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stack.tif");
+        let metadata = metadata();
+        let pages = pages(4, &ArgEndianess::Native);
+        let stack =
+            PreallocatedStack::create(&path, &pages[0], 4, &ArgEndianess::Native, &metadata)
+                .unwrap();
+        for idx in [3, 1, 2] {
+            stack.write_page(idx, &pages[idx]).unwrap();
+        }
+        stack.finish().unwrap();
+        check_stack(std::fs::read(&path).unwrap(), 4, &metadata);
+    }
+
+    #[test]
+    fn incomplete_stack() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stack.tif");
+        let pages = pages(1, &ArgEndianess::Big);
+        let writer =
+            StackWriter::create(&path, &pages[0], 2, &ArgEndianess::Big, &metadata()).unwrap();
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn big_tiff_ifds() {
+        let metadata = metadata();
+        let pages = pages(3, &ArgEndianess::Big);
+        let mut layout = StackLayout::new(pages[0].layout, 3, &ArgEndianess::Big, &metadata);
+        assert!(!layout.big_tiff);
+        // too large to write in a test, so switch to BigTIFF by hand:
+        layout.big_tiff = true;
+        let mut bytes = layout.header().bytes;
+        for page in &pages {
+            bytes.extend_from_slice(&page.data);
+        }
+        bytes.resize(layout.ifd_start() as usize, 0);
+        bytes.extend_from_slice(&layout.ifds(&metadata).bytes);
+        assert_eq!(bytes.len() as u64, layout.file_len(&metadata));
+        check_stack(bytes, 3, &metadata);
+    }
+
+    #[test]
+    fn preallocated_big_endian() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("slice.tif");
+        let backend = WriteBackend::Preallocated;
+        let metadata = TiffMetadata::default();
+        write_tiff(
+            &path,
+            &samples(0),
+            WIDTH,
+            HEIGHT,
+            &ArgEndianess::Big,
+            backend,
+            &metadata,
+        )
+        .unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..2], b"MM");
+        check_stack(bytes, 1, &metadata);
+    }
+}
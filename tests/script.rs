@@ -0,0 +1,123 @@
+//! Runs GUI scripts against a small generated stack, which exercises opening
+//! a file, selecting a range and exporting it the same way the window does.
+#![cfg(feature = "gui")]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use tiff::decoder::{Decoder, DecodingResult};
+
+const NX: usize = 6;
+const NY: usize = 4;
+const NZ: usize = 5;
+
+fn value(x: usize, y: usize, z: usize) -> i16 {
+    (z * 1000 + y * 10 + x) as i16 - 2000
+}
+
+/// Write a mode 1 (int16) stack with a minimal MRC2014 header.
+fn write_mrc(path: &Path) {
+    let data: Vec<i16> = (0..NZ)
+        .flat_map(|z| (0..NY).flat_map(move |y| (0..NX).map(move |x| value(x, y, z))))
+        .collect();
+    let (min, max) = (*data.iter().min().unwrap(), *data.iter().max().unwrap());
+    let mean = data.iter().map(|&v| f32::from(v)).sum::<f32>() / data.len() as f32;
+
+    let mut header = vec![0u8; 1024];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        header[offset..][..bytes.len()].copy_from_slice(bytes);
+    };
+    for (idx, value) in [NX, NY, NZ].into_iter().enumerate() {
+        put(idx * 4, &(value as i32).to_le_bytes()); // nx, ny, nz
+        put(28 + idx * 4, &(value as i32).to_le_bytes()); // mx, my, mz
+        put(40 + idx * 4, &(value as f32).to_le_bytes()); // cella, 1Å pixels
+        put(52 + idx * 4, &90.0f32.to_le_bytes()); // cellb
+        put(64 + idx * 4, &(idx as i32 + 1).to_le_bytes()); // mapc, mapr, maps
+    }
+    put(12, &1i32.to_le_bytes()); // mode
+    put(76, &f32::from(min).to_le_bytes());
+    put(80, &f32::from(max).to_le_bytes());
+    put(84, &mean.to_le_bytes());
+    put(88, &1i32.to_le_bytes()); // ispg
+    put(108, &20140i32.to_le_bytes()); // nversion
+    put(208, b"MAP ");
+    put(212, &[0x44, 0x44, 0, 0]); // machst, little endian
+    header.extend(data.iter().flat_map(|v| v.to_le_bytes()));
+    fs::write(path, header).unwrap();
+}
+
+fn run_script(dir: &Path, script: &str) -> std::process::Output {
+    let script_path = dir.join("script.txt");
+    fs::write(&script_path, script).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_mrc-to-tiff-gui"))
+        .arg("--script")
+        .arg(&script_path)
+        .output()
+        .unwrap()
+}
+
+fn read_slice(path: &Path) -> Vec<i16> {
+    let mut decoder = Decoder::new(fs::File::open(path).unwrap()).unwrap();
+    assert_eq!(decoder.dimensions().unwrap(), (NX as u32, NY as u32));
+    match decoder.read_image().unwrap() {
+        DecodingResult::I16(data) => data,
+        other => panic!("unexpected samples in {path:?}: {other:?}"),
+    }
+}
+
+#[test]
+fn export_a_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let mrc_path = dir.path().join("stack.mrc");
+    let dest: PathBuf = dir.path().join("out");
+    write_mrc(&mrc_path);
+    fs::create_dir(&dest).unwrap();
+
+    let script = [
+        "# export the middle three slices".to_owned(),
+        format!("open {}", mrc_path.display()),
+        format!("destination {}", dest.display()),
+        "range 2 4".to_owned(),
+        "slice 3".to_owned(),
+        "export".to_owned(),
+    ];
+    let output = run_script(dir.path(), &script.join("\n"));
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut written: Vec<String> = fs::read_dir(&dest)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    written.sort();
+    assert_eq!(
+        written,
+        ["slice_00001.tif", "slice_00002.tif", "slice_00003.tif"]
+    );
+    for (idx, file) in written.iter().enumerate() {
+        let z = idx + 1;
+        let expected: Vec<i16> = (0..NY)
+            .flat_map(|y| (0..NX).map(move |x| value(x, y, z)))
+            .collect();
+        assert_eq!(read_slice(&dest.join(file)), expected, "{file}");
+    }
+}
+
+#[test]
+fn invalid_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let mrc_path = dir.path().join("stack.mrc");
+    write_mrc(&mrc_path);
+    let output = run_script(
+        dir.path(),
+        &format!("open {}\nrange 3 9\n", mrc_path.display()),
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid range 3 to 9"));
+}